@@ -0,0 +1,41 @@
+//! Feeds logs built from arbitrary bytes to `extract_event`, which must reject
+//! malformed logs with an error or `Ok(None)` instead of panicking.
+//!
+//! Input layout: the first byte modulo 5 is the number of topics, followed by
+//! 32 bytes per topic. The remaining bytes are the log data.
+#![no_main]
+
+use alloy::primitives::{B256, LogData};
+use alloy::rpc::types::Log;
+use libfuzzer_sys::fuzz_target;
+use monad_staking_indexer::{STAKING_CONTRACT_ADDRESS, events::extract_event};
+
+fn log_from_bytes(input: &[u8]) -> Option<Log> {
+    let (&topic_count, mut rest) = input.split_first()?;
+    let mut topics = Vec::new();
+    for _ in 0..topic_count % 5 {
+        let (topic, tail) = rest.split_at_checked(32)?;
+        topics.push(B256::from_slice(topic));
+        rest = tail;
+    }
+
+    Some(Log {
+        inner: alloy::primitives::Log {
+            address: STAKING_CONTRACT_ADDRESS,
+            data: LogData::new_unchecked(topics, rest.to_vec().into()),
+        },
+        block_hash: Some(B256::repeat_byte(0xbb)),
+        block_number: Some(100),
+        block_timestamp: Some(1234567890),
+        transaction_hash: Some(B256::repeat_byte(0xcc)),
+        transaction_index: Some(0),
+        log_index: Some(0),
+        removed: false,
+    })
+}
+
+fuzz_target!(|input: &[u8]| {
+    if let Some(log) = log_from_bytes(input) {
+        let _ = extract_event(&log, STAKING_CONTRACT_ADDRESS);
+    }
+});
@@ -0,0 +1,19 @@
+-- Current state per validator, maintained from ValidatorCreated,
+-- CommissionChanged and ValidatorStatusChanged events. The *_block and
+-- *_transaction_index columns record the position of the event that last set
+-- each attribute, so that backfilling older blocks never overwrites newer state.
+CREATE TABLE validators (
+    validator_id BIGINT PRIMARY KEY,
+    auth_address VARCHAR(40),
+    commission NUMERIC(78, 0),
+    flags BIGINT,
+    created_block BIGINT,
+    updated_block BIGINT NOT NULL,
+    commission_block BIGINT,
+    commission_transaction_index BIGINT,
+    flags_block BIGINT,
+    flags_transaction_index BIGINT,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_validators_auth_address ON validators(auth_address);
@@ -0,0 +1,14 @@
+-- Current stake per (validator, delegator), maintained from Delegate and
+-- Undelegate events. The stake is the exact running sum of the indexed events
+-- and may be transiently negative while older blocks are still being
+-- backfilled; readers clamp it at zero.
+CREATE TABLE delegations (
+    val_id BIGINT NOT NULL,
+    delegator VARCHAR(40) NOT NULL,
+    stake NUMERIC(78, 0) NOT NULL,
+    updated_block BIGINT NOT NULL,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (val_id, delegator)
+);
+
+CREATE INDEX idx_delegations_delegator ON delegations(delegator);
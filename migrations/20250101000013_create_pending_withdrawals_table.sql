@@ -0,0 +1,16 @@
+-- Withdrawals per (validator, delegator, withdrawal id), maintained from
+-- Undelegate and Withdraw events. Withdrawal ids are reused by the staking
+-- contract, so a row is pending when its latest Undelegate happened after its
+-- latest Withdraw. A Withdraw backfilled before its Undelegate leaves a row
+-- with only withdraw_block set, which is completed when the Undelegate lands.
+CREATE TABLE pending_withdrawals (
+    val_id BIGINT NOT NULL,
+    delegator VARCHAR(40) NOT NULL,
+    withdrawal_id SMALLINT NOT NULL,
+    amount NUMERIC(78, 0),
+    undelegate_block BIGINT,
+    undelegate_transaction_index BIGINT,
+    withdraw_block BIGINT,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (val_id, delegator, withdrawal_id)
+);
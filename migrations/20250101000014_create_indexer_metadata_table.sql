@@ -0,0 +1,22 @@
+-- Key/value state of the indexer itself. `pruned_below` is the block number
+-- below which raw events and blocks have been deleted by retention pruning;
+-- gap detection ignores everything below it.
+CREATE TABLE indexer_metadata (
+    key VARCHAR(64) PRIMARY KEY,
+    value BIGINT NOT NULL,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Retention pruning deletes old rows from the raw event tables and blocks.
+GRANT DELETE ON
+    delegate_events,
+    undelegate_events,
+    withdraw_events,
+    claim_rewards_events,
+    validator_rewarded_events,
+    epoch_changed_events,
+    validator_created_events,
+    validator_status_changed_events,
+    commission_changed_events,
+    blocks
+TO monad_staking_app;
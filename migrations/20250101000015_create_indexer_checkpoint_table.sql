@@ -0,0 +1,8 @@
+-- Highest block up to which every block has been scanned for staking events,
+-- including blocks without any. Unlike MAX(blocks.block_number) this advances
+-- through quiet ranges, so a restart does not scan them again.
+CREATE TABLE indexer_checkpoint (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    block_number BIGINT NOT NULL,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
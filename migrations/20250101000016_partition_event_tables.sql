@@ -0,0 +1,191 @@
+-- Partition the raw event tables and blocks by block_number in ranges of
+-- 10M blocks. Unique constraints of a partitioned table must include the
+-- partition key, so block_number is added to all of them. A transaction
+-- belongs to exactly one block, so deduplication is unchanged for real data.
+--
+-- Downtime: the tables are copied in one statement each, within the single
+-- transaction the migration runs in. Every event table and blocks stay locked
+-- until it commits, so stop the indexer and the API readers first. The time
+-- grows with the stored rows, as each table and its indexes are written again,
+-- and the database needs free space for a second copy of them until then.
+-- Copying in batches would not help, as nothing commits before the end.
+
+-- Creates the missing partitions of all partitioned tables up to and
+-- including the one containing up_to_block, and returns how many were
+-- created. Runs as the table owner so the application role can call it.
+CREATE FUNCTION ensure_block_partitions(up_to_block BIGINT) RETURNS INTEGER
+LANGUAGE plpgsql SECURITY DEFINER SET search_path = public AS $$
+DECLARE
+    partition_size CONSTANT BIGINT := 10000000;
+    parent TEXT;
+    partition_start BIGINT;
+    partition_name TEXT;
+    created INTEGER := 0;
+BEGIN
+    FOREACH parent IN ARRAY ARRAY[
+        'delegate_events',
+        'undelegate_events',
+        'withdraw_events',
+        'claim_rewards_events',
+        'validator_rewarded_events',
+        'epoch_changed_events',
+        'validator_created_events',
+        'validator_status_changed_events',
+        'commission_changed_events',
+        'blocks'
+    ] LOOP
+        partition_start := 0;
+        WHILE partition_start <= up_to_block LOOP
+            partition_name := format('%s_p%s', parent, partition_start / partition_size);
+            IF to_regclass(partition_name) IS NULL THEN
+                EXECUTE format(
+                    'CREATE TABLE %I PARTITION OF %I FOR VALUES FROM (%s) TO (%s)',
+                    partition_name, parent, partition_start, partition_start + partition_size
+                );
+                created := created + 1;
+            END IF;
+            partition_start := partition_start + partition_size;
+        END LOOP;
+    END LOOP;
+    RETURN created;
+END
+$$;
+
+REVOKE ALL ON FUNCTION ensure_block_partitions(BIGINT) FROM PUBLIC;
+GRANT EXECUTE ON FUNCTION ensure_block_partitions(BIGINT) TO monad_staking_app;
+
+-- Replace each table by a partitioned copy. Sequences are handed over to the
+-- new tables so ids keep counting from where they were.
+DO $$
+DECLARE
+    tbl TEXT;
+BEGIN
+    FOREACH tbl IN ARRAY ARRAY[
+        'delegate_events',
+        'undelegate_events',
+        'withdraw_events',
+        'claim_rewards_events',
+        'validator_rewarded_events',
+        'epoch_changed_events',
+        'validator_created_events',
+        'validator_status_changed_events',
+        'commission_changed_events',
+        'blocks'
+    ] LOOP
+        EXECUTE format('ALTER TABLE %I RENAME TO %I', tbl, tbl || '_unpartitioned');
+        EXECUTE format(
+            'CREATE TABLE %I (LIKE %I INCLUDING DEFAULTS) PARTITION BY RANGE (block_number)',
+            tbl, tbl || '_unpartitioned'
+        );
+        IF tbl <> 'blocks' THEN
+            EXECUTE format('ALTER SEQUENCE %I OWNED BY %I.id', tbl || '_id_seq', tbl);
+        END IF;
+    END LOOP;
+
+    PERFORM ensure_block_partitions(
+        COALESCE((SELECT MAX(block_number) FROM blocks_unpartitioned), 0)
+    );
+
+    FOREACH tbl IN ARRAY ARRAY[
+        'delegate_events',
+        'undelegate_events',
+        'withdraw_events',
+        'claim_rewards_events',
+        'validator_rewarded_events',
+        'epoch_changed_events',
+        'validator_created_events',
+        'validator_status_changed_events',
+        'commission_changed_events',
+        'blocks'
+    ] LOOP
+        EXECUTE format('INSERT INTO %I SELECT * FROM %I', tbl, tbl || '_unpartitioned');
+        EXECUTE format('DROP TABLE %I', tbl || '_unpartitioned');
+    END LOOP;
+END
+$$;
+
+ALTER TABLE delegate_events
+    ADD PRIMARY KEY (id, block_number),
+    ADD UNIQUE (val_id, transaction_hash, block_number);
+CREATE INDEX idx_delegate_val_id ON delegate_events(val_id);
+CREATE INDEX idx_delegate_delegator ON delegate_events(delegator);
+CREATE INDEX idx_delegate_block_number ON delegate_events(block_number);
+CREATE INDEX idx_delegate_activation_epoch ON delegate_events(activation_epoch);
+
+ALTER TABLE undelegate_events
+    ADD PRIMARY KEY (id, block_number),
+    ADD UNIQUE (val_id, transaction_hash, block_number);
+CREATE INDEX idx_undelegate_val_id ON undelegate_events(val_id);
+CREATE INDEX idx_undelegate_delegator ON undelegate_events(delegator);
+CREATE INDEX idx_undelegate_block_number ON undelegate_events(block_number);
+CREATE INDEX idx_undelegate_activation_epoch ON undelegate_events(activation_epoch);
+
+ALTER TABLE withdraw_events
+    ADD PRIMARY KEY (id, block_number),
+    ADD UNIQUE (val_id, transaction_hash, block_number);
+CREATE INDEX idx_withdraw_val_id ON withdraw_events(val_id);
+CREATE INDEX idx_withdraw_delegator ON withdraw_events(delegator);
+CREATE INDEX idx_withdraw_block_number ON withdraw_events(block_number);
+CREATE INDEX idx_withdraw_activation_epoch ON withdraw_events(activation_epoch);
+
+ALTER TABLE claim_rewards_events
+    ADD PRIMARY KEY (id, block_number),
+    ADD UNIQUE (val_id, transaction_hash, block_number);
+CREATE INDEX idx_claim_rewards_val_id ON claim_rewards_events(val_id);
+CREATE INDEX idx_claim_rewards_delegator ON claim_rewards_events(delegator);
+CREATE INDEX idx_claim_rewards_block_number ON claim_rewards_events(block_number);
+CREATE INDEX idx_claim_rewards_epoch ON claim_rewards_events(epoch);
+
+ALTER TABLE validator_rewarded_events
+    ADD PRIMARY KEY (id, block_number),
+    ADD UNIQUE (transaction_hash, block_number);
+CREATE INDEX idx_validator_rewarded_validator_id ON validator_rewarded_events(validator_id);
+CREATE INDEX idx_validator_rewarded_from_address ON validator_rewarded_events(from_address);
+CREATE INDEX idx_validator_rewarded_block_number ON validator_rewarded_events(block_number);
+CREATE INDEX idx_validator_rewarded_epoch ON validator_rewarded_events(epoch);
+
+ALTER TABLE epoch_changed_events
+    ADD PRIMARY KEY (id, block_number),
+    ADD UNIQUE (transaction_hash, block_number);
+CREATE INDEX idx_epoch_changed_old_epoch ON epoch_changed_events(old_epoch);
+CREATE INDEX idx_epoch_changed_new_epoch ON epoch_changed_events(new_epoch);
+CREATE INDEX idx_epoch_changed_block_number ON epoch_changed_events(block_number);
+
+ALTER TABLE validator_created_events
+    ADD PRIMARY KEY (id, block_number),
+    ADD UNIQUE (transaction_hash, block_number);
+CREATE INDEX idx_validator_created_validator_id ON validator_created_events(validator_id);
+CREATE INDEX idx_validator_created_auth_address ON validator_created_events(auth_address);
+CREATE INDEX idx_validator_created_block_number ON validator_created_events(block_number);
+
+ALTER TABLE validator_status_changed_events
+    ADD PRIMARY KEY (id, block_number),
+    ADD UNIQUE (validator_id, transaction_hash, block_number);
+CREATE INDEX idx_validator_status_changed_validator_id ON validator_status_changed_events(validator_id);
+CREATE INDEX idx_validator_status_changed_block_number ON validator_status_changed_events(block_number);
+
+ALTER TABLE commission_changed_events
+    ADD PRIMARY KEY (id, block_number),
+    ADD UNIQUE (validator_id, transaction_hash, block_number);
+CREATE INDEX idx_commission_changed_validator_id ON commission_changed_events(validator_id);
+CREATE INDEX idx_commission_changed_block_number ON commission_changed_events(block_number);
+
+ALTER TABLE blocks
+    ADD PRIMARY KEY (block_number),
+    ADD UNIQUE (block_hash, block_number);
+CREATE INDEX idx_blocks_timestamp ON blocks(block_timestamp);
+
+-- Default privileges cover the new tables, but the DELETE grant used by
+-- retention pruning was on the replaced ones.
+GRANT DELETE ON
+    delegate_events,
+    undelegate_events,
+    withdraw_events,
+    claim_rewards_events,
+    validator_rewarded_events,
+    epoch_changed_events,
+    validator_created_events,
+    validator_status_changed_events,
+    commission_changed_events,
+    blocks
+TO monad_staking_app;
@@ -0,0 +1,18 @@
+-- Dead-letter queue of block batches that could not be inserted. Each row
+-- holds the serialized batch; the indexer replays due rows periodically and
+-- deletes them once the insert succeeds, backing off after every failure.
+CREATE TABLE failed_batches (
+    id BIGSERIAL PRIMARY KEY,
+    first_block BIGINT,
+    last_block BIGINT,
+    batch JSONB NOT NULL,
+    error TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_failed_batches_next_attempt_at ON failed_batches(next_attempt_at);
+
+-- Replayed batches are removed from the queue.
+GRANT DELETE ON failed_batches TO monad_staking_app;
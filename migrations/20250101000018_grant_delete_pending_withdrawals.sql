@@ -0,0 +1,3 @@
+-- Re-indexing a block range removes withdrawals that only events in the
+-- range had set.
+GRANT DELETE ON pending_withdrawals TO monad_staking_app;
@@ -0,0 +1,20 @@
+-- First block of each epoch, maintained from EpochChanged events. Backfill can
+-- deliver an epoch's EpochChanged event after later ones, so each row keeps
+-- the lowest start block seen for its epoch.
+CREATE TABLE epochs (
+    epoch BIGINT PRIMARY KEY,
+    start_block BIGINT NOT NULL,
+    start_timestamp BIGINT NOT NULL,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_epochs_start_block ON epochs(start_block);
+
+INSERT INTO epochs (epoch, start_block, start_timestamp)
+SELECT DISTINCT ON (e.new_epoch) e.new_epoch, e.block_number, b.block_timestamp
+FROM epoch_changed_events e
+JOIN blocks b ON b.block_number = e.block_number
+ORDER BY e.new_epoch, e.block_number;
+
+-- Re-indexing a block range removes the epochs that started in it.
+GRANT DELETE ON epochs TO monad_staking_app;
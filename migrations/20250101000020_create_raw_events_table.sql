@@ -0,0 +1,20 @@
+-- Logs of the staking contract whose event signature the indexer could not
+-- decode, kept so a later release can decode them without scanning the chain
+-- again. They are rare, so the table is not partitioned.
+CREATE TABLE raw_events (
+    id BIGSERIAL PRIMARY KEY,
+    topic0 VARCHAR(64) NOT NULL,
+    topics TEXT[] NOT NULL,
+    data BYTEA NOT NULL,
+    block_number BIGINT NOT NULL,
+    transaction_hash VARCHAR(64) NOT NULL,
+    transaction_index BIGINT NOT NULL,
+    log_index BIGINT NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE(block_number, transaction_hash, log_index)
+);
+
+CREATE INDEX idx_raw_events_topic0 ON raw_events(topic0);
+
+-- Deleted by retention pruning and re-indexing like the other event tables.
+GRANT DELETE ON raw_events TO monad_staking_app;
@@ -0,0 +1,46 @@
+-- Every event row belongs to an indexed block. Without a blocks row an event
+-- is invisible to gap detection, so events now reference their block and are
+-- deleted together with it.
+
+-- Events left without a block, e.g. by a manual cleanup, get a placeholder
+-- block with an empty hash. Re-index those blocks with the
+-- `reindex --from N --to M` command to restore their hash, timestamp and any
+-- events missing next to them.
+SELECT ensure_block_partitions(COALESCE((SELECT MAX(block_number) FROM raw_events), 0));
+
+INSERT INTO blocks (block_number, block_hash, block_timestamp)
+SELECT e.block_number, '', 0
+FROM (
+    SELECT block_number FROM delegate_events
+    UNION SELECT block_number FROM undelegate_events
+    UNION SELECT block_number FROM withdraw_events
+    UNION SELECT block_number FROM claim_rewards_events
+    UNION SELECT block_number FROM validator_rewarded_events
+    UNION SELECT block_number FROM epoch_changed_events
+    UNION SELECT block_number FROM validator_created_events
+    UNION SELECT block_number FROM validator_status_changed_events
+    UNION SELECT block_number FROM commission_changed_events
+    UNION SELECT block_number FROM raw_events
+) e
+WHERE NOT EXISTS (SELECT 1 FROM blocks b WHERE b.block_number = e.block_number);
+
+ALTER TABLE delegate_events
+    ADD FOREIGN KEY (block_number) REFERENCES blocks(block_number) ON DELETE CASCADE;
+ALTER TABLE undelegate_events
+    ADD FOREIGN KEY (block_number) REFERENCES blocks(block_number) ON DELETE CASCADE;
+ALTER TABLE withdraw_events
+    ADD FOREIGN KEY (block_number) REFERENCES blocks(block_number) ON DELETE CASCADE;
+ALTER TABLE claim_rewards_events
+    ADD FOREIGN KEY (block_number) REFERENCES blocks(block_number) ON DELETE CASCADE;
+ALTER TABLE validator_rewarded_events
+    ADD FOREIGN KEY (block_number) REFERENCES blocks(block_number) ON DELETE CASCADE;
+ALTER TABLE epoch_changed_events
+    ADD FOREIGN KEY (block_number) REFERENCES blocks(block_number) ON DELETE CASCADE;
+ALTER TABLE validator_created_events
+    ADD FOREIGN KEY (block_number) REFERENCES blocks(block_number) ON DELETE CASCADE;
+ALTER TABLE validator_status_changed_events
+    ADD FOREIGN KEY (block_number) REFERENCES blocks(block_number) ON DELETE CASCADE;
+ALTER TABLE commission_changed_events
+    ADD FOREIGN KEY (block_number) REFERENCES blocks(block_number) ON DELETE CASCADE;
+ALTER TABLE raw_events
+    ADD FOREIGN KEY (block_number) REFERENCES blocks(block_number) ON DELETE CASCADE;
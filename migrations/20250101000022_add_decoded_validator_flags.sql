@@ -0,0 +1,19 @@
+-- Decoded bits of the validator status flags, so that SQL consumers can filter
+-- on them without knowing the bit layout. Must match ValidatorFlags. Bits
+-- without a meaning yet are kept in the raw flags and exposed in unknown_flags.
+ALTER TABLE validator_status_changed_events
+    ADD COLUMN is_active BOOLEAN GENERATED ALWAYS AS (flags = 0) STORED,
+    ADD COLUMN is_stake_too_low BOOLEAN GENERATED ALWAYS AS (flags & 1 <> 0) STORED,
+    ADD COLUMN is_withdrawn BOOLEAN GENERATED ALWAYS AS (flags & 2 <> 0) STORED,
+    ADD COLUMN is_jailed BOOLEAN GENERATED ALWAYS AS (flags & 4 <> 0) STORED,
+    ADD COLUMN unknown_flags BIGINT GENERATED ALWAYS AS (flags & ~7::BIGINT) STORED;
+
+-- NULL until a status change of the validator has been indexed.
+ALTER TABLE validators
+    ADD COLUMN is_active BOOLEAN GENERATED ALWAYS AS (flags = 0) STORED,
+    ADD COLUMN is_stake_too_low BOOLEAN GENERATED ALWAYS AS (flags & 1 <> 0) STORED,
+    ADD COLUMN is_withdrawn BOOLEAN GENERATED ALWAYS AS (flags & 2 <> 0) STORED,
+    ADD COLUMN is_jailed BOOLEAN GENERATED ALWAYS AS (flags & 4 <> 0) STORED,
+    ADD COLUMN unknown_flags BIGINT GENERATED ALWAYS AS (flags & ~7::BIGINT) STORED;
+
+CREATE INDEX idx_validators_is_jailed ON validators(validator_id) WHERE is_jailed;
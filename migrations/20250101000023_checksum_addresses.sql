@@ -0,0 +1,5 @@
+-- Addresses are stored EIP-55 checksummed from now on. Postgres cannot compute
+-- the Keccak-256 hash the checksum is derived from, so this only marks the
+-- addresses stored so far as pending (0); the indexer rewrites them at startup
+-- before indexing and then sets the value to 1.
+INSERT INTO indexer_metadata (key, value) VALUES ('addresses_checksummed', 0);
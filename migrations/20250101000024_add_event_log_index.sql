@@ -0,0 +1,22 @@
+-- Position of each event's log within its block. A transaction can emit
+-- several staking events, so events of a block are ordered by it rather than
+-- by transaction index. Rows indexed before this migration have none and sort
+-- first within their block.
+ALTER TABLE delegate_events ADD COLUMN log_index BIGINT;
+ALTER TABLE undelegate_events ADD COLUMN log_index BIGINT;
+ALTER TABLE withdraw_events ADD COLUMN log_index BIGINT;
+ALTER TABLE claim_rewards_events ADD COLUMN log_index BIGINT;
+ALTER TABLE validator_rewarded_events ADD COLUMN log_index BIGINT;
+ALTER TABLE epoch_changed_events ADD COLUMN log_index BIGINT;
+ALTER TABLE validator_created_events ADD COLUMN log_index BIGINT;
+ALTER TABLE validator_status_changed_events ADD COLUMN log_index BIGINT;
+ALTER TABLE commission_changed_events ADD COLUMN log_index BIGINT;
+
+-- The validator attributes record the (block, log index) position of the
+-- event that last set them. The positions of existing rows are unknown, so an
+-- event of the same block replaces them.
+ALTER TABLE validators
+    DROP COLUMN commission_transaction_index,
+    DROP COLUMN flags_transaction_index,
+    ADD COLUMN commission_log_index BIGINT,
+    ADD COLUMN flags_log_index BIGINT;
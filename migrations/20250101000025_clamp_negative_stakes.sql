@@ -0,0 +1,3 @@
+-- Stakes are clamped to zero when an insert would make them negative, instead
+-- of being kept as a negative running sum that readers clamp.
+UPDATE delegations SET stake = 0, updated_at = CURRENT_TIMESTAMP WHERE stake < 0;
@@ -0,0 +1,3 @@
+-- Batches that failed dead_letter_max_attempts replays are parked: they stay
+-- in the queue for inspection but are not replayed until parked_at is cleared.
+ALTER TABLE failed_batches ADD COLUMN parked_at TIMESTAMP;
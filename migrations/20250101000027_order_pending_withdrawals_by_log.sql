@@ -0,0 +1,8 @@
+-- Positions of the latest Undelegate and Withdraw of a withdrawal as (block,
+-- transaction index, log index), so that a Withdraw and an Undelegate reusing
+-- its id in the same block are ordered. Rows stored before this migration have
+-- no indexes and sort first within their block.
+ALTER TABLE pending_withdrawals
+    ADD COLUMN undelegate_log_index BIGINT,
+    ADD COLUMN withdraw_transaction_index BIGINT,
+    ADD COLUMN withdraw_log_index BIGINT;
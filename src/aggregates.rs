@@ -0,0 +1,96 @@
+//! Per-validator figures aggregated over the indexed events, for reporting.
+
+use bigdecimal::BigDecimal;
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::db::repository::DbError;
+
+/// Snapshot of one validator. Validators only known from delegations or
+/// rewards, without an indexed creation, have no attributes.
+#[derive(Debug, Clone, PartialEq, Serialize, sqlx::FromRow)]
+pub struct ValidatorSummary {
+    pub validator_id: i64,
+    pub auth_address: Option<String>,
+    /// Current commission, `None` until the creation or a commission change
+    /// has been indexed.
+    pub commission: Option<BigDecimal>,
+    pub created_block: Option<i64>,
+    /// Whether the current status has no flag set, `None` until a status
+    /// change has been indexed.
+    pub is_active: Option<bool>,
+    /// Stake currently delegated, with each delegation clamped at zero.
+    pub total_delegated: BigDecimal,
+    /// Delegators with a positive stake.
+    pub delegator_count: i64,
+    /// Rewards paid to the validator.
+    pub total_rewards: BigDecimal,
+    /// Rewards claimed by its delegators.
+    pub total_claimed: BigDecimal,
+    pub commission_changes: i64,
+}
+
+/// Aggregates the state and totals of `validator_id` from the `validators`,
+/// `delegations`, `validator_rewarded_events`, `claim_rewards_events` and
+/// `commission_changed_events` tables in one query. `None` if nothing has
+/// been indexed for `validator_id` in any of them.
+pub async fn compute_validator_summary(
+    pool: &PgPool,
+    validator_id: i64,
+) -> Result<Option<ValidatorSummary>, DbError> {
+    let summary = sqlx::query_as::<_, ValidatorSummary>(
+        r#"
+        WITH validator AS (
+            SELECT auth_address, commission, created_block, is_active
+            FROM validators
+            WHERE validator_id = $1
+        ),
+        delegated AS (
+            SELECT COALESCE(SUM(stake), 0) AS total_delegated,
+                   COUNT(*) FILTER (WHERE stake > 0) AS delegator_count
+            FROM delegations
+            WHERE val_id = $1
+        ),
+        rewarded AS (
+            SELECT COALESCE(SUM(amount), 0) AS total_rewards
+            FROM validator_rewarded_events
+            WHERE validator_id = $1
+        ),
+        claimed AS (
+            SELECT COALESCE(SUM(amount), 0) AS total_claimed
+            FROM claim_rewards_events
+            WHERE val_id = $1
+        ),
+        commission_changed AS (
+            SELECT COUNT(*) AS commission_changes
+            FROM commission_changed_events
+            WHERE validator_id = $1
+        )
+        SELECT $1::BIGINT AS validator_id,
+               validator.auth_address,
+               validator.commission,
+               validator.created_block,
+               validator.is_active,
+               delegated.total_delegated,
+               delegated.delegator_count,
+               rewarded.total_rewards,
+               claimed.total_claimed,
+               commission_changed.commission_changes
+        FROM delegated
+        CROSS JOIN rewarded
+        CROSS JOIN claimed
+        CROSS JOIN commission_changed
+        LEFT JOIN validator ON TRUE
+        WHERE EXISTS (SELECT 1 FROM validator)
+           OR EXISTS (SELECT 1 FROM delegations WHERE val_id = $1)
+           OR EXISTS (SELECT 1 FROM validator_rewarded_events WHERE validator_id = $1)
+           OR EXISTS (SELECT 1 FROM claim_rewards_events WHERE val_id = $1)
+           OR commission_changed.commission_changes > 0
+        "#,
+    )
+    .bind(validator_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(summary)
+}
@@ -0,0 +1,414 @@
+//! Webhook alerts when failure metrics cross configured thresholds.
+//!
+//! The metrics task feeds every metric to an [`Alerter`] and evaluates the
+//! thresholds on a timer. An alert is posted once when its condition starts,
+//! repeated at most every `repeat_interval_secs` while it holds, and followed
+//! by a recovery notification when it clears.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use eyre::Result;
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+use crate::config::AlertsConfig;
+use crate::metrics::Metric;
+
+/// Time limit of a single webhook post.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AlertKind {
+    FailedInserts,
+    RpcConnectionFailures,
+    HeadLag,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlertStatus {
+    Firing,
+    Resolved,
+}
+
+/// A notification to post, with a human-readable description of the condition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alert {
+    pub kind: AlertKind,
+    pub status: AlertStatus,
+    pub message: String,
+}
+
+impl Alert {
+    /// Slack-compatible webhook payload.
+    fn payload(&self) -> serde_json::Value {
+        let status = match self.status {
+            AlertStatus::Firing => "FIRING",
+            AlertStatus::Resolved => "RESOLVED",
+        };
+        serde_json::json!({
+            "text": format!("[{status}] monad-staking-indexer: {}", self.message),
+        })
+    }
+}
+
+/// Thresholds of the alert conditions, unset ones are never raised.
+#[derive(Debug, Clone, Default)]
+pub struct AlertThresholds {
+    pub failed_inserts: Option<u64>,
+    pub rpc_connection_failures: Option<u64>,
+    /// Blocks the stored ones may be behind the chain head.
+    pub head_lag_blocks: Option<u64>,
+}
+
+/// Failures observed within a sliding window and the alerts they raised.
+#[derive(Debug)]
+pub struct AlertState {
+    window: Duration,
+    repeat_interval: Duration,
+    thresholds: AlertThresholds,
+    failed_inserts: VecDeque<Instant>,
+    consecutive_rpc_failures: u64,
+    head_lag_blocks: Option<u64>,
+    /// Firing alerts and when they were last posted.
+    firing: HashMap<AlertKind, Instant>,
+}
+
+impl AlertState {
+    pub fn new(window: Duration, repeat_interval: Duration, thresholds: AlertThresholds) -> Self {
+        Self {
+            window,
+            repeat_interval,
+            thresholds,
+            failed_inserts: VecDeque::new(),
+            consecutive_rpc_failures: 0,
+            head_lag_blocks: None,
+            firing: HashMap::new(),
+        }
+    }
+
+    pub fn observe(&mut self, metric: &Metric, now: Instant) {
+        match metric {
+            Metric::FailedToInsert { .. } => self.failed_inserts.push_back(now),
+            Metric::RpcConnRefused | Metric::RpcTimeout => self.consecutive_rpc_failures += 1,
+            Metric::RpcConnected => self.consecutive_rpc_failures = 0,
+            Metric::HeadLagBlocks(lag) => self.head_lag_blocks = Some(*lag),
+            _ => {}
+        }
+    }
+
+    /// Alerts to post at `now`: new and repeated alerts whose condition holds,
+    /// and recoveries of firing alerts whose condition cleared.
+    pub fn evaluate(&mut self, now: Instant) -> Vec<Alert> {
+        while self
+            .failed_inserts
+            .front()
+            .is_some_and(|at| now.duration_since(*at) > self.window)
+        {
+            self.failed_inserts.pop_front();
+        }
+
+        let window_secs = self.window.as_secs();
+        let failed_inserts = self.failed_inserts.len() as u64;
+        let conditions = [
+            (
+                AlertKind::FailedInserts,
+                self.thresholds.failed_inserts.map(|threshold| {
+                    (
+                        failed_inserts >= threshold,
+                        format!(
+                            "{failed_inserts} failed inserts in the last {window_secs}s \
+                             (threshold {threshold})"
+                        ),
+                    )
+                }),
+            ),
+            (
+                AlertKind::RpcConnectionFailures,
+                self.thresholds.rpc_connection_failures.map(|threshold| {
+                    (
+                        self.consecutive_rpc_failures >= threshold,
+                        format!(
+                            "{} consecutive RPC connection failures (threshold {threshold})",
+                            self.consecutive_rpc_failures
+                        ),
+                    )
+                }),
+            ),
+            (
+                AlertKind::HeadLag,
+                self.thresholds.head_lag_blocks.map(|threshold| {
+                    let lag = self.head_lag_blocks.unwrap_or_default();
+                    (
+                        lag >= threshold,
+                        format!(
+                            "stored blocks are {lag} blocks behind the chain head \
+                             (threshold {threshold})"
+                        ),
+                    )
+                }),
+            ),
+        ];
+
+        let mut alerts = Vec::new();
+        for (kind, condition) in conditions {
+            let Some((active, message)) = condition else {
+                continue;
+            };
+            let status = match (active, self.firing.get(&kind)) {
+                (true, Some(sent_at)) if now.duration_since(*sent_at) < self.repeat_interval => {
+                    continue;
+                }
+                (true, _) => {
+                    self.firing.insert(kind, now);
+                    AlertStatus::Firing
+                }
+                (false, Some(_)) => {
+                    self.firing.remove(&kind);
+                    AlertStatus::Resolved
+                }
+                (false, None) => continue,
+            };
+            alerts.push(Alert {
+                kind,
+                status,
+                message,
+            });
+        }
+        alerts
+    }
+}
+
+/// Posts the alerts of an [`AlertState`] to the configured webhook.
+pub struct Alerter {
+    state: AlertState,
+    client: reqwest::Client,
+    webhook_url: String,
+    check_interval: Duration,
+    metrics_tx: mpsc::UnboundedSender<Metric>,
+}
+
+impl Alerter {
+    pub fn new(config: &AlertsConfig, metrics_tx: mpsc::UnboundedSender<Metric>) -> Result<Self> {
+        Ok(Self {
+            state: AlertState::new(
+                Duration::from_secs(config.window_secs),
+                Duration::from_secs(config.repeat_interval_secs),
+                config.thresholds(),
+            ),
+            client: reqwest::Client::builder()
+                .timeout(WEBHOOK_TIMEOUT)
+                .build()?,
+            webhook_url: config.webhook_url.clone(),
+            check_interval: Duration::from_secs(config.check_interval_secs),
+            metrics_tx,
+        })
+    }
+
+    pub fn check_interval(&self) -> Duration {
+        self.check_interval
+    }
+
+    pub fn observe(&mut self, metric: &Metric) {
+        self.state.observe(metric, Instant::now());
+    }
+
+    /// Evaluate the thresholds and post the resulting alerts in the background,
+    /// so a slow webhook does not hold up the metrics. Failed posts are logged
+    /// and counted.
+    pub fn check(&mut self) {
+        for alert in self.state.evaluate(Instant::now()) {
+            info!("Sending {:?} alert: {}", alert.status, alert.message);
+            let request = self
+                .client
+                .post(&self.webhook_url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(alert.payload().to_string());
+            let metrics_tx = self.metrics_tx.clone();
+            tokio::spawn(async move {
+                if let Err(e) = request.send().await.and_then(|r| r.error_for_status()) {
+                    error!("Failed to post {:?} alert: {e}", alert.kind);
+                    let _ = metrics_tx.send(Metric::AlertFailed);
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(600);
+    const REPEAT: Duration = Duration::from_secs(3600);
+
+    fn state(thresholds: AlertThresholds) -> AlertState {
+        AlertState::new(WINDOW, REPEAT, thresholds)
+    }
+
+    fn statuses(alerts: &[Alert]) -> Vec<(AlertKind, AlertStatus)> {
+        alerts.iter().map(|a| (a.kind, a.status)).collect()
+    }
+
+    fn failed_insert() -> Metric {
+        Metric::FailedToInsert { table: None }
+    }
+
+    #[test]
+    fn test_failed_inserts_fire_within_window() {
+        let mut state = state(AlertThresholds {
+            failed_inserts: Some(3),
+            ..Default::default()
+        });
+        let start = Instant::now();
+
+        state.observe(&failed_insert(), start);
+        state.observe(&failed_insert(), start + Duration::from_secs(60));
+        assert!(state.evaluate(start + Duration::from_secs(60)).is_empty());
+
+        state.observe(&failed_insert(), start + Duration::from_secs(120));
+        let alerts = state.evaluate(start + Duration::from_secs(120));
+        assert_eq!(
+            statuses(&alerts),
+            vec![(AlertKind::FailedInserts, AlertStatus::Firing)]
+        );
+        assert!(alerts[0].message.contains("3 failed inserts"));
+    }
+
+    #[test]
+    fn test_failed_inserts_outside_window_are_dropped() {
+        let mut state = state(AlertThresholds {
+            failed_inserts: Some(3),
+            ..Default::default()
+        });
+        let start = Instant::now();
+
+        // Spread out so that no window ever holds three failures.
+        for minute in [0, 6, 12, 18] {
+            let at = start + Duration::from_secs(minute * 60);
+            state.observe(&failed_insert(), at);
+            assert!(state.evaluate(at).is_empty(), "fired at minute {minute}");
+        }
+    }
+
+    #[test]
+    fn test_failed_inserts_resolve_when_window_passes() {
+        let mut state = state(AlertThresholds {
+            failed_inserts: Some(2),
+            ..Default::default()
+        });
+        let start = Instant::now();
+        state.observe(&failed_insert(), start);
+        state.observe(&failed_insert(), start);
+        assert_eq!(state.evaluate(start).len(), 1);
+
+        assert!(state.evaluate(start + WINDOW).is_empty());
+        let alerts = state.evaluate(start + WINDOW + Duration::from_secs(1));
+        assert_eq!(
+            statuses(&alerts),
+            vec![(AlertKind::FailedInserts, AlertStatus::Resolved)]
+        );
+        assert!(state.evaluate(start + WINDOW * 2).is_empty());
+    }
+
+    #[test]
+    fn test_firing_alert_is_rate_limited() {
+        let mut state = state(AlertThresholds {
+            failed_inserts: Some(1),
+            ..Default::default()
+        });
+        let start = Instant::now();
+        let mut sent = 0;
+        // One failure a minute for two hours keeps the condition active.
+        for minute in 0..120 {
+            let at = start + Duration::from_secs(minute * 60);
+            state.observe(&failed_insert(), at);
+            sent += state.evaluate(at).len();
+        }
+        assert_eq!(sent, 2);
+    }
+
+    #[test]
+    fn test_rpc_failures_reset_on_connect() {
+        let mut state = state(AlertThresholds {
+            rpc_connection_failures: Some(3),
+            ..Default::default()
+        });
+        let now = Instant::now();
+
+        for metric in [
+            Metric::RpcConnRefused,
+            Metric::RpcTimeout,
+            Metric::RpcConnected,
+            Metric::RpcConnRefused,
+            Metric::RpcConnRefused,
+        ] {
+            state.observe(&metric, now);
+        }
+        assert!(state.evaluate(now).is_empty());
+
+        state.observe(&Metric::RpcConnRefused, now);
+        assert_eq!(
+            statuses(&state.evaluate(now)),
+            vec![(AlertKind::RpcConnectionFailures, AlertStatus::Firing)]
+        );
+
+        state.observe(&Metric::RpcConnected, now);
+        assert_eq!(
+            statuses(&state.evaluate(now)),
+            vec![(AlertKind::RpcConnectionFailures, AlertStatus::Resolved)]
+        );
+    }
+
+    #[test]
+    fn test_head_lag_follows_latest_sample() {
+        let mut state = state(AlertThresholds {
+            head_lag_blocks: Some(100),
+            ..Default::default()
+        });
+        let now = Instant::now();
+        assert!(state.evaluate(now).is_empty());
+
+        // Ingestion stopped, so the sampled lag keeps growing.
+        state.observe(&Metric::HeadLagBlocks(60), now);
+        assert!(state.evaluate(now).is_empty());
+        state.observe(&Metric::HeadLagBlocks(120), now);
+        let alerts = state.evaluate(now);
+        assert_eq!(
+            statuses(&alerts),
+            vec![(AlertKind::HeadLag, AlertStatus::Firing)]
+        );
+        assert!(alerts[0].message.contains("120 blocks behind"));
+
+        state.observe(&Metric::HeadLagBlocks(2), now);
+        assert_eq!(
+            statuses(&state.evaluate(now)),
+            vec![(AlertKind::HeadLag, AlertStatus::Resolved)]
+        );
+    }
+
+    #[test]
+    fn test_unset_thresholds_never_fire() {
+        let mut state = state(AlertThresholds::default());
+        let now = Instant::now();
+        for _ in 0..100 {
+            state.observe(&failed_insert(), now);
+            state.observe(&Metric::RpcConnRefused, now);
+        }
+        state.observe(&Metric::HeadLagBlocks(1_000_000), now);
+        assert!(state.evaluate(now).is_empty());
+    }
+
+    #[test]
+    fn test_payload_is_slack_compatible() {
+        let alert = Alert {
+            kind: AlertKind::HeadLag,
+            status: AlertStatus::Resolved,
+            message: "caught up".to_string(),
+        };
+        assert_eq!(
+            alert.payload(),
+            serde_json::json!({"text": "[RESOLVED] monad-staking-indexer: caught up"})
+        );
+    }
+}
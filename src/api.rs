@@ -0,0 +1,191 @@
+use std::str::FromStr;
+
+use alloy::primitives::Address;
+use axum::extract::{Path, Query};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json, Router, routing::get};
+use serde::Deserialize;
+use sqlx::PgPool;
+use tracing::error;
+
+use crate::aggregates;
+use crate::db::repository::{self, DbError, GapOptions};
+use crate::events;
+use crate::filter::EventFilter;
+use crate::validator_state::SharedValidatorStateCache;
+
+/// Page size of `/v1/delegates` when the request does not set one.
+const DEFAULT_LIMIT: u64 = 20;
+/// Largest page `/v1/delegates` returns, larger limits are capped.
+const MAX_LIMIT: u64 = 1000;
+/// Most blocks whose events `/v1/events` returns at once.
+const MAX_EVENT_BLOCKS: u64 = 10_000;
+
+/// Failure of an API request, returned as `{"error": "..."}`.
+#[derive(Debug)]
+enum ApiError {
+    BadRequest(String),
+    NotFound(String),
+    Db(DbError),
+}
+
+impl From<DbError> for ApiError {
+    fn from(e: DbError) -> Self {
+        ApiError::Db(e)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, message) = match self {
+            ApiError::BadRequest(message) => (StatusCode::BAD_REQUEST, message),
+            ApiError::NotFound(message) => (StatusCode::NOT_FOUND, message),
+            ApiError::Db(e) => {
+                error!("API query failed: {e}");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "database query failed".to_string(),
+                )
+            }
+        };
+        (status, Json(serde_json::json!({ "error": message }))).into_response()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DelegatesQuery {
+    delegator: String,
+    limit: Option<u64>,
+    offset: Option<u64>,
+}
+
+/// Delegations of one delegator, oldest first. The address is accepted with or
+/// without `0x` prefix, in any case.
+async fn delegates_handler(
+    Extension(pool): Extension<PgPool>,
+    Query(query): Query<DelegatesQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let delegator = Address::from_str(&query.delegator)
+        .map_err(|e| ApiError::BadRequest(format!("invalid delegator address: {e}")))?;
+    let events = repository::get_delegate_events_by_delegator(
+        &pool,
+        &events::to_checksum_address(delegator.as_slice()),
+        query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT),
+        query.offset.unwrap_or(0),
+    )
+    .await?;
+    Ok(Json(events))
+}
+
+#[derive(Debug, Deserialize)]
+struct EventsQuery {
+    from_block: u64,
+    to_block: u64,
+    validator_ids: Option<String>,
+    delegators: Option<String>,
+    event_types: Option<String>,
+}
+
+/// Events of blocks `from_block..to_block` in block order, selected by the
+/// comma-separated `validator_ids`, `delegators` and `event_types`.
+async fn events_handler(
+    Extension(pool): Extension<PgPool>,
+    Query(query): Query<EventsQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    if query.from_block >= query.to_block {
+        return Err(ApiError::BadRequest(format!(
+            "from_block {} must be below to_block {}",
+            query.from_block, query.to_block
+        )));
+    }
+    if query.to_block - query.from_block > MAX_EVENT_BLOCKS {
+        return Err(ApiError::BadRequest(format!(
+            "at most {MAX_EVENT_BLOCKS} blocks can be queried at once"
+        )));
+    }
+    let filter = EventFilter::from_lists(
+        query.validator_ids.as_deref(),
+        query.delegators.as_deref(),
+        query.event_types.as_deref(),
+    )
+    .map_err(ApiError::BadRequest)?;
+    let events =
+        repository::get_events_in_block_range(&pool, query.from_block..query.to_block).await?;
+    let events: Vec<_> = events
+        .into_iter()
+        .filter(|event| filter.apply(event))
+        .collect();
+    Ok(Json(events))
+}
+
+#[derive(Debug, Deserialize)]
+struct RewardsQuery {
+    epoch_start: Option<u64>,
+    epoch_end: Option<u64>,
+}
+
+/// Rewards of one validator within an inclusive epoch range, all epochs by default.
+async fn validator_rewards_handler(
+    Extension(pool): Extension<PgPool>,
+    Path(validator_id): Path<u64>,
+    Query(query): Query<RewardsQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let epoch_start = query.epoch_start.unwrap_or(0);
+    let epoch_end = query.epoch_end.unwrap_or(u64::MAX);
+    if epoch_start > epoch_end {
+        return Err(ApiError::BadRequest(format!(
+            "epoch_start {epoch_start} is after epoch_end {epoch_end}"
+        )));
+    }
+    let rewards =
+        repository::get_validator_rewards(&pool, validator_id, epoch_start..=epoch_end).await?;
+    Ok(Json(rewards))
+}
+
+/// Current state and totals of one validator, 404 if nothing has been indexed
+/// for it.
+async fn validator_summary_handler(
+    Extension(pool): Extension<PgPool>,
+    Path(validator_id): Path<i64>,
+) -> Result<impl IntoResponse, ApiError> {
+    let summary = aggregates::compute_validator_summary(&pool, validator_id)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("unknown validator {validator_id}")))?;
+    Ok(Json(summary))
+}
+
+/// Latest flags, commission and auth address of one validator, served from
+/// memory.
+async fn validator_state_handler(
+    Extension(validator_state): Extension<SharedValidatorStateCache>,
+    Path(validator_id): Path<u64>,
+) -> Result<impl IntoResponse, ApiError> {
+    let state = validator_state
+        .read()
+        .unwrap()
+        .get(validator_id)
+        .cloned()
+        .ok_or_else(|| ApiError::NotFound(format!("unknown validator {validator_id}")))?;
+    Ok(Json(state))
+}
+
+/// All block gaps that are still to be backfilled, unmerged.
+async fn gaps_handler(Extension(pool): Extension<PgPool>) -> Result<impl IntoResponse, ApiError> {
+    let gaps = repository::get_block_gaps(&pool, &GapOptions::default()).await?;
+    Ok(Json(gaps))
+}
+
+/// Read-only routes over the indexed data, queried from `pool` except for the
+/// validator state kept in `validator_state`.
+pub fn router(pool: PgPool, validator_state: SharedValidatorStateCache) -> Router {
+    Router::new()
+        .route("/v1/delegates", get(delegates_handler))
+        .route("/v1/events", get(events_handler))
+        .route("/v1/validators/:id/rewards", get(validator_rewards_handler))
+        .route("/v1/validators/:id/summary", get(validator_summary_handler))
+        .route("/v1/validators/:id/state", get(validator_state_handler))
+        .route("/v1/gaps", get(gaps_handler))
+        .layer(Extension(pool))
+        .layer(Extension(validator_state))
+}
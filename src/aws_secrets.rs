@@ -0,0 +1,171 @@
+//! Database credentials stored in AWS Secrets Manager.
+//!
+//! The secret holds the same `{"user": "...", "password": "..."}` object as the
+//! Vault secret. Reading it from AWS requires the `aws-secrets` feature.
+
+use futures_util::future::BoxFuture;
+use serde_json::error::Category;
+use thiserror::Error;
+
+use crate::config::DbCredentials;
+
+#[derive(Debug, Error)]
+pub enum AwsSecretError {
+    #[error("AWS secret '{secret_arn}' not found")]
+    NotFound { secret_arn: String },
+    #[error("AWS secret '{secret_arn}' is malformed: {reason}")]
+    Malformed { secret_arn: String, reason: String },
+    #[error("Failed to read AWS secret '{secret_arn}': {reason}")]
+    Request { secret_arn: String, reason: String },
+}
+
+/// Store that secrets are read from, Secrets Manager outside of tests.
+pub trait SecretSource: Send + Sync {
+    /// The `SecretString` of `secret_arn`, `None` for a binary secret.
+    fn secret_string<'a>(
+        &'a self,
+        secret_arn: &'a str,
+    ) -> BoxFuture<'a, Result<Option<String>, AwsSecretError>>;
+}
+
+/// Read the database credentials in `secret_arn` from `source`.
+pub async fn read_credentials(
+    source: &dyn SecretSource,
+    secret_arn: &str,
+) -> Result<DbCredentials, AwsSecretError> {
+    let malformed = |reason: &str| AwsSecretError::Malformed {
+        secret_arn: secret_arn.to_string(),
+        reason: reason.to_string(),
+    };
+    let secret = source
+        .secret_string(secret_arn)
+        .await?
+        .ok_or_else(|| malformed("it is binary, expected a secret string"))?;
+    // The serde error could quote the password, so only its category is reported.
+    serde_json::from_str(&secret).map_err(|e| match e.classify() {
+        Category::Data => malformed("expected string fields user and password"),
+        _ => malformed("it is not valid JSON"),
+    })
+}
+
+/// The Secrets Manager API, with credentials from the default provider chain,
+/// e.g. the IAM role of the Kubernetes service account.
+#[cfg(feature = "aws-secrets")]
+pub struct SecretsManager {
+    client: aws_sdk_secretsmanager::Client,
+}
+
+#[cfg(feature = "aws-secrets")]
+impl SecretsManager {
+    pub async fn new(region: &str) -> Self {
+        let config = aws_config::defaults(aws_config::BehaviorVersion::latest())
+            .region(aws_config::Region::new(region.to_string()))
+            .load()
+            .await;
+        Self {
+            client: aws_sdk_secretsmanager::Client::new(&config),
+        }
+    }
+}
+
+#[cfg(feature = "aws-secrets")]
+impl SecretSource for SecretsManager {
+    fn secret_string<'a>(
+        &'a self,
+        secret_arn: &'a str,
+    ) -> BoxFuture<'a, Result<Option<String>, AwsSecretError>> {
+        use aws_sdk_secretsmanager::error::DisplayErrorContext;
+        use aws_sdk_secretsmanager::operation::get_secret_value::GetSecretValueError;
+
+        Box::pin(async move {
+            let output = self
+                .client
+                .get_secret_value()
+                .secret_id(secret_arn)
+                .send()
+                .await
+                .map_err(|e| match e.into_service_error() {
+                    GetSecretValueError::ResourceNotFoundException(_) => AwsSecretError::NotFound {
+                        secret_arn: secret_arn.to_string(),
+                    },
+                    e => AwsSecretError::Request {
+                        secret_arn: secret_arn.to_string(),
+                        reason: DisplayErrorContext(e).to_string(),
+                    },
+                })?;
+            Ok(output.secret_string().map(str::to_string))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const ARN: &str = "arn:aws:secretsmanager:eu-west-1:123456789012:secret:indexer-db";
+
+    /// Secrets by ARN, `None` for binary secrets.
+    struct MockSource(HashMap<&'static str, Option<&'static str>>);
+
+    impl SecretSource for MockSource {
+        fn secret_string<'a>(
+            &'a self,
+            secret_arn: &'a str,
+        ) -> BoxFuture<'a, Result<Option<String>, AwsSecretError>> {
+            Box::pin(async move {
+                match self.0.get(secret_arn) {
+                    Some(secret) => Ok(secret.map(str::to_string)),
+                    None => Err(AwsSecretError::NotFound {
+                        secret_arn: secret_arn.to_string(),
+                    }),
+                }
+            })
+        }
+    }
+
+    fn read(secret: Option<&'static str>) -> Result<DbCredentials, AwsSecretError> {
+        let source = MockSource(HashMap::from([(ARN, secret)]));
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(read_credentials(&source, ARN))
+    }
+
+    #[test]
+    fn test_read_credentials() {
+        let credentials = read(Some(r#"{"user": "indexer", "password": "p4ss"}"#)).unwrap();
+        let expected: DbCredentials =
+            serde_json::from_value(serde_json::json!({"user": "indexer", "password": "p4ss"}))
+                .unwrap();
+        assert_eq!(credentials, expected);
+    }
+
+    #[test]
+    fn test_read_credentials_not_found() {
+        let source = MockSource(HashMap::new());
+        let error = tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(read_credentials(&source, ARN))
+            .unwrap_err();
+        assert!(matches!(error, AwsSecretError::NotFound { .. }), "{error}");
+        assert!(error.to_string().contains("not found"), "{error}");
+    }
+
+    #[test]
+    fn test_read_credentials_malformed() {
+        for secret in [
+            None,
+            Some("user=indexer"),
+            Some(r#"{"user": "indexer"}"#),
+            Some(r#"{"user": "indexer", "password": 24680}"#),
+        ] {
+            let error = read(secret).unwrap_err();
+            assert!(
+                matches!(error, AwsSecretError::Malformed { .. }),
+                "{secret:?}: {error}"
+            );
+            assert!(error.to_string().contains("is malformed"), "{error}");
+            assert!(!error.to_string().contains("24680"), "{error}");
+        }
+    }
+}
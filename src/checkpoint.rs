@@ -0,0 +1,74 @@
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+
+use eyre::{Result, WrapErr};
+
+/// Last committed block, mirrored to a local file so that tools can follow the
+/// indexing progress without querying the database.
+pub struct Checkpoint;
+
+impl Checkpoint {
+    /// Atomically replace the checkpoint file at `path` with `block_number`.
+    ///
+    /// The value is written to a temporary file in the same directory, which is
+    /// then renamed over `path`, so readers never see a partially written file.
+    pub fn save(path: &Path, block_number: u64) -> Result<()> {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut file = tempfile::NamedTempFile::new_in(dir)
+            .wrap_err_with(|| format!("Failed to create a temporary file in {}", dir.display()))?;
+        writeln!(file, "{block_number}")?;
+        file.as_file().sync_all()?;
+        file.persist(path)
+            .wrap_err_with(|| format!("Failed to write checkpoint to {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Read the checkpoint file at `path`, or `None` if it does not exist yet.
+    pub fn load(path: &Path) -> Result<Option<u64>> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e)
+                    .wrap_err_with(|| format!("Failed to read checkpoint {}", path.display()));
+            }
+        };
+        let block_number = contents
+            .trim()
+            .parse()
+            .wrap_err_with(|| format!("Invalid checkpoint in {}", path.display()))?;
+        Ok(Some(block_number))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checkpoint");
+
+        assert_eq!(Checkpoint::load(&path).unwrap(), None);
+
+        Checkpoint::save(&path, 100).unwrap();
+        assert_eq!(Checkpoint::load(&path).unwrap(), Some(100));
+
+        Checkpoint::save(&path, 250).unwrap();
+        assert_eq!(Checkpoint::load(&path).unwrap(), Some(250));
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_load_rejects_invalid_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checkpoint");
+        std::fs::write(&path, "not a block").unwrap();
+
+        assert!(Checkpoint::load(&path).is_err());
+    }
+}
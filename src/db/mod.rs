@@ -1,28 +1,237 @@
+mod notifications;
 pub mod repository;
 mod repository_batch;
 
-pub use repository_batch::insert_blocks;
+pub use notifications::{BlockNotification, NOTIFICATION_CHANNEL, subscribe_notifications};
+pub use repository::{BlockConflictStrategy, ensure_partitions};
+pub use repository_batch::{
+    ConflictStrategies, ConflictStrategy, DuplicatePolicy, InsertReport, TableInsertStats,
+    insert_blocks,
+};
 
+use crate::config::{DbSslMode, DbTlsConfig};
+use crate::db::repository::DbError;
 use crate::metrics::Metric;
 use eyre::Result;
-use log::info;
-use sqlx::{PgPool, postgres::PgPoolOptions};
+use sqlx::migrate::Migrator;
+use sqlx::pool::PoolConnectionMetadata;
+use sqlx::{
+    Executor, PgPool,
+    postgres::{PgConnectOptions, PgPoolOptions, PgSslMode},
+};
+use std::collections::HashSet;
+use std::time::Duration;
 use tokio::sync::mpsc;
+use tracing::{info, warn};
 
-pub async fn create_pool(database_url: &str, metrics_tx: mpsc::UnboundedSender<Metric>) -> Result<PgPool> {
+/// Settings of the connection pool and of each connection it opens.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolSettings {
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub acquire_timeout: Duration,
+    /// Close connections idle for this long, `None` keeps them open.
+    pub idle_timeout: Option<Duration>,
+    /// Close connections older than this, `None` keeps them open.
+    pub max_lifetime: Option<Duration>,
+    /// Server-side `statement_timeout`, zero disables it.
+    pub statement_timeout: Duration,
+    /// Server-side `lock_timeout`, zero disables it.
+    pub lock_timeout: Duration,
+    /// TLS of each connection, on top of any `sslmode` in the URL.
+    pub tls: Option<DbTlsConfig>,
+}
+
+impl Default for PoolSettings {
+    fn default() -> Self {
+        Self {
+            max_connections: 5,
+            min_connections: 0,
+            acquire_timeout: Duration::from_secs(5),
+            idle_timeout: Some(Duration::from_secs(600)),
+            max_lifetime: Some(Duration::from_secs(1800)),
+            statement_timeout: Duration::from_secs(10),
+            lock_timeout: Duration::from_secs(5),
+            tls: None,
+        }
+    }
+}
+
+/// Options of the connections to `database_url` with the TLS of `settings`.
+pub fn connect_options(database_url: &str, settings: &PoolSettings) -> Result<PgConnectOptions> {
+    let mut options: PgConnectOptions = database_url.parse()?;
+    if let Some(tls) = &settings.tls {
+        options = options.ssl_mode(match tls.sslmode {
+            DbSslMode::Disable => PgSslMode::Disable,
+            DbSslMode::Require => PgSslMode::Require,
+            DbSslMode::VerifyCa => PgSslMode::VerifyCa,
+            DbSslMode::VerifyFull => PgSslMode::VerifyFull,
+        });
+        if let Some(path) = &tls.ca_cert_path {
+            options = options.ssl_root_cert(path);
+        }
+        if let Some(path) = &tls.client_cert_path {
+            options = options.ssl_client_cert(path);
+        }
+        if let Some(path) = &tls.client_key_path {
+            options = options.ssl_client_key(path);
+        }
+    }
+    Ok(options)
+}
+
+pub async fn create_pool(
+    database_url: &str,
+    settings: &PoolSettings,
+    metrics_tx: mpsc::UnboundedSender<Metric>,
+) -> Result<PgPool> {
+    let connect_options = connect_options(database_url, settings)?;
+    let session_settings = format!(
+        "SET statement_timeout = {}; SET lock_timeout = {}",
+        settings.statement_timeout.as_millis(),
+        settings.lock_timeout.as_millis()
+    );
+    let max_lifetime = settings.max_lifetime;
+    let acquire_metrics_tx = metrics_tx.clone();
     let pool = PgPoolOptions::new()
-        .max_connections(5)
-        .after_connect(move |_conn, _meta| {
+        .max_connections(settings.max_connections)
+        .min_connections(settings.min_connections)
+        .acquire_timeout(settings.acquire_timeout)
+        .idle_timeout(settings.idle_timeout)
+        .max_lifetime(settings.max_lifetime)
+        .before_acquire(move |_conn, meta| {
+            let keep = keep_connection(&meta, max_lifetime, &acquire_metrics_tx);
+            Box::pin(async move { Ok(keep) })
+        })
+        .after_connect(move |conn, _meta| {
             let metrics_tx = metrics_tx.clone();
+            let session_settings = session_settings.clone();
             Box::pin(async move {
                 info!("Establishing a DB connection");
+                conn.execute(session_settings.as_str()).await?;
                 let _ = metrics_tx.send(Metric::DbConnected);
                 Ok(())
             })
         })
-        .connect(database_url)
+        .connect_with(connect_options)
         .await?;
 
-    info!("Database connection pool created with max 5 connections");
+    info!(
+        "Database connection pool created with max {} connections",
+        settings.max_connections
+    );
     Ok(pool)
 }
+
+/// Whether an idle connection about to be handed out stays open, sending
+/// `Metric::DbConnectionClosed` if it is discarded for having outlived
+/// `max_lifetime`.
+///
+/// sqlx enforces the lifetime when a connection is released and in its idle
+/// reaper without calling any hook, but hands out idle connections past it
+/// without checking. Closes on release and by the reaper are not counted.
+fn keep_connection(
+    meta: &PoolConnectionMetadata,
+    max_lifetime: Option<Duration>,
+    metrics_tx: &mpsc::UnboundedSender<Metric>,
+) -> bool {
+    if max_lifetime.is_some_and(|max| meta.age >= max) {
+        info!("Closing a DB connection after {:?}", meta.age);
+        let _ = metrics_tx.send(Metric::DbConnectionClosed);
+        return false;
+    }
+    true
+}
+
+/// Pools for the primary database, which takes all writes, and for the database
+/// that query workloads read from.
+#[derive(Debug, Clone)]
+pub struct DbPools {
+    pub writer: PgPool,
+    /// A read replica if one is configured, otherwise the writer pool.
+    pub reader: PgPool,
+}
+
+impl DbPools {
+    /// Reads and writes both go through `pool`.
+    pub fn single(pool: PgPool) -> Self {
+        Self {
+            reader: pool.clone(),
+            writer: pool,
+        }
+    }
+
+    /// Open new connections with the credentials in these URLs, e.g. after they
+    /// were rotated. Open connections are kept until they are closed.
+    pub fn set_database_urls(
+        &self,
+        database_url: &str,
+        read_database_url: Option<&str>,
+        settings: &PoolSettings,
+    ) -> Result<()> {
+        self.writer
+            .set_connect_options(connect_options(database_url, settings)?);
+        if let Some(read_database_url) = read_database_url {
+            self.reader
+                .set_connect_options(connect_options(read_database_url, settings)?);
+        }
+        Ok(())
+    }
+}
+
+/// Create the writer pool from `database_url`, and a separate reader pool from
+/// `read_database_url` if given. Both use the same `settings`.
+pub async fn create_pools(
+    database_url: &str,
+    read_database_url: Option<&str>,
+    settings: &PoolSettings,
+    metrics_tx: mpsc::UnboundedSender<Metric>,
+) -> Result<DbPools> {
+    let writer = create_pool(database_url, settings, metrics_tx.clone()).await?;
+    let Some(read_database_url) = read_database_url else {
+        return Ok(DbPools::single(writer));
+    };
+    let reader = create_pool(read_database_url, settings, metrics_tx).await?;
+    info!("Routing queries to the read replica");
+    Ok(DbPools { writer, reader })
+}
+
+/// Migrations this binary was built against.
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Make sure every migration the binary expects has been applied to the database,
+/// so a missing migration is reported at startup instead of as a query error later.
+pub async fn check_schema_version(pool: &PgPool) -> Result<(), DbError> {
+    let has_migrations_table =
+        sqlx::query_scalar::<_, bool>("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+            .fetch_one(pool)
+            .await?;
+    let applied: HashSet<i64> = if has_migrations_table {
+        sqlx::query_scalar::<_, i64>("SELECT version FROM _sqlx_migrations WHERE success")
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .collect()
+    } else {
+        HashSet::new()
+    };
+
+    let missing: Vec<String> = MIGRATOR
+        .iter()
+        .filter(|migration| !applied.contains(&migration.version))
+        .map(|migration| format!("{} ({})", migration.version, migration.description))
+        .collect();
+    if !missing.is_empty() {
+        return Err(DbError::MissingMigrations(missing));
+    }
+
+    let unknown = applied
+        .iter()
+        .filter(|version| !MIGRATOR.iter().any(|m| m.version == **version))
+        .count();
+    if unknown > 0 {
+        warn!("Database has {unknown} migrations this binary does not know about");
+    }
+
+    Ok(())
+}
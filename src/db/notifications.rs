@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+
+use async_stream::stream;
+use futures_util::stream::Stream;
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgListener;
+use tracing::{error, warn};
+
+use crate::db::repository::DbError;
+use crate::events::{StakingEvent, StakingEventType};
+
+/// Channel on which a notification is sent for every newly indexed block.
+pub const NOTIFICATION_CHANNEL: &str = "staking_events";
+
+/// Payload of a notification on [`NOTIFICATION_CHANNEL`], sent when the
+/// transaction indexing the block commits.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockNotification {
+    pub block_number: u64,
+    /// Number of events of each type in the block.
+    pub counts: HashMap<StakingEventType, u64>,
+}
+
+impl BlockNotification {
+    /// One notification per block in `block_numbers`, with the counts of `events`
+    /// belonging to it.
+    pub(crate) fn for_blocks(
+        block_numbers: &[u64],
+        events: impl Iterator<Item = StakingEvent>,
+    ) -> Vec<BlockNotification> {
+        let mut notifications: HashMap<u64, BlockNotification> = block_numbers
+            .iter()
+            .map(|&block_number| {
+                (
+                    block_number,
+                    BlockNotification {
+                        block_number,
+                        counts: HashMap::new(),
+                    },
+                )
+            })
+            .collect();
+        for event in events {
+            if let Some(notification) = notifications.get_mut(&event.block_meta().block_number) {
+                *notification.counts.entry(event.event_type()).or_default() += 1;
+            }
+        }
+
+        let mut notifications: Vec<_> = notifications.into_values().collect();
+        notifications.sort_by_key(|notification| notification.block_number);
+        notifications
+    }
+}
+
+/// Listens on [`NOTIFICATION_CHANNEL`] with a dedicated connection to `pool_url`.
+///
+/// The listener reconnects on its own; notifications sent while it is
+/// disconnected are lost. The stream ends on an unrecoverable error.
+pub async fn subscribe_notifications(
+    pool_url: &str,
+) -> Result<impl Stream<Item = BlockNotification>, DbError> {
+    let mut listener = PgListener::connect(pool_url).await?;
+    listener.listen(NOTIFICATION_CHANNEL).await?;
+
+    Ok(stream! {
+        loop {
+            let notification = match listener.recv().await {
+                Ok(notification) => notification,
+                Err(e) => {
+                    error!("Notification listener failed: {e}");
+                    break;
+                }
+            };
+            match serde_json::from_str::<BlockNotification>(notification.payload()) {
+                Ok(notification) => yield notification,
+                Err(e) => warn!("Ignoring malformed notification {:?}: {e}", notification.payload()),
+            }
+        }
+    })
+}
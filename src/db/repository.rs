@@ -1,14 +1,40 @@
-use std::ops::Range;
+use std::collections::HashMap;
+use std::ops::{Range, RangeInclusive};
+use std::time::{Duration, Instant};
 
-use sqlx::PgPool;
+use bigdecimal::BigDecimal;
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgRow;
+use sqlx::types::Json;
+use sqlx::{PgPool, Row};
 use thiserror::Error;
+use tracing::info;
 
-use crate::events::{BlockMeta, StakingEventType, TxMeta};
+use crate::BlockBatch;
+use crate::events::{self, BlockMeta, StakingEvent, StakingEventType, TxMeta, ValidatorFlags};
+
+/// SQLSTATE of a statement canceled by `statement_timeout`.
+const QUERY_CANCELED: &str = "57014";
 
 #[derive(Debug, Error)]
 pub enum DbError {
     #[error("Database error: {0}")]
-    Sqlx(#[from] sqlx::Error),
+    Sqlx(sqlx::Error),
+    #[error("Statement timed out: {0}")]
+    StatementTimeout(sqlx::Error),
+    #[error("Operation timed out after {elapsed:?} ({rows} rows)")]
+    OperationTimedOut { elapsed: Duration, rows: usize },
+    #[error("Database schema is missing migrations: {}", .0.join(", "))]
+    MissingMigrations(Vec<String>),
+    /// Inserting the events of one table failed. Statement timeouts are not
+    /// wrapped, they stay [`DbError::StatementTimeout`].
+    #[error("Failed to insert {table} events of blocks {}..={}: {source}", block_range.0, block_range.1)]
+    TableInsert {
+        table: StakingEventType,
+        /// Lowest and highest block of the events that were being inserted.
+        block_range: (u64, u64),
+        source: sqlx::Error,
+    },
     #[error("Duplicate event: {event_type} at block {} tx {}", block_meta.block_number, tx_meta.transaction_hash)]
     DuplicateEvent {
         event_type: StakingEventType,
@@ -17,6 +43,113 @@ pub enum DbError {
     },
 }
 
+impl DbError {
+    /// Whether the operation or one of its statements ran out of time, which
+    /// may succeed when tried again.
+    pub fn is_timeout(&self) -> bool {
+        matches!(
+            self,
+            DbError::OperationTimedOut { .. } | DbError::StatementTimeout(_)
+        )
+    }
+}
+
+impl From<sqlx::Error> for DbError {
+    fn from(e: sqlx::Error) -> Self {
+        match &e {
+            sqlx::Error::Database(db_err) if db_err.code().as_deref() == Some(QUERY_CANCELED) => {
+                DbError::StatementTimeout(e)
+            }
+            _ => DbError::Sqlx(e),
+        }
+    }
+}
+
+/// Current state of a validator, as maintained in the `validators` table.
+///
+/// Attributes are `None` until the event that sets them has been indexed, e.g.
+/// `auth_address` is unknown if a status change was backfilled before creation.
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct ValidatorRow {
+    pub validator_id: i64,
+    pub auth_address: Option<String>,
+    pub commission: Option<BigDecimal>,
+    pub flags: Option<i64>,
+    pub created_block: Option<i64>,
+    pub updated_block: i64,
+}
+
+impl ValidatorRow {
+    /// Decoded `flags`, `None` until a status change has been indexed.
+    pub fn decoded_flags(&self) -> Option<ValidatorFlags> {
+        self.flags
+            .map(|flags| ValidatorFlags::from_bits_retain(flags as u64))
+    }
+}
+
+/// Attributes of a validator with the position of the event that last set
+/// each, as stored in the `validators` table.
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct ValidatorStateRow {
+    pub validator_id: i64,
+    pub auth_address: Option<String>,
+    pub commission: Option<BigDecimal>,
+    pub commission_block: Option<i64>,
+    pub commission_log_index: Option<i64>,
+    pub flags: Option<i64>,
+    pub flags_block: Option<i64>,
+    pub flags_log_index: Option<i64>,
+}
+
+/// A row of the `validator_created_events` table.
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct ValidatorCreatedRow {
+    pub validator_id: i64,
+    pub auth_address: String,
+    pub commission: BigDecimal,
+    pub block_number: i64,
+    pub transaction_hash: String,
+    pub transaction_index: i64,
+}
+
+/// An undelegation that has not been withdrawn yet.
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct PendingWithdrawalRow {
+    pub val_id: i64,
+    pub delegator: String,
+    pub withdrawal_id: i16,
+    pub amount: BigDecimal,
+    pub undelegate_block: i64,
+}
+
+/// A row of the `epochs` table.
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct EpochRow {
+    pub epoch: i64,
+    pub start_block: i64,
+    pub start_timestamp: i64,
+}
+
+/// Rows removed by [`prune_before`].
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PruneReport {
+    pub events_deleted: u64,
+    pub blocks_deleted: u64,
+}
+
+/// Rows removed by [`delete_range`].
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DeleteReport {
+    pub events_deleted: u64,
+    pub blocks_deleted: u64,
+}
+
+/// Check that the database answers queries.
+pub async fn get_db_health(pool: &PgPool) -> Result<(), DbError> {
+    sqlx::query("SELECT 1").execute(pool).await?;
+    Ok(())
+}
+
 pub async fn get_max_block_number(pool: &PgPool) -> Result<Option<u64>, DbError> {
     let row = sqlx::query_scalar::<_, Option<i64>>("SELECT MAX(block_number) FROM blocks")
         .fetch_one(pool)
@@ -25,28 +158,1334 @@ pub async fn get_max_block_number(pool: &PgPool) -> Result<Option<u64>, DbError>
     Ok(row.map(|b| b as u64))
 }
 
-pub async fn get_block_gaps(pool: &PgPool) -> Result<Vec<Range<u64>>, DbError> {
+pub async fn get_min_block_number(pool: &PgPool) -> Result<Option<u64>, DbError> {
+    let row = sqlx::query_scalar::<_, Option<i64>>("SELECT MIN(block_number) FROM blocks")
+        .fetch_one(pool)
+        .await?;
+
+    Ok(row.map(|b| b as u64))
+}
+
+/// Highest block of the run of consecutive stored blocks that starts at the
+/// lowest stored one, i.e. the last block before the first gap.
+pub async fn get_first_run_end(pool: &PgPool) -> Result<Option<u64>, DbError> {
+    let row = sqlx::query_scalar::<_, Option<i64>>(
+        r#"
+        WITH runs AS (
+            SELECT block_number,
+                   block_number - ROW_NUMBER() OVER (ORDER BY block_number) AS run
+            FROM blocks
+        )
+        SELECT MAX(block_number) FROM runs
+        WHERE run = (SELECT MIN(run) FROM runs)
+        "#,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row.map(|b| b as u64))
+}
+
+pub async fn get_block_count(pool: &PgPool) -> Result<u64, DbError> {
+    let count = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM blocks")
+        .fetch_one(pool)
+        .await?;
+
+    Ok(count as u64)
+}
+
+/// What to do with blocks that are already stored when they are indexed again.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlockConflictStrategy {
+    /// Keep the stored hash and timestamp.
+    #[default]
+    Ignore,
+    /// Overwrite them with [`upsert_blocks`] in the insert transaction, so a
+    /// block replaced by a reorg carries the hash of the canonical one.
+    Upsert,
+}
+
+/// Insert the blocks, overwriting the hash and timestamp of stored blocks with
+/// different values. Returns the number of blocks inserted or changed.
+pub async fn upsert_blocks<'e, E>(executor: E, blocks: &[BlockMeta]) -> Result<u64, DbError>
+where
+    E: sqlx::PgExecutor<'e>,
+{
+    if blocks.is_empty() {
+        return Ok(0);
+    }
+
+    let mut query_builder =
+        sqlx::QueryBuilder::new("INSERT INTO blocks (block_number, block_hash, block_timestamp) ");
+    query_builder.push_values(blocks, |mut b, block_meta| {
+        b.push_bind(block_meta.block_number as i64)
+            .push_bind(&block_meta.block_hash)
+            .push_bind(block_meta.block_timestamp as i64);
+    });
+    query_builder.push(
+        r#" ON CONFLICT (block_number) DO UPDATE SET
+            block_hash = EXCLUDED.block_hash,
+            block_timestamp = EXCLUDED.block_timestamp
+        WHERE (blocks.block_hash, blocks.block_timestamp)
+            IS DISTINCT FROM (EXCLUDED.block_hash, EXCLUDED.block_timestamp)"#,
+    );
+
+    let result = query_builder.build().execute(executor).await?;
+    Ok(result.rows_affected())
+}
+
+/// The `count` highest stored blocks, newest first.
+pub async fn get_latest_blocks(pool: &PgPool, count: usize) -> Result<Vec<BlockMeta>, DbError> {
+    let blocks = sqlx::query_as::<_, (i64, String, i64)>(
+        "SELECT block_number, block_hash, block_timestamp FROM blocks \
+         ORDER BY block_number DESC LIMIT $1",
+    )
+    .bind(count.min(i64::MAX as usize) as i64)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(blocks
+        .into_iter()
+        .map(|(block_number, block_hash, block_timestamp)| BlockMeta {
+            block_number: block_number as u64,
+            block_hash,
+            block_timestamp: block_timestamp as u64,
+        })
+        .collect())
+}
+
+/// Lowest and highest indexed block, if any.
+pub async fn get_block_range(pool: &PgPool) -> Result<Option<(u64, u64)>, DbError> {
+    let (min, max) = sqlx::query_as::<_, (Option<i64>, Option<i64>)>(
+        "SELECT MIN(block_number), MAX(block_number) FROM blocks",
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(min.zip(max).map(|(min, max)| (min as u64, max as u64)))
+}
+
+/// Build an event of `event_type` from a row of its table joined with `blocks`.
+fn event_from_row(event_type: StakingEventType, row: &PgRow) -> Result<StakingEvent, sqlx::Error> {
+    let block_meta = BlockMeta {
+        block_number: row.try_get::<i64, _>("block_number")? as u64,
+        block_hash: row.try_get("block_hash")?,
+        block_timestamp: row.try_get::<i64, _>("block_timestamp")? as u64,
+    };
+    let tx_meta = TxMeta {
+        transaction_hash: row.try_get("transaction_hash")?,
+        transaction_index: row.try_get::<i64, _>("transaction_index")? as u64,
+        log_index: row
+            .try_get::<Option<i64>, _>("log_index")?
+            .unwrap_or_default() as u64,
+    };
+    let u64_column = |name: &str| row.try_get::<i64, _>(name).map(|value| value as u64);
+
+    Ok(match event_type {
+        StakingEventType::Delegate => StakingEvent::Delegate(events::DelegateEvent {
+            val_id: u64_column("val_id")?,
+            delegator: row.try_get("delegator")?,
+            amount: row.try_get("amount")?,
+            activation_epoch: u64_column("activation_epoch")?,
+            block_meta,
+            tx_meta,
+        }),
+        StakingEventType::Undelegate => StakingEvent::Undelegate(events::UndelegateEvent {
+            val_id: u64_column("val_id")?,
+            delegator: row.try_get("delegator")?,
+            withdrawal_id: row.try_get("withdrawal_id")?,
+            amount: row.try_get("amount")?,
+            activation_epoch: u64_column("activation_epoch")?,
+            block_meta,
+            tx_meta,
+        }),
+        StakingEventType::Withdraw => StakingEvent::Withdraw(events::WithdrawEvent {
+            val_id: u64_column("val_id")?,
+            delegator: row.try_get("delegator")?,
+            withdrawal_id: row.try_get("withdrawal_id")?,
+            amount: row.try_get("amount")?,
+            activation_epoch: u64_column("activation_epoch")?,
+            block_meta,
+            tx_meta,
+        }),
+        StakingEventType::ClaimRewards => StakingEvent::ClaimRewards(events::ClaimRewardsEvent {
+            val_id: u64_column("val_id")?,
+            delegator: row.try_get("delegator")?,
+            amount: row.try_get("amount")?,
+            epoch: u64_column("epoch")?,
+            block_meta,
+            tx_meta,
+        }),
+        StakingEventType::ValidatorRewarded => {
+            StakingEvent::ValidatorRewarded(events::ValidatorRewardedEvent {
+                validator_id: u64_column("validator_id")?,
+                from: row.try_get("from_address")?,
+                amount: row.try_get("amount")?,
+                epoch: u64_column("epoch")?,
+                block_meta,
+                tx_meta,
+            })
+        }
+        StakingEventType::EpochChanged => StakingEvent::EpochChanged(events::EpochChangedEvent {
+            old_epoch: u64_column("old_epoch")?,
+            new_epoch: u64_column("new_epoch")?,
+            block_meta,
+            tx_meta,
+        }),
+        StakingEventType::ValidatorCreated => {
+            StakingEvent::ValidatorCreated(events::ValidatorCreatedEvent {
+                validator_id: u64_column("validator_id")?,
+                auth_address: row.try_get("auth_address")?,
+                commission: row.try_get("commission")?,
+                block_meta,
+                tx_meta,
+            })
+        }
+        StakingEventType::ValidatorStatusChanged => {
+            StakingEvent::ValidatorStatusChanged(events::ValidatorStatusChangedEvent {
+                validator_id: u64_column("validator_id")?,
+                flags: u64_column("flags")?,
+                block_meta,
+                tx_meta,
+            })
+        }
+        StakingEventType::CommissionChanged => {
+            StakingEvent::CommissionChanged(events::CommissionChangedEvent {
+                validator_id: u64_column("validator_id")?,
+                old_commission: row.try_get("old_commission")?,
+                new_commission: row.try_get("new_commission")?,
+                block_meta,
+                tx_meta,
+            })
+        }
+        StakingEventType::Unknown => StakingEvent::Unknown(events::RawEvent {
+            topic0: row.try_get("topic0")?,
+            topics: row.try_get("topics")?,
+            data: row.try_get("data")?,
+            block_meta,
+            tx_meta,
+        }),
+    })
+}
+
+/// All stored events whose row in `blocks` matches `condition`, ordered by
+/// block and log index. `condition` refers to the event table as `e`
+/// and to `blocks` as `b`, and binds `start` and `end` as `$1` and `$2`.
+async fn get_events_where(
+    pool: &PgPool,
+    condition: &str,
+    start: u64,
+    end: u64,
+) -> Result<Vec<StakingEvent>, DbError> {
+    let mut events = Vec::new();
+    for &event_type in StakingEventType::all_types() {
+        let query = format!(
+            "SELECT e.*, b.block_hash, b.block_timestamp FROM {} e \
+             JOIN blocks b ON b.block_number = e.block_number \
+             WHERE {condition}",
+            event_type.table_name()
+        );
+        let rows = sqlx::query(&query)
+            .bind(start.min(i64::MAX as u64) as i64)
+            .bind(end.min(i64::MAX as u64) as i64)
+            .fetch_all(pool)
+            .await?;
+        for row in &rows {
+            events.push(event_from_row(event_type, row)?);
+        }
+    }
+
+    events.sort_by_key(|event| (event.block_meta().block_number, event.tx_meta().log_index));
+
+    Ok(events)
+}
+
+/// All stored events within `block_range`, ordered by block and log index.
+pub async fn get_events_in_block_range(
+    pool: &PgPool,
+    block_range: Range<u64>,
+) -> Result<Vec<StakingEvent>, DbError> {
+    get_events_where(
+        pool,
+        "e.block_number >= $1 AND e.block_number < $2",
+        block_range.start,
+        block_range.end,
+    )
+    .await
+}
+
+/// All stored blocks and events with a block timestamp from `from_ts` up to and
+/// including `to_ts`.
+pub async fn get_events_by_timestamp_range(
+    pool: &PgPool,
+    from_ts: u64,
+    to_ts: u64,
+) -> Result<BlockBatch, DbError> {
+    let mut batch = BlockBatch::new();
+
+    let blocks = sqlx::query_as::<_, (i64, String, i64)>(
+        "SELECT block_number, block_hash, block_timestamp FROM blocks \
+         WHERE block_timestamp BETWEEN $1 AND $2 ORDER BY block_number",
+    )
+    .bind(from_ts.min(i64::MAX as u64) as i64)
+    .bind(to_ts.min(i64::MAX as u64) as i64)
+    .fetch_all(pool)
+    .await?;
+    for (block_number, block_hash, block_timestamp) in blocks {
+        batch.add_block_meta(BlockMeta {
+            block_number: block_number as u64,
+            block_hash,
+            block_timestamp: block_timestamp as u64,
+        });
+    }
+
+    let condition = "b.block_timestamp BETWEEN $1 AND $2";
+    for event in get_events_where(pool, condition, from_ts, to_ts).await? {
+        batch.add_event(event);
+    }
+
+    Ok(batch)
+}
+
+/// Delegations made by `delegator`, oldest first, skipping the first `offset`
+/// and returning at most `limit`.
+pub async fn get_delegate_events_by_delegator(
+    pool: &PgPool,
+    delegator: &str,
+    limit: u64,
+    offset: u64,
+) -> Result<Vec<events::DelegateEvent>, DbError> {
+    let rows = sqlx::query(
+        "SELECT e.*, b.block_hash, b.block_timestamp FROM delegate_events e \
+         JOIN blocks b ON b.block_number = e.block_number \
+         WHERE e.delegator = $1 \
+         ORDER BY e.block_number, e.log_index NULLS FIRST, e.val_id \
+         LIMIT $2 OFFSET $3",
+    )
+    .bind(delegator)
+    .bind(limit.min(i64::MAX as u64) as i64)
+    .bind(offset.min(i64::MAX as u64) as i64)
+    .fetch_all(pool)
+    .await?;
+
+    rows.iter()
+        .map(
+            |row| match event_from_row(StakingEventType::Delegate, row)? {
+                StakingEvent::Delegate(event) => Ok(event),
+                _ => unreachable!("delegate_events rows decode to delegate events"),
+            },
+        )
+        .collect()
+}
+
+/// Rewards paid to `validator_id` in `epochs`, ordered by epoch and block.
+pub async fn get_validator_rewards(
+    pool: &PgPool,
+    validator_id: u64,
+    epochs: RangeInclusive<u64>,
+) -> Result<Vec<events::ValidatorRewardedEvent>, DbError> {
+    let rows = sqlx::query(
+        "SELECT e.*, b.block_hash, b.block_timestamp FROM validator_rewarded_events e \
+         JOIN blocks b ON b.block_number = e.block_number \
+         WHERE e.validator_id = $1 AND e.epoch BETWEEN $2 AND $3 \
+         ORDER BY e.epoch, e.block_number, e.log_index NULLS FIRST",
+    )
+    .bind(validator_id.min(i64::MAX as u64) as i64)
+    .bind((*epochs.start()).min(i64::MAX as u64) as i64)
+    .bind((*epochs.end()).min(i64::MAX as u64) as i64)
+    .fetch_all(pool)
+    .await?;
+
+    rows.iter()
+        .map(
+            |row| match event_from_row(StakingEventType::ValidatorRewarded, row)? {
+                StakingEvent::ValidatorRewarded(event) => Ok(event),
+                _ => unreachable!("validator_rewarded_events rows decode to reward events"),
+            },
+        )
+        .collect()
+}
+
+/// Number of stored events per type.
+pub async fn get_event_counts(pool: &PgPool) -> Result<HashMap<StakingEventType, u64>, DbError> {
+    get_event_counts_in_range(pool, 0..u64::MAX).await
+}
+
+/// Number of stored events of `event_type`.
+pub async fn get_event_count(pool: &PgPool, event_type: StakingEventType) -> Result<u64, DbError> {
+    let count: i64 =
+        sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", event_type.table_name()))
+            .fetch_one(pool)
+            .await?;
+    Ok(count as u64)
+}
+
+/// Number of stored events per type within `block_range`.
+///
+/// All event tables are counted in a single `UNION ALL` query.
+pub async fn get_event_counts_in_range(
+    pool: &PgPool,
+    block_range: Range<u64>,
+) -> Result<HashMap<StakingEventType, u64>, DbError> {
+    let event_types = StakingEventType::all_types();
+    let query = event_types
+        .iter()
+        .enumerate()
+        .map(|(i, event_type)| {
+            format!(
+                "SELECT {i}::INT, COUNT(*) FROM {} WHERE block_number >= $1 AND block_number < $2",
+                event_type.table_name()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" UNION ALL ");
+
+    let rows = sqlx::query_as::<_, (i32, i64)>(&query)
+        .bind(block_range.start.min(i64::MAX as u64) as i64)
+        .bind(block_range.end.min(i64::MAX as u64) as i64)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(i, count)| (event_types[i as usize], count as u64))
+        .collect())
+}
+
+/// Limits on the gaps returned by [`get_block_gaps`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct GapOptions {
+    /// Maximum number of ranges returned per call, oldest first. The rest is
+    /// picked up by a later call once these have been backfilled.
+    pub max_gaps: usize,
+    /// Gaps separated by fewer known blocks than this are merged into one range.
+    pub merge_distance: u64,
+}
+
+impl Default for GapOptions {
+    fn default() -> Self {
+        Self {
+            max_gaps: usize::MAX,
+            merge_distance: 0,
+        }
+    }
+}
+
+/// Result of [`get_block_gaps`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BlockGaps {
+    /// Ranges to backfill, after merging and capping.
+    pub ranges: Vec<Range<u64>>,
+    /// Number of missing blocks across all gaps, including those not returned.
+    pub missing_blocks: u64,
+}
+
+/// Block gaps above the checkpoint and the `pruned_below` watermark.
+///
+/// Everything up to the checkpoint has been scanned and pruned history must not be
+/// backfilled again. The checkpoint itself counts as a known block, so a range
+/// missing right after it is still reported.
+///
+/// Ranges are returned oldest first and limited by `options`, so a sparsely
+/// indexed history does not queue one backfill per missing range at once.
+pub async fn get_block_gaps(pool: &PgPool, options: &GapOptions) -> Result<BlockGaps, DbError> {
     let rows = sqlx::query_as::<_, (i64, i64)>(
         r#"
-        WITH gaps AS (
+        WITH floor AS (
+            SELECT GREATEST(
+                COALESCE((SELECT value FROM indexer_metadata WHERE key = 'pruned_below'), 0),
+                COALESCE((SELECT block_number FROM indexer_checkpoint), 0)
+            ) AS block_number
+        ),
+        known AS (
+            SELECT block_number FROM blocks
+            WHERE block_number >= (SELECT block_number FROM floor)
+            UNION
+            SELECT block_number FROM indexer_checkpoint
+            WHERE block_number >= (SELECT block_number FROM floor)
+        ),
+        gaps AS (
             SELECT block_number + 1 AS gap_start,
                    LEAD(block_number) OVER (ORDER BY block_number) - 1 AS gap_end
-            FROM blocks
+            FROM known
         )
         SELECT gap_start, gap_end
         FROM gaps
         WHERE gap_end IS NOT NULL
         AND gap_end >= gap_start
+        ORDER BY gap_start
         "#,
     )
     .fetch_all(pool)
     .await?;
 
-    Ok(rows
+    let gaps = rows.iter().map(|r| Range {
+        start: u64::try_from(r.0).unwrap(),
+        end: u64::try_from(r.1).unwrap() + 1, // +1 because Range is exclusive end
+    });
+
+    let mut missing_blocks = 0;
+    let mut ranges: Vec<Range<u64>> = Vec::new();
+    for gap in gaps {
+        missing_blocks += gap.end - gap.start;
+        match ranges.last_mut() {
+            Some(last) if gap.start - last.end < options.merge_distance => last.end = gap.end,
+            _ => ranges.push(gap),
+        }
+    }
+    ranges.truncate(options.max_gaps);
+
+    Ok(BlockGaps {
+        ranges,
+        missing_blocks,
+    })
+}
+
+pub async fn get_validator(
+    pool: &PgPool,
+    validator_id: u64,
+) -> Result<Option<ValidatorRow>, DbError> {
+    let row = sqlx::query_as::<_, ValidatorRow>(
+        r#"
+        SELECT validator_id, auth_address, commission, flags, created_block, updated_block
+        FROM validators
+        WHERE validator_id = $1
+        "#,
+    )
+    .bind(validator_id as i64)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row)
+}
+
+pub async fn list_validators(pool: &PgPool) -> Result<Vec<ValidatorRow>, DbError> {
+    let rows = sqlx::query_as::<_, ValidatorRow>(
+        r#"
+        SELECT validator_id, auth_address, commission, flags, created_block, updated_block
+        FROM validators
+        ORDER BY validator_id
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+pub async fn list_validator_states(pool: &PgPool) -> Result<Vec<ValidatorStateRow>, DbError> {
+    let rows = sqlx::query_as::<_, ValidatorStateRow>(
+        r#"
+        SELECT validator_id, auth_address,
+            commission, commission_block, commission_log_index,
+            flags, flags_block, flags_log_index
+        FROM validators
+        ORDER BY validator_id
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+/// Number of validators whose current flags mark them as jailed.
+pub async fn get_jailed_validator_count(pool: &PgPool) -> Result<u64, DbError> {
+    let count = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM validators WHERE is_jailed")
+        .fetch_one(pool)
+        .await?;
+
+    Ok(count as u64)
+}
+
+/// Validators created between blocks `start` and `end`, both inclusive, in
+/// chain order.
+pub async fn get_validators_created_in_block_range(
+    pool: &PgPool,
+    start: u64,
+    end: u64,
+) -> Result<Vec<ValidatorCreatedRow>, DbError> {
+    let rows = sqlx::query_as::<_, ValidatorCreatedRow>(
+        r#"
+        SELECT validator_id, auth_address, commission, block_number, transaction_hash, transaction_index
+        FROM validator_created_events
+        WHERE block_number BETWEEN $1 AND $2
+        ORDER BY block_number, log_index NULLS FIRST
+        "#,
+    )
+    .bind(start as i64)
+    .bind(end as i64)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+/// Current stake of `delegator` with validator `val_id`.
+pub async fn get_stake(pool: &PgPool, val_id: u64, delegator: &str) -> Result<BigDecimal, DbError> {
+    let stake = sqlx::query_scalar::<_, Option<BigDecimal>>(
+        "SELECT stake FROM delegations WHERE val_id = $1 AND delegator = $2",
+    )
+    .bind(val_id as i64)
+    .bind(delegator)
+    .fetch_optional(pool)
+    .await?
+    .flatten();
+
+    Ok(stake.unwrap_or_else(|| BigDecimal::from(0)))
+}
+
+/// Total stake delegated to validator `val_id`.
+pub async fn get_total_stake(pool: &PgPool, val_id: u64) -> Result<BigDecimal, DbError> {
+    let stake = sqlx::query_scalar::<_, Option<BigDecimal>>(
+        "SELECT SUM(stake) FROM delegations WHERE val_id = $1",
+    )
+    .bind(val_id as i64)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(stake.unwrap_or_else(|| BigDecimal::from(0)))
+}
+
+/// Total stake delegated to each of `val_ids`. Validators without delegations
+/// map to zero.
+pub async fn get_total_stakes(
+    pool: &PgPool,
+    val_ids: &[u64],
+) -> Result<HashMap<u64, BigDecimal>, DbError> {
+    let ids: Vec<i64> = val_ids.iter().map(|&id| id as i64).collect();
+    let rows = sqlx::query_as::<_, (i64, BigDecimal)>(
+        r#"
+        SELECT val_id, SUM(stake)
+        FROM delegations
+        WHERE val_id = ANY($1)
+        GROUP BY val_id
+        "#,
+    )
+    .bind(&ids)
+    .fetch_all(pool)
+    .await?;
+
+    let mut stakes: HashMap<u64, BigDecimal> = val_ids
         .iter()
-        .map(|r| Range {
-            start: u64::try_from(r.0).unwrap(),
-            end: u64::try_from(r.1).unwrap() + 1, // +1 because Range is exclusive end
+        .map(|&id| (id, BigDecimal::from(0)))
+        .collect();
+    stakes.extend(rows.into_iter().map(|(id, total)| (id as u64, total)));
+    Ok(stakes)
+}
+
+/// Delegators with the largest net stake across all validators, largest first.
+///
+/// Reads the `delegations` table rather than summing `delegate_events` and
+/// `undelegate_events`, which would scan the full event history on every call
+/// and give wrong results once old events are pruned. It still aggregates over
+/// every delegation; if this becomes hot, keep a per-delegator total in a
+/// materialized view refreshed after inserts instead.
+pub async fn get_top_delegators(
+    pool: &PgPool,
+    limit: i64,
+) -> Result<Vec<(String, BigDecimal)>, DbError> {
+    let rows = sqlx::query_as::<_, (String, BigDecimal)>(
+        r#"
+        SELECT delegator, SUM(stake) AS total
+        FROM delegations
+        GROUP BY delegator
+        HAVING SUM(stake) > 0
+        ORDER BY total DESC, delegator
+        LIMIT $1
+        "#,
+    )
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+/// Condition selecting rows of `pending_withdrawals` that are still pending:
+/// their latest Undelegate comes after their latest Withdraw, compared by
+/// (block, transaction index, log index).
+const PENDING_WITHDRAWAL: &str = "undelegate_block IS NOT NULL AND (withdraw_block IS NULL OR \
+    (withdraw_block, COALESCE(withdraw_transaction_index, -1), COALESCE(withdraw_log_index, -1)) \
+    < (undelegate_block, COALESCE(undelegate_transaction_index, -1), COALESCE(undelegate_log_index, -1)))";
+
+/// Epoch that `block_number` belongs to: the latest one starting at or before it.
+pub async fn get_epoch_for_block(pool: &PgPool, block_number: u64) -> Result<Option<u64>, DbError> {
+    let epoch = sqlx::query_scalar::<_, i64>(
+        "SELECT epoch FROM epochs WHERE start_block <= $1 ORDER BY start_block DESC LIMIT 1",
+    )
+    .bind(block_number as i64)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(epoch.map(|epoch| epoch as u64))
+}
+
+/// Start of each known epoch numbered within `epochs`, ordered by epoch.
+pub async fn get_epochs(pool: &PgPool, epochs: Range<u64>) -> Result<Vec<EpochRow>, DbError> {
+    let rows = sqlx::query_as::<_, EpochRow>(
+        "SELECT epoch, start_block, start_timestamp FROM epochs WHERE epoch >= $1 AND epoch < $2 ORDER BY epoch",
+    )
+    .bind(epochs.start as i64)
+    .bind(epochs.end as i64)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+/// `(new_epoch, block_number)` of every EpochChanged event, ordered by block.
+///
+/// The epoch is a `u64` rather than a `BigDecimal` because `new_epoch` is a
+/// `BIGINT` column, as are the epochs of [`get_epochs`] and [`get_epoch_for_block`].
+pub async fn get_epoch_boundary_blocks(pool: &PgPool) -> Result<Vec<(u64, u64)>, DbError> {
+    let rows = sqlx::query_as::<_, (i64, i64)>(
+        "SELECT new_epoch, block_number FROM epoch_changed_events ORDER BY block_number, log_index NULLS FIRST",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(epoch, block_number)| (epoch as u64, block_number as u64))
+        .collect())
+}
+
+pub async fn get_pending_withdrawals(
+    pool: &PgPool,
+    val_id: u64,
+) -> Result<Vec<PendingWithdrawalRow>, DbError> {
+    let rows = sqlx::query_as::<_, PendingWithdrawalRow>(&format!(
+        r#"
+        SELECT val_id, delegator, withdrawal_id, amount, undelegate_block
+        FROM pending_withdrawals
+        WHERE val_id = $1 AND {PENDING_WITHDRAWAL}
+        ORDER BY undelegate_block, delegator, withdrawal_id
+        "#
+    ))
+    .bind(val_id as i64)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+/// Total amount of all pending withdrawals across validators.
+pub async fn get_total_pending_withdrawals(pool: &PgPool) -> Result<BigDecimal, DbError> {
+    let total = sqlx::query_scalar::<_, Option<BigDecimal>>(&format!(
+        "SELECT SUM(amount) FROM pending_withdrawals WHERE {PENDING_WITHDRAWAL}"
+    ))
+    .fetch_one(pool)
+    .await?;
+
+    Ok(total.unwrap_or_else(|| BigDecimal::from(0)))
+}
+
+/// Number of blocks per partition of the event tables and `blocks`. Must match
+/// `ensure_block_partitions` in the migrations.
+pub const PARTITION_SIZE: u64 = 10_000_000;
+
+/// Create any missing partitions needed to store blocks up to `up_to_block`.
+/// Returns the number of partitions created.
+pub async fn ensure_partitions(pool: &PgPool, up_to_block: u64) -> Result<u64, DbError> {
+    let created = sqlx::query_scalar::<_, i32>("SELECT ensure_block_partitions($1)")
+        .bind(up_to_block as i64)
+        .fetch_one(pool)
+        .await?;
+
+    if created > 0 {
+        info!("Created {created} partitions for blocks up to {up_to_block}");
+    }
+
+    Ok(created as u64)
+}
+
+/// Highest block up to which every block has been scanned, if known.
+pub async fn get_checkpoint(pool: &PgPool) -> Result<Option<u64>, DbError> {
+    let value = sqlx::query_scalar::<_, i64>("SELECT block_number FROM indexer_checkpoint")
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(value.map(|v| v as u64))
+}
+
+/// Advance the checkpoint to `block_number`. The checkpoint never moves backwards.
+///
+/// Takes any executor so it can also run inside the insert transaction.
+pub async fn set_checkpoint<'e, E>(executor: E, block_number: u64) -> Result<(), DbError>
+where
+    E: sqlx::PgExecutor<'e>,
+{
+    sqlx::query(
+        r#"
+        INSERT INTO indexer_checkpoint (id, block_number) VALUES (TRUE, $1)
+        ON CONFLICT (id) DO UPDATE SET
+            block_number = GREATEST(indexer_checkpoint.block_number, EXCLUDED.block_number),
+            updated_at = CURRENT_TIMESTAMP
+        "#,
+    )
+    .bind(block_number as i64)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Raw event tables subject to retention pruning, with their primary key column.
+///
+/// Deleting a block cascades to its events. The event tables still come first
+/// so that the deleted events are counted and deleted in bounded batches.
+const PRUNABLE_TABLES: [(&str, &str); 11] = [
+    ("delegate_events", "id"),
+    ("undelegate_events", "id"),
+    ("withdraw_events", "id"),
+    ("claim_rewards_events", "id"),
+    ("validator_rewarded_events", "id"),
+    ("epoch_changed_events", "id"),
+    ("validator_created_events", "id"),
+    ("validator_status_changed_events", "id"),
+    ("commission_changed_events", "id"),
+    ("raw_events", "id"),
+    ("blocks", "block_number"),
+];
+
+const PRUNE_BATCH_SIZE: i64 = 10_000;
+
+/// Tables and columns holding addresses.
+const ADDRESS_COLUMNS: &[(&str, &str)] = &[
+    ("delegate_events", "delegator"),
+    ("undelegate_events", "delegator"),
+    ("withdraw_events", "delegator"),
+    ("claim_rewards_events", "delegator"),
+    ("validator_rewarded_events", "from_address"),
+    ("validator_created_events", "auth_address"),
+    ("validators", "auth_address"),
+    ("delegations", "delegator"),
+    ("pending_withdrawals", "delegator"),
+];
+
+/// Distinct addresses rewritten per transaction by [`checksum_legacy_addresses`].
+const CHECKSUM_BATCH_SIZE: i64 = 10_000;
+
+/// Checksum the addresses stored before addresses were EIP-55 checksummed, if
+/// the `addresses_checksummed` marker says this is still pending. Returns the
+/// number of rows rewritten.
+///
+/// Each table is rewritten in batches that commit on their own, so that large
+/// tables are not locked for the whole run. The marker is set once all tables
+/// are done; a run that was interrupted resumes with the addresses that are
+/// still lowercase.
+pub async fn checksum_legacy_addresses(pool: &PgPool) -> Result<u64, DbError> {
+    checksum_legacy_addresses_in_batches(pool, CHECKSUM_BATCH_SIZE).await
+}
+
+pub async fn checksum_legacy_addresses_in_batches(
+    pool: &PgPool,
+    batch_size: i64,
+) -> Result<u64, DbError> {
+    let done = sqlx::query_scalar::<_, i64>(
+        "SELECT value FROM indexer_metadata WHERE key = 'addresses_checksummed'",
+    )
+    .fetch_optional(pool)
+    .await?;
+    if done != Some(0) {
+        return Ok(0);
+    }
+
+    let mut rewritten = 0;
+    for (table, column) in ADDRESS_COLUMNS {
+        // Lowercase addresses whose checksum is lowercase too stay behind, so
+        // the batches page through the addresses instead of repeating the query.
+        let mut after = String::new();
+        loop {
+            let mut tx = pool.begin().await?;
+            let stored = sqlx::query_scalar::<_, String>(&format!(
+                "SELECT DISTINCT {column} FROM {table} \
+                 WHERE {column} = LOWER({column}) AND {column} > $1 \
+                 ORDER BY {column} LIMIT $2"
+            ))
+            .bind(&after)
+            .bind(batch_size)
+            .fetch_all(&mut *tx)
+            .await?;
+            let Some(last) = stored.last().cloned() else {
+                break;
+            };
+            let (old, new): (Vec<String>, Vec<String>) = stored
+                .into_iter()
+                .filter_map(|address| {
+                    let bytes = hex::decode(&address).ok().filter(|b| b.len() == 20)?;
+                    let checksummed = events::to_checksum_address(&bytes);
+                    (checksummed != address).then_some((address, checksummed))
+                })
+                .unzip();
+            rewritten += sqlx::query(&format!(
+                "UPDATE {table} SET {column} = m.new \
+                 FROM UNNEST($1::TEXT[], $2::TEXT[]) AS m(old, new) \
+                 WHERE {table}.{column} = m.old"
+            ))
+            .bind(&old)
+            .bind(&new)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+            tx.commit().await?;
+            after = last;
+        }
+    }
+
+    sqlx::query(
+        "UPDATE indexer_metadata SET value = 1, updated_at = CURRENT_TIMESTAMP \
+         WHERE key = 'addresses_checksummed'",
+    )
+    .execute(pool)
+    .await?;
+
+    info!("Checksummed {rewritten} stored addresses");
+    Ok(rewritten)
+}
+
+/// Block number below which history has been pruned, if any.
+pub async fn get_pruned_below(pool: &PgPool) -> Result<Option<u64>, DbError> {
+    let value = sqlx::query_scalar::<_, i64>(
+        "SELECT value FROM indexer_metadata WHERE key = 'pruned_below'",
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(value.map(|v| v as u64))
+}
+
+/// Delete events and blocks below `block_number`.
+///
+/// The `pruned_below` watermark is raised first so that gap detection never
+/// sees a partially pruned region. Rows are deleted in batches to keep locks short.
+pub async fn prune_before(pool: &PgPool, block_number: u64) -> Result<PruneReport, DbError> {
+    prune_before_in_batches(pool, block_number, PRUNE_BATCH_SIZE).await
+}
+
+pub async fn prune_before_in_batches(
+    pool: &PgPool,
+    block_number: u64,
+    batch_size: i64,
+) -> Result<PruneReport, DbError> {
+    let start = Instant::now();
+
+    sqlx::query(
+        r#"
+        INSERT INTO indexer_metadata (key, value) VALUES ('pruned_below', $1)
+        ON CONFLICT (key) DO UPDATE SET
+            value = GREATEST(indexer_metadata.value, EXCLUDED.value),
+            updated_at = CURRENT_TIMESTAMP
+        "#,
+    )
+    .bind(block_number as i64)
+    .execute(pool)
+    .await?;
+
+    let mut report = PruneReport::default();
+    for (table, key) in PRUNABLE_TABLES {
+        let query = format!(
+            "DELETE FROM {table} WHERE {key} IN \
+             (SELECT {key} FROM {table} WHERE block_number < $1 LIMIT $2)"
+        );
+        loop {
+            let deleted = sqlx::query(&query)
+                .bind(block_number as i64)
+                .bind(batch_size)
+                .execute(pool)
+                .await?
+                .rows_affected();
+
+            if table == "blocks" {
+                report.blocks_deleted += deleted;
+            } else {
+                report.events_deleted += deleted;
+            }
+            if deleted < batch_size as u64 {
+                break;
+            }
+        }
+    }
+
+    info!(
+        "Pruned {} events and {} blocks below block {} in {:?}",
+        report.events_deleted,
+        report.blocks_deleted,
+        block_number,
+        start.elapsed()
+    );
+
+    Ok(report)
+}
+
+/// Delete the blocks and events in `range` in one transaction.
+///
+/// Stakes in `delegations` are reduced by the deleted Delegate and Undelegate
+/// events, down to zero. Attributes of `pending_withdrawals` last set by an event in the
+/// range are cleared, attributes of `validators` fall back to the last stored
+/// event before the range. Epochs starting in the range are removed.
+///
+/// Indexing a range again with the `reindex` command does not use this, as
+/// the range would stay empty if the process stopped before it is backfilled:
+/// the backfilled batches delete it in their insert transaction, see
+/// [`BlockBatch::replaces`](crate::BlockBatch::replaces).
+pub async fn delete_range(pool: &PgPool, range: Range<u64>) -> Result<DeleteReport, DbError> {
+    let mut tx = pool.begin().await?;
+    let report = delete_range_in_tx(&mut tx, &range).await?;
+    tx.commit().await?;
+
+    info!(
+        "Deleted {} events and {} blocks in {:?}",
+        report.events_deleted, report.blocks_deleted, range
+    );
+
+    Ok(report)
+}
+
+/// Delete the blocks and events of `blocks` in one transaction, like
+/// [`delete_range`] for each run of consecutive blocks.
+///
+/// Used for blocks replaced by a reorg, which must not be served until they
+/// are indexed again. The checkpoint is lowered to just below the first of
+/// them in the same transaction, so that the gap check reports them if they
+/// are not indexed again, e.g. after a restart.
+pub async fn delete_events_for_blocks(
+    pool: &PgPool,
+    blocks: &[u64],
+) -> Result<DeleteReport, DbError> {
+    let ranges = crate::contiguous_ranges(blocks);
+    let Some(first) = ranges.first().map(|range| range.start) else {
+        return Ok(DeleteReport::default());
+    };
+    let mut tx = pool.begin().await?;
+    let mut report = DeleteReport::default();
+    for range in &ranges {
+        let deleted = delete_range_in_tx(&mut tx, range).await?;
+        report.events_deleted += deleted.events_deleted;
+        report.blocks_deleted += deleted.blocks_deleted;
+    }
+    // Not `set_checkpoint`, which only moves the checkpoint up.
+    sqlx::query(
+        r#"
+        UPDATE indexer_checkpoint SET
+            block_number = $1,
+            updated_at = CURRENT_TIMESTAMP
+        WHERE block_number >= $2
+        "#,
+    )
+    .bind(first as i64 - 1)
+    .bind(first as i64)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    info!(
+        "Deleted {} events and {} blocks of {} reorged blocks",
+        report.events_deleted,
+        report.blocks_deleted,
+        blocks.len()
+    );
+
+    Ok(report)
+}
+
+/// [`delete_range`] within `tx`.
+pub(crate) async fn delete_range_in_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    range: &Range<u64>,
+) -> Result<DeleteReport, DbError> {
+    let start = range.start as i64;
+    let end = range.end as i64;
+
+    // Data-modifying CTEs run whether or not the final SELECT reads them.
+    let delegation_events = sqlx::query_scalar::<_, i64>(
+        r#"
+        WITH deleted_delegate AS (
+            DELETE FROM delegate_events WHERE block_number >= $1 AND block_number < $2
+            RETURNING val_id, delegator, amount
+        ), deleted_undelegate AS (
+            DELETE FROM undelegate_events WHERE block_number >= $1 AND block_number < $2
+            RETURNING val_id, delegator, amount
+        ), deltas AS (
+            SELECT val_id, delegator, SUM(amount) AS amount FROM (
+                SELECT val_id, delegator, amount FROM deleted_delegate
+                UNION ALL
+                SELECT val_id, delegator, -amount FROM deleted_undelegate
+            ) d
+            GROUP BY val_id, delegator
+        ), updated AS (
+            UPDATE delegations SET
+                stake = GREATEST(delegations.stake - deltas.amount, 0),
+                updated_at = CURRENT_TIMESTAMP
+            FROM deltas
+            WHERE delegations.val_id = deltas.val_id AND delegations.delegator = deltas.delegator
+        )
+        SELECT (SELECT COUNT(*) FROM deleted_delegate) + (SELECT COUNT(*) FROM deleted_undelegate)
+        "#,
+    )
+    .bind(start)
+    .bind(end)
+    .fetch_one(&mut **tx)
+    .await?;
+    let mut report = DeleteReport {
+        events_deleted: delegation_events as u64,
+        blocks_deleted: 0,
+    };
+
+    for (table, _) in PRUNABLE_TABLES {
+        if matches!(table, "delegate_events" | "undelegate_events") {
+            continue;
+        }
+        let deleted = sqlx::query(&format!(
+            "DELETE FROM {table} WHERE block_number >= $1 AND block_number < $2"
+        ))
+        .bind(start)
+        .bind(end)
+        .execute(&mut **tx)
+        .await?
+        .rows_affected();
+        if table == "blocks" {
+            report.blocks_deleted = deleted;
+        } else {
+            report.events_deleted += deleted;
+        }
+    }
+
+    sqlx::query(
+        r#"
+        UPDATE pending_withdrawals SET
+            amount = CASE WHEN undelegate_block >= $1 AND undelegate_block < $2 THEN NULL ELSE amount END,
+            undelegate_transaction_index = CASE WHEN undelegate_block >= $1 AND undelegate_block < $2 THEN NULL ELSE undelegate_transaction_index END,
+            undelegate_log_index = CASE WHEN undelegate_block >= $1 AND undelegate_block < $2 THEN NULL ELSE undelegate_log_index END,
+            undelegate_block = CASE WHEN undelegate_block >= $1 AND undelegate_block < $2 THEN NULL ELSE undelegate_block END,
+            withdraw_transaction_index = CASE WHEN withdraw_block >= $1 AND withdraw_block < $2 THEN NULL ELSE withdraw_transaction_index END,
+            withdraw_log_index = CASE WHEN withdraw_block >= $1 AND withdraw_block < $2 THEN NULL ELSE withdraw_log_index END,
+            withdraw_block = CASE WHEN withdraw_block >= $1 AND withdraw_block < $2 THEN NULL ELSE withdraw_block END,
+            updated_at = CURRENT_TIMESTAMP
+        WHERE (undelegate_block >= $1 AND undelegate_block < $2)
+            OR (withdraw_block >= $1 AND withdraw_block < $2)
+        "#,
+    )
+    .bind(start)
+    .bind(end)
+    .execute(&mut **tx)
+    .await?;
+    sqlx::query(
+        "DELETE FROM pending_withdrawals WHERE undelegate_block IS NULL AND withdraw_block IS NULL",
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query(
+        r#"
+        UPDATE validators SET
+            auth_address = CASE WHEN created_block >= $1 AND created_block < $2 THEN NULL ELSE auth_address END,
+            created_block = CASE WHEN created_block >= $1 AND created_block < $2 THEN NULL ELSE created_block END,
+            commission = CASE WHEN commission_block >= $1 AND commission_block < $2 THEN NULL ELSE commission END,
+            commission_log_index = CASE WHEN commission_block >= $1 AND commission_block < $2 THEN NULL ELSE commission_log_index END,
+            commission_block = CASE WHEN commission_block >= $1 AND commission_block < $2 THEN NULL ELSE commission_block END,
+            flags = CASE WHEN flags_block >= $1 AND flags_block < $2 THEN NULL ELSE flags END,
+            flags_log_index = CASE WHEN flags_block >= $1 AND flags_block < $2 THEN NULL ELSE flags_log_index END,
+            flags_block = CASE WHEN flags_block >= $1 AND flags_block < $2 THEN NULL ELSE flags_block END,
+            updated_at = CURRENT_TIMESTAMP
+        WHERE (created_block >= $1 AND created_block < $2)
+            OR (commission_block >= $1 AND commission_block < $2)
+            OR (flags_block >= $1 AND flags_block < $2)
+        "#,
+    )
+    .bind(start)
+    .bind(end)
+    .execute(&mut **tx)
+    .await?;
+
+    // Events after the range would have set the cleared attributes themselves,
+    // so the last stored event of each validator is the one before the range.
+    sqlx::query(
+        r#"
+        UPDATE validators SET
+            auth_address = latest.auth_address,
+            created_block = latest.block_number
+        FROM (
+            SELECT DISTINCT ON (validator_id) validator_id, auth_address, block_number
+            FROM validator_created_events
+            ORDER BY validator_id, block_number DESC, log_index DESC NULLS LAST
+        ) latest
+        WHERE validators.validator_id = latest.validator_id
+            AND validators.created_block IS NULL
+        "#,
+    )
+    .execute(&mut **tx)
+    .await?;
+    sqlx::query(
+        r#"
+        UPDATE validators SET
+            commission = latest.commission,
+            commission_block = latest.block_number,
+            commission_log_index = latest.log_index
+        FROM (
+            SELECT DISTINCT ON (validator_id) validator_id, commission, block_number, log_index
+            FROM (
+                SELECT validator_id, commission, block_number, log_index
+                FROM validator_created_events
+                UNION ALL
+                SELECT validator_id, new_commission, block_number, log_index
+                FROM commission_changed_events
+            ) events
+            ORDER BY validator_id, block_number DESC, log_index DESC NULLS LAST
+        ) latest
+        WHERE validators.validator_id = latest.validator_id
+            AND validators.commission_block IS NULL
+        "#,
+    )
+    .execute(&mut **tx)
+    .await?;
+    sqlx::query(
+        r#"
+        UPDATE validators SET
+            flags = latest.flags,
+            flags_block = latest.block_number,
+            flags_log_index = latest.log_index
+        FROM (
+            SELECT DISTINCT ON (validator_id) validator_id, flags, block_number, log_index
+            FROM validator_status_changed_events
+            ORDER BY validator_id, block_number DESC, log_index DESC NULLS LAST
+        ) latest
+        WHERE validators.validator_id = latest.validator_id
+            AND validators.flags_block IS NULL
+        "#,
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query("DELETE FROM epochs WHERE start_block >= $1 AND start_block < $2")
+        .bind(start)
+        .bind(end)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(report)
+}
+
+/// Delay before the first retry of a dead-lettered batch, doubled after every
+/// failed attempt up to `DEAD_LETTER_MAX_BACKOFF_SECS`.
+const DEAD_LETTER_BACKOFF_SECS: i64 = 60;
+const DEAD_LETTER_MAX_BACKOFF_SECS: i64 = 3600;
+
+/// A batch from the `failed_batches` dead-letter queue.
+#[derive(Debug)]
+pub struct FailedBatch {
+    pub id: i64,
+    pub attempts: i32,
+    pub batch: BlockBatch,
+}
+
+/// Store a batch that could not be inserted so it can be replayed later.
+pub async fn spool_failed_batch(
+    pool: &PgPool,
+    batch: &BlockBatch,
+    error: &str,
+) -> Result<(), DbError> {
+    sqlx::query(
+        r#"
+        INSERT INTO failed_batches (first_block, last_block, batch, error)
+        VALUES ($1, $2, $3, $4)
+        "#,
+    )
+    .bind(batch.block_meta.first().map(|m| m.block_number as i64))
+    .bind(batch.block_meta.last().map(|m| m.block_number as i64))
+    .bind(Json(batch))
+    .bind(error)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Dead-lettered batches whose backoff has expired, oldest first. Parked
+/// batches are not due.
+pub async fn get_due_failed_batches(
+    pool: &PgPool,
+    limit: i64,
+) -> Result<Vec<FailedBatch>, DbError> {
+    let rows = sqlx::query_as::<_, (i64, i32, Json<BlockBatch>)>(
+        r#"
+        SELECT id, attempts, batch FROM failed_batches
+        WHERE next_attempt_at <= CURRENT_TIMESTAMP AND parked_at IS NULL
+        ORDER BY id
+        LIMIT $1
+        "#,
+    )
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(id, attempts, Json(batch))| FailedBatch {
+            id,
+            attempts,
+            batch,
         })
         .collect())
 }
+
+/// Remove a batch from the dead-letter queue after a successful replay.
+pub async fn delete_failed_batch(pool: &PgPool, id: i64) -> Result<(), DbError> {
+    sqlx::query("DELETE FROM failed_batches WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Record a failed replay and push the next attempt back exponentially. The
+/// batch is parked once it failed `max_attempts` replays. Returns whether it
+/// was parked.
+pub async fn defer_failed_batch(
+    pool: &PgPool,
+    id: i64,
+    error: &str,
+    max_attempts: u32,
+) -> Result<bool, DbError> {
+    let parked = sqlx::query_scalar::<_, bool>(
+        r#"
+        UPDATE failed_batches SET
+            attempts = attempts + 1,
+            error = $2,
+            next_attempt_at = CURRENT_TIMESTAMP + make_interval(
+                secs => LEAST($3 * POWER(2, LEAST(attempts, 30)), $4)
+            ),
+            parked_at = CASE WHEN attempts + 1 >= $5 THEN CURRENT_TIMESTAMP END
+        WHERE id = $1
+        RETURNING parked_at IS NOT NULL
+        "#,
+    )
+    .bind(id)
+    .bind(error)
+    .bind(DEAD_LETTER_BACKOFF_SECS)
+    .bind(DEAD_LETTER_MAX_BACKOFF_SECS)
+    .bind(max_attempts.min(i32::MAX as u32) as i32)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(parked.unwrap_or(false))
+}
+
+/// Number of batches in the dead-letter queue as `(waiting, parked)`, where
+/// waiting batches are still replayed.
+pub async fn get_failed_batch_counts(pool: &PgPool) -> Result<(u64, u64), DbError> {
+    let (waiting, parked) = sqlx::query_as::<_, (i64, i64)>(
+        r#"
+        SELECT COUNT(*) FILTER (WHERE parked_at IS NULL),
+               COUNT(*) FILTER (WHERE parked_at IS NOT NULL)
+        FROM failed_batches
+        "#,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok((waiting as u64, parked as u64))
+}
@@ -1,281 +1,1132 @@
-use sqlx::PgPool;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+
+use bigdecimal::{BigDecimal, num_bigint::Sign};
+use serde::Deserialize;
+use sqlx::query_builder::Separated;
+use sqlx::{PgPool, Postgres};
 use tokio::time::Duration;
+use tracing::{debug, warn};
+
+use crate::db::notifications::{BlockNotification, NOTIFICATION_CHANNEL};
+use crate::db::repository::{
+    BlockConflictStrategy, DbError, DeleteReport, delete_range_in_tx, set_checkpoint, upsert_blocks,
+};
+use crate::events::{self, BlockMeta, StakingEventType, TxMeta};
+
+/// Outcome of inserting a [`crate::BlockBatch`].
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct InsertReport {
+    /// `(inserted, total)` event counts per event type. Stored events updated
+    /// under [`ConflictStrategy::Upsert`] count as inserted.
+    pub event_counts: HashMap<StakingEventType, (u64, u64)>,
+    /// Number of delegations whose stake went negative after applying the
+    /// batch and was clamped to zero.
+    pub negative_stakes: u64,
+    /// Time spent on each table that had rows to insert.
+    pub table_stats: Vec<TableInsertStats>,
+    /// Newly stored undecoded events per hex-encoded `topic0`.
+    pub unknown_events: HashMap<String, u64>,
+    /// Rows deleted first because the batch replaces them, see
+    /// [`crate::BlockBatch::replaces`].
+    pub replaced: DeleteReport,
+    /// Stored blocks whose hash or timestamp was overwritten under
+    /// [`BlockConflictStrategy::Upsert`].
+    pub blocks_updated: u64,
+}
 
-use crate::db::repository::DbError;
-use crate::events::{self, BlockMeta, StakingEventType};
+/// Rows written to one table by an insert, and how long it took.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableInsertStats {
+    pub table: &'static str,
+    pub rows: u64,
+    pub elapsed: Duration,
+}
 
-async fn insert_delegate_events_in_tx(
-    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
-    events: &[events::DelegateEvent],
-) -> Result<(u64, u64), DbError> {
-    let total = events.len() as u64;
-    if events.is_empty() {
-        return Ok((0, 0));
+impl InsertReport {
+    fn record(&mut self, event_type: StakingEventType, counts: (u64, u64), elapsed: Duration) {
+        self.event_counts.insert(event_type, counts);
+        if counts.1 > 0 {
+            self.table_stats.push(TableInsertStats {
+                table: event_type.table_name(),
+                rows: counts.0,
+                elapsed,
+            });
+        }
     }
+}
 
-    let mut query_builder = sqlx::QueryBuilder::new(
-        "INSERT INTO delegate_events (val_id, delegator, amount, activation_epoch, block_number, transaction_hash, transaction_index) ",
-    );
+/// Net stake change per `(val_id, delegator)` and the highest block contributing to it.
+type StakeDeltas = HashMap<(i64, String), (BigDecimal, i64)>;
+
+fn add_stake_delta(
+    deltas: &mut StakeDeltas,
+    val_id: i64,
+    delegator: String,
+    amount: BigDecimal,
+    block_number: i64,
+) {
+    let entry = deltas
+        .entry((val_id, delegator))
+        .or_insert_with(|| (BigDecimal::from(0), block_number));
+    entry.0 += amount;
+    entry.1 = entry.1.max(block_number);
+}
 
-    query_builder.push_values(events, |mut b, event| {
-        b.push_bind(event.val_id as i64)
-            .push_bind(&event.delegator)
-            .push_bind(&event.amount)
-            .push_bind(event.activation_epoch as i64)
-            .push_bind(event.block_meta.block_number as i64)
-            .push_bind(&event.tx_meta.transaction_hash)
-            .push_bind(event.tx_meta.transaction_index as i64);
-    });
+/// Unique key of an event row: `(validator id, transaction hash, block number)`.
+/// The validator id is `None` for tables whose unique constraint does not include it.
+type EventKey = (Option<i64>, String, i64);
+
+/// What to do with events that are already stored when a batch is inserted.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicatePolicy {
+    /// Skip them, replays are expected after restarts and backfills.
+    #[default]
+    Ignore,
+    /// Fail the insert with [`DbError::DuplicateEvent`].
+    Error,
+    /// Skip them if the stored row has the same values, and fail the insert
+    /// with [`DbError::DuplicateEvent`] otherwise.
+    Verify,
+}
 
-    query_builder.push(" ON CONFLICT (val_id, transaction_hash) DO NOTHING");
+/// What an insert does with an event whose key is already stored with
+/// different values.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictStrategy {
+    /// Keep the stored row.
+    #[default]
+    Ignore,
+    /// Overwrite the stored row, e.g. when the contract emits corrective
+    /// events. Stakes in `delegations` follow the new amount. Only allowed for
+    /// tables without other derived state, see
+    /// [`ConflictStrategies::upserts_with_derived_state`]. Requires
+    /// [`DuplicatePolicy::Ignore`].
+    Upsert,
+}
 
-    let res = query_builder.build().execute(&mut **tx).await?;
+/// The [`ConflictStrategy`] of each event table.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ConflictStrategies {
+    pub delegate: ConflictStrategy,
+    pub undelegate: ConflictStrategy,
+    pub withdraw: ConflictStrategy,
+    pub claim_rewards: ConflictStrategy,
+    pub validator_rewarded: ConflictStrategy,
+    pub epoch_changed: ConflictStrategy,
+    pub validator_created: ConflictStrategy,
+    pub validator_status_changed: ConflictStrategy,
+    pub commission_changed: ConflictStrategy,
+}
 
-    Ok((res.rows_affected(), total))
+impl ConflictStrategies {
+    /// The strategy of `event_type`'s table. Undecoded events are never updated.
+    pub fn get(&self, event_type: StakingEventType) -> ConflictStrategy {
+        match event_type {
+            StakingEventType::Delegate => self.delegate,
+            StakingEventType::Undelegate => self.undelegate,
+            StakingEventType::Withdraw => self.withdraw,
+            StakingEventType::ClaimRewards => self.claim_rewards,
+            StakingEventType::ValidatorRewarded => self.validator_rewarded,
+            StakingEventType::EpochChanged => self.epoch_changed,
+            StakingEventType::ValidatorCreated => self.validator_created,
+            StakingEventType::ValidatorStatusChanged => self.validator_status_changed,
+            StakingEventType::CommissionChanged => self.commission_changed,
+            StakingEventType::Unknown => ConflictStrategy::Ignore,
+        }
+    }
+
+    /// Whether any table uses [`ConflictStrategy::Upsert`].
+    pub fn upserts_any(&self) -> bool {
+        [
+            self.delegate,
+            self.undelegate,
+            self.withdraw,
+            self.claim_rewards,
+            self.validator_rewarded,
+            self.epoch_changed,
+            self.validator_created,
+            self.validator_status_changed,
+            self.commission_changed,
+        ]
+        .contains(&ConflictStrategy::Upsert)
+    }
+
+    /// Tables using [`ConflictStrategy::Upsert`] whose events feed derived state
+    /// that an overwrite would not correct: pending withdrawals, validator
+    /// commissions and flags, and epochs.
+    pub fn upserts_with_derived_state(&self) -> Vec<&'static str> {
+        [
+            ("undelegate", self.undelegate),
+            ("withdraw", self.withdraw),
+            ("epoch_changed", self.epoch_changed),
+            ("validator_created", self.validator_created),
+            ("validator_status_changed", self.validator_status_changed),
+            ("commission_changed", self.commission_changed),
+        ]
+        .into_iter()
+        .filter(|(_, strategy)| *strategy == ConflictStrategy::Upsert)
+        .map(|(table, _)| table)
+        .collect()
+    }
 }
 
-async fn insert_undelegate_events_in_tx(
-    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
-    events: &[events::UndelegateEvent],
-) -> Result<(u64, u64), DbError> {
-    let total = events.len() as u64;
-    if events.is_empty() {
-        return Ok((0, 0));
+/// An event stored in its own table, keyed by [`EventKey`].
+trait EventRow: Clone {
+    const EVENT_TYPE: StakingEventType;
+    /// Column of the validator id, if it is part of the table's unique key.
+    const ID_COLUMN: Option<&'static str>;
+    /// Columns written on insert, in the order [`EventRow::push_values`] binds them.
+    const COLUMNS: &'static str;
+
+    fn key(&self) -> EventKey;
+    fn metas(&self) -> (&BlockMeta, &TxMeta);
+    fn push_values<'args>(&'args self, b: Separated<'_, 'args, Postgres, &'static str>);
+}
+
+impl EventRow for events::DelegateEvent {
+    const EVENT_TYPE: StakingEventType = StakingEventType::Delegate;
+    const ID_COLUMN: Option<&'static str> = Some("val_id");
+    const COLUMNS: &'static str = "val_id, delegator, amount, activation_epoch, block_number, transaction_hash, transaction_index, log_index";
+
+    fn key(&self) -> EventKey {
+        (
+            Some(self.val_id as i64),
+            self.tx_meta.transaction_hash.clone(),
+            self.block_meta.block_number as i64,
+        )
     }
 
-    let mut query_builder = sqlx::QueryBuilder::new(
-        "INSERT INTO undelegate_events (val_id, delegator, withdrawal_id, amount, activation_epoch, block_number, transaction_hash, transaction_index) ",
-    );
+    fn metas(&self) -> (&BlockMeta, &TxMeta) {
+        (&self.block_meta, &self.tx_meta)
+    }
 
-    query_builder.push_values(events, |mut b, event| {
-        b.push_bind(event.val_id as i64)
-            .push_bind(&event.delegator)
-            .push_bind(event.withdrawal_id)
-            .push_bind(&event.amount)
-            .push_bind(event.activation_epoch as i64)
-            .push_bind(event.block_meta.block_number as i64)
-            .push_bind(&event.tx_meta.transaction_hash)
-            .push_bind(event.tx_meta.transaction_index as i64);
-    });
+    fn push_values<'args>(&'args self, mut b: Separated<'_, 'args, Postgres, &'static str>) {
+        b.push_bind(self.val_id as i64)
+            .push_bind(&self.delegator)
+            .push_bind(&self.amount)
+            .push_bind(self.activation_epoch as i64)
+            .push_bind(self.block_meta.block_number as i64)
+            .push_bind(&self.tx_meta.transaction_hash)
+            .push_bind(self.tx_meta.transaction_index as i64)
+            .push_bind(self.tx_meta.log_index as i64);
+    }
+}
 
-    query_builder.push(" ON CONFLICT (val_id, transaction_hash) DO NOTHING");
+impl EventRow for events::UndelegateEvent {
+    const EVENT_TYPE: StakingEventType = StakingEventType::Undelegate;
+    const ID_COLUMN: Option<&'static str> = Some("val_id");
+    const COLUMNS: &'static str = "val_id, delegator, withdrawal_id, amount, activation_epoch, block_number, transaction_hash, transaction_index, log_index";
 
-    let res = query_builder.build().execute(&mut **tx).await?;
+    fn key(&self) -> EventKey {
+        (
+            Some(self.val_id as i64),
+            self.tx_meta.transaction_hash.clone(),
+            self.block_meta.block_number as i64,
+        )
+    }
 
-    Ok((res.rows_affected(), total))
+    fn metas(&self) -> (&BlockMeta, &TxMeta) {
+        (&self.block_meta, &self.tx_meta)
+    }
+
+    fn push_values<'args>(&'args self, mut b: Separated<'_, 'args, Postgres, &'static str>) {
+        b.push_bind(self.val_id as i64)
+            .push_bind(&self.delegator)
+            .push_bind(self.withdrawal_id)
+            .push_bind(&self.amount)
+            .push_bind(self.activation_epoch as i64)
+            .push_bind(self.block_meta.block_number as i64)
+            .push_bind(&self.tx_meta.transaction_hash)
+            .push_bind(self.tx_meta.transaction_index as i64)
+            .push_bind(self.tx_meta.log_index as i64);
+    }
 }
 
-async fn insert_withdraw_events_in_tx(
-    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
-    events: &[events::WithdrawEvent],
-) -> Result<(u64, u64), DbError> {
-    let total = events.len() as u64;
-    if events.is_empty() {
-        return Ok((0, 0));
+impl EventRow for events::WithdrawEvent {
+    const EVENT_TYPE: StakingEventType = StakingEventType::Withdraw;
+    const ID_COLUMN: Option<&'static str> = Some("val_id");
+    const COLUMNS: &'static str = "val_id, delegator, withdrawal_id, amount, activation_epoch, block_number, transaction_hash, transaction_index, log_index";
+
+    fn key(&self) -> EventKey {
+        (
+            Some(self.val_id as i64),
+            self.tx_meta.transaction_hash.clone(),
+            self.block_meta.block_number as i64,
+        )
     }
 
-    let mut query_builder = sqlx::QueryBuilder::new(
-        "INSERT INTO withdraw_events (val_id, delegator, withdrawal_id, amount, activation_epoch, block_number, transaction_hash, transaction_index) ",
-    );
+    fn metas(&self) -> (&BlockMeta, &TxMeta) {
+        (&self.block_meta, &self.tx_meta)
+    }
 
-    query_builder.push_values(events, |mut b, event| {
-        b.push_bind(event.val_id as i64)
-            .push_bind(&event.delegator)
-            .push_bind(event.withdrawal_id)
-            .push_bind(&event.amount)
-            .push_bind(event.activation_epoch as i64)
-            .push_bind(event.block_meta.block_number as i64)
-            .push_bind(&event.tx_meta.transaction_hash)
-            .push_bind(event.tx_meta.transaction_index as i64);
-    });
+    fn push_values<'args>(&'args self, mut b: Separated<'_, 'args, Postgres, &'static str>) {
+        b.push_bind(self.val_id as i64)
+            .push_bind(&self.delegator)
+            .push_bind(self.withdrawal_id)
+            .push_bind(&self.amount)
+            .push_bind(self.activation_epoch as i64)
+            .push_bind(self.block_meta.block_number as i64)
+            .push_bind(&self.tx_meta.transaction_hash)
+            .push_bind(self.tx_meta.transaction_index as i64)
+            .push_bind(self.tx_meta.log_index as i64);
+    }
+}
 
-    query_builder.push(" ON CONFLICT (val_id, transaction_hash) DO NOTHING");
+impl EventRow for events::ClaimRewardsEvent {
+    const EVENT_TYPE: StakingEventType = StakingEventType::ClaimRewards;
+    const ID_COLUMN: Option<&'static str> = Some("val_id");
+    const COLUMNS: &'static str = "val_id, delegator, amount, epoch, block_number, transaction_hash, transaction_index, log_index";
 
-    let res = query_builder.build().execute(&mut **tx).await?;
+    fn key(&self) -> EventKey {
+        (
+            Some(self.val_id as i64),
+            self.tx_meta.transaction_hash.clone(),
+            self.block_meta.block_number as i64,
+        )
+    }
 
-    Ok((res.rows_affected(), total))
+    fn metas(&self) -> (&BlockMeta, &TxMeta) {
+        (&self.block_meta, &self.tx_meta)
+    }
+
+    fn push_values<'args>(&'args self, mut b: Separated<'_, 'args, Postgres, &'static str>) {
+        b.push_bind(self.val_id as i64)
+            .push_bind(&self.delegator)
+            .push_bind(&self.amount)
+            .push_bind(self.epoch as i64)
+            .push_bind(self.block_meta.block_number as i64)
+            .push_bind(&self.tx_meta.transaction_hash)
+            .push_bind(self.tx_meta.transaction_index as i64)
+            .push_bind(self.tx_meta.log_index as i64);
+    }
 }
 
-async fn insert_claim_rewards_events_in_tx(
-    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
-    events: &[events::ClaimRewardsEvent],
-) -> Result<(u64, u64), DbError> {
-    let total = events.len() as u64;
-    if events.is_empty() {
-        return Ok((0, 0));
+impl EventRow for events::ValidatorRewardedEvent {
+    const EVENT_TYPE: StakingEventType = StakingEventType::ValidatorRewarded;
+    const ID_COLUMN: Option<&'static str> = None;
+    const COLUMNS: &'static str = "validator_id, from_address, amount, epoch, block_number, transaction_hash, transaction_index, log_index";
+
+    fn key(&self) -> EventKey {
+        (
+            None,
+            self.tx_meta.transaction_hash.clone(),
+            self.block_meta.block_number as i64,
+        )
     }
 
-    let mut query_builder = sqlx::QueryBuilder::new(
-        "INSERT INTO claim_rewards_events (val_id, delegator, amount, epoch, block_number, transaction_hash, transaction_index) ",
+    fn metas(&self) -> (&BlockMeta, &TxMeta) {
+        (&self.block_meta, &self.tx_meta)
+    }
+
+    fn push_values<'args>(&'args self, mut b: Separated<'_, 'args, Postgres, &'static str>) {
+        b.push_bind(self.validator_id as i64)
+            .push_bind(&self.from)
+            .push_bind(&self.amount)
+            .push_bind(self.epoch as i64)
+            .push_bind(self.block_meta.block_number as i64)
+            .push_bind(&self.tx_meta.transaction_hash)
+            .push_bind(self.tx_meta.transaction_index as i64)
+            .push_bind(self.tx_meta.log_index as i64);
+    }
+}
+
+impl EventRow for events::EpochChangedEvent {
+    const EVENT_TYPE: StakingEventType = StakingEventType::EpochChanged;
+    const ID_COLUMN: Option<&'static str> = None;
+    const COLUMNS: &'static str =
+        "old_epoch, new_epoch, block_number, transaction_hash, transaction_index, log_index";
+
+    fn key(&self) -> EventKey {
+        (
+            None,
+            self.tx_meta.transaction_hash.clone(),
+            self.block_meta.block_number as i64,
+        )
+    }
+
+    fn metas(&self) -> (&BlockMeta, &TxMeta) {
+        (&self.block_meta, &self.tx_meta)
+    }
+
+    fn push_values<'args>(&'args self, mut b: Separated<'_, 'args, Postgres, &'static str>) {
+        b.push_bind(self.old_epoch as i64)
+            .push_bind(self.new_epoch as i64)
+            .push_bind(self.block_meta.block_number as i64)
+            .push_bind(&self.tx_meta.transaction_hash)
+            .push_bind(self.tx_meta.transaction_index as i64)
+            .push_bind(self.tx_meta.log_index as i64);
+    }
+}
+
+impl EventRow for events::ValidatorCreatedEvent {
+    const EVENT_TYPE: StakingEventType = StakingEventType::ValidatorCreated;
+    const ID_COLUMN: Option<&'static str> = None;
+    const COLUMNS: &'static str = "validator_id, auth_address, commission, block_number, transaction_hash, transaction_index, log_index";
+
+    fn key(&self) -> EventKey {
+        (
+            None,
+            self.tx_meta.transaction_hash.clone(),
+            self.block_meta.block_number as i64,
+        )
+    }
+
+    fn metas(&self) -> (&BlockMeta, &TxMeta) {
+        (&self.block_meta, &self.tx_meta)
+    }
+
+    fn push_values<'args>(&'args self, mut b: Separated<'_, 'args, Postgres, &'static str>) {
+        b.push_bind(self.validator_id as i64)
+            .push_bind(&self.auth_address)
+            .push_bind(&self.commission)
+            .push_bind(self.block_meta.block_number as i64)
+            .push_bind(&self.tx_meta.transaction_hash)
+            .push_bind(self.tx_meta.transaction_index as i64)
+            .push_bind(self.tx_meta.log_index as i64);
+    }
+}
+
+impl EventRow for events::ValidatorStatusChangedEvent {
+    const EVENT_TYPE: StakingEventType = StakingEventType::ValidatorStatusChanged;
+    const ID_COLUMN: Option<&'static str> = Some("validator_id");
+    const COLUMNS: &'static str =
+        "validator_id, flags, block_number, transaction_hash, transaction_index, log_index";
+
+    fn key(&self) -> EventKey {
+        (
+            Some(self.validator_id as i64),
+            self.tx_meta.transaction_hash.clone(),
+            self.block_meta.block_number as i64,
+        )
+    }
+
+    fn metas(&self) -> (&BlockMeta, &TxMeta) {
+        (&self.block_meta, &self.tx_meta)
+    }
+
+    fn push_values<'args>(&'args self, mut b: Separated<'_, 'args, Postgres, &'static str>) {
+        b.push_bind(self.validator_id as i64)
+            .push_bind(self.flags as i64)
+            .push_bind(self.block_meta.block_number as i64)
+            .push_bind(&self.tx_meta.transaction_hash)
+            .push_bind(self.tx_meta.transaction_index as i64)
+            .push_bind(self.tx_meta.log_index as i64);
+    }
+}
+
+impl EventRow for events::CommissionChangedEvent {
+    const EVENT_TYPE: StakingEventType = StakingEventType::CommissionChanged;
+    const ID_COLUMN: Option<&'static str> = Some("validator_id");
+    const COLUMNS: &'static str = "validator_id, old_commission, new_commission, block_number, transaction_hash, transaction_index, log_index";
+
+    fn key(&self) -> EventKey {
+        (
+            Some(self.validator_id as i64),
+            self.tx_meta.transaction_hash.clone(),
+            self.block_meta.block_number as i64,
+        )
+    }
+
+    fn metas(&self) -> (&BlockMeta, &TxMeta) {
+        (&self.block_meta, &self.tx_meta)
+    }
+
+    fn push_values<'args>(&'args self, mut b: Separated<'_, 'args, Postgres, &'static str>) {
+        b.push_bind(self.validator_id as i64)
+            .push_bind(&self.old_commission)
+            .push_bind(&self.new_commission)
+            .push_bind(self.block_meta.block_number as i64)
+            .push_bind(&self.tx_meta.transaction_hash)
+            .push_bind(self.tx_meta.transaction_index as i64)
+            .push_bind(self.tx_meta.log_index as i64);
+    }
+}
+
+/// Columns of the unique key of `T`'s table.
+fn key_columns<T: EventRow>() -> String {
+    match T::ID_COLUMN {
+        Some(id_column) => format!("{id_column}, transaction_hash, block_number"),
+        None => "transaction_hash, block_number".to_string(),
+    }
+}
+
+/// The `ON CONFLICT` clause of inserts into `T`'s table. Under
+/// [`ConflictStrategy::Upsert`] only rows with different values are written.
+fn on_conflict_clause<T: EventRow>(strategy: ConflictStrategy) -> String {
+    let key_columns = key_columns::<T>();
+    match strategy {
+        ConflictStrategy::Ignore => format!(" ON CONFLICT ({key_columns}) DO NOTHING"),
+        ConflictStrategy::Upsert => {
+            let keys: Vec<&str> = key_columns.split(", ").collect();
+            let columns: Vec<&str> = T::COLUMNS
+                .split(", ")
+                .filter(|column| !keys.contains(column))
+                .collect();
+            let list = |prefix: &str| {
+                columns
+                    .iter()
+                    .map(|column| format!("{prefix}{column}"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            let assignments = columns
+                .iter()
+                .map(|column| format!("{column} = EXCLUDED.{column}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!(
+                " ON CONFLICT ({key_columns}) DO UPDATE SET {assignments} WHERE ({}) IS DISTINCT FROM ({})",
+                list(&format!("{}.", T::EVENT_TYPE.table_name())),
+                list("EXCLUDED."),
+            )
+        }
+    }
+}
+
+/// Keep only the last of the events with the same key. A row can only be
+/// updated once per statement.
+fn last_per_key<T: EventRow>(events: Cow<'_, [T]>) -> Cow<'_, [T]> {
+    let mut last: HashMap<EventKey, usize> = HashMap::new();
+    for (index, event) in events.iter().enumerate() {
+        last.insert(event.key(), index);
+    }
+    if last.len() == events.len() {
+        return events;
+    }
+    events
+        .iter()
+        .enumerate()
+        .filter(|(index, event)| last[&event.key()] == *index)
+        .map(|(_, event)| event.clone())
+        .collect()
+}
+
+fn duplicate_event_error<T: EventRow>(event: &T) -> DbError {
+    let (block_meta, tx_meta) = event.metas();
+    DbError::DuplicateEvent {
+        event_type: T::EVENT_TYPE,
+        block_meta: block_meta.clone(),
+        tx_meta: tx_meta.clone(),
+    }
+}
+
+/// Keys of those of `events`, which are all stored already, whose stored row
+/// has different values.
+async fn changed_event_keys_in_tx<T: EventRow>(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    events: &[&T],
+) -> Result<HashSet<EventKey>, DbError> {
+    let table = T::EVENT_TYPE.table_name();
+    let key_columns = key_columns::<T>();
+    let columns = |alias: &str| {
+        T::COLUMNS
+            .split(", ")
+            .map(|column| format!("{alias}.{column}"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    let mut query_builder = sqlx::QueryBuilder::new(format!(
+        "SELECT {}, transaction_hash, block_number FROM {table} AS stored JOIN (",
+        T::ID_COLUMN.unwrap_or("NULL::BIGINT")
+    ));
+    query_builder.push_values(events.iter(), |b, event| event.push_values(b));
+    query_builder.push(format!(
+        ") AS incoming ({}) USING ({key_columns}) WHERE ({}) IS DISTINCT FROM ({})",
+        T::COLUMNS,
+        columns("stored"),
+        columns("incoming")
+    ));
+
+    Ok(query_builder
+        .build_query_as::<EventKey>()
+        .fetch_all(&mut **tx)
+        .await?
+        .into_iter()
+        .collect())
+}
+
+/// Drop events that are already stored before inserting them, so replayed
+/// blocks do not cost a write each. Returns the remaining events and the
+/// number of events dropped.
+///
+/// Under [`DuplicatePolicy::Error`] a stored event fails the insert instead,
+/// and under [`DuplicatePolicy::Verify`] a stored event with different values.
+/// Under [`ConflictStrategy::Upsert`] stored events with different values are
+/// kept, to be written over the stored rows.
+async fn drop_existing_events_in_tx<'a, T: EventRow>(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    events: &'a [T],
+    policy: DuplicatePolicy,
+    strategy: ConflictStrategy,
+) -> Result<(Cow<'a, [T]>, u64), DbError> {
+    let table = T::EVENT_TYPE.table_name();
+    let keys: Vec<EventKey> = events.iter().map(T::key).collect();
+    let (Some(min_block), Some(max_block)) = (
+        keys.iter().map(|(_, _, block)| *block).min(),
+        keys.iter().map(|(_, _, block)| *block).max(),
+    ) else {
+        return Ok((Cow::Borrowed(events), 0));
+    };
+    let hashes: Vec<&str> = keys.iter().map(|(_, hash, _)| hash.as_str()).collect();
+
+    let query = format!(
+        "SELECT {}, transaction_hash, block_number FROM {table} \
+         WHERE block_number BETWEEN $1 AND $2 AND transaction_hash = ANY($3)",
+        T::ID_COLUMN.unwrap_or("NULL::BIGINT")
     );
+    let existing: HashSet<EventKey> = sqlx::query_as::<_, EventKey>(&query)
+        .bind(min_block)
+        .bind(max_block)
+        .bind(hashes)
+        .fetch_all(&mut **tx)
+        .await?
+        .into_iter()
+        .collect();
+
+    if existing.is_empty() {
+        return Ok((Cow::Borrowed(events), 0));
+    }
 
-    query_builder.push_values(events, |mut b, event| {
-        b.push_bind(event.val_id as i64)
-            .push_bind(&event.delegator)
-            .push_bind(&event.amount)
-            .push_bind(event.epoch as i64)
-            .push_bind(event.block_meta.block_number as i64)
-            .push_bind(&event.tx_meta.transaction_hash)
-            .push_bind(event.tx_meta.transaction_index as i64);
-    });
+    let (duplicates, remaining): (Vec<_>, Vec<_>) = events
+        .iter()
+        .zip(&keys)
+        .partition(|(_, key)| existing.contains(*key));
+    let duplicates: Vec<&T> = duplicates.into_iter().map(|(event, _)| event).collect();
+    match policy {
+        DuplicatePolicy::Ignore => {}
+        DuplicatePolicy::Error => return Err(duplicate_event_error(duplicates[0])),
+        DuplicatePolicy::Verify => {
+            let changed = changed_event_keys_in_tx(tx, &duplicates).await?;
+            if let Some(event) = duplicates
+                .iter()
+                .find(|event| changed.contains(&event.key()))
+            {
+                return Err(duplicate_event_error(*event));
+            }
+        }
+    }
 
-    query_builder.push(" ON CONFLICT (val_id, transaction_hash) DO NOTHING");
+    let changed = match strategy {
+        ConflictStrategy::Ignore => HashSet::new(),
+        ConflictStrategy::Upsert => changed_event_keys_in_tx(tx, &duplicates).await?,
+    };
+    let (changed, duplicates): (Vec<&T>, Vec<&T>) = duplicates
+        .into_iter()
+        .partition(|event| changed.contains(&event.key()));
+    let remaining: Vec<T> = remaining
+        .into_iter()
+        .map(|(event, _)| event)
+        .chain(changed)
+        .cloned()
+        .collect();
+    let dropped = duplicates.len() as u64;
+    debug!("Skipping {dropped} events already stored in {table}");
+
+    Ok((Cow::Owned(remaining), dropped))
+}
 
-    let res = query_builder.build().execute(&mut **tx).await?;
+/// Start an insert of `events` into their table.
+fn insert_query<T: EventRow>(events: &[T]) -> sqlx::QueryBuilder<'_, Postgres> {
+    let mut query_builder = sqlx::QueryBuilder::new(format!(
+        "INSERT INTO {} ({}) ",
+        T::EVENT_TYPE.table_name(),
+        T::COLUMNS
+    ));
+    query_builder.push_values(events.iter(), |b, event| event.push_values(b));
+    query_builder
+}
 
-    Ok((res.rows_affected(), total))
+/// `(delegator, amount)` of the stored rows of `events`, which an update
+/// under [`ConflictStrategy::Upsert`] replaces.
+async fn stored_amounts_in_tx<T: EventRow>(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    events: &[T],
+) -> Result<HashMap<EventKey, (String, BigDecimal)>, DbError> {
+    let keys: HashSet<EventKey> = events.iter().map(T::key).collect();
+    let (Some(min_block), Some(max_block)) = (
+        keys.iter().map(|(_, _, block)| *block).min(),
+        keys.iter().map(|(_, _, block)| *block).max(),
+    ) else {
+        return Ok(HashMap::new());
+    };
+    let hashes: Vec<&str> = keys.iter().map(|(_, hash, _)| hash.as_str()).collect();
+
+    let query = format!(
+        "SELECT val_id, transaction_hash, block_number, delegator, amount FROM {} \
+         WHERE block_number BETWEEN $1 AND $2 AND transaction_hash = ANY($3)",
+        T::EVENT_TYPE.table_name()
+    );
+    let rows = sqlx::query_as::<_, (Option<i64>, String, i64, String, BigDecimal)>(&query)
+        .bind(min_block)
+        .bind(max_block)
+        .bind(hashes)
+        .fetch_all(&mut **tx)
+        .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(val_id, hash, block, delegator, amount)| {
+            ((val_id, hash, block), (delegator, amount))
+        })
+        .filter(|(key, _)| keys.contains(key))
+        .collect())
 }
 
-async fn insert_validator_rewarded_events_in_tx(
+/// Insert events that change the stake of their delegator, adding `sign`
+/// times their amount to `stake_deltas`.
+async fn insert_stake_events_in_tx<T: EventRow>(
     tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
-    events: &[events::ValidatorRewardedEvent],
+    events: &[T],
+    stake_deltas: &mut StakeDeltas,
+    sign: Sign,
+    policy: DuplicatePolicy,
+    strategy: ConflictStrategy,
 ) -> Result<(u64, u64), DbError> {
-    let total = events.len() as u64;
     if events.is_empty() {
         return Ok((0, 0));
     }
+    let (events, known) = drop_existing_events_in_tx(tx, events, policy, strategy).await?;
+    let total = events.len() as u64 + known;
+    if events.is_empty() {
+        return Ok((0, total));
+    }
+    let (events, stored) = match strategy {
+        ConflictStrategy::Ignore => (events, HashMap::new()),
+        ConflictStrategy::Upsert => {
+            let events = last_per_key(events);
+            let stored = stored_amounts_in_tx(tx, &events).await?;
+            (events, stored)
+        }
+    };
+
+    let mut query_builder = insert_query(&events);
+
+    // Only rows that were actually written contribute to the stake, so that
+    // replaying duplicate events does not count them twice.
+    query_builder.push(on_conflict_clause::<T>(strategy));
+    query_builder.push(" RETURNING val_id, delegator, amount, block_number, transaction_hash");
+
+    let written = query_builder
+        .build_query_as::<(i64, String, BigDecimal, i64, String)>()
+        .fetch_all(&mut **tx)
+        .await?;
+
+    let signed = |amount: BigDecimal| match sign {
+        Sign::Minus => -amount,
+        _ => amount,
+    };
+    let rows_affected = written.len() as u64;
+    for (val_id, delegator, amount, block_number, hash) in written {
+        // An updated row takes back the amount of the row it replaced.
+        if let Some((old_delegator, old_amount)) = stored.get(&(Some(val_id), hash, block_number)) {
+            add_stake_delta(
+                stake_deltas,
+                val_id,
+                old_delegator.clone(),
+                -signed(old_amount.clone()),
+                block_number,
+            );
+        }
+        add_stake_delta(
+            stake_deltas,
+            val_id,
+            delegator,
+            signed(amount),
+            block_number,
+        );
+    }
 
-    let mut query_builder = sqlx::QueryBuilder::new(
-        "INSERT INTO validator_rewarded_events (validator_id, from_address, amount, epoch, block_number, transaction_hash, transaction_index) ",
-    );
+    Ok((rows_affected, total))
+}
 
-    query_builder.push_values(events, |mut b, event| {
-        b.push_bind(event.validator_id as i64)
-            .push_bind(&event.from)
-            .push_bind(&event.amount)
-            .push_bind(event.epoch as i64)
-            .push_bind(event.block_meta.block_number as i64)
-            .push_bind(&event.tx_meta.transaction_hash)
-            .push_bind(event.tx_meta.transaction_index as i64);
-    });
+/// Insert events of a table that has no derived state to update.
+async fn insert_plain_events_in_tx<T: EventRow>(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    events: &[T],
+    policy: DuplicatePolicy,
+    strategy: ConflictStrategy,
+) -> Result<(u64, u64), DbError> {
+    if events.is_empty() {
+        return Ok((0, 0));
+    }
+    let (events, known) = drop_existing_events_in_tx(tx, events, policy, strategy).await?;
+    let total = events.len() as u64 + known;
+    if events.is_empty() {
+        return Ok((0, total));
+    }
+    let events = match strategy {
+        ConflictStrategy::Ignore => events,
+        ConflictStrategy::Upsert => last_per_key(events),
+    };
+
+    let mut query_builder = insert_query(&events);
 
-    query_builder.push(" ON CONFLICT (transaction_hash) DO NOTHING");
+    query_builder.push(on_conflict_clause::<T>(strategy));
 
     let res = query_builder.build().execute(&mut **tx).await?;
 
     Ok((res.rows_affected(), total))
 }
 
-async fn insert_epoch_changed_events_in_tx(
+/// Store undecoded events. Their `topic0` counts are added to
+/// `report.unknown_events` for the rows actually inserted.
+async fn insert_raw_events_in_tx(
     tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
-    events: &[events::EpochChangedEvent],
+    events: &[events::RawEvent],
+    report: &mut InsertReport,
 ) -> Result<(u64, u64), DbError> {
-    let total = events.len() as u64;
     if events.is_empty() {
         return Ok((0, 0));
     }
 
     let mut query_builder = sqlx::QueryBuilder::new(
-        "INSERT INTO epoch_changed_events (old_epoch, new_epoch, block_number, transaction_hash, transaction_index) ",
+        "INSERT INTO raw_events (topic0, topics, data, block_number, transaction_hash, transaction_index, log_index) ",
     );
 
-    query_builder.push_values(events, |mut b, event| {
-        b.push_bind(event.old_epoch as i64)
-            .push_bind(event.new_epoch as i64)
+    query_builder.push_values(events.iter(), |mut b, event| {
+        b.push_bind(&event.topic0)
+            .push_bind(&event.topics)
+            .push_bind(&event.data)
             .push_bind(event.block_meta.block_number as i64)
             .push_bind(&event.tx_meta.transaction_hash)
-            .push_bind(event.tx_meta.transaction_index as i64);
+            .push_bind(event.tx_meta.transaction_index as i64)
+            .push_bind(event.tx_meta.log_index as i64);
     });
 
-    query_builder.push(" ON CONFLICT (transaction_hash) DO NOTHING");
+    query_builder.push(
+        " ON CONFLICT (block_number, transaction_hash, log_index) DO NOTHING RETURNING topic0",
+    );
 
-    let res = query_builder.build().execute(&mut **tx).await?;
+    let inserted = query_builder
+        .build_query_scalar::<String>()
+        .fetch_all(&mut **tx)
+        .await?;
+    let rows = inserted.len() as u64;
+    for topic0 in inserted {
+        *report.unknown_events.entry(topic0).or_default() += 1;
+    }
 
-    Ok((res.rows_affected(), total))
+    Ok((rows, events.len() as u64))
 }
 
-async fn insert_validator_created_events_in_tx(
+/// Apply the net stake changes of newly inserted events to the `delegations` table.
+///
+/// A negative stake after the update indicates missing history or an anomaly,
+/// e.g. an `Undelegate` indexed before its `Delegate` is backfilled. It is
+/// logged, counted and clamped to zero, so the amount that went below zero is
+/// lost until the range is indexed again.
+async fn update_delegations_in_tx(
     tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
-    events: &[events::ValidatorCreatedEvent],
-) -> Result<(u64, u64), DbError> {
-    let total = events.len() as u64;
-    if events.is_empty() {
-        return Ok((0, 0));
+    stake_deltas: StakeDeltas,
+) -> Result<u64, DbError> {
+    if stake_deltas.is_empty() {
+        return Ok(0);
     }
 
     let mut query_builder = sqlx::QueryBuilder::new(
-        "INSERT INTO validator_created_events (validator_id, auth_address, commission, block_number, transaction_hash, transaction_index) ",
+        "INSERT INTO delegations (val_id, delegator, stake, updated_block) ",
     );
 
-    query_builder.push_values(events, |mut b, event| {
-        b.push_bind(event.validator_id as i64)
-            .push_bind(&event.auth_address)
-            .push_bind(&event.commission)
-            .push_bind(event.block_meta.block_number as i64)
-            .push_bind(&event.tx_meta.transaction_hash)
-            .push_bind(event.tx_meta.transaction_index as i64);
-    });
+    query_builder.push_values(
+        stake_deltas,
+        |mut b, ((val_id, delegator), (delta, block))| {
+            b.push_bind(val_id)
+                .push_bind(delegator)
+                .push_bind(delta)
+                .push_bind(block);
+        },
+    );
 
-    query_builder.push(" ON CONFLICT (transaction_hash) DO NOTHING");
+    query_builder.push(
+        r#" ON CONFLICT (val_id, delegator) DO UPDATE SET
+            stake = delegations.stake + EXCLUDED.stake,
+            updated_block = GREATEST(delegations.updated_block, EXCLUDED.updated_block)
+        RETURNING val_id, delegator, stake"#,
+    );
 
-    let res = query_builder.build().execute(&mut **tx).await?;
+    let rows = query_builder
+        .build_query_as::<(i64, String, BigDecimal)>()
+        .fetch_all(&mut **tx)
+        .await?;
+
+    let negative: Vec<(i64, String)> = rows
+        .into_iter()
+        .filter(|(_, _, stake)| stake.sign() == Sign::Minus)
+        .map(|(val_id, delegator, stake)| {
+            warn!(
+                "Negative stake {stake} for delegator {delegator} on validator {val_id}, clamped to zero"
+            );
+            (val_id, delegator)
+        })
+        .collect();
+    if !negative.is_empty() {
+        let mut query_builder = sqlx::QueryBuilder::new(
+            "UPDATE delegations SET stake = 0 WHERE (val_id, delegator) IN ",
+        );
+        query_builder.push_tuples(&negative, |mut b, (val_id, delegator)| {
+            b.push_bind(*val_id).push_bind(delegator);
+        });
+        query_builder.build().execute(&mut **tx).await?;
+    }
 
-    Ok((res.rows_affected(), total))
+    Ok(negative.len() as u64)
 }
 
-async fn insert_validator_status_changed_events_in_tx(
+/// Apply Undelegate and Withdraw events to the `pending_withdrawals` table.
+///
+/// Only the latest Undelegate and the latest Withdraw per withdrawal id are
+/// kept, by (block, transaction index, log index), so the result does not
+/// depend on the order in which blocks are indexed.
+async fn update_pending_withdrawals_in_tx(
     tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
-    events: &[events::ValidatorStatusChangedEvent],
-) -> Result<(u64, u64), DbError> {
-    let total = events.len() as u64;
-    if events.is_empty() {
-        return Ok((0, 0));
+    batch: &crate::BlockBatch,
+) -> Result<(), DbError> {
+    type Key<'a> = (i64, &'a str, i16);
+    type Position = (u64, u64, u64);
+
+    fn event_position(block_meta: &BlockMeta, tx_meta: &TxMeta) -> Position {
+        (
+            block_meta.block_number,
+            tx_meta.transaction_index,
+            tx_meta.log_index,
+        )
     }
 
-    let mut query_builder = sqlx::QueryBuilder::new(
-        "INSERT INTO validator_status_changed_events (validator_id, flags, block_number, transaction_hash, transaction_index) ",
-    );
+    let mut undelegations: HashMap<Key, &events::UndelegateEvent> = HashMap::new();
+    for event in &batch.undelegate {
+        let key = (
+            event.val_id as i64,
+            event.delegator.as_str(),
+            event.withdrawal_id,
+        );
+        let position = event_position(&event.block_meta, &event.tx_meta);
+        undelegations
+            .entry(key)
+            .and_modify(|latest| {
+                if event_position(&latest.block_meta, &latest.tx_meta) < position {
+                    *latest = event;
+                }
+            })
+            .or_insert(event);
+    }
 
-    query_builder.push_values(events, |mut b, event| {
-        b.push_bind(event.validator_id as i64)
-            .push_bind(event.flags as i64)
-            .push_bind(event.block_meta.block_number as i64)
-            .push_bind(&event.tx_meta.transaction_hash)
-            .push_bind(event.tx_meta.transaction_index as i64);
-    });
+    let mut withdrawals: HashMap<Key, Position> = HashMap::new();
+    for event in &batch.withdraw {
+        let key = (
+            event.val_id as i64,
+            event.delegator.as_str(),
+            event.withdrawal_id,
+        );
+        let position = event_position(&event.block_meta, &event.tx_meta);
+        withdrawals
+            .entry(key)
+            .and_modify(|latest| *latest = (*latest).max(position))
+            .or_insert(position);
+    }
 
-    query_builder.push(" ON CONFLICT (validator_id, transaction_hash) DO NOTHING");
+    if !undelegations.is_empty() {
+        let mut query_builder = sqlx::QueryBuilder::new(
+            "INSERT INTO pending_withdrawals (val_id, delegator, withdrawal_id, amount, undelegate_block, undelegate_transaction_index, undelegate_log_index) ",
+        );
+        query_builder.push_values(
+            undelegations,
+            |mut b, ((val_id, delegator, withdrawal_id), event)| {
+                b.push_bind(val_id)
+                    .push_bind(delegator)
+                    .push_bind(withdrawal_id)
+                    .push_bind(&event.amount)
+                    .push_bind(event.block_meta.block_number as i64)
+                    .push_bind(event.tx_meta.transaction_index as i64)
+                    .push_bind(event.tx_meta.log_index as i64);
+            },
+        );
+        query_builder.push(
+            r#" ON CONFLICT (val_id, delegator, withdrawal_id) DO UPDATE SET
+                amount = EXCLUDED.amount,
+                undelegate_block = EXCLUDED.undelegate_block,
+                undelegate_transaction_index = EXCLUDED.undelegate_transaction_index,
+                undelegate_log_index = EXCLUDED.undelegate_log_index,
+                updated_at = CURRENT_TIMESTAMP
+            WHERE (COALESCE(pending_withdrawals.undelegate_block, -1), COALESCE(pending_withdrawals.undelegate_transaction_index, -1), COALESCE(pending_withdrawals.undelegate_log_index, -1))
+                < (EXCLUDED.undelegate_block, EXCLUDED.undelegate_transaction_index, EXCLUDED.undelegate_log_index)"#,
+        );
+        query_builder.build().execute(&mut **tx).await?;
+    }
 
-    let res = query_builder.build().execute(&mut **tx).await?;
+    if !withdrawals.is_empty() {
+        let mut query_builder = sqlx::QueryBuilder::new(
+            "INSERT INTO pending_withdrawals (val_id, delegator, withdrawal_id, withdraw_block, withdraw_transaction_index, withdraw_log_index) ",
+        );
+        query_builder.push_values(
+            withdrawals,
+            |mut b, ((val_id, delegator, withdrawal_id), (block, transaction_index, log_index))| {
+                b.push_bind(val_id)
+                    .push_bind(delegator)
+                    .push_bind(withdrawal_id)
+                    .push_bind(block as i64)
+                    .push_bind(transaction_index as i64)
+                    .push_bind(log_index as i64);
+            },
+        );
+        query_builder.push(
+            r#" ON CONFLICT (val_id, delegator, withdrawal_id) DO UPDATE SET
+                withdraw_block = EXCLUDED.withdraw_block,
+                withdraw_transaction_index = EXCLUDED.withdraw_transaction_index,
+                withdraw_log_index = EXCLUDED.withdraw_log_index,
+                updated_at = CURRENT_TIMESTAMP
+            WHERE (COALESCE(pending_withdrawals.withdraw_block, -1), COALESCE(pending_withdrawals.withdraw_transaction_index, -1), COALESCE(pending_withdrawals.withdraw_log_index, -1))
+                < (EXCLUDED.withdraw_block, EXCLUDED.withdraw_transaction_index, EXCLUDED.withdraw_log_index)"#,
+        );
+        query_builder.build().execute(&mut **tx).await?;
+    }
 
-    Ok((res.rows_affected(), total))
+    Ok(())
 }
 
-async fn insert_commission_changed_events_in_tx(
+/// Apply validator events to the `validators` table.
+///
+/// Every attribute is only overwritten by an event at a later (block, log index)
+/// position than the one that last set it, so backfilling old blocks after newer
+/// ones does not regress the state.
+async fn update_validators_in_tx(
     tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
-    events: &[events::CommissionChangedEvent],
-) -> Result<(u64, u64), DbError> {
-    let total = events.len() as u64;
-    if events.is_empty() {
-        return Ok((0, 0));
+    batch: &crate::BlockBatch,
+) -> Result<(), DbError> {
+    for event in &batch.validator_created {
+        sqlx::query(
+            r#"
+            INSERT INTO validators (validator_id, auth_address, commission, created_block, updated_block, commission_block, commission_log_index)
+            VALUES ($1, $2, $3, $4, $4, $4, $5)
+            ON CONFLICT (validator_id) DO UPDATE SET
+                auth_address = EXCLUDED.auth_address,
+                created_block = EXCLUDED.created_block,
+                commission = CASE
+                    WHEN (COALESCE(validators.commission_block, -1), COALESCE(validators.commission_log_index, -1))
+                        < (EXCLUDED.commission_block, EXCLUDED.commission_log_index)
+                    THEN EXCLUDED.commission ELSE validators.commission END,
+                commission_log_index = CASE
+                    WHEN (COALESCE(validators.commission_block, -1), COALESCE(validators.commission_log_index, -1))
+                        < (EXCLUDED.commission_block, EXCLUDED.commission_log_index)
+                    THEN EXCLUDED.commission_log_index ELSE validators.commission_log_index END,
+                commission_block = CASE
+                    WHEN (COALESCE(validators.commission_block, -1), COALESCE(validators.commission_log_index, -1))
+                        < (EXCLUDED.commission_block, EXCLUDED.commission_log_index)
+                    THEN EXCLUDED.commission_block ELSE validators.commission_block END,
+                updated_block = GREATEST(validators.updated_block, EXCLUDED.updated_block),
+                updated_at = CURRENT_TIMESTAMP
+            "#,
+        )
+        .bind(event.validator_id as i64)
+        .bind(&event.auth_address)
+        .bind(&event.commission)
+        .bind(event.block_meta.block_number as i64)
+        .bind(event.tx_meta.log_index as i64)
+        .execute(&mut **tx)
+        .await?;
     }
 
-    let mut query_builder = sqlx::QueryBuilder::new(
-        "INSERT INTO commission_changed_events (validator_id, old_commission, new_commission, block_number, transaction_hash, transaction_index) ",
-    );
+    for event in &batch.commission_changed {
+        sqlx::query(
+            r#"
+            INSERT INTO validators (validator_id, commission, updated_block, commission_block, commission_log_index)
+            VALUES ($1, $2, $3, $3, $4)
+            ON CONFLICT (validator_id) DO UPDATE SET
+                commission = EXCLUDED.commission,
+                commission_block = EXCLUDED.commission_block,
+                commission_log_index = EXCLUDED.commission_log_index,
+                updated_block = GREATEST(validators.updated_block, EXCLUDED.updated_block),
+                updated_at = CURRENT_TIMESTAMP
+            WHERE (COALESCE(validators.commission_block, -1), COALESCE(validators.commission_log_index, -1))
+                < (EXCLUDED.commission_block, EXCLUDED.commission_log_index)
+            "#,
+        )
+        .bind(event.validator_id as i64)
+        .bind(&event.new_commission)
+        .bind(event.block_meta.block_number as i64)
+        .bind(event.tx_meta.log_index as i64)
+        .execute(&mut **tx)
+        .await?;
+    }
 
-    query_builder.push_values(events, |mut b, event| {
-        b.push_bind(event.validator_id as i64)
-            .push_bind(&event.old_commission)
-            .push_bind(&event.new_commission)
-            .push_bind(event.block_meta.block_number as i64)
-            .push_bind(&event.tx_meta.transaction_hash)
-            .push_bind(event.tx_meta.transaction_index as i64);
-    });
+    for event in &batch.validator_status_changed {
+        sqlx::query(
+            r#"
+            INSERT INTO validators (validator_id, flags, updated_block, flags_block, flags_log_index)
+            VALUES ($1, $2, $3, $3, $4)
+            ON CONFLICT (validator_id) DO UPDATE SET
+                flags = EXCLUDED.flags,
+                flags_block = EXCLUDED.flags_block,
+                flags_log_index = EXCLUDED.flags_log_index,
+                updated_block = GREATEST(validators.updated_block, EXCLUDED.updated_block),
+                updated_at = CURRENT_TIMESTAMP
+            WHERE (COALESCE(validators.flags_block, -1), COALESCE(validators.flags_log_index, -1))
+                < (EXCLUDED.flags_block, EXCLUDED.flags_log_index)
+            "#,
+        )
+        .bind(event.validator_id as i64)
+        .bind(event.flags as i64)
+        .bind(event.block_meta.block_number as i64)
+        .bind(event.tx_meta.log_index as i64)
+        .execute(&mut **tx)
+        .await?;
+    }
 
-    query_builder.push(" ON CONFLICT (validator_id, transaction_hash) DO NOTHING");
+    Ok(())
+}
 
-    let res = query_builder.build().execute(&mut **tx).await?;
+/// Apply EpochChanged events to the `epochs` table, keeping the lowest start
+/// block seen per epoch.
+async fn update_epochs_in_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    events: &[events::EpochChangedEvent],
+) -> Result<(), DbError> {
+    let mut starts: HashMap<u64, &BlockMeta> = HashMap::new();
+    for event in events {
+        starts
+            .entry(event.new_epoch)
+            .and_modify(|start| {
+                if event.block_meta.block_number < start.block_number {
+                    *start = &event.block_meta;
+                }
+            })
+            .or_insert(&event.block_meta);
+    }
+    if starts.is_empty() {
+        return Ok(());
+    }
 
-    Ok((res.rows_affected(), total))
+    let mut query_builder =
+        sqlx::QueryBuilder::new("INSERT INTO epochs (epoch, start_block, start_timestamp) ");
+    query_builder.push_values(starts, |mut b, (epoch, block_meta)| {
+        b.push_bind(epoch as i64)
+            .push_bind(block_meta.block_number as i64)
+            .push_bind(block_meta.block_timestamp as i64);
+    });
+    query_builder.push(
+        r#" ON CONFLICT (epoch) DO UPDATE SET
+            start_block = EXCLUDED.start_block,
+            start_timestamp = EXCLUDED.start_timestamp,
+            updated_at = CURRENT_TIMESTAMP
+        WHERE EXCLUDED.start_block < epochs.start_block"#,
+    );
+    query_builder.build().execute(&mut **tx).await?;
+
+    Ok(())
 }
 
+/// Inserts the blocks and returns the numbers of those not indexed before.
 async fn insert_blocks_in_tx(
     tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
     blocks: &[BlockMeta],
-) -> Result<u64, DbError> {
+) -> Result<Vec<u64>, DbError> {
     if blocks.is_empty() {
-        return Ok(0);
+        return Ok(Vec::new());
     }
 
     let mut query_builder =
@@ -287,80 +1138,262 @@ async fn insert_blocks_in_tx(
             .push_bind(block_meta.block_timestamp as i64);
     });
 
-    query_builder.push(" ON CONFLICT (block_number) DO NOTHING");
+    query_builder.push(" ON CONFLICT (block_number) DO NOTHING RETURNING block_number");
 
-    let res = query_builder.build().execute(&mut **tx).await?;
+    let inserted = query_builder
+        .build_query_scalar::<i64>()
+        .fetch_all(&mut **tx)
+        .await?;
 
-    Ok(res.rows_affected())
+    Ok(inserted.into_iter().map(|block| block as u64).collect())
+}
+
+/// Queues a notification per newly indexed block, delivered when the
+/// transaction commits.
+async fn notify_blocks_in_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    batch: &crate::BlockBatch,
+    inserted_blocks: &[u64],
+) -> Result<(), DbError> {
+    if inserted_blocks.is_empty() {
+        return Ok(());
+    }
+
+    let payloads = BlockNotification::for_blocks(inserted_blocks, batch.events())
+        .iter()
+        .map(serde_json::to_string)
+        .collect::<Result<Vec<_>, _>>()
+        .expect("BlockNotification serializes to JSON");
+
+    sqlx::query("SELECT pg_notify($1, payload) FROM UNNEST($2::text[]) AS payload")
+        .bind(NOTIFICATION_CHANNEL)
+        .bind(payloads)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+/// Attributes a database error from inserting `table` events to the blocks
+/// those events are in.
+fn table_insert_error(
+    batch: &crate::BlockBatch,
+    table: StakingEventType,
+) -> impl FnOnce(DbError) -> DbError + '_ {
+    move |e| match e {
+        DbError::Sqlx(source) => {
+            let block_range = batch
+                .events()
+                .filter(|event| event.event_type() == table)
+                .map(|event| event.block_meta().block_number)
+                .fold((u64::MAX, 0), |(min, max), block| {
+                    (min.min(block), max.max(block))
+                });
+            DbError::TableInsert {
+                table,
+                block_range,
+                source,
+            }
+        }
+        e => e,
+    }
 }
 
 async fn insert_many_blocks_inner(
     pool: &PgPool,
     batch: &crate::BlockBatch,
-) -> Result<std::collections::HashMap<StakingEventType, (u64, u64)>, DbError> {
-    if batch.block_meta.is_empty() {
-        return Ok(std::collections::HashMap::new());
+    policy: DuplicatePolicy,
+    strategies: ConflictStrategies,
+    block_strategy: BlockConflictStrategy,
+) -> Result<InsertReport, DbError> {
+    if batch.block_meta.is_empty() && batch.replaces.is_none() {
+        if let Some(checkpoint) = batch.checkpoint {
+            set_checkpoint(pool, checkpoint).await?;
+        }
+        return Ok(InsertReport::default());
     }
 
     let mut tx = pool.begin().await?;
 
-    let mut result = std::collections::HashMap::new();
-    result.insert(
-        StakingEventType::Delegate,
-        insert_delegate_events_in_tx(&mut tx, batch.delegate.as_slice()).await?,
-    );
-    result.insert(
-        StakingEventType::Undelegate,
-        insert_undelegate_events_in_tx(&mut tx, batch.undelegate.as_slice()).await?,
-    );
-    result.insert(
-        StakingEventType::Withdraw,
-        insert_withdraw_events_in_tx(&mut tx, batch.withdraw.as_slice()).await?,
-    );
-    result.insert(
-        StakingEventType::ClaimRewards,
-        insert_claim_rewards_events_in_tx(&mut tx, batch.claim_rewards.as_slice()).await?,
-    );
-    result.insert(
+    let mut stake_deltas = StakeDeltas::new();
+    let mut report = InsertReport::default();
+
+    // In the same transaction, so the range is never left empty.
+    if let Some(range) = &batch.replaces {
+        report.replaced = delete_range_in_tx(&mut tx, range).await?;
+    }
+
+    // Blocks go first, events reference them.
+    let start = Instant::now();
+    let inserted_blocks = insert_blocks_in_tx(&mut tx, batch.block_meta.as_slice()).await?;
+    if block_strategy == BlockConflictStrategy::Upsert {
+        report.blocks_updated = upsert_blocks(&mut *tx, batch.block_meta.as_slice()).await?;
+    }
+    report.table_stats.push(TableInsertStats {
+        table: "blocks",
+        rows: inserted_blocks.len() as u64,
+        elapsed: start.elapsed(),
+    });
+
+    let start = Instant::now();
+    let counts = insert_stake_events_in_tx(
+        &mut tx,
+        batch.delegate.as_slice(),
+        &mut stake_deltas,
+        Sign::Plus,
+        policy,
+        strategies.delegate,
+    )
+    .await
+    .map_err(table_insert_error(batch, StakingEventType::Delegate))?;
+    report.record(StakingEventType::Delegate, counts, start.elapsed());
+
+    let start = Instant::now();
+    let counts = insert_stake_events_in_tx(
+        &mut tx,
+        batch.undelegate.as_slice(),
+        &mut stake_deltas,
+        Sign::Minus,
+        policy,
+        strategies.undelegate,
+    )
+    .await
+    .map_err(table_insert_error(batch, StakingEventType::Undelegate))?;
+    report.record(StakingEventType::Undelegate, counts, start.elapsed());
+
+    let start = Instant::now();
+    let counts = insert_plain_events_in_tx(
+        &mut tx,
+        batch.withdraw.as_slice(),
+        policy,
+        strategies.withdraw,
+    )
+    .await
+    .map_err(table_insert_error(batch, StakingEventType::Withdraw))?;
+    report.record(StakingEventType::Withdraw, counts, start.elapsed());
+
+    let start = Instant::now();
+    let counts = insert_plain_events_in_tx(
+        &mut tx,
+        batch.claim_rewards.as_slice(),
+        policy,
+        strategies.claim_rewards,
+    )
+    .await
+    .map_err(table_insert_error(batch, StakingEventType::ClaimRewards))?;
+    report.record(StakingEventType::ClaimRewards, counts, start.elapsed());
+
+    let start = Instant::now();
+    let counts = insert_plain_events_in_tx(
+        &mut tx,
+        batch.validator_rewarded.as_slice(),
+        policy,
+        strategies.validator_rewarded,
+    )
+    .await
+    .map_err(table_insert_error(
+        batch,
         StakingEventType::ValidatorRewarded,
-        insert_validator_rewarded_events_in_tx(&mut tx, batch.validator_rewarded.as_slice())
-            .await?,
-    );
-    result.insert(
-        StakingEventType::EpochChanged,
-        insert_epoch_changed_events_in_tx(&mut tx, batch.epoch_changed.as_slice()).await?,
-    );
-    result.insert(
+    ))?;
+    report.record(StakingEventType::ValidatorRewarded, counts, start.elapsed());
+
+    let start = Instant::now();
+    let counts = insert_plain_events_in_tx(
+        &mut tx,
+        batch.epoch_changed.as_slice(),
+        policy,
+        strategies.epoch_changed,
+    )
+    .await
+    .map_err(table_insert_error(batch, StakingEventType::EpochChanged))?;
+    report.record(StakingEventType::EpochChanged, counts, start.elapsed());
+
+    let start = Instant::now();
+    let counts = insert_plain_events_in_tx(
+        &mut tx,
+        batch.validator_created.as_slice(),
+        policy,
+        strategies.validator_created,
+    )
+    .await
+    .map_err(table_insert_error(
+        batch,
         StakingEventType::ValidatorCreated,
-        insert_validator_created_events_in_tx(&mut tx, batch.validator_created.as_slice()).await?,
-    );
-    result.insert(
+    ))?;
+    report.record(StakingEventType::ValidatorCreated, counts, start.elapsed());
+
+    let start = Instant::now();
+    let counts = insert_plain_events_in_tx(
+        &mut tx,
+        batch.validator_status_changed.as_slice(),
+        policy,
+        strategies.validator_status_changed,
+    )
+    .await
+    .map_err(table_insert_error(
+        batch,
         StakingEventType::ValidatorStatusChanged,
-        insert_validator_status_changed_events_in_tx(
-            &mut tx,
-            batch.validator_status_changed.as_slice(),
-        )
-        .await?,
+    ))?;
+    report.record(
+        StakingEventType::ValidatorStatusChanged,
+        counts,
+        start.elapsed(),
     );
-    result.insert(
+
+    let start = Instant::now();
+    let counts = insert_plain_events_in_tx(
+        &mut tx,
+        batch.commission_changed.as_slice(),
+        policy,
+        strategies.commission_changed,
+    )
+    .await
+    .map_err(table_insert_error(
+        batch,
         StakingEventType::CommissionChanged,
-        insert_commission_changed_events_in_tx(&mut tx, batch.commission_changed.as_slice())
-            .await?,
-    );
+    ))?;
+    report.record(StakingEventType::CommissionChanged, counts, start.elapsed());
+
+    let start = Instant::now();
+    let counts = insert_raw_events_in_tx(&mut tx, batch.raw.as_slice(), &mut report)
+        .await
+        .map_err(table_insert_error(batch, StakingEventType::Unknown))?;
+    report.record(StakingEventType::Unknown, counts, start.elapsed());
 
-    insert_blocks_in_tx(&mut tx, batch.block_meta.as_slice()).await?;
+    report.negative_stakes = update_delegations_in_tx(&mut tx, stake_deltas).await?;
+    update_pending_withdrawals_in_tx(&mut tx, batch).await?;
+    update_validators_in_tx(&mut tx, batch).await?;
+    update_epochs_in_tx(&mut tx, batch.epoch_changed.as_slice()).await?;
+
+    notify_blocks_in_tx(&mut tx, batch, &inserted_blocks).await?;
+    if let Some(checkpoint) = batch.checkpoint {
+        set_checkpoint(&mut *tx, checkpoint).await?;
+    }
 
     tx.commit().await?;
 
-    Ok(result)
+    Ok(report)
 }
 
+/// Insert `batch` in one transaction, handling already stored events as
+/// `policy` says, stored events with different values as `strategies` say and
+/// stored blocks with a different hash or timestamp as `block_strategy` says.
+/// Undecoded events are never checked against `policy`.
 pub async fn insert_blocks(
     pool: &PgPool,
     batch: &crate::BlockBatch,
     timeout: Duration,
-) -> Result<std::collections::HashMap<StakingEventType, (u64, u64)>, DbError> {
-    tokio::time::timeout(timeout, insert_many_blocks_inner(pool, batch))
-        .await
-        .map_err(|_| DbError::Sqlx(sqlx::Error::PoolTimedOut))?
+    policy: DuplicatePolicy,
+    strategies: ConflictStrategies,
+    block_strategy: BlockConflictStrategy,
+) -> Result<InsertReport, DbError> {
+    tokio::time::timeout(
+        timeout,
+        insert_many_blocks_inner(pool, batch, policy, strategies, block_strategy),
+    )
+    .await
+    .map_err(|_| DbError::OperationTimedOut {
+        elapsed: timeout,
+        rows: batch.block_meta.len() + batch.total_event_count(),
+    })?
 }
@@ -1,10 +1,16 @@
-use alloy::{primitives::Log as PrimitiveLog, rpc::types::Log, sol_types::SolEvent};
+use alloy::{
+    primitives::{Address, Log as PrimitiveLog},
+    rpc::types::Log,
+    sol_types::SolEvent,
+};
 use bigdecimal::{
     BigDecimal,
     num_bigint::{BigInt, Sign},
 };
 use eyre::Result;
+use serde::{Deserialize, Serialize};
 use std::fmt;
+use tracing::warn;
 
 use crate::contract_abi::StakingPrecompile;
 
@@ -14,20 +20,31 @@ fn u256_to_bigdecimal(value: alloy::primitives::U256) -> BigDecimal {
     BigDecimal::from(bigint)
 }
 
-#[derive(Debug, Clone)]
+/// EIP-55 checksummed hex of a 20-byte address, without `0x` prefix like the
+/// other hex columns. Panics if `bytes` is not 20 bytes long.
+pub fn to_checksum_address(bytes: &[u8]) -> String {
+    let checksummed = Address::from_slice(bytes).to_checksum(None);
+    checksummed.trim_start_matches("0x").to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct BlockMeta {
     pub block_number: u64,
     pub block_hash: String,
     pub block_timestamp: u64,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct TxMeta {
     pub transaction_hash: String,
     pub transaction_index: u64,
+    /// Position of the event's log within its block, which orders the events
+    /// of a block. Zero for events indexed before it was recorded.
+    #[serde(default)]
+    pub log_index: u64,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct DelegateEvent {
     pub val_id: u64,
     pub delegator: String,
@@ -47,7 +64,7 @@ impl fmt::Display for DelegateEvent {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct UndelegateEvent {
     pub val_id: u64,
     pub delegator: String,
@@ -68,7 +85,7 @@ impl fmt::Display for UndelegateEvent {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct WithdrawEvent {
     pub val_id: u64,
     pub delegator: String,
@@ -89,7 +106,7 @@ impl fmt::Display for WithdrawEvent {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ClaimRewardsEvent {
     pub val_id: u64,
     pub delegator: String,
@@ -109,7 +126,7 @@ impl fmt::Display for ClaimRewardsEvent {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ValidatorRewardedEvent {
     pub validator_id: u64,
     pub from: String,
@@ -129,7 +146,7 @@ impl fmt::Display for ValidatorRewardedEvent {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct EpochChangedEvent {
     pub old_epoch: u64,
     pub new_epoch: u64,
@@ -143,7 +160,7 @@ impl fmt::Display for EpochChangedEvent {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ValidatorCreatedEvent {
     pub validator_id: u64,
     pub auth_address: String,
@@ -162,7 +179,40 @@ impl fmt::Display for ValidatorCreatedEvent {
     }
 }
 
-#[derive(Debug, Clone)]
+bitflags::bitflags! {
+    /// Status flags of a validator as set by the staking precompile. A validator
+    /// without any flag is eligible for the active set.
+    ///
+    /// Bits without a meaning here are retained, see [`ValidatorFlags::unknown_bits`].
+    /// The generated `is_*` columns of `validators` and
+    /// `validator_status_changed_events` decode the same bits.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct ValidatorFlags: u64 {
+        /// The validator's stake is below the minimum for the active set.
+        const STAKE_TOO_LOW = 1 << 0;
+        /// The validator's own stake has been withdrawn, it is exiting.
+        const WITHDRAWN = 1 << 1;
+        /// The validator double signed and is jailed.
+        const DOUBLE_SIGN = 1 << 2;
+    }
+}
+
+impl ValidatorFlags {
+    pub fn is_active(self) -> bool {
+        self.is_empty()
+    }
+
+    pub fn is_jailed(self) -> bool {
+        self.contains(ValidatorFlags::DOUBLE_SIGN)
+    }
+
+    /// Set bits that are none of the known flags.
+    pub fn unknown_bits(self) -> u64 {
+        self.bits() & !ValidatorFlags::all().bits()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ValidatorStatusChangedEvent {
     pub validator_id: u64,
     pub flags: u64,
@@ -170,6 +220,12 @@ pub struct ValidatorStatusChangedEvent {
     pub tx_meta: TxMeta,
 }
 
+impl ValidatorStatusChangedEvent {
+    pub fn decoded_flags(&self) -> ValidatorFlags {
+        ValidatorFlags::from_bits_retain(self.flags)
+    }
+}
+
 impl fmt::Display for ValidatorStatusChangedEvent {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
@@ -180,7 +236,7 @@ impl fmt::Display for ValidatorStatusChangedEvent {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct CommissionChangedEvent {
     pub validator_id: u64,
     pub old_commission: BigDecimal,
@@ -199,7 +255,30 @@ impl fmt::Display for CommissionChangedEvent {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// A log of the staking contract whose event this version cannot decode, kept
+/// so it can be decoded later without scanning the chain again.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RawEvent {
+    /// Hex-encoded event signature hash.
+    pub topic0: String,
+    /// All hex-encoded topics, including `topic0`.
+    pub topics: Vec<String>,
+    pub data: Vec<u8>,
+    pub block_meta: BlockMeta,
+    pub tx_meta: TxMeta,
+}
+
+impl fmt::Display for RawEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Unknown block={} topic0={}",
+            self.block_meta.block_number, self.topic0
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum StakingEventType {
     Delegate,
     Undelegate,
@@ -210,9 +289,11 @@ pub enum StakingEventType {
     ValidatorCreated,
     ValidatorStatusChanged,
     CommissionChanged,
+    Unknown,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(test, derive(strum_macros::EnumCount))]
 pub enum StakingEvent {
     Delegate(DelegateEvent),
     Undelegate(UndelegateEvent),
@@ -223,6 +304,7 @@ pub enum StakingEvent {
     ValidatorCreated(ValidatorCreatedEvent),
     ValidatorStatusChanged(ValidatorStatusChangedEvent),
     CommissionChanged(CommissionChangedEvent),
+    Unknown(RawEvent),
 }
 
 impl fmt::Display for StakingEvent {
@@ -237,6 +319,7 @@ impl fmt::Display for StakingEvent {
             StakingEvent::ValidatorCreated(e) => write!(f, "{}", e),
             StakingEvent::ValidatorStatusChanged(e) => write!(f, "{}", e),
             StakingEvent::CommissionChanged(e) => write!(f, "{}", e),
+            StakingEvent::Unknown(e) => write!(f, "{}", e),
         }
     }
 }
@@ -253,13 +336,28 @@ impl fmt::Display for StakingEventType {
             StakingEventType::ValidatorCreated => write!(f, "ValidatorCreated"),
             StakingEventType::ValidatorStatusChanged => write!(f, "ValidatorStatusChanged"),
             StakingEventType::CommissionChanged => write!(f, "CommissionChanged"),
+            StakingEventType::Unknown => write!(f, "Unknown"),
         }
     }
 }
 
+impl std::str::FromStr for StakingEventType {
+    type Err = String;
+
+    /// The type named as by `Display`, in any case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        StakingEventType::all_types()
+            .iter()
+            .copied()
+            .find(|event_type| event_type.to_string().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("unknown event type '{s}'"))
+    }
+}
+
 impl StakingEventType {
-    pub fn all_types() -> Vec<StakingEventType> {
-        vec![
+    /// Every event type, in declaration order.
+    pub const fn all_types() -> &'static [StakingEventType] {
+        &[
             StakingEventType::Delegate,
             StakingEventType::Undelegate,
             StakingEventType::Withdraw,
@@ -269,8 +367,25 @@ impl StakingEventType {
             StakingEventType::ValidatorCreated,
             StakingEventType::ValidatorStatusChanged,
             StakingEventType::CommissionChanged,
+            StakingEventType::Unknown,
         ]
     }
+
+    /// Database table holding events of this type.
+    pub fn table_name(&self) -> &'static str {
+        match self {
+            StakingEventType::Delegate => "delegate_events",
+            StakingEventType::Undelegate => "undelegate_events",
+            StakingEventType::Withdraw => "withdraw_events",
+            StakingEventType::ClaimRewards => "claim_rewards_events",
+            StakingEventType::ValidatorRewarded => "validator_rewarded_events",
+            StakingEventType::EpochChanged => "epoch_changed_events",
+            StakingEventType::ValidatorCreated => "validator_created_events",
+            StakingEventType::ValidatorStatusChanged => "validator_status_changed_events",
+            StakingEventType::CommissionChanged => "commission_changed_events",
+            StakingEventType::Unknown => "raw_events",
+        }
+    }
 }
 
 impl StakingEvent {
@@ -285,6 +400,39 @@ impl StakingEvent {
             StakingEvent::ValidatorCreated(_) => StakingEventType::ValidatorCreated,
             StakingEvent::ValidatorStatusChanged(_) => StakingEventType::ValidatorStatusChanged,
             StakingEvent::CommissionChanged(_) => StakingEventType::CommissionChanged,
+            StakingEvent::Unknown(_) => StakingEventType::Unknown,
+        }
+    }
+
+    /// The validator the event refers to, if any.
+    pub fn validator_id(&self) -> Option<u64> {
+        match self {
+            StakingEvent::Delegate(e) => Some(e.val_id),
+            StakingEvent::Undelegate(e) => Some(e.val_id),
+            StakingEvent::Withdraw(e) => Some(e.val_id),
+            StakingEvent::ClaimRewards(e) => Some(e.val_id),
+            StakingEvent::ValidatorRewarded(e) => Some(e.validator_id),
+            StakingEvent::EpochChanged(_) => None,
+            StakingEvent::ValidatorCreated(e) => Some(e.validator_id),
+            StakingEvent::ValidatorStatusChanged(e) => Some(e.validator_id),
+            StakingEvent::CommissionChanged(e) => Some(e.validator_id),
+            StakingEvent::Unknown(_) => None,
+        }
+    }
+
+    /// The delegator the event refers to, if any.
+    pub fn delegator(&self) -> Option<&str> {
+        match self {
+            StakingEvent::Delegate(e) => Some(&e.delegator),
+            StakingEvent::Undelegate(e) => Some(&e.delegator),
+            StakingEvent::Withdraw(e) => Some(&e.delegator),
+            StakingEvent::ClaimRewards(e) => Some(&e.delegator),
+            StakingEvent::ValidatorRewarded(_)
+            | StakingEvent::EpochChanged(_)
+            | StakingEvent::ValidatorCreated(_)
+            | StakingEvent::ValidatorStatusChanged(_)
+            | StakingEvent::CommissionChanged(_)
+            | StakingEvent::Unknown(_) => None,
         }
     }
 
@@ -299,11 +447,33 @@ impl StakingEvent {
             StakingEvent::ValidatorCreated(e) => &e.block_meta,
             StakingEvent::ValidatorStatusChanged(e) => &e.block_meta,
             StakingEvent::CommissionChanged(e) => &e.block_meta,
+            StakingEvent::Unknown(e) => &e.block_meta,
+        }
+    }
+
+    pub fn tx_meta(&self) -> &TxMeta {
+        match self {
+            StakingEvent::Delegate(e) => &e.tx_meta,
+            StakingEvent::Undelegate(e) => &e.tx_meta,
+            StakingEvent::Withdraw(e) => &e.tx_meta,
+            StakingEvent::ClaimRewards(e) => &e.tx_meta,
+            StakingEvent::ValidatorRewarded(e) => &e.tx_meta,
+            StakingEvent::EpochChanged(e) => &e.tx_meta,
+            StakingEvent::ValidatorCreated(e) => &e.tx_meta,
+            StakingEvent::ValidatorStatusChanged(e) => &e.tx_meta,
+            StakingEvent::CommissionChanged(e) => &e.tx_meta,
+            StakingEvent::Unknown(e) => &e.tx_meta,
         }
     }
 }
 
-pub fn extract_event(log: &Log) -> Result<Option<StakingEvent>> {
+/// Decode a log of the staking contract at `contract_address`. Logs of other
+/// contracts are skipped, as are logs without topics.
+pub fn extract_event(log: &Log, contract_address: Address) -> Result<Option<StakingEvent>> {
+    if log.address() != contract_address {
+        return Ok(None);
+    }
+
     let block_number = log
         .block_number
         .ok_or_else(|| eyre::eyre!("Missing block number"))?;
@@ -319,6 +489,9 @@ pub fn extract_event(log: &Log) -> Result<Option<StakingEvent>> {
     let transaction_index = log
         .transaction_index
         .ok_or_else(|| eyre::eyre!("Missing transaction index"))?;
+    let log_index = log
+        .log_index
+        .ok_or_else(|| eyre::eyre!("Missing log index"))?;
 
     let Some(topic0) = log.topic0() else {
         return Ok(None);
@@ -333,6 +506,7 @@ pub fn extract_event(log: &Log) -> Result<Option<StakingEvent>> {
     let tx_meta = TxMeta {
         transaction_hash: hex::encode(transaction_hash),
         transaction_index,
+        log_index,
     };
 
     let inner_log = PrimitiveLog {
@@ -345,7 +519,7 @@ pub fn extract_event(log: &Log) -> Result<Option<StakingEvent>> {
             let decoded = StakingPrecompile::Delegate::decode_log(&inner_log, true)?;
             Ok(Some(StakingEvent::Delegate(DelegateEvent {
                 val_id: decoded.valId,
-                delegator: hex::encode(decoded.delegator),
+                delegator: to_checksum_address(decoded.delegator.as_slice()),
                 amount: u256_to_bigdecimal(decoded.amount),
                 activation_epoch: decoded.activationEpoch,
                 block_meta,
@@ -356,7 +530,7 @@ pub fn extract_event(log: &Log) -> Result<Option<StakingEvent>> {
             let decoded = StakingPrecompile::Undelegate::decode_log(&inner_log, true)?;
             Ok(Some(StakingEvent::Undelegate(UndelegateEvent {
                 val_id: decoded.valId,
-                delegator: hex::encode(decoded.delegator),
+                delegator: to_checksum_address(decoded.delegator.as_slice()),
                 withdrawal_id: decoded.withdrawal_id as i16,
                 amount: u256_to_bigdecimal(decoded.amount),
                 activation_epoch: decoded.activationEpoch,
@@ -368,7 +542,7 @@ pub fn extract_event(log: &Log) -> Result<Option<StakingEvent>> {
             let decoded = StakingPrecompile::Withdraw::decode_log(&inner_log, true)?;
             Ok(Some(StakingEvent::Withdraw(WithdrawEvent {
                 val_id: decoded.valId,
-                delegator: hex::encode(decoded.delegator),
+                delegator: to_checksum_address(decoded.delegator.as_slice()),
                 withdrawal_id: decoded.withdrawal_id as i16,
                 amount: u256_to_bigdecimal(decoded.amount),
                 activation_epoch: decoded.activationEpoch,
@@ -380,7 +554,7 @@ pub fn extract_event(log: &Log) -> Result<Option<StakingEvent>> {
             let decoded = StakingPrecompile::ClaimRewards::decode_log(&inner_log, true)?;
             Ok(Some(StakingEvent::ClaimRewards(ClaimRewardsEvent {
                 val_id: decoded.valId,
-                delegator: hex::encode(decoded.delegator),
+                delegator: to_checksum_address(decoded.delegator.as_slice()),
                 amount: u256_to_bigdecimal(decoded.amount),
                 epoch: decoded.epoch,
                 block_meta,
@@ -392,7 +566,7 @@ pub fn extract_event(log: &Log) -> Result<Option<StakingEvent>> {
             Ok(Some(StakingEvent::ValidatorRewarded(
                 ValidatorRewardedEvent {
                     validator_id: decoded.validatorId,
-                    from: hex::encode(decoded.from),
+                    from: to_checksum_address(decoded.from.as_slice()),
                     amount: u256_to_bigdecimal(decoded.amount),
                     epoch: decoded.epoch,
                     block_meta,
@@ -414,7 +588,7 @@ pub fn extract_event(log: &Log) -> Result<Option<StakingEvent>> {
             Ok(Some(StakingEvent::ValidatorCreated(
                 ValidatorCreatedEvent {
                     validator_id: decoded.validatorId,
-                    auth_address: hex::encode(decoded.authAddress),
+                    auth_address: to_checksum_address(decoded.authAddress.as_slice()),
                     commission: u256_to_bigdecimal(decoded.commission),
                     block_meta,
                     tx_meta,
@@ -423,14 +597,22 @@ pub fn extract_event(log: &Log) -> Result<Option<StakingEvent>> {
         }
         StakingPrecompile::ValidatorStatusChanged::SIGNATURE_HASH => {
             let decoded = StakingPrecompile::ValidatorStatusChanged::decode_log(&inner_log, true)?;
-            Ok(Some(StakingEvent::ValidatorStatusChanged(
-                ValidatorStatusChangedEvent {
-                    validator_id: decoded.validatorId,
-                    flags: decoded.flags,
-                    block_meta,
-                    tx_meta,
-                },
-            )))
+            let event = ValidatorStatusChangedEvent {
+                validator_id: decoded.validatorId,
+                flags: decoded.flags,
+                block_meta,
+                tx_meta,
+            };
+            let unknown_bits = event.decoded_flags().unknown_bits();
+            if unknown_bits != 0 {
+                warn!(
+                    validator_id = event.validator_id,
+                    block_number = event.block_meta.block_number,
+                    "Validator status has unknown flags {:#x}",
+                    unknown_bits
+                );
+            }
+            Ok(Some(StakingEvent::ValidatorStatusChanged(event)))
         }
         StakingPrecompile::CommissionChanged::SIGNATURE_HASH => {
             let decoded = StakingPrecompile::CommissionChanged::decode_log(&inner_log, true)?;
@@ -444,7 +626,20 @@ pub fn extract_event(log: &Log) -> Result<Option<StakingEvent>> {
                 },
             )))
         }
-        _ => Ok(None),
+        _ => {
+            warn!(
+                block_number = block_meta.block_number,
+                topic0 = %topic0,
+                "Storing staking event with unknown signature"
+            );
+            Ok(Some(StakingEvent::Unknown(RawEvent {
+                topic0: hex::encode(topic0),
+                topics: log.topics().iter().map(hex::encode).collect(),
+                data: log.data().data.to_vec(),
+                block_meta,
+                tx_meta,
+            })))
+        }
     }
 }
 
@@ -455,6 +650,24 @@ mod tests {
     use bigdecimal::BigDecimal;
     use std::str::FromStr;
 
+    #[test]
+    fn test_to_checksum_address() {
+        // Test vectors from EIP-55.
+        for expected in [
+            "5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "fB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+            "dbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+            "D1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb",
+        ] {
+            let bytes = hex::decode(expected).unwrap();
+            assert_eq!(to_checksum_address(&bytes), expected);
+        }
+        assert_eq!(
+            to_checksum_address(&[0u8; 20]),
+            "0000000000000000000000000000000000000000"
+        );
+    }
+
     #[test]
     fn test_u256_to_bigdecimal_small_value() {
         let u256_value = U256::from(12345u64);
@@ -472,4 +685,57 @@ mod tests {
         let expected = BigDecimal::from_str(u256_str).unwrap();
         assert_eq!(result, expected);
     }
+
+    fn status_changed(flags: u64) -> ValidatorStatusChangedEvent {
+        ValidatorStatusChangedEvent {
+            validator_id: 1,
+            flags,
+            block_meta: BlockMeta {
+                block_number: 100,
+                block_hash: "0xhash".to_string(),
+                block_timestamp: 1234567890,
+            },
+            tx_meta: TxMeta {
+                transaction_hash: "0xtx".to_string(),
+                transaction_index: 0,
+                log_index: 0,
+            },
+        }
+    }
+
+    #[test]
+    fn test_decoded_flags() {
+        let flags = status_changed(0).decoded_flags();
+        assert!(flags.is_active());
+        assert!(!flags.is_jailed());
+
+        let flags = status_changed(0b101).decoded_flags();
+        assert_eq!(
+            flags,
+            ValidatorFlags::STAKE_TOO_LOW | ValidatorFlags::DOUBLE_SIGN
+        );
+        assert!(!flags.is_active());
+        assert!(flags.is_jailed());
+        assert!(!flags.contains(ValidatorFlags::WITHDRAWN));
+        assert_eq!(flags.unknown_bits(), 0);
+    }
+
+    #[test]
+    fn test_decoded_flags_keep_unknown_bits() {
+        let flags = status_changed(1 << 40 | 0b10).decoded_flags();
+        assert!(flags.contains(ValidatorFlags::WITHDRAWN));
+        assert!(!flags.is_active());
+        assert_eq!(flags.unknown_bits(), 1 << 40);
+        assert_eq!(flags.bits(), 1 << 40 | 0b10);
+    }
+
+    #[test]
+    fn test_all_types_covers_every_event() {
+        use strum::EnumCount;
+
+        let types = StakingEventType::all_types();
+        assert_eq!(types.len(), StakingEvent::COUNT);
+        let distinct: std::collections::HashSet<_> = types.iter().collect();
+        assert_eq!(distinct.len(), types.len());
+    }
 }
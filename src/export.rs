@@ -0,0 +1,86 @@
+use std::ops::Range;
+use std::path::PathBuf;
+
+use eyre::{Result, WrapErr};
+use sqlx::PgPool;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tracing::info;
+
+use crate::filter::EventFilter;
+use crate::{chunk_range, db};
+
+/// Number of blocks whose events are loaded into memory at once.
+const EXPORT_CHUNK_BLOCKS: u64 = 10_000;
+
+/// Write the events of blocks `start_block..end_block` that match `filter` to
+/// `writer`, one JSON-serialized [`crate::events::StakingEvent`] per line, in
+/// block order. Returns the number of events written.
+pub async fn export_events_ndjson(
+    pool: &PgPool,
+    start_block: u64,
+    end_block: u64,
+    filter: &EventFilter,
+    writer: impl AsyncWrite,
+) -> Result<u64> {
+    tokio::pin!(writer);
+    let mut written = 0;
+    for chunk in chunk_range(start_block..end_block, EXPORT_CHUNK_BLOCKS) {
+        let events = db::repository::get_events_in_block_range(pool, chunk).await?;
+        for event in events.iter().filter(|event| filter.apply(event)) {
+            let mut line = serde_json::to_vec(&event)?;
+            line.push(b'\n');
+            writer.write_all(&line).await?;
+            written += 1;
+        }
+    }
+    writer.flush().await?;
+
+    info!("Exported {written} events of blocks {start_block}..{end_block}");
+    Ok(written)
+}
+
+/// Parse the `start:end:path` argument of `--export-ndjson`. `end` is exclusive.
+pub fn parse_export_spec(spec: &str) -> Result<(Range<u64>, PathBuf)> {
+    let mut parts = spec.splitn(3, ':');
+    let (Some(start), Some(end), Some(path)) = (parts.next(), parts.next(), parts.next()) else {
+        eyre::bail!("Expected start:end:path, got '{spec}'");
+    };
+    let start: u64 = start
+        .parse()
+        .wrap_err_with(|| format!("Invalid start block '{start}'"))?;
+    let end: u64 = end
+        .parse()
+        .wrap_err_with(|| format!("Invalid end block '{end}'"))?;
+    if start >= end {
+        eyre::bail!("Start block {start} must be below end block {end}");
+    }
+    if path.is_empty() {
+        eyre::bail!("Missing output path in '{spec}'");
+    }
+
+    Ok((start..end, PathBuf::from(path)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_export_spec() {
+        let (range, path) = parse_export_spec("100:200:/tmp/events.ndjson").unwrap();
+        assert_eq!(range, 100..200);
+        assert_eq!(path, PathBuf::from("/tmp/events.ndjson"));
+
+        // Only the first two colons separate fields.
+        let (_, path) = parse_export_spec("1:2:C:/events.ndjson").unwrap();
+        assert_eq!(path, PathBuf::from("C:/events.ndjson"));
+    }
+
+    #[test]
+    fn test_parse_export_spec_invalid() {
+        assert!(parse_export_spec("100:200").is_err());
+        assert!(parse_export_spec("a:200:out").is_err());
+        assert!(parse_export_spec("200:100:out").is_err());
+        assert!(parse_export_spec("100:200:").is_err());
+    }
+}
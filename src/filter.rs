@@ -0,0 +1,311 @@
+//! Selecting staking events by validator, delegator, type and block.
+
+use std::collections::HashSet;
+use std::ops::Range;
+use std::str::FromStr;
+
+use alloy::primitives::Address;
+
+use crate::events::{self, StakingEvent, StakingEventType};
+
+/// Criteria an event has to meet, all of them. A criterion that is `None`
+/// accepts every event, so the default filter accepts everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EventFilter {
+    /// Events without a validator, such as epoch changes, never match.
+    pub validator_ids: Option<HashSet<u64>>,
+    /// Addresses without `0x` prefix, compared with the delegators of the
+    /// events ignoring case. Events without a delegator never match.
+    pub delegators: Option<HashSet<String>>,
+    pub event_types: Option<HashSet<StakingEventType>>,
+    pub block_range: Option<Range<u64>>,
+}
+
+impl EventFilter {
+    /// Whether `event` meets every criterion.
+    pub fn apply(&self, event: &StakingEvent) -> bool {
+        let matches_validator = match (&self.validator_ids, event.validator_id()) {
+            (None, _) => true,
+            (Some(ids), Some(id)) => ids.contains(&id),
+            (Some(_), None) => false,
+        };
+        let matches_delegator = match (&self.delegators, event.delegator()) {
+            (None, _) => true,
+            (Some(delegators), Some(delegator)) => delegators
+                .iter()
+                .any(|wanted| wanted.eq_ignore_ascii_case(delegator)),
+            (Some(_), None) => false,
+        };
+        let matches_type = self
+            .event_types
+            .as_ref()
+            .is_none_or(|types| types.contains(&event.event_type()));
+
+        matches_validator
+            && matches_delegator
+            && matches_type
+            && self.includes_block(event.block_meta().block_number)
+    }
+
+    /// A filter on the comma-separated lists of `/v1/events` and the export
+    /// flags, where a list that is `None` accepts everything. Delegators are
+    /// accepted with or without `0x` prefix and event types by name, both in
+    /// any case.
+    pub fn from_lists(
+        validator_ids: Option<&str>,
+        delegators: Option<&str>,
+        event_types: Option<&str>,
+    ) -> Result<EventFilter, String> {
+        let validator_ids = validator_ids
+            .map(|ids| {
+                split_list(ids)
+                    .map(|id| {
+                        id.parse()
+                            .map_err(|e| format!("invalid validator id '{id}': {e}"))
+                    })
+                    .collect::<Result<_, _>>()
+            })
+            .transpose()?;
+        let delegators = delegators
+            .map(|delegators| {
+                split_list(delegators)
+                    .map(|delegator| {
+                        let address = Address::from_str(delegator)
+                            .map_err(|e| format!("invalid delegator '{delegator}': {e}"))?;
+                        Ok(events::to_checksum_address(address.as_slice()))
+                    })
+                    .collect::<Result<_, String>>()
+            })
+            .transpose()?;
+        let event_types = event_types
+            .map(|types| split_list(types).map(StakingEventType::from_str).collect())
+            .transpose()?;
+
+        Ok(EventFilter {
+            validator_ids,
+            delegators,
+            event_types,
+            block_range: None,
+        })
+    }
+
+    /// Whether `block_number` is within the block range.
+    pub fn includes_block(&self, block_number: u64) -> bool {
+        self.block_range
+            .as_ref()
+            .is_none_or(|range| range.contains(&block_number))
+    }
+}
+
+fn split_list(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BlockBatch;
+    use crate::test_utils::{
+        make_block_meta, make_claim_rewards_event, make_delegate_event, make_epoch_changed_event,
+        make_validator_created_event, make_validator_rewarded_event,
+    };
+    use proptest::prelude::*;
+
+    const DELEGATORS: [&str; 2] = [
+        "1234567890123456789012345678901234567890",
+        "ABCDEF0123456789ABCDEF0123456789ABCDEF01",
+    ];
+
+    fn event_strategy() -> impl Strategy<Value = StakingEvent> {
+        (0..50u64, 0..4u64, 0..DELEGATORS.len(), 1..1000u64, 0..5u8).prop_map(
+            |(block, val_id, delegator, amount, kind)| {
+                let delegator = DELEGATORS[delegator];
+                match kind {
+                    0 => make_delegate_event(block, val_id, delegator, amount),
+                    1 => make_claim_rewards_event(block, val_id, delegator, amount),
+                    2 => make_validator_rewarded_event(block, val_id, amount),
+                    3 => make_validator_created_event(block, val_id, amount),
+                    _ => make_epoch_changed_event(block, amount),
+                }
+            },
+        )
+    }
+
+    fn filter_strategy() -> impl Strategy<Value = EventFilter> {
+        (
+            proptest::option::of(proptest::collection::hash_set(0..4u64, 0..3)),
+            proptest::option::of(proptest::sample::subsequence(DELEGATORS.to_vec(), 0..=2)),
+            proptest::option::of(proptest::sample::subsequence(
+                StakingEventType::all_types().to_vec(),
+                0..4,
+            )),
+            proptest::option::of((0..50u64, 0..50u64)),
+        )
+            .prop_map(
+                |(validator_ids, delegators, event_types, blocks)| EventFilter {
+                    validator_ids,
+                    delegators: delegators
+                        .map(|delegators| delegators.into_iter().map(str::to_string).collect()),
+                    event_types: event_types.map(|types| types.into_iter().collect()),
+                    block_range: blocks.map(|(start, len)| start..start + len),
+                },
+            )
+    }
+
+    /// A batch of `events` as produced by scanning every block they can be in.
+    fn batch_of(events: &[StakingEvent]) -> BlockBatch {
+        let mut batch = BlockBatch::new();
+        for event in events {
+            batch.add_block_meta(event.block_meta().clone());
+            batch.add_event(event.clone());
+        }
+        batch.scanned = Some(0..50);
+        batch.checkpoint = Some(49);
+        batch
+    }
+
+    fn to_json(batch: &BlockBatch) -> serde_json::Value {
+        serde_json::to_value(batch).unwrap()
+    }
+
+    #[test]
+    fn test_apply() {
+        let delegate = make_delegate_event(100, 1, DELEGATORS[0], 10);
+        let epoch_changed = make_epoch_changed_event(100, 5);
+
+        let filter = EventFilter {
+            validator_ids: Some(HashSet::from([1])),
+            ..Default::default()
+        };
+        assert!(filter.apply(&delegate));
+        assert!(!filter.apply(&epoch_changed));
+
+        let filter = EventFilter {
+            delegators: Some(HashSet::from([DELEGATORS[1].to_string()])),
+            ..Default::default()
+        };
+        assert!(!filter.apply(&delegate));
+        let filter = EventFilter {
+            delegators: Some(HashSet::from([DELEGATORS[1].to_lowercase()])),
+            ..Default::default()
+        };
+        assert!(filter.apply(&make_delegate_event(100, 1, DELEGATORS[1], 10)));
+
+        let filter = EventFilter {
+            event_types: Some(HashSet::from([StakingEventType::EpochChanged])),
+            block_range: Some(100..101),
+            ..Default::default()
+        };
+        assert!(!filter.apply(&delegate));
+        assert!(filter.apply(&epoch_changed));
+
+        let filter = EventFilter {
+            block_range: Some(50..100),
+            ..Default::default()
+        };
+        assert!(!filter.apply(&delegate));
+    }
+
+    #[test]
+    fn test_filter_keeps_blocks_in_range() {
+        let mut batch = batch_of(&[make_delegate_event(100, 1, DELEGATORS[0], 10)]);
+        batch.add_block_meta(make_block_meta(101));
+        batch.add_block_meta(make_block_meta(102));
+        batch.scanned = Some(100..103);
+
+        let filtered = batch.filter(&EventFilter {
+            block_range: Some(101..103),
+            ..Default::default()
+        });
+        assert_eq!(filtered.total_event_count(), 0);
+        assert_eq!(
+            filtered.block_meta,
+            vec![make_block_meta(101), make_block_meta(102)]
+        );
+        assert_eq!(filtered.scanned, None);
+    }
+
+    #[test]
+    fn test_filter_without_block_range_keeps_scanned_range() {
+        let batch = batch_of(&[make_delegate_event(10, 1, DELEGATORS[0], 10)]);
+        let filtered = batch.filter(&EventFilter {
+            validator_ids: Some(HashSet::from([2])),
+            ..Default::default()
+        });
+        assert_eq!(filtered.total_event_count(), 0);
+        assert_eq!(filtered.block_meta, vec![make_block_meta(10)]);
+        assert_eq!(filtered.scanned, Some(0..50));
+        assert_eq!(filtered.checkpoint, Some(49));
+    }
+
+    #[test]
+    fn test_from_lists() {
+        let filter = EventFilter::from_lists(
+            Some("1, 2"),
+            Some(&format!("0x{}", DELEGATORS[1].to_lowercase())),
+            Some("delegate,CLAIMREWARDS"),
+        )
+        .unwrap();
+        assert_eq!(filter.validator_ids, Some(HashSet::from([1, 2])));
+        assert_eq!(
+            filter.delegators,
+            Some(HashSet::from([
+                "abCDeF0123456789AbcdEf0123456789aBCDEF01".to_string()
+            ]))
+        );
+        assert_eq!(
+            filter.event_types,
+            Some(HashSet::from([
+                StakingEventType::Delegate,
+                StakingEventType::ClaimRewards
+            ]))
+        );
+        assert_eq!(
+            EventFilter::from_lists(None, None, None).unwrap(),
+            EventFilter::default()
+        );
+
+        assert!(EventFilter::from_lists(Some("x"), None, None).is_err());
+        assert!(EventFilter::from_lists(None, Some("0x1234"), None).is_err());
+        assert!(EventFilter::from_lists(None, None, Some("Stake")).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn test_default_filter_is_identity(
+            events in proptest::collection::vec(event_strategy(), 0..30),
+        ) {
+            let batch = batch_of(&events);
+            prop_assert_eq!(to_json(&batch.filter(&EventFilter::default())), to_json(&batch));
+        }
+
+        #[test]
+        fn test_filter_keeps_matching_events(
+            events in proptest::collection::vec(event_strategy(), 0..30),
+            filter in filter_strategy(),
+        ) {
+            let batch = batch_of(&events);
+            let filtered = batch.filter(&filter);
+            prop_assert!(filtered.events().all(|event| filter.apply(&event)));
+            prop_assert_eq!(
+                filtered.total_event_count(),
+                batch.events().filter(|event| filter.apply(event)).count()
+            );
+        }
+
+        #[test]
+        fn test_filters_compose(
+            events in proptest::collection::vec(event_strategy(), 0..30),
+            first in filter_strategy(),
+            second in filter_strategy(),
+        ) {
+            let batch = batch_of(&events);
+            prop_assert_eq!(
+                to_json(&batch.filter(&first).filter(&second)),
+                to_json(&batch.filter(&second).filter(&first))
+            );
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tokio::sync::watch;
+use tokio::time::{Duration, Instant};
+
+/// Liveness and readiness signals shared between the indexer tasks and the
+/// `/healthz` and `/readyz` endpoints of the metrics server.
+#[derive(Debug, Clone)]
+pub struct HealthState {
+    /// Time of the last heartbeat of each event loop, by task name.
+    heartbeats: Arc<watch::Sender<HashMap<&'static str, Instant>>>,
+    rpc_connected: Arc<AtomicBool>,
+    liveness_timeout: Duration,
+}
+
+impl HealthState {
+    pub fn new(liveness_timeout: Duration) -> Self {
+        HealthState {
+            heartbeats: Arc::new(watch::Sender::new(HashMap::new())),
+            rpc_connected: Arc::new(AtomicBool::new(false)),
+            liveness_timeout,
+        }
+    }
+
+    /// Records that the event loop `task` is still making progress. A task is
+    /// only checked for liveness once it pinged for the first time.
+    pub fn ping(&self, task: &'static str) {
+        self.heartbeats.send_modify(|heartbeats| {
+            heartbeats.insert(task, Instant::now());
+        });
+    }
+
+    /// Records that an RPC connection succeeded.
+    pub fn record_rpc_connected(&self) {
+        self.rpc_connected.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether any RPC connection succeeded since startup.
+    pub fn rpc_connected(&self) -> bool {
+        self.rpc_connected.load(Ordering::Relaxed)
+    }
+
+    /// Fails with the names of the tasks whose last heartbeat is older than the
+    /// liveness timeout.
+    pub fn check_liveness(&self) -> Result<(), String> {
+        let heartbeats = self.heartbeats.borrow();
+        let mut stale: Vec<&str> = heartbeats
+            .iter()
+            .filter(|(_, last)| last.elapsed() > self.liveness_timeout)
+            .map(|(task, _)| *task)
+            .collect();
+        if stale.is_empty() {
+            return Ok(());
+        }
+        stale.sort_unstable();
+        Err(format!("no heartbeat from {}", stale.join(", ")))
+    }
+}
+
+impl Default for HealthState {
+    fn default() -> Self {
+        HealthState::new(Duration::from_secs(900))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_liveness_reports_stale_tasks() {
+        let health = HealthState::new(Duration::from_millis(20));
+        assert_eq!(health.check_liveness(), Ok(()));
+
+        health.ping("db");
+        health.ping("live");
+        assert_eq!(health.check_liveness(), Ok(()));
+
+        std::thread::sleep(Duration::from_millis(30));
+        health.ping("live");
+        assert_eq!(
+            health.check_liveness(),
+            Err("no heartbeat from db".to_string())
+        );
+    }
+
+    #[test]
+    fn test_rpc_connected_is_shared() {
+        let health = HealthState::default();
+        let clone = health.clone();
+        assert!(!health.rpc_connected());
+        clone.record_rpc_connected();
+        assert!(health.rpc_connected());
+    }
+}
@@ -1,11 +1,30 @@
+pub mod aggregates;
+pub mod alerts;
+pub mod api;
+pub mod aws_secrets;
+pub mod checkpoint;
+pub mod cli;
 pub mod config;
 pub mod contract_abi;
 pub mod db;
 pub mod error;
 pub mod events;
+pub mod export;
+pub mod filter;
+pub mod health;
+pub mod logging;
 pub mod metrics;
 pub mod pg_utils;
+pub mod pipeline;
 pub mod provider;
+pub mod pushgateway;
+pub mod queue;
+pub mod rate_limit;
+pub mod reload;
+pub mod reorg;
+pub mod sink;
+pub mod validator_state;
+pub mod vault;
 
 pub mod test_utils;
 
@@ -14,18 +33,24 @@ use alloy::primitives::Address;
 pub const STAKING_CONTRACT_ADDRESS: Address =
     alloy::primitives::address!("0000000000000000000000000000000000001000");
 
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::hash::Hash;
 use std::ops::Range;
+use std::path::{Path, PathBuf};
 
 use eyre::Result;
-use log::{error, info};
+use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Duration;
+use tracing::{Instrument, error, info, info_span, warn};
 
 use crate::events::{
     BlockMeta, ClaimRewardsEvent, CommissionChangedEvent, DelegateEvent, EpochChangedEvent,
-    StakingEvent, UndelegateEvent, ValidatorCreatedEvent, ValidatorRewardedEvent,
-    ValidatorStatusChangedEvent, WithdrawEvent,
+    RawEvent, StakingEvent, StakingEventType, UndelegateEvent, ValidatorCreatedEvent,
+    ValidatorRewardedEvent, ValidatorStatusChangedEvent, WithdrawEvent,
 };
+use crate::filter::EventFilter;
 
 pub fn chunk_range(range: Range<u64>, chunk_size: u64) -> Vec<Range<u64>> {
     let mut chunks = Vec::with_capacity(((range.end - range.start) / chunk_size) as usize);
@@ -40,13 +65,254 @@ pub fn chunk_range(range: Range<u64>, chunk_size: u64) -> Vec<Range<u64>> {
     chunks
 }
 
+/// Group block numbers into ascending ranges of consecutive blocks. Repeated
+/// numbers are merged.
+pub fn contiguous_ranges(blocks: &[u64]) -> Vec<Range<u64>> {
+    let mut sorted = blocks.to_vec();
+    sorted.sort_unstable();
+    sorted.dedup();
+
+    let mut ranges: Vec<Range<u64>> = Vec::new();
+    for block in sorted {
+        match ranges.last_mut() {
+            Some(range) if range.end == block => range.end = block + 1,
+            _ => ranges.push(block..block + 1),
+        }
+    }
+    ranges
+}
+
+/// Decode `logs` into a batch holding every block with at least one event of the
+/// staking contract at `contract_address`, in block order. Within a block,
+/// events keep their order in the chain.
+pub fn build_block_batch_from_logs(
+    mut logs: Vec<alloy::rpc::types::Log>,
+    contract_address: Address,
+) -> Result<BlockBatch> {
+    logs.sort_by_key(|l| (l.block_number, l.transaction_index, l.log_index));
+
+    let mut blocks_map: BTreeMap<u64, (BlockMeta, Vec<StakingEvent>)> = BTreeMap::new();
+    for log in logs {
+        if let Some(event) = events::extract_event(&log, contract_address)? {
+            let block_num = event.block_meta().block_number;
+            blocks_map
+                .entry(block_num)
+                .or_insert_with(|| (event.block_meta().clone(), Vec::new()))
+                .1
+                .push(event);
+        }
+    }
+
+    let mut batch = BlockBatch::new();
+    for (meta, events) in blocks_map.into_values() {
+        batch.add_block_meta(meta);
+        for event in events {
+            batch.add_event(event);
+        }
+    }
+
+    Ok(batch)
+}
+
+/// Block from which the live stream queues a catch-up range on startup.
+///
+/// This is the checkpoint or the highest indexed block, whichever is further, or
+/// `min_start_block` on a first run with an empty database. `None` means no
+/// catch-up is queued.
+///
+/// Without a checkpoint yet, one is recorded where scanning is known to be
+/// complete: at `min_start_block` if nothing below it is stored, else at the
+/// last block before the first gap in the stored blocks. Gaps above it are
+/// then found and backfilled like any other.
+///
+/// If the highest indexed block cannot be queried, the checkpoint file at
+/// `checkpoint_path` is used instead, when there is one.
+pub async fn startup_start_block(
+    pool: &PgPool,
+    min_start_block: Option<u64>,
+    checkpoint_path: Option<&Path>,
+) -> Result<Option<u64>, db::repository::DbError> {
+    let max_block = match db::repository::get_max_block_number(pool).await {
+        Ok(max_block) => max_block,
+        Err(e) => {
+            let saved = checkpoint_path.map(checkpoint::Checkpoint::load);
+            match saved {
+                Some(Ok(Some(checkpoint))) => {
+                    warn!(
+                        "Failed to get the highest indexed block, starting from the checkpoint file at {checkpoint}: {e}"
+                    );
+                    return Ok(Some(checkpoint));
+                }
+                Some(Err(load_error)) => {
+                    error!("Failed to read the checkpoint file: {load_error:#}");
+                }
+                Some(Ok(None)) | None => {}
+            }
+            return Err(e);
+        }
+    };
+    let checkpoint = db::repository::get_checkpoint(pool).await?;
+    let start_block = checkpoint.max(max_block).or(min_start_block);
+
+    if checkpoint.is_none() {
+        let min_block = db::repository::get_min_block_number(pool).await?;
+        let seed = match (min_start_block, min_block) {
+            (Some(min_start_block), Some(min_block)) if min_start_block < min_block => {
+                Some(min_start_block)
+            }
+            (_, Some(_)) => db::repository::get_first_run_end(pool).await?,
+            (min_start_block, None) => min_start_block,
+        };
+        if let Some(seed) = seed {
+            db::repository::set_checkpoint(pool, seed).await?;
+        }
+    }
+
+    Ok(start_block)
+}
+
+/// Tracks which block ranges have been scanned to advance the checkpoint.
+///
+/// Ranges from the live stream and from backfill arrive in any order; the
+/// checkpoint only moves once everything up to it has been scanned. Ranges
+/// beyond the checkpoint are kept, merged, until the missing ones arrive.
+#[derive(Debug, Default, Clone)]
+pub struct ScanProgress {
+    checkpoint: Option<u64>,
+    /// Disjoint, non-adjacent scanned ranges above the checkpoint, by start block.
+    pending: BTreeMap<u64, u64>,
+}
+
+impl ScanProgress {
+    /// Start from a persisted checkpoint. Without one, the first scanned range
+    /// defines the checkpoint.
+    pub fn new(checkpoint: Option<u64>) -> Self {
+        Self {
+            checkpoint,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    pub fn checkpoint(&self) -> Option<u64> {
+        self.checkpoint
+    }
+
+    /// The checkpoint that [`ScanProgress::add`] would produce for `range`.
+    pub fn checkpoint_with(&self, range: &Range<u64>) -> Option<u64> {
+        let mut progress = self.clone();
+        progress.add(range.clone());
+        progress.checkpoint
+    }
+
+    pub fn add(&mut self, range: Range<u64>) {
+        if range.is_empty() {
+            return;
+        }
+
+        let Some(mut checkpoint) = self.checkpoint else {
+            self.checkpoint = Some(range.end - 1);
+            return;
+        };
+
+        if range.start > checkpoint + 1 {
+            let mut start = range.start;
+            let mut end = range.end;
+            let overlapping: Vec<u64> = self
+                .pending
+                .range(..=end)
+                .filter(|&(_, &pending_end)| pending_end >= start)
+                .map(|(&pending_start, _)| pending_start)
+                .collect();
+            for pending_start in overlapping {
+                let pending_end = self.pending.remove(&pending_start).unwrap();
+                start = start.min(pending_start);
+                end = end.max(pending_end);
+            }
+            self.pending.insert(start, end);
+            return;
+        }
+
+        checkpoint = checkpoint.max(range.end - 1);
+        while let Some((&start, &end)) = self.pending.first_key_value() {
+            if start > checkpoint + 1 {
+                break;
+            }
+            checkpoint = checkpoint.max(end - 1);
+            self.pending.pop_first();
+        }
+        self.checkpoint = Some(checkpoint);
+    }
+
+    /// Marks `ranges` as not scanned, e.g. after their blocks were deleted.
+    /// The checkpoint moves to just below the first of them, the scanned
+    /// blocks between them are kept as pending.
+    pub fn rewind(&mut self, ranges: &[Range<u64>]) {
+        let Some(first) = ranges
+            .iter()
+            .filter(|range| !range.is_empty())
+            .map(|range| range.start)
+            .min()
+        else {
+            return;
+        };
+        if let Some(checkpoint) = self.checkpoint
+            && checkpoint >= first
+        {
+            // Pending ranges start beyond `checkpoint + 1`, so this one is
+            // neither adjacent to nor overlapping them.
+            self.pending.insert(first, checkpoint + 1);
+            self.checkpoint = first.checked_sub(1);
+        }
+        for range in ranges.iter().filter(|range| !range.is_empty()) {
+            let overlapping: Vec<(u64, u64)> = self
+                .pending
+                .range(..range.end)
+                .filter(|&(_, &pending_end)| pending_end > range.start)
+                .map(|(&start, &end)| (start, end))
+                .collect();
+            for (start, end) in overlapping {
+                self.pending.remove(&start);
+                if start < range.start {
+                    self.pending.insert(start, range.start);
+                }
+                if range.end < end {
+                    self.pending.insert(range.end, end);
+                }
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct CompleteBlock {
     pub block_meta: BlockMeta,
     pub events: Vec<StakingEvent>,
 }
 
-#[derive(Debug, Default)]
+/// Rows removed from a batch by [`BlockBatch::dedupe`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BatchDuplicates {
+    /// Repeated block metadata, by block number.
+    pub blocks: u64,
+    /// Exact copies of an event earlier in the batch, per event type.
+    pub events: HashMap<StakingEventType, u64>,
+}
+
+impl BatchDuplicates {
+    pub fn is_empty(&self) -> bool {
+        self.blocks == 0 && self.events.is_empty()
+    }
+}
+
+/// Keep the first of each set of equal items, returning how many were removed.
+fn dedupe_vec<T: Clone + Eq + Hash>(items: &mut Vec<T>) -> u64 {
+    let before = items.len();
+    let mut seen = HashSet::new();
+    items.retain(|item| seen.insert(item.clone()));
+    (before - items.len()) as u64
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct BlockBatch {
     pub block_meta: Vec<BlockMeta>,
     pub delegate: Vec<DelegateEvent>,
@@ -58,6 +324,20 @@ pub struct BlockBatch {
     pub validator_created: Vec<ValidatorCreatedEvent>,
     pub validator_status_changed: Vec<ValidatorStatusChangedEvent>,
     pub commission_changed: Vec<CommissionChangedEvent>,
+    /// Events of the staking contract that could not be decoded.
+    #[serde(default)]
+    pub raw: Vec<RawEvent>,
+    /// Block range fully scanned by the producer of this batch, including blocks
+    /// without events.
+    pub scanned: Option<Range<u64>>,
+    /// Block range whose stored blocks and events are deleted in the insert
+    /// transaction before the batch is inserted, to index it again. Kept when
+    /// the batch is dead-lettered, so that its replay replaces them too.
+    #[serde(default)]
+    pub replaces: Option<Range<u64>>,
+    /// Checkpoint to record together with this batch, see [`ScanProgress`].
+    #[serde(skip)]
+    pub checkpoint: Option<u64>,
 }
 
 impl BlockBatch {
@@ -73,6 +353,10 @@ impl BlockBatch {
             validator_created: Vec::new(),
             validator_status_changed: Vec::new(),
             commission_changed: Vec::new(),
+            raw: Vec::new(),
+            scanned: None,
+            replaces: None,
+            checkpoint: None,
         }
     }
 
@@ -87,76 +371,1111 @@ impl BlockBatch {
             StakingEvent::ValidatorCreated(e) => self.validator_created.push(e),
             StakingEvent::ValidatorStatusChanged(e) => self.validator_status_changed.push(e),
             StakingEvent::CommissionChanged(e) => self.commission_changed.push(e),
+            StakingEvent::Unknown(e) => self.raw.push(e),
         }
     }
 
     pub fn add_block_meta(&mut self, meta: BlockMeta) {
         self.block_meta.push(meta);
     }
+
+    /// Number of events in the batch, across all event types.
+    pub fn total_event_count(&self) -> usize {
+        self.event_count_by_type().values().sum()
+    }
+
+    /// Number of events per event type. Types without events are left out.
+    pub fn event_count_by_type(&self) -> HashMap<StakingEventType, usize> {
+        [
+            (StakingEventType::Delegate, self.delegate.len()),
+            (StakingEventType::Undelegate, self.undelegate.len()),
+            (StakingEventType::Withdraw, self.withdraw.len()),
+            (StakingEventType::ClaimRewards, self.claim_rewards.len()),
+            (
+                StakingEventType::ValidatorRewarded,
+                self.validator_rewarded.len(),
+            ),
+            (StakingEventType::EpochChanged, self.epoch_changed.len()),
+            (
+                StakingEventType::ValidatorCreated,
+                self.validator_created.len(),
+            ),
+            (
+                StakingEventType::ValidatorStatusChanged,
+                self.validator_status_changed.len(),
+            ),
+            (
+                StakingEventType::CommissionChanged,
+                self.commission_changed.len(),
+            ),
+            (StakingEventType::Unknown, self.raw.len()),
+        ]
+        .into_iter()
+        .filter(|(_, count)| *count > 0)
+        .collect()
+    }
+
+    /// Names of the event types in the batch, comma-separated in declaration
+    /// order, e.g. for the `event_type` field of spans.
+    pub fn event_type_names(&self) -> String {
+        let counts = self.event_count_by_type();
+        StakingEventType::all_types()
+            .iter()
+            .filter(|event_type| counts.contains_key(event_type))
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Remove events that occur more than once in the batch and repeated block
+    /// metadata, keeping the first occurrence.
+    ///
+    /// Reconnects and overlapping backfill chunks can deliver the same log twice.
+    /// Those copies are dropped here so that the duplicates reported by the
+    /// insert are events already stored by an earlier batch.
+    pub fn dedupe(&mut self) -> BatchDuplicates {
+        let mut seen_blocks = HashSet::new();
+        let block_count = self.block_meta.len();
+        self.block_meta
+            .retain(|meta| seen_blocks.insert(meta.block_number));
+
+        let events = [
+            (StakingEventType::Delegate, dedupe_vec(&mut self.delegate)),
+            (
+                StakingEventType::Undelegate,
+                dedupe_vec(&mut self.undelegate),
+            ),
+            (StakingEventType::Withdraw, dedupe_vec(&mut self.withdraw)),
+            (
+                StakingEventType::ClaimRewards,
+                dedupe_vec(&mut self.claim_rewards),
+            ),
+            (
+                StakingEventType::ValidatorRewarded,
+                dedupe_vec(&mut self.validator_rewarded),
+            ),
+            (
+                StakingEventType::EpochChanged,
+                dedupe_vec(&mut self.epoch_changed),
+            ),
+            (
+                StakingEventType::ValidatorCreated,
+                dedupe_vec(&mut self.validator_created),
+            ),
+            (
+                StakingEventType::ValidatorStatusChanged,
+                dedupe_vec(&mut self.validator_status_changed),
+            ),
+            (
+                StakingEventType::CommissionChanged,
+                dedupe_vec(&mut self.commission_changed),
+            ),
+            (StakingEventType::Unknown, dedupe_vec(&mut self.raw)),
+        ];
+
+        BatchDuplicates {
+            blocks: (block_count - self.block_meta.len()) as u64,
+            events: events
+                .into_iter()
+                .filter(|(_, removed)| *removed > 0)
+                .collect(),
+        }
+    }
+
+    /// Iterate over clones of all events in the batch, grouped by event type.
+    pub fn events(&self) -> impl Iterator<Item = StakingEvent> + '_ {
+        self.delegate
+            .iter()
+            .cloned()
+            .map(StakingEvent::Delegate)
+            .chain(
+                self.undelegate
+                    .iter()
+                    .cloned()
+                    .map(StakingEvent::Undelegate),
+            )
+            .chain(self.withdraw.iter().cloned().map(StakingEvent::Withdraw))
+            .chain(
+                self.claim_rewards
+                    .iter()
+                    .cloned()
+                    .map(StakingEvent::ClaimRewards),
+            )
+            .chain(
+                self.validator_rewarded
+                    .iter()
+                    .cloned()
+                    .map(StakingEvent::ValidatorRewarded),
+            )
+            .chain(
+                self.epoch_changed
+                    .iter()
+                    .cloned()
+                    .map(StakingEvent::EpochChanged),
+            )
+            .chain(
+                self.validator_created
+                    .iter()
+                    .cloned()
+                    .map(StakingEvent::ValidatorCreated),
+            )
+            .chain(
+                self.validator_status_changed
+                    .iter()
+                    .cloned()
+                    .map(StakingEvent::ValidatorStatusChanged),
+            )
+            .chain(
+                self.commission_changed
+                    .iter()
+                    .cloned()
+                    .map(StakingEvent::CommissionChanged),
+            )
+            .chain(self.raw.iter().cloned().map(StakingEvent::Unknown))
+    }
+
+    /// Clones of all events in the batch in the order the chain emitted them,
+    /// by block, transaction index and log index.
+    pub fn events_in_chain_order(&self) -> Vec<StakingEvent> {
+        let mut events: Vec<_> = self.events().collect();
+        events.sort_by_key(|event| {
+            let tx_meta = event.tx_meta();
+            (
+                event.block_meta().block_number,
+                tx_meta.transaction_index,
+                tx_meta.log_index,
+            )
+        });
+        events
+    }
+
+    /// A batch with the events matching `filter`, and the metadata of the
+    /// blocks in its block range. With a block range the scanned range and
+    /// checkpoint are left out, as the result may not cover those blocks.
+    pub fn filter(&self, filter: &EventFilter) -> BlockBatch {
+        let mut batch = BlockBatch::new();
+        batch.block_meta = self
+            .block_meta
+            .iter()
+            .filter(|meta| filter.includes_block(meta.block_number))
+            .cloned()
+            .collect();
+        for event in self.events().filter(|event| filter.apply(event)) {
+            batch.add_event(event);
+        }
+        if filter.block_range.is_none() {
+            batch.scanned = self.scanned.clone();
+            batch.checkpoint = self.checkpoint;
+        }
+        batch
+    }
+
+    /// Partition the batch into sub-batches of at most `max_events` events each.
+    ///
+    /// Blocks are never split across sub-batches, so a single block with more
+    /// than `max_events` events ends up alone in an oversized sub-batch. Block
+    /// order is preserved. The scanned range and checkpoint go with the last
+    /// sub-batch, the replaced range is split so that each sub-batch replaces
+    /// the blocks up to the first one of the next.
+    pub fn split_at_size(&self, max_events: usize) -> Vec<BlockBatch> {
+        let mut block_order: Vec<u64> = Vec::new();
+        let mut events_per_block: HashMap<u64, usize> = HashMap::new();
+        for meta in &self.block_meta {
+            if let std::collections::hash_map::Entry::Vacant(entry) =
+                events_per_block.entry(meta.block_number)
+            {
+                entry.insert(0);
+                block_order.push(meta.block_number);
+            }
+        }
+        for event in self.events() {
+            let block_number = event.block_meta().block_number;
+            let count = events_per_block.entry(block_number).or_insert_with(|| {
+                block_order.push(block_number);
+                0
+            });
+            *count += 1;
+        }
+
+        let mut batch_of_block: HashMap<u64, usize> = HashMap::new();
+        let mut batch_count = 0;
+        let mut current_size = 0;
+        for block_number in block_order {
+            let block_size = events_per_block[&block_number];
+            if batch_count == 0 || (current_size > 0 && current_size + block_size > max_events) {
+                batch_count += 1;
+                current_size = 0;
+            }
+            current_size += block_size;
+            batch_of_block.insert(block_number, batch_count - 1);
+        }
+
+        let mut batches: Vec<BlockBatch> = (0..batch_count).map(|_| BlockBatch::new()).collect();
+        for meta in &self.block_meta {
+            batches[batch_of_block[&meta.block_number]].add_block_meta(meta.clone());
+        }
+        for event in self.events() {
+            batches[batch_of_block[&event.block_meta().block_number]].add_event(event);
+        }
+        if let Some(replaces) = &self.replaces {
+            let mut ends: Vec<u64> = batches
+                .iter()
+                .skip(1)
+                .filter_map(|batch| batch.block_meta.first().map(|meta| meta.block_number))
+                .collect();
+            ends.push(replaces.end);
+            let mut start = replaces.start;
+            for (batch, end) in batches.iter_mut().zip(ends) {
+                batch.replaces = Some(start..end);
+                start = end;
+            }
+        }
+        if let Some(last) = batches.last_mut() {
+            last.scanned = self.scanned.clone();
+            last.checkpoint = self.checkpoint;
+        }
+
+        batches
+    }
+
+    /// A batch with only the events of validator `validator_id` and the blocks
+    /// they are in, once each and in block order.
+    ///
+    /// Events without a validator, such as epoch changes, are left out. The
+    /// result covers only part of the scanned range, so it carries neither the
+    /// scanned range nor the checkpoint.
+    pub fn filter_events_by_validator(&self, validator_id: u64) -> BlockBatch {
+        let mut filtered = BlockBatch::new();
+        let mut blocks = BTreeMap::new();
+        for event in self
+            .events()
+            .filter(|event| event.validator_id() == Some(validator_id))
+        {
+            blocks
+                .entry(event.block_meta().block_number)
+                .or_insert_with(|| event.block_meta().clone());
+            filtered.add_event(event);
+        }
+        filtered.block_meta = blocks.into_values().collect();
+        filtered
+    }
+}
+
+/// Groups the events of the live stream into batches of complete blocks.
+///
+/// The events of a block arrive one by one, so the block of the latest event is
+/// buffered until an event of a later block shows that it is complete, the
+/// stream has been quiet for a flush interval, or the stream closes.
+#[derive(Debug, Default)]
+pub struct LiveBatcher {
+    batch_size: usize,
+    batch: BlockBatch,
+    block_count: usize,
+    current_block_meta: Option<BlockMeta>,
+    current_block_buffer: Vec<StakingEvent>,
+    /// Start of the range received from the stream that is not yet part of a batch.
+    scanned_from: Option<u64>,
+    /// End of the range covered by the blocks moved into the batch.
+    completed_to: Option<u64>,
+    /// Whether events arrived since the last [`LiveBatcher::flush`].
+    received_since_flush: bool,
+}
+
+impl LiveBatcher {
+    pub fn new(batch_size: usize) -> Self {
+        Self {
+            batch_size,
+            ..Default::default()
+        }
+    }
+
+    /// Add an event from the stream, returning a batch once `batch_size` blocks
+    /// are complete.
+    pub fn push(&mut self, event: StakingEvent) -> Option<BlockBatch> {
+        let block_number = event.block_meta().block_number;
+        self.received_since_flush = true;
+        if self.scanned_from.is_none() {
+            self.scanned_from = Some(block_number);
+        }
+        if self
+            .current_block_meta
+            .as_ref()
+            .is_some_and(|meta| meta.block_number != block_number)
+        {
+            self.complete_current_block();
+        }
+
+        self.current_block_meta = Some(event.block_meta().clone());
+        self.current_block_buffer.push(event);
+
+        if self.block_count >= self.batch_size {
+            self.take_batch(block_number)
+        } else {
+            None
+        }
+    }
+
+    /// The complete blocks received so far, sent even when the batch is not full
+    /// or holds no blocks at all, so that the scanned range advances.
+    pub fn checkpoint(&mut self) -> Option<BlockBatch> {
+        let scanned_to = self.current_block_meta.as_ref()?.block_number;
+        self.take_batch(scanned_to)
+    }
+
+    /// A partial batch, for when the flush interval elapses.
+    ///
+    /// The buffered block is only considered complete when no events arrived
+    /// since the previous flush, so that a flush in the middle of a block does
+    /// not split it across batches.
+    pub fn flush(&mut self) -> Option<BlockBatch> {
+        if !std::mem::take(&mut self.received_since_flush) {
+            self.complete_current_block();
+        }
+        if self.batch.block_meta.is_empty() {
+            return None;
+        }
+        let scanned_to = self
+            .current_block_meta
+            .as_ref()
+            .map(|meta| meta.block_number)
+            .or(self.completed_to)?;
+        self.take_batch(scanned_to)
+    }
+
+    /// Everything buffered, once the stream closed.
+    ///
+    /// Blocks missed until the stream is reconnected are not part of the
+    /// scanned range and are left to gap detection.
+    pub fn stream_closed(&mut self) -> Option<BlockBatch> {
+        self.complete_current_block();
+        let batch = self
+            .completed_to
+            .and_then(|scanned_to| self.take_batch(scanned_to));
+        self.scanned_from = None;
+        self.received_since_flush = false;
+        batch
+    }
+
+    fn complete_current_block(&mut self) {
+        let Some(meta) = self.current_block_meta.take() else {
+            return;
+        };
+        self.completed_to = Some(meta.block_number + 1);
+        self.batch.add_block_meta(meta);
+        for event in self.current_block_buffer.drain(..) {
+            self.batch.add_event(event);
+        }
+        self.block_count += 1;
+    }
+
+    /// Take the batch, together with the range `scanned_from..scanned_to` it covers.
+    fn take_batch(&mut self, scanned_to: u64) -> Option<BlockBatch> {
+        let mut batch = std::mem::take(&mut self.batch);
+        self.block_count = 0;
+        if let Some(from) = self.scanned_from.filter(|&from| from < scanned_to) {
+            batch.scanned = Some(from..scanned_to);
+            self.scanned_from = Some(scanned_to);
+        }
+        if batch.block_meta.is_empty() && batch.scanned.is_none() {
+            return None;
+        }
+        Some(batch)
+    }
+}
+
+/// Where the blocks of a batch came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchOrigin {
+    /// New blocks from the live subscription.
+    Live,
+    /// Old blocks fetched by range, during backfill, gap filling or reindexing.
+    Backfill,
+}
+
+/// Why a range is queued for the gaps task, which selects its
+/// [`config::BackfillProfile`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GapOrigin {
+    /// The blocks between the last indexed one and the first live event after
+    /// startup.
+    CatchUp,
+    /// A gap found by the gap check.
+    Repair,
+    /// Stored blocks to index again, replaced by the batches that backfill
+    /// them. Found by the reorg check or requested by the `reindex` command.
+    Reindex,
 }
 
 pub enum DbRequest {
-    InsertCompleteBlocks(Box<BlockBatch>),
+    InsertCompleteBlocks(Box<BlockBatch>, BatchOrigin),
     GetBlockGaps,
+    ReplayFailedBatches,
+    /// Delete blocks replaced by a reorg along with their events, then
+    /// backfill them again.
+    ReindexReorgedBlocks(Vec<u64>),
+    /// Reply with the number of stored events of the given type.
+    GetEventCount(StakingEventType, oneshot::Sender<u64>),
+}
+
+async fn report_pending_withdrawals(
+    pool: &PgPool,
+    metrics_tx: &mpsc::UnboundedSender<metrics::Metric>,
+) {
+    match db::repository::get_total_pending_withdrawals(pool).await {
+        Ok(total) => {
+            let _ = metrics_tx.send(metrics::Metric::PendingWithdrawals(total));
+        }
+        Err(e) => {
+            error!("Failed to get pending withdrawals: {}", e);
+        }
+    }
+}
+
+/// Report the total delegation of every validator with events in `blocks`.
+async fn report_validator_delegations(
+    pool: &PgPool,
+    blocks: &BlockBatch,
+    metrics_tx: &mpsc::UnboundedSender<metrics::Metric>,
+) {
+    let validator_ids: Vec<u64> = blocks
+        .events()
+        .filter_map(|event| event.validator_id())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    if validator_ids.is_empty() {
+        return;
+    }
+    match db::repository::get_total_stakes(pool, &validator_ids).await {
+        Ok(stakes) => {
+            for (validator_id, total) in stakes {
+                let _ = metrics_tx.send(metrics::Metric::ValidatorDelegation {
+                    validator_id,
+                    total,
+                });
+            }
+        }
+        Err(e) => {
+            error!("Failed to get validator delegations: {}", e);
+        }
+    }
+}
+
+/// Make sure partitions exist for `block_number` and at least one partition
+/// beyond it. `covered` is the first block not known to have a partition.
+async fn ensure_partitions_for(pool: &PgPool, block_number: u64, covered: &mut u64) {
+    let partition_size = db::repository::PARTITION_SIZE;
+    if block_number + partition_size < *covered {
+        return;
+    }
+
+    let up_to_block = block_number + partition_size;
+    match db::ensure_partitions(pool, up_to_block).await {
+        Ok(_) => *covered = (up_to_block / partition_size + 1) * partition_size,
+        Err(e) => error!("Failed to create partitions up to block {up_to_block}: {e}"),
+    }
+}
+
+/// How much longer the single retry of a timed out insert may take.
+const INSERT_RETRY_TIMEOUT_FACTOR: u32 = 2;
+
+/// Insert `batch`, retrying once with a longer timeout if the first attempt or
+/// one of its statements ran out of time. Each timed out attempt is reported
+/// as `InsertTimeout`.
+pub async fn insert_blocks_with_retry(
+    pool: &PgPool,
+    batch: &BlockBatch,
+    timeout: Duration,
+    policy: db::DuplicatePolicy,
+    strategies: db::ConflictStrategies,
+    block_strategy: db::BlockConflictStrategy,
+    metrics_tx: &mpsc::UnboundedSender<metrics::Metric>,
+) -> Result<db::InsertReport, db::repository::DbError> {
+    match db::insert_blocks(pool, batch, timeout, policy, strategies, block_strategy).await {
+        Err(e) if e.is_timeout() => {
+            let _ = metrics_tx.send(metrics::Metric::InsertTimeout);
+            let retry_timeout = timeout * INSERT_RETRY_TIMEOUT_FACTOR;
+            warn!(
+                "Insert timed out ({}), retrying with a timeout of {:?}",
+                e, retry_timeout
+            );
+            let result = db::insert_blocks(
+                pool,
+                batch,
+                retry_timeout,
+                policy,
+                strategies,
+                block_strategy,
+            )
+            .await;
+            if result.as_ref().is_err_and(|e| e.is_timeout()) {
+                let _ = metrics_tx.send(metrics::Metric::InsertTimeout);
+            }
+            result
+        }
+        result => result,
+    }
+}
+
+/// Maximum number of dead-lettered batches replayed per `ReplayFailedBatches` request.
+const DEAD_LETTER_REPLAY_LIMIT: i64 = 100;
+
+async fn report_dead_letter_depth(
+    pool: &PgPool,
+    metrics_tx: &mpsc::UnboundedSender<metrics::Metric>,
+) {
+    match db::repository::get_failed_batch_counts(pool).await {
+        Ok((waiting, parked)) => {
+            let _ = metrics_tx.send(metrics::Metric::DeadLetterDepth(waiting));
+            let _ = metrics_tx.send(metrics::Metric::DeadLetterParked(parked));
+        }
+        Err(e) => {
+            error!("Failed to count dead-lettered batches: {}", e);
+        }
+    }
+}
+
+/// Time from the newest block of `blocks` being produced until `now`, zero if
+/// its timestamp is in the future.
+fn ingest_latency(blocks: &BlockBatch, now: std::time::SystemTime) -> Option<Duration> {
+    let newest = blocks.block_meta.iter().map(|m| m.block_timestamp).max()?;
+    let produced = std::time::UNIX_EPOCH + Duration::from_secs(newest);
+    Some(now.duration_since(produced).unwrap_or_default())
 }
 
+/// Insert one batch on behalf of the DB task, advancing `progress` and
+/// reporting metrics. Failures are logged and counted before being returned.
+async fn insert_batch(
+    pool: &PgPool,
+    blocks: &mut BlockBatch,
+    origin: BatchOrigin,
+    progress: &mut ScanProgress,
+    partitions_covered: &mut u64,
+    options: &DbTaskOptions,
+    metrics_tx: &mpsc::UnboundedSender<metrics::Metric>,
+) -> Result<(), db::repository::DbError> {
+    let duplicates = blocks.dedupe();
+    if !duplicates.is_empty() {
+        info!(
+            duplicate_blocks = duplicates.blocks,
+            "Dropped {} repeated events from batch",
+            duplicates.events.values().sum::<u64>()
+        );
+        let _ = metrics_tx.send(metrics::Metric::IntraBatchDuplicates(duplicates));
+    }
+    if let Some(scanned) = &blocks.scanned {
+        blocks.checkpoint = progress
+            .checkpoint_with(scanned)
+            .filter(|&checkpoint| Some(checkpoint) > progress.checkpoint());
+    }
+    let first_block = blocks.block_meta.first().map(|m| m.block_number);
+    let last_block = blocks.block_meta.last().map(|m| m.block_number);
+    let event_counts = blocks.event_count_by_type();
+    let event_count: usize = event_counts.values().sum();
+    let breakdown = StakingEventType::all_types()
+        .iter()
+        .filter_map(|event_type| {
+            let count = event_counts.get(event_type)?;
+            Some(format!("{count} {event_type}"))
+        })
+        .collect::<Vec<_>>();
+    info!(
+        block_count = blocks.block_meta.len(),
+        event_count = event_count,
+        first_block = first_block,
+        last_block = last_block,
+        "Inserting {} blocks with {} events{}",
+        blocks.block_meta.len(),
+        event_count,
+        if breakdown.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", breakdown.join(", "))
+        }
+    );
+
+    if let Some(max_block) = blocks.block_meta.iter().map(|m| m.block_number).max() {
+        ensure_partitions_for(pool, max_block, partitions_covered).await;
+    }
+
+    let report = match insert_blocks_with_retry(
+        pool,
+        blocks,
+        options.operation_timeout,
+        options.duplicate_policy,
+        options.conflict_strategies,
+        options.block_conflict_strategy,
+        metrics_tx,
+    )
+    .await
+    {
+        Ok(report) => report,
+        Err(e) => {
+            match &e {
+                db::repository::DbError::OperationTimedOut { elapsed, rows } => {
+                    error!(
+                        first_block = first_block,
+                        last_block = last_block,
+                        rows = rows,
+                        "Insert operation timed out after {:?} on retry",
+                        elapsed
+                    );
+                    let _ = metrics_tx.send(metrics::Metric::FailedToInsert { table: None });
+                }
+                db::repository::DbError::StatementTimeout(e) => {
+                    error!(
+                        first_block = first_block,
+                        last_block = last_block,
+                        "Insert statement timed out on retry: {}",
+                        e
+                    );
+                    let _ = metrics_tx.send(metrics::Metric::FailedToInsert { table: None });
+                }
+                db::repository::DbError::Sqlx(sqlx::Error::PoolTimedOut) => {
+                    error!(
+                        first_block = first_block,
+                        last_block = last_block,
+                        "No database connection available for insert"
+                    );
+                    let _ = metrics_tx.send(metrics::Metric::DbPoolExhausted);
+                }
+                db::repository::DbError::TableInsert {
+                    table,
+                    block_range,
+                    source,
+                } => {
+                    error!(
+                        table = %table,
+                        table_first_block = block_range.0,
+                        table_last_block = block_range.1,
+                        "Failed to insert {} events of blocks {}..={}: {:?}",
+                        table,
+                        block_range.0,
+                        block_range.1,
+                        source
+                    );
+                    let _ = metrics_tx.send(metrics::Metric::FailedToInsert {
+                        table: Some(*table),
+                    });
+                }
+                e => {
+                    error!(
+                        first_block = first_block,
+                        last_block = last_block,
+                        "Failed to insert blocks: {:?}",
+                        e
+                    );
+                    let _ = metrics_tx.send(metrics::Metric::FailedToInsert { table: None });
+                }
+            }
+            return Err(e);
+        }
+    };
+
+    if report.blocks_updated > 0 {
+        warn!(
+            first_block = first_block,
+            last_block = last_block,
+            "Updated the hash or timestamp of {} already indexed blocks",
+            report.blocks_updated
+        );
+    }
+
+    if let Some(scanned) = blocks.scanned.take() {
+        progress.add(scanned);
+    }
+    if let Some(replaces) = &blocks.replaces {
+        info!(
+            events_deleted = report.replaced.events_deleted,
+            blocks_deleted = report.replaced.blocks_deleted,
+            "Replaced {} events and {} blocks in {:?}",
+            report.replaced.events_deleted,
+            report.replaced.blocks_deleted,
+            replaces
+        );
+        if report.replaced.blocks_deleted > 0 {
+            let _ = metrics_tx.send(metrics::Metric::ReorgedBlocksDeleted(
+                report.replaced.blocks_deleted,
+            ));
+        }
+    }
+    let total_inserted: u64 = report
+        .event_counts
+        .values()
+        .map(|(inserted, _)| inserted)
+        .sum();
+    info!(
+        events_inserted = total_inserted,
+        first_block = first_block,
+        last_block = last_block,
+        "Successfully inserted {} events",
+        total_inserted
+    );
+    for stats in &report.table_stats {
+        let _ = metrics_tx.send(metrics::Metric::TableInsert {
+            table: stats.table,
+            rows: stats.rows,
+            millis: stats.elapsed.as_millis() as u64,
+        });
+    }
+    if let Some(max_block) = blocks.block_meta.iter().map(|m| m.block_number).max() {
+        let _ = metrics_tx.send(metrics::Metric::BlocksInserted(
+            blocks.block_meta.len() as u64
+        ));
+        let _ = metrics_tx.send(metrics::Metric::MaxBlockInserted(max_block));
+    }
+    // Old blocks would swamp the histogram, so only live batches are measured.
+    if origin == BatchOrigin::Live
+        && let Some(latency) = ingest_latency(blocks, std::time::SystemTime::now())
+    {
+        let _ = metrics_tx.send(metrics::Metric::IngestLatency(latency));
+    }
+    let duplicates = metrics::duplicate_counts(&report.event_counts);
+    let _ = metrics_tx.send(metrics::Metric::InsertedEvents(report.event_counts));
+    if !duplicates.is_empty() {
+        let _ = metrics_tx.send(metrics::Metric::DuplicateEvents(duplicates));
+    }
+    if !report.unknown_events.is_empty() {
+        let _ = metrics_tx.send(metrics::Metric::UnknownEvents(report.unknown_events));
+    }
+    if report.negative_stakes > 0 {
+        let _ = metrics_tx.send(metrics::Metric::NegativeStakes(report.negative_stakes));
+    }
+    if !blocks.undelegate.is_empty() || !blocks.withdraw.is_empty() {
+        report_pending_withdrawals(pool, metrics_tx).await;
+    }
+    report_validator_delegations(pool, blocks, metrics_tx).await;
+    if blocks.replaces.is_some() {
+        // Deleted events may have set the cached state.
+        reload_validator_state(pool, options).await;
+    } else if let Some(validator_state) = &options.validator_state {
+        let mut cache = validator_state.write().unwrap();
+        for event in blocks.events() {
+            cache.update(&event);
+        }
+    }
+    if let Some(sink) = &options.sink {
+        publish_events(sink.as_ref(), blocks).await;
+    }
+
+    Ok(())
+}
+
+/// Forwards the events of an inserted batch to `sink`. The database stays the
+/// source of truth, so failures are only logged.
+async fn publish_events(sink: &dyn sink::EventSink, blocks: &BlockBatch) {
+    for event in blocks.events_in_chain_order() {
+        if let Err(e) = sink.publish(&event).await {
+            error!(
+                block_number = event.block_meta().block_number,
+                event_type = %event.event_type(),
+                "Failed to publish {event}: {e:?}"
+            );
+        }
+    }
+}
+
+/// Settings of [`process_db_requests`].
+#[derive(Debug, Clone)]
+pub struct DbTaskOptions {
+    /// Time limit of each database operation.
+    pub operation_timeout: Duration,
+    pub gap_options: db::repository::GapOptions,
+    /// File the committed checkpoint is mirrored to. Disabled when unset.
+    pub checkpoint_path: Option<PathBuf>,
+    /// How inserts treat events that are already stored. Under a policy other
+    /// than `Ignore`, a rejected duplicate stops the task.
+    pub duplicate_policy: db::DuplicatePolicy,
+    /// Whether inserts overwrite stored events with different values, per table.
+    pub conflict_strategies: db::ConflictStrategies,
+    /// Whether inserts overwrite the hash and timestamp of stored blocks.
+    pub block_conflict_strategy: db::BlockConflictStrategy,
+    /// Pinged for every request, so `/healthz` notices a stuck task.
+    pub health: health::HealthState,
+    /// Receives the events of every inserted batch. Disabled when unset.
+    pub sink: Option<std::sync::Arc<dyn sink::EventSink>>,
+    /// Updated with the events of every inserted batch. Disabled when unset.
+    pub validator_state: Option<validator_state::SharedValidatorStateCache>,
+    /// Failed replays after which a dead-lettered batch is parked.
+    pub dead_letter_max_attempts: u32,
+}
+
+/// The defaults of the configuration, without checkpoint file, sink or
+/// validator state cache.
+impl Default for DbTaskOptions {
+    fn default() -> Self {
+        Self {
+            operation_timeout: Duration::from_secs(10),
+            gap_options: Default::default(),
+            checkpoint_path: None,
+            duplicate_policy: Default::default(),
+            conflict_strategies: Default::default(),
+            block_conflict_strategy: Default::default(),
+            health: Default::default(),
+            sink: None,
+            validator_state: None,
+            dead_letter_max_attempts: 10,
+        }
+    }
+}
+
+/// Load the validator state cache of `options` again, after deleted events
+/// cleared attributes in the `validators` table.
+async fn reload_validator_state(pool: &PgPool, options: &DbTaskOptions) {
+    let Some(validator_state) = &options.validator_state else {
+        return;
+    };
+    match validator_state::ValidatorStateCache::load(pool).await {
+        Ok(cache) => *validator_state.write().unwrap() = cache,
+        Err(e) => error!("Failed to reload the validator state: {}", e),
+    }
+}
+
+/// Span of inserting `blocks` in the DB task.
+fn insert_span(blocks: &BlockBatch, origin: BatchOrigin) -> tracing::Span {
+    info_span!(
+        "insert_batch",
+        task_name = "db",
+        block_number = blocks.block_meta.first().map(|meta| meta.block_number),
+        block_count = blocks.block_meta.len(),
+        event_type = %blocks.event_type_names(),
+        origin = ?origin,
+    )
+}
+
+/// Serves the requests on `rx` until it closes. Returns the number of batches
+/// that failed to insert, moved to the dead-letter queue or lost.
 pub async fn process_db_requests(
-    pool: PgPool,
-    mut rx: mpsc::UnboundedReceiver<DbRequest>,
-    gap_tx: mpsc::UnboundedSender<Range<u64>>,
+    pools: db::DbPools,
+    mut rx: queue::CountingReceiver<DbRequest>,
+    gap_tx: queue::CountingSender<(Range<u64>, GapOrigin)>,
     metrics_tx: mpsc::UnboundedSender<metrics::Metric>,
-    db_operation_timeout_secs: u64,
-) -> Result<()> {
-    use tokio::time::Duration;
-    let timeout = Duration::from_secs(db_operation_timeout_secs);
+    options: DbTaskOptions,
+) -> Result<u64> {
+    let pool = &pools.writer;
+    let mut progress = ScanProgress::new(db::repository::get_checkpoint(pool).await?);
+    let mut partitions_covered = 0;
+    let mut failed_batches = 0;
     while let Some(req) = rx.recv().await {
+        options.health.ping("db");
+        let previous_checkpoint = progress.checkpoint();
         match req {
             DbRequest::GetBlockGaps => {
-                match db::repository::get_block_gaps(&pool).await {
+                match db::repository::get_max_block_number(&pools.reader).await {
+                    Ok(max_block) => {
+                        ensure_partitions_for(pool, max_block.unwrap_or(0), &mut partitions_covered)
+                            .await
+                    }
+                    Err(e) => error!("Failed to get max block: {}", e),
+                }
+                match db::repository::get_block_gaps(&pools.reader, &options.gap_options).await {
                     Ok(gaps) => {
-                        if gaps.is_empty() {
+                        let _ =
+                            metrics_tx.send(metrics::Metric::MissingBlocks(gaps.missing_blocks));
+                        if gaps.ranges.is_empty() {
                             info!("No gaps detected");
                         } else {
-                            info!("Detected {} gap(s)", gaps.len());
-                            for range in gaps {
-                                info!("Queueing gap for backfill: {:?}", range);
-                                gap_tx.send(range)?;
+                            info!(
+                                missing_blocks = gaps.missing_blocks,
+                                "Queueing {} gap(s) for backfill",
+                                gaps.ranges.len()
+                            );
+                            for range in gaps.ranges {
+                                info!(
+                                    gap_start = range.start,
+                                    gap_end = range.end,
+                                    "Queueing gap for backfill: {:?}",
+                                    range
+                                );
+                                gap_tx.send((range, GapOrigin::Repair))?;
+                            }
+                        }
+                        match db::repository::get_block_count(&pools.reader).await {
+                            Ok(count) => {
+                                let _ = metrics_tx.send(metrics::Metric::IndexedBlockCount(count));
                             }
+                            Err(e) => error!("Failed to count indexed blocks: {}", e),
                         }
                     }
                     Err(e) => {
                         error!("Failed to check for gaps: {}", e);
                     }
                 };
+                report_pending_withdrawals(&pools.reader, &metrics_tx).await;
             }
-            DbRequest::InsertCompleteBlocks(blocks) => {
-                info!("Inserting {} blocks", blocks.block_meta.len(),);
-
-                match db::insert_blocks(&pool, &blocks, timeout).await {
-                    Ok(event_counts) => {
-                        let total_inserted: u64 =
-                            event_counts.values().map(|(inserted, _)| inserted).sum();
-                        info!("Successfully inserted {} events", total_inserted);
-                        let _ = metrics_tx.send(metrics::Metric::InsertedEvents(event_counts));
+            DbRequest::InsertCompleteBlocks(mut blocks, origin) => {
+                let span = insert_span(&blocks, origin);
+                let insert = insert_batch(
+                    pool,
+                    &mut blocks,
+                    origin,
+                    &mut progress,
+                    &mut partitions_covered,
+                    &options,
+                    &metrics_tx,
+                )
+                .instrument(span)
+                .await;
+                if let Err(e) = insert {
+                    if matches!(e, db::repository::DbError::DuplicateEvent { .. }) {
+                        return Err(e.into());
                     }
-                    Err(db::repository::DbError::Sqlx(sqlx::Error::PoolTimedOut)) => {
-                        error!("Insert operation timed out");
-                        let _ = metrics_tx.send(metrics::Metric::InsertTimeout);
+                    failed_batches += 1;
+                    match db::repository::spool_failed_batch(pool, &blocks, &e.to_string()).await {
+                        Ok(()) => info!("Moved failed batch to the dead-letter queue"),
+                        Err(e) => error!("Failed to dead-letter batch, it is lost: {}", e),
                     }
+                    report_dead_letter_depth(pool, &metrics_tx).await;
+                }
+            }
+            DbRequest::ReplayFailedBatches => {
+                let failed =
+                    db::repository::get_due_failed_batches(pool, DEAD_LETTER_REPLAY_LIMIT).await;
+                let failed = match failed {
+                    Ok(failed) => failed,
                     Err(e) => {
-                        error!("Failed to insert blocks: {:?}", e);
-                        let _ = metrics_tx.send(metrics::Metric::FailedToInsert);
+                        error!("Failed to load dead-lettered batches: {}", e);
+                        continue;
+                    }
+                };
+                for mut failed in failed {
+                    info!(
+                        id = failed.id,
+                        attempts = failed.attempts,
+                        "Replaying dead-lettered batch"
+                    );
+                    let span = insert_span(&failed.batch, BatchOrigin::Backfill);
+                    let result = match insert_batch(
+                        pool,
+                        &mut failed.batch,
+                        BatchOrigin::Backfill,
+                        &mut progress,
+                        &mut partitions_covered,
+                        &options,
+                        &metrics_tx,
+                    )
+                    .instrument(span)
+                    .await
+                    {
+                        Ok(()) => db::repository::delete_failed_batch(pool, failed.id).await,
+                        Err(e @ db::repository::DbError::DuplicateEvent { .. }) => {
+                            return Err(e.into());
+                        }
+                        Err(e) => db::repository::defer_failed_batch(
+                            pool,
+                            failed.id,
+                            &e.to_string(),
+                            options.dead_letter_max_attempts,
+                        )
+                        .await
+                        .map(|parked| {
+                            if parked {
+                                warn!(
+                                    id = failed.id,
+                                    "Parked dead-lettered batch after {} failed replays: {}",
+                                    failed.attempts + 1,
+                                    e
+                                );
+                            }
+                        }),
+                    };
+                    if let Err(e) = result {
+                        error!(
+                            id = failed.id,
+                            "Failed to update dead-lettered batch: {}", e
+                        );
                     }
                 }
+                report_dead_letter_depth(pool, &metrics_tx).await;
+            }
+            DbRequest::ReindexReorgedBlocks(blocks) => {
+                // Blocks of an abandoned fork are not served until they are
+                // indexed again. If deleting fails, the backfilled batches
+                // still replace them.
+                match db::repository::delete_events_for_blocks(pool, &blocks).await {
+                    Ok(report) => {
+                        // The checkpoint was lowered with the deletion, so the
+                        // gap check finds the blocks again after a restart.
+                        progress.rewind(&contiguous_ranges(&blocks));
+                        if report.blocks_deleted > 0 {
+                            let _ = metrics_tx
+                                .send(metrics::Metric::ReorgedBlocksDeleted(report.blocks_deleted));
+                        }
+                    }
+                    Err(e) => error!("Failed to delete reorged blocks: {}", e),
+                }
+                for range in contiguous_ranges(&blocks) {
+                    info!(
+                        gap_start = range.start,
+                        gap_end = range.end,
+                        "Queueing reorged blocks for re-indexing: {:?}",
+                        range
+                    );
+                    gap_tx.send((range, GapOrigin::Reindex))?;
+                }
+            }
+            DbRequest::GetEventCount(event_type, reply) => {
+                match db::repository::get_event_count(&pools.reader, event_type).await {
+                    Ok(count) => {
+                        let _ = reply.send(count);
+                    }
+                    Err(e) => error!("Failed to count {} events: {}", event_type, e),
+                }
+            }
+        }
+        // Mirror the committed checkpoint to the file once the batch that moved
+        // it is stored. Saving syncs the file, so it runs off the async workers.
+        if let Some(path) = &options.checkpoint_path
+            && let Some(checkpoint) = progress.checkpoint()
+            && progress.checkpoint() != previous_checkpoint
+        {
+            let save_path = path.clone();
+            let saved = tokio::task::spawn_blocking(move || {
+                checkpoint::Checkpoint::save(&save_path, checkpoint)
+            })
+            .await;
+            match saved {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => warn!("Failed to save checkpoint file {}: {:?}", path.display(), e),
+                Err(e) => warn!("Failed to save checkpoint file {}: {}", path.display(), e),
             }
         }
     }
-    Ok(())
+    Ok(failed_batches)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_utils::{make_delegate_log, make_epoch_changed_log, make_rpc_log};
+
+    #[test]
+    fn test_ingest_latency_uses_newest_block() {
+        let mut batch = BlockBatch::new();
+        assert_eq!(ingest_latency(&batch, std::time::SystemTime::now()), None);
+
+        for (block_number, block_timestamp) in [(1, 1_000), (3, 1_030), (2, 1_020)] {
+            batch.add_block_meta(BlockMeta {
+                block_number,
+                block_hash: format!("0xhash{block_number}"),
+                block_timestamp,
+            });
+        }
+        let now = std::time::UNIX_EPOCH + Duration::from_secs(1_040);
+        assert_eq!(ingest_latency(&batch, now), Some(Duration::from_secs(10)));
+
+        let before = std::time::UNIX_EPOCH + Duration::from_secs(1_000);
+        assert_eq!(ingest_latency(&batch, before), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_contiguous_ranges() {
+        assert_eq!(contiguous_ranges(&[]), Vec::<Range<u64>>::new());
+        assert_eq!(
+            contiguous_ranges(&[12, 10, 11, 11, 20, 22, 21, 30]),
+            vec![10..13, 20..23, 30..31]
+        );
+    }
 
     #[test]
     fn test_chunk_range_even_division() {
@@ -209,4 +1528,527 @@ mod tests {
         assert_eq!(chunks[0].start, 0);
         assert_eq!(chunks[chunks.len() - 1].end, 100);
     }
+
+    fn block_meta(block_number: u64) -> BlockMeta {
+        BlockMeta {
+            block_number,
+            block_hash: format!("{:064x}", block_number),
+            block_timestamp: 1234567890 + block_number,
+        }
+    }
+
+    fn delegate(block_number: u64, val_id: u64, tx_index: u64) -> StakingEvent {
+        StakingEvent::Delegate(DelegateEvent {
+            val_id,
+            delegator: "1234567890123456789012345678901234567890".to_string(),
+            amount: 1000u64.into(),
+            activation_epoch: 1,
+            block_meta: block_meta(block_number),
+            tx_meta: events::TxMeta {
+                transaction_hash: format!("{:064x}", block_number * 1000 + tx_index),
+                transaction_index: tx_index,
+                log_index: tx_index,
+            },
+        })
+    }
+
+    fn batch_with_blocks(events_per_block: &[(u64, u64)]) -> BlockBatch {
+        let mut batch = BlockBatch::new();
+        for &(block_number, event_count) in events_per_block {
+            batch.add_block_meta(block_meta(block_number));
+            for i in 0..event_count {
+                batch.add_event(delegate(block_number, 1, i));
+            }
+        }
+        batch
+    }
+
+    fn block_numbers(batch: &BlockBatch) -> Vec<u64> {
+        batch.block_meta.iter().map(|m| m.block_number).collect()
+    }
+
+    #[test]
+    fn test_split_at_size_keeps_blocks_together() {
+        let batch = batch_with_blocks(&[(1, 2), (2, 2), (3, 2), (4, 1)]);
+        let split = batch.split_at_size(4);
+
+        assert_eq!(split.len(), 2);
+        assert_eq!(block_numbers(&split[0]), vec![1, 2]);
+        assert_eq!(split[0].delegate.len(), 4);
+        assert_eq!(block_numbers(&split[1]), vec![3, 4]);
+        assert_eq!(split[1].delegate.len(), 3);
+
+        for sub_batch in &split {
+            for event in &sub_batch.delegate {
+                assert!(block_numbers(sub_batch).contains(&event.block_meta.block_number));
+            }
+        }
+    }
+
+    #[test]
+    fn test_split_at_size_oversized_block() {
+        let batch = batch_with_blocks(&[(1, 1), (2, 10), (3, 1)]);
+        let split = batch.split_at_size(3);
+
+        assert_eq!(split.len(), 3);
+        assert_eq!(block_numbers(&split[0]), vec![1]);
+        assert_eq!(block_numbers(&split[1]), vec![2]);
+        assert_eq!(split[1].delegate.len(), 10);
+        assert_eq!(block_numbers(&split[2]), vec![3]);
+    }
+
+    #[test]
+    fn test_split_at_size_mixed_event_types() {
+        let mut batch = batch_with_blocks(&[(1, 1), (2, 1)]);
+        batch.add_event(StakingEvent::EpochChanged(EpochChangedEvent {
+            old_epoch: 1,
+            new_epoch: 2,
+            block_meta: block_meta(1),
+            tx_meta: events::TxMeta {
+                transaction_hash: format!("{:064x}", 42),
+                transaction_index: 1,
+                log_index: 0,
+            },
+        }));
+
+        let split = batch.split_at_size(2);
+        assert_eq!(split.len(), 2);
+        assert_eq!(split[0].delegate.len(), 1);
+        assert_eq!(split[0].epoch_changed.len(), 1);
+        assert_eq!(block_numbers(&split[1]), vec![2]);
+        assert_eq!(split[1].delegate.len(), 1);
+        assert!(split[1].epoch_changed.is_empty());
+    }
+
+    #[test]
+    fn test_split_at_size_splits_replaced_range() {
+        let mut batch = batch_with_blocks(&[(1, 2), (2, 2), (3, 2)]);
+        batch.replaces = Some(0..10);
+        let split = batch.split_at_size(4);
+
+        assert_eq!(split.len(), 2);
+        assert_eq!(split[0].replaces, Some(0..3));
+        assert_eq!(split[1].replaces, Some(3..10));
+    }
+
+    #[test]
+    fn test_split_at_size_fits_in_one() {
+        let batch = batch_with_blocks(&[(1, 1), (2, 0), (3, 1)]);
+        let split = batch.split_at_size(100);
+        assert_eq!(split.len(), 1);
+        assert_eq!(block_numbers(&split[0]), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_split_at_size_empty() {
+        assert!(BlockBatch::new().split_at_size(10).is_empty());
+    }
+
+    fn validator_ids(batch: &BlockBatch) -> HashSet<Option<u64>> {
+        batch.events().map(|event| event.validator_id()).collect()
+    }
+
+    #[test]
+    fn test_filter_events_by_validator() {
+        let mut batch = batch_with_blocks(&[(1, 1), (2, 0), (3, 1)]);
+        batch.add_event(delegate(2, 7, 0));
+        batch.add_event(delegate(3, 7, 1));
+        batch.add_event(delegate(4, 1, 0));
+        batch.add_block_meta(block_meta(4));
+        batch.scanned = Some(1..5);
+
+        let filtered = batch.filter_events_by_validator(7);
+        assert_eq!(block_numbers(&filtered), vec![2, 3]);
+        assert_eq!(filtered.delegate.len(), 2);
+        assert_eq!(validator_ids(&filtered), HashSet::from([Some(7)]));
+        assert_eq!(filtered.scanned, None);
+
+        let filtered = batch.filter_events_by_validator(1);
+        assert_eq!(block_numbers(&filtered), vec![1, 3, 4]);
+        assert_eq!(filtered.delegate.len(), 3);
+    }
+
+    #[test]
+    fn test_filter_events_by_validator_all_events_match() {
+        let batch = batch_with_blocks(&[(1, 2), (2, 0), (3, 3), (1, 1)]);
+
+        let filtered = batch.filter_events_by_validator(1);
+        // Blocks without events are dropped and repeated ones kept once.
+        assert_eq!(block_numbers(&filtered), vec![1, 3]);
+        assert_eq!(filtered.delegate, batch.delegate);
+    }
+
+    #[test]
+    fn test_filter_events_by_validator_skips_events_without_validator() {
+        let mut batch = batch_with_blocks(&[(1, 1)]);
+        batch.add_block_meta(block_meta(2));
+        batch.add_event(StakingEvent::EpochChanged(EpochChangedEvent {
+            old_epoch: 1,
+            new_epoch: 2,
+            block_meta: block_meta(2),
+            tx_meta: events::TxMeta {
+                transaction_hash: format!("{:064x}", 42),
+                transaction_index: 0,
+                log_index: 0,
+            },
+        }));
+        batch.add_event(StakingEvent::CommissionChanged(CommissionChangedEvent {
+            validator_id: 1,
+            old_commission: 1u64.into(),
+            new_commission: 2u64.into(),
+            block_meta: block_meta(2),
+            tx_meta: events::TxMeta {
+                transaction_hash: format!("{:064x}", 43),
+                transaction_index: 1,
+                log_index: 0,
+            },
+        }));
+
+        let filtered = batch.filter_events_by_validator(1);
+        assert_eq!(block_numbers(&filtered), vec![1, 2]);
+        assert_eq!(filtered.delegate.len(), 1);
+        assert_eq!(filtered.commission_changed.len(), 1);
+        assert!(filtered.epoch_changed.is_empty());
+    }
+
+    #[test]
+    fn test_filter_events_by_validator_without_matches() {
+        let batch = batch_with_blocks(&[(1, 2), (2, 1)]);
+
+        let filtered = batch.filter_events_by_validator(99);
+        assert!(filtered.block_meta.is_empty());
+        assert_eq!(filtered.total_event_count(), 0);
+    }
+
+    #[test]
+    fn test_dedupe_removes_repeated_blocks_and_events() {
+        // The same block delivered twice, e.g. by overlapping backfill chunks.
+        let mut batch = batch_with_blocks(&[(1, 2), (2, 1), (1, 2)]);
+        batch.add_event(delegate(2, 1, 0));
+
+        let duplicates = batch.dedupe();
+        assert_eq!(
+            duplicates,
+            BatchDuplicates {
+                blocks: 1,
+                events: HashMap::from([(StakingEventType::Delegate, 3)]),
+            }
+        );
+        assert_eq!(block_numbers(&batch), vec![1, 2]);
+        assert_eq!(batch.delegate.len(), 3);
+
+        assert!(batch.dedupe().is_empty());
+    }
+
+    #[test]
+    fn test_event_count_by_type() {
+        let mut batch = batch_with_blocks(&[(1, 2), (2, 1)]);
+        batch.add_event(StakingEvent::EpochChanged(EpochChangedEvent {
+            old_epoch: 1,
+            new_epoch: 2,
+            block_meta: block_meta(2),
+            tx_meta: events::TxMeta {
+                transaction_hash: format!("{:064x}", 42),
+                transaction_index: 1,
+                log_index: 0,
+            },
+        }));
+
+        assert_eq!(
+            batch.event_count_by_type(),
+            HashMap::from([
+                (StakingEventType::Delegate, 3),
+                (StakingEventType::EpochChanged, 1),
+            ])
+        );
+        assert_eq!(batch.total_event_count(), 4);
+        assert_eq!(BlockBatch::new().total_event_count(), 0);
+    }
+
+    #[test]
+    fn test_dedupe_keeps_distinct_events() {
+        let mut batch = batch_with_blocks(&[(1, 1)]);
+        // Same transaction, different validator.
+        batch.add_event(delegate(1, 2, 0));
+
+        assert!(batch.dedupe().is_empty());
+        assert_eq!(batch.delegate.len(), 2);
+    }
+
+    #[test]
+    fn test_scan_progress_in_order() {
+        let mut progress = ScanProgress::new(Some(99));
+        progress.add(100..200);
+        assert_eq!(progress.checkpoint(), Some(199));
+        progress.add(200..210);
+        assert_eq!(progress.checkpoint(), Some(209));
+    }
+
+    #[test]
+    fn test_scan_progress_out_of_order() {
+        let mut progress = ScanProgress::new(Some(99));
+        progress.add(300..400);
+        progress.add(200..250);
+        assert_eq!(progress.checkpoint(), Some(99));
+        assert_eq!(progress.checkpoint_with(&(100..200)), Some(249));
+        assert_eq!(progress.checkpoint(), Some(99));
+
+        progress.add(100..200);
+        assert_eq!(progress.checkpoint(), Some(249));
+        progress.add(250..300);
+        assert_eq!(progress.checkpoint(), Some(399));
+    }
+
+    #[test]
+    fn test_scan_progress_overlapping_and_stale_ranges() {
+        let mut progress = ScanProgress::new(Some(99));
+        progress.add(150..200);
+        progress.add(120..160);
+        progress.add(50..90);
+        assert_eq!(progress.checkpoint(), Some(99));
+        progress.add(90..125);
+        assert_eq!(progress.checkpoint(), Some(199));
+    }
+
+    #[test]
+    fn test_scan_progress_first_range_sets_checkpoint() {
+        let mut progress = ScanProgress::new(None);
+        assert_eq!(progress.checkpoint(), None);
+        progress.add(500..510);
+        assert_eq!(progress.checkpoint(), Some(509));
+    }
+
+    #[test]
+    fn test_scan_progress_rewind() {
+        let mut progress = ScanProgress::new(Some(299));
+        progress.add(400..450);
+        progress.rewind(&[200..202, 210..211, 420..430]);
+        assert_eq!(progress.checkpoint(), Some(199));
+
+        // The blocks between the rewound ranges count as scanned again once
+        // the ranges are.
+        progress.add(210..211);
+        assert_eq!(progress.checkpoint(), Some(199));
+        progress.add(200..202);
+        assert_eq!(progress.checkpoint(), Some(299));
+        progress.add(300..400);
+        assert_eq!(progress.checkpoint(), Some(419));
+        progress.add(420..430);
+        assert_eq!(progress.checkpoint(), Some(449));
+
+        // Ranges above the checkpoint leave it alone.
+        progress.rewind(&[500..510, 520..521]);
+        assert_eq!(progress.checkpoint(), Some(449));
+    }
+
+    #[test]
+    fn test_build_block_batch_from_logs_orders_blocks_and_events() {
+        let batch = build_block_batch_from_logs(
+            vec![
+                make_delegate_log(11, 0, 3),
+                make_epoch_changed_log(10, 1),
+                make_delegate_log(10, 2, 2),
+                make_delegate_log(10, 0, 1),
+            ],
+            STAKING_CONTRACT_ADDRESS,
+        )
+        .unwrap();
+
+        assert_eq!(block_numbers(&batch), vec![10, 11]);
+        let delegations: Vec<(u64, u64)> = batch
+            .delegate
+            .iter()
+            .map(|e| (e.block_meta.block_number, e.val_id))
+            .collect();
+        assert_eq!(delegations, vec![(10, 1), (10, 2), (11, 3)]);
+        assert_eq!(batch.epoch_changed.len(), 1);
+        assert_eq!(batch.epoch_changed[0].block_meta.block_number, 10);
+        assert_eq!(batch.scanned, None);
+    }
+
+    #[test]
+    fn test_build_block_batch_from_logs_keeps_unknown_logs() {
+        let unknown = alloy::primitives::LogData::new_unchecked(
+            vec![alloy::primitives::B256::repeat_byte(0xff)],
+            vec![0xab, 0xcd].into(),
+        );
+        let batch = build_block_batch_from_logs(
+            vec![
+                make_rpc_log(5, 0, 0, unknown),
+                make_rpc_log(6, 0, 0, Default::default()),
+                make_delegate_log(7, 0, 1),
+            ],
+            STAKING_CONTRACT_ADDRESS,
+        )
+        .unwrap();
+
+        assert_eq!(block_numbers(&batch), vec![5, 7]);
+        assert_eq!(batch.events().count(), 2);
+        assert_eq!(batch.raw.len(), 1);
+        assert_eq!(batch.raw[0].topic0, "ff".repeat(32));
+        assert_eq!(batch.raw[0].data, vec![0xab, 0xcd]);
+
+        assert!(
+            build_block_batch_from_logs(Vec::new(), STAKING_CONTRACT_ADDRESS)
+                .unwrap()
+                .block_meta
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_build_block_batch_from_logs_skips_other_contracts() {
+        let mut other = make_delegate_log(8, 0, 2);
+        other.inner.address = Address::repeat_byte(0x42);
+        let batch = build_block_batch_from_logs(
+            vec![make_delegate_log(7, 0, 1), other.clone()],
+            STAKING_CONTRACT_ADDRESS,
+        )
+        .unwrap();
+        assert_eq!(block_numbers(&batch), vec![7]);
+
+        let batch = build_block_batch_from_logs(
+            vec![make_delegate_log(7, 0, 1), other],
+            Address::repeat_byte(0x42),
+        )
+        .unwrap();
+        assert_eq!(block_numbers(&batch), vec![8]);
+    }
+
+    #[test]
+    fn test_build_block_batch_from_logs_rejects_incomplete_logs() {
+        let mut log = make_delegate_log(7, 0, 1);
+        log.block_hash = None;
+        assert!(build_block_batch_from_logs(vec![log], STAKING_CONTRACT_ADDRESS).is_err());
+    }
+
+    #[test]
+    fn test_mock_provider_serves_logs() {
+        use crate::provider::LogProvider;
+        use futures_util::StreamExt;
+
+        let provider = test_utils::MockProvider::with_logs(vec![
+            make_delegate_log(10, 0, 1),
+            make_epoch_changed_log(11, 0),
+            make_delegate_log(12, 0, 2),
+        ]);
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let logs = runtime
+            .block_on(provider.historical_logs(&(11..13)))
+            .unwrap();
+        assert_eq!(
+            block_numbers(&build_block_batch_from_logs(logs, STAKING_CONTRACT_ADDRESS).unwrap()),
+            vec![11, 12]
+        );
+
+        let streamed: Vec<_> =
+            runtime.block_on(async { provider.stream_events().await.unwrap().collect().await });
+        assert_eq!(
+            block_numbers(
+                &build_block_batch_from_logs(streamed, STAKING_CONTRACT_ADDRESS).unwrap()
+            ),
+            vec![10, 11, 12]
+        );
+    }
+
+    /// The events a live subscription to `logs` yields, in order.
+    fn streamed_events(logs: Vec<alloy::rpc::types::Log>) -> Vec<StakingEvent> {
+        use crate::provider::LogProvider;
+        use futures_util::StreamExt;
+
+        let provider = test_utils::MockProvider::with_logs(logs);
+        let logs: Vec<_> = tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(async { provider.stream_events().await.unwrap().collect().await });
+        logs.iter()
+            .filter_map(|log| events::extract_event(log, STAKING_CONTRACT_ADDRESS).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_live_batcher_sends_full_batches() {
+        let mut batcher = LiveBatcher::new(2);
+        let mut batches = Vec::new();
+        for event in streamed_events(vec![
+            make_delegate_log(10, 0, 1),
+            make_delegate_log(10, 1, 2),
+            make_delegate_log(11, 0, 1),
+            make_epoch_changed_log(13, 0),
+            make_delegate_log(14, 0, 1),
+        ]) {
+            batches.extend(batcher.push(event));
+        }
+
+        assert_eq!(batches.len(), 1);
+        assert_eq!(block_numbers(&batches[0]), vec![10, 11]);
+        assert_eq!(batches[0].delegate.len(), 3);
+        assert_eq!(batches[0].scanned, Some(10..13));
+
+        let batch = batcher.checkpoint().unwrap();
+        assert_eq!(block_numbers(&batch), vec![13]);
+        assert_eq!(batch.scanned, Some(13..14));
+    }
+
+    #[test]
+    fn test_live_batcher_flushes_buffered_block_when_quiet() {
+        let mut batcher = LiveBatcher::new(100);
+        assert!(batcher.flush().is_none());
+
+        for event in streamed_events(vec![
+            make_delegate_log(10, 0, 1),
+            make_delegate_log(11, 0, 1),
+        ]) {
+            assert!(batcher.push(event).is_none());
+        }
+
+        // Block 11 may still be receiving events, so only block 10 is sent.
+        let batch = batcher.flush().unwrap();
+        assert_eq!(block_numbers(&batch), vec![10]);
+        assert_eq!(batch.scanned, Some(10..11));
+
+        // Nothing arrived since, block 11 is complete.
+        let batch = batcher.flush().unwrap();
+        assert_eq!(block_numbers(&batch), vec![11]);
+        assert_eq!(batch.scanned, Some(11..12));
+        assert!(batcher.flush().is_none());
+
+        // The range up to the next block is scanned, blocks between had no events.
+        for event in streamed_events(vec![
+            make_delegate_log(15, 0, 1),
+            make_delegate_log(16, 0, 1),
+        ]) {
+            assert!(batcher.push(event).is_none());
+        }
+        let batch = batcher.flush().unwrap();
+        assert_eq!(block_numbers(&batch), vec![15]);
+        assert_eq!(batch.scanned, Some(12..16));
+    }
+
+    #[test]
+    fn test_live_batcher_keeps_events_across_reconnects() {
+        let mut batcher = LiveBatcher::new(100);
+        assert!(batcher.stream_closed().is_none());
+
+        for event in streamed_events(vec![
+            make_delegate_log(10, 0, 1),
+            make_delegate_log(11, 0, 1),
+            make_delegate_log(11, 1, 2),
+        ]) {
+            assert!(batcher.push(event).is_none());
+        }
+        let batch = batcher.stream_closed().unwrap();
+        assert_eq!(block_numbers(&batch), vec![10, 11]);
+        assert_eq!(batch.delegate.len(), 3);
+        assert_eq!(batch.scanned, Some(10..12));
+
+        // Blocks missed while reconnecting are not marked as scanned.
+        for event in streamed_events(vec![make_delegate_log(20, 0, 1)]) {
+            assert!(batcher.push(event).is_none());
+        }
+        assert!(batcher.checkpoint().is_none());
+        let batch = batcher.stream_closed().unwrap();
+        assert_eq!(block_numbers(&batch), vec![20]);
+        assert_eq!(batch.scanned, Some(20..21));
+    }
 }
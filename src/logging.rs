@@ -0,0 +1,141 @@
+//! Subscriber setup for the text and JSON log formats.
+
+use std::sync::OnceLock;
+
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{EnvFilter, Registry, reload};
+
+use crate::config::LogFormat;
+
+/// Filter of the subscriber installed by [`init_logger`], replaced by [`set_level`].
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// Parse a level spec such as `info,monad_staking_indexer::provider=debug`.
+/// Targets without a matching directive log at `info` unless a directive
+/// without a target sets another default.
+pub fn parse_filter(spec: &str) -> Result<EnvFilter, String> {
+    EnvFilter::builder()
+        .with_default_directive(LevelFilter::INFO.into())
+        .parse(spec)
+        .map_err(|e| e.to_string())
+}
+
+/// Install the global subscriber with the `level` spec and output format. Its
+/// filter can be replaced later with [`set_level`]. Records of the `log` crate,
+/// e.g. from dependencies, go through the same subscriber.
+///
+/// In JSON mode every event is printed as one object per line with the
+/// `timestamp`, `level`, `target` and `message` fields, plus the fields of the
+/// event (e.g. `block_number`, `url`) and its spans. With `spans`, every span of
+/// the processing pipeline is also printed with its busy and idle time when it
+/// closes.
+pub fn init_logger(level: &str, format: LogFormat, spans: bool) -> Result<(), String> {
+    let (filter, handle) = reload::Layer::new(parse_filter(level)?);
+    let span_events = if spans { FmtSpan::CLOSE } else { FmtSpan::NONE };
+    let output = match format {
+        LogFormat::Text => tracing_subscriber::fmt::layer()
+            .with_target(false)
+            .with_span_events(span_events)
+            .with_writer(std::io::stderr)
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .flatten_event(true)
+            .with_span_events(span_events)
+            .with_writer(std::io::stderr)
+            .boxed(),
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(output)
+        .try_init()
+        .map_err(|e| e.to_string())?;
+    let _ = FILTER.set(handle);
+    Ok(())
+}
+
+/// Replace the level spec of the subscriber installed by [`init_logger`].
+pub fn set_level(level: &str) -> Result<(), String> {
+    let filter = parse_filter(level)?;
+    match FILTER.get() {
+        Some(handle) => handle.reload(filter).map_err(|e| e.to_string()),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::Value as JsonValue;
+    use tracing::subscriber::with_default;
+
+    /// A writer appending to a shared buffer.
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::registry().with(
+            tracing_subscriber::fmt::layer()
+                .json()
+                .flatten_event(true)
+                .with_writer(move || writer.clone())
+                .with_filter(parse_filter("debug").unwrap()),
+        );
+        with_default(subscriber, || {
+            tracing::info!("Inserting {} blocks", 3);
+            tracing::debug!(
+                block_number = 100u64,
+                event_type = "Delegate",
+                "Received event"
+            );
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<JsonValue> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2, "{output}");
+        assert!(lines[0]["timestamp"].is_string());
+        assert_eq!(lines[0]["level"], "INFO");
+        assert_eq!(lines[0]["target"], module_path!());
+        assert_eq!(lines[0]["message"], "Inserting 3 blocks");
+        assert_eq!(lines[1]["level"], "DEBUG");
+        assert_eq!(lines[1]["block_number"], 100);
+        assert_eq!(lines[1]["event_type"], "Delegate");
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        let filter = parse_filter("").unwrap();
+        assert_eq!(filter.max_level_hint(), Some(LevelFilter::INFO));
+
+        let filter = parse_filter("warn,monad_staking_indexer::provider=debug").unwrap();
+        assert_eq!(filter.max_level_hint(), Some(LevelFilter::DEBUG));
+    }
+
+    #[test]
+    fn test_parse_filter_rejects_invalid_levels() {
+        let err = parse_filter("info,sqlx=verbose").unwrap_err();
+        assert!(!err.is_empty());
+    }
+}
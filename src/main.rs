@@ -1,301 +1,587 @@
-use env_logger::TimestampPrecision;
-use monad_staking_indexer::provider::ReconnectProvider;
+use monad_staking_indexer::alerts::Alerter;
+use monad_staking_indexer::cli::{self, Cli, Command};
+use monad_staking_indexer::health::HealthState;
+use monad_staking_indexer::provider::{CircuitState, Connector, LogProvider, ReconnectProvider};
+use monad_staking_indexer::queue::{CountingSender, QueueDepth, counting_channel};
+use monad_staking_indexer::reload::{ConfigReloader, ReloadableInterval, RuntimeConfig};
+use monad_staking_indexer::sink::EventSink;
+use monad_staking_indexer::validator_state::ValidatorStateCache;
+use monad_staking_indexer::vault::{self, VaultCredentialRenewer, VaultTokenRefresher};
 use monad_staking_indexer::{
-    BlockBatch, DbRequest, chunk_range, config::Config, db, events, metrics, process_db_requests,
+    DbRequest, DbTaskOptions,
+    config::{Config, SettingSources},
+    db, export, logging, metrics, pipeline, process_db_requests, pushgateway, reorg,
+    startup_start_block,
 };
 
-use std::ops::Range;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
 
+use clap::Parser;
 use eyre::Result;
-use futures_util::stream::StreamExt;
-use log::{debug, error, info};
-use tokio::sync::mpsc;
+use sqlx::PgPool;
+use tokio::sync::{mpsc, watch};
 use tokio::time::{Duration, interval};
+use tracing::{error, info, warn};
+
+/// How often the depths of the internal channels are exported.
+const QUEUE_DEPTH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often the distance of the stored blocks to the chain head is sampled.
+const HEAD_LAG_INTERVAL: Duration = Duration::from_secs(30);
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let config = Config::load().expect("Failed to load configuration");
+    let cli = Cli::parse();
+    let profile = cli.options.profile();
+    let (config, sources) = Config::load_with_sources(
+        cli.options.config.as_deref(),
+        profile.as_deref(),
+        &cli.options.config_overrides(),
+    )
+    .map_err(|e| eyre::eyre!("Failed to load configuration: {e}"))?;
+
+    logging::init_logger(
+        &config.logging.level,
+        config.logging.format,
+        config.logging.tracing_enabled,
+    )
+    .map_err(|e| eyre::eyre!(e))?;
+    for warning in &config.warnings {
+        warn!("{warning}");
+    }
 
-    env_logger::builder()
-        .filter_level(config.parse_log_level())
-        .format_timestamp(Some(TimestampPrecision::Millis))
-        .format_target(false)
-        .init();
+    if cli.command() == Command::CheckConfig {
+        return check_config(&config, &sources).await;
+    }
 
     info!("Config is {config:#?}");
 
     info!("Connecting to database...");
+    let vault_client = config
+        .vault_client()
+        .await
+        .expect("Failed to authenticate with Vault");
     let database_url = config
-        .connection_string()
+        .connection_string(vault_client.as_ref())
         .await
         .expect("Failed to build database connection string");
     let (metrics_tx, metrics_rx) = mpsc::unbounded_channel();
-    let pool = db::create_pool(&database_url, metrics_tx.clone()).await?;
+    let read_database_url = config
+        .read_connection_string(vault_client.as_ref())
+        .await
+        .expect("Failed to build read replica connection string");
+    let pools = db::create_pools(
+        &database_url.url,
+        read_database_url.as_ref().map(|url| url.url.as_str()),
+        &config.pool_settings(),
+        metrics_tx.clone(),
+    )
+    .await?;
+    let pool = pools.writer.clone();
     info!("Database connected");
 
-    info!("Getting current indexing state...");
-    let max_block_on_startup = db::repository::get_max_block_number(&pool).await?;
-    info!("Max block at startup {max_block_on_startup:?}");
-
-    info!("Creating ReconnectProviders...");
-    let live_reconnect_provider =
-        ReconnectProvider::new(config.rpc_urls.clone(), config.watchdog_timeout_secs);
+    if cli.options.skip_schema_check {
+        warn!("Skipping the database schema check");
+    } else {
+        db::check_schema_version(&pool).await?;
+    }
+    db::repository::checksum_legacy_addresses(&pool).await?;
+
+    db::repository::get_db_health(&pool).await?;
+
+    if let Some(spec) = &cli.options.export_ndjson {
+        let (range, path) = export::parse_export_spec(spec)?;
+        let filter = cli.options.export_filter().map_err(|e| eyre::eyre!(e))?;
+        let file = tokio::fs::File::create(&path).await?;
+        let written =
+            export::export_events_ndjson(&pools.reader, range.start, range.end, &filter, file)
+                .await?;
+        info!("Wrote {written} events to {}", path.display());
+        return Ok(());
+    }
 
-    let gaps_reconnect_provider =
-        ReconnectProvider::new(config.rpc_urls.clone(), config.watchdog_timeout_secs);
+    let backfill = match (
+        cli.command().backfill_range(),
+        cli.command().reindex_range(),
+    ) {
+        (Some(range), _) => Some((range, &config.backfill.initial, false)),
+        (_, Some(range)) => Some((range, &config.backfill.gaps, true)),
+        _ => None,
+    };
+    if let Some((range, profile, replace)) = backfill {
+        let reconnect_provider = ReconnectProvider::new(
+            config.rpc_urls.clone(),
+            config.contract_address(),
+            config.watchdog_timeout_secs,
+            config.rpc_max_retries,
+            metrics_tx.clone(),
+        )?;
+        return pipeline::backfill(
+            reconnect_provider,
+            pools,
+            db_task_options(&config, HealthState::default()),
+            profile,
+            metrics_tx,
+            range,
+            replace,
+        )
+        .await;
+    }
 
-    let (gap_tx, gap_rx) = mpsc::unbounded_channel();
+    info!("Getting current indexing state...");
+    let start_block = startup_start_block(
+        &pool,
+        config.first_start_block(),
+        config.checkpoint_path.as_deref(),
+    )
+    .await?;
+    info!("Start block at startup {start_block:?}");
 
-    let (db_tx, db_rx) = mpsc::unbounded_channel();
+    info!("Creating ReconnectProviders...");
+    // The clones connect separately but share the request rate limit.
+    let live_reconnect_provider = ReconnectProvider::new(
+        config.rpc_urls.clone(),
+        config.contract_address(),
+        config.watchdog_timeout_secs,
+        config.rpc_max_retries,
+        metrics_tx.clone(),
+    )?;
+    let gaps_reconnect_provider = live_reconnect_provider.clone();
+    let reorg_reconnect_provider = live_reconnect_provider.clone();
+    let head_lag_reconnect_provider = live_reconnect_provider.clone();
+
+    let (gap_tx, gap_rx) = counting_channel();
+    let (runtime_tx, runtime_rx) =
+        watch::channel(config.runtime_config().map_err(|e| eyre::eyre!(e))?);
+
+    let validator_state = ValidatorStateCache::load(&pool).await?;
+    info!("Loaded the state of {} validators", validator_state.len());
+    let validator_state = Arc::new(RwLock::new(validator_state));
+
+    let (db_tx, db_rx) = counting_channel();
     let (metrics_request_tx, metrics_request_rx) = mpsc::unbounded_channel();
-
-    let tasks = vec![
-        tokio::spawn(metrics::process_metrics(metrics_rx, metrics_request_rx)),
-        tokio::spawn(metrics::run_metrics_server(
-            metrics_request_tx,
-            config.metrics_bind_addr().clone(),
-        )),
-        tokio::spawn(process_db_requests(
+    let health = HealthState::new(Duration::from_secs(config.metrics.liveness_timeout_secs));
+    let metrics_depth = QueueDepth::default();
+    // The gaps task drains its channel into the gap queue, whose length is
+    // `staking_gap_queue_depth`, so "gap" only counts ranges not drained yet.
+    let queue_depths = vec![
+        ("db", db_tx.depth()),
+        ("gap", gap_tx.depth()),
+        ("metrics", metrics_depth.clone()),
+    ];
+    let alerter = config
+        .alerts
+        .as_ref()
+        .map(|alerts| Alerter::new(alerts, metrics_tx.clone()))
+        .transpose()?;
+
+    let mut tasks = vec![
+        tokio::spawn(metrics::process_metrics(
+            metrics_rx,
+            metrics_depth,
+            metrics_request_rx,
             pool.clone(),
-            db_rx,
-            gap_tx.clone(),
-            metrics_tx.clone(),
-            config.db_operation_timeout_secs,
+            config.metrics.max_tracked_validators,
+            alerter,
         )),
+        tokio::spawn({
+            let db_task = process_db_requests(
+                pools.clone(),
+                db_rx,
+                gap_tx.clone(),
+                metrics_tx.clone(),
+                DbTaskOptions {
+                    validator_state: Some(validator_state.clone()),
+                    ..db_task_options(&config, health.clone())
+                },
+            );
+            // Failed batches are dead-lettered and replayed while running.
+            async move { db_task.await.map(|_| ()) }
+        }),
         tokio::spawn(periodic_gap_check(
-            config.gap_check_interval_secs,
+            runtime_rx.clone(),
             db_tx.clone(),
+            pools.reader.clone(),
+            reorg_reconnect_provider,
+            config.reorg_check_blocks,
+            metrics_tx.clone(),
+        )),
+        tokio::spawn(periodic_dead_letter_replay(
+            config.dead_letter_retry_interval_secs,
+            db_tx.clone(),
+        )),
+        tokio::spawn(periodic_queue_depths(queue_depths, metrics_tx.clone())),
+        tokio::spawn(periodic_head_lag(
+            pools.reader.clone(),
+            head_lag_reconnect_provider,
+            metrics_tx.clone(),
         )),
-        tokio::spawn(process_gaps_task(
+        tokio::spawn(periodic_row_counts(
+            pools.reader.clone(),
+            config.row_count_interval_secs,
+            metrics_tx.clone(),
+        )),
+        tokio::spawn(pipeline::process_gaps_task(
             gaps_reconnect_provider,
             db_tx.clone(),
             gap_rx,
-            config.backfill_chunk_size,
+            runtime_rx,
             metrics_tx.clone(),
+            health.clone(),
         )),
-        tokio::spawn(process_live_blocks(
+        tokio::spawn(pipeline::process_live_blocks(
             live_reconnect_provider,
-            max_block_on_startup,
+            start_block,
             db_tx,
             gap_tx,
             config.db_batch_size,
+            Duration::from_secs(config.batch_flush_timeout_secs),
             metrics_tx.clone(),
+            health.clone(),
         )),
     ];
 
-    for task in tasks {
-        if let Err(e) = task.await {
-            error!("Task panicked: {:?}", e);
-            std::process::exit(1);
+    // The metrics task keeps running when the server is disabled, so the
+    // senders of metrics do not fail.
+    if config.metrics.enabled {
+        tasks.push(tokio::spawn(metrics::run_metrics_server(
+            metrics_request_tx.clone(),
+            config.metrics_bind_addr().clone(),
+            pool.clone(),
+            pools.reader.clone(),
+            validator_state,
+            metrics_tx.clone(),
+            health,
+            config.metrics_server_options(),
+        )));
+    } else {
+        info!("Metrics server disabled");
+    }
+
+    let reloader = ConfigReloader::new(
+        cli.options.config.clone(),
+        profile,
+        cli.options.config_overrides(),
+        config.clone(),
+        runtime_tx,
+        metrics_tx.clone(),
+    );
+    tasks.push(tokio::spawn(reloader.run()));
+
+    if let (Some(client), Some(vault)) = (vault_client, config.vault()) {
+        let client = Arc::new(client);
+        let refresher = VaultTokenRefresher::new(
+            client.clone(),
+            Duration::from_secs(vault.token_ttl_warn_secs),
+        );
+        tasks.push(tokio::spawn(refresher.run()));
+
+        if let Some(lease) = vault::shortest_lease(&database_url, read_database_url.as_ref()) {
+            let renewer = VaultCredentialRenewer::new(
+                config.clone(),
+                client,
+                pools.clone(),
+                lease,
+                metrics_tx.clone(),
+            );
+            tasks.push(tokio::spawn(renewer.run()));
+        }
+    }
+
+    if let Some(push) = config.metrics.push.clone() {
+        tasks.push(tokio::spawn(pushgateway::run_pushgateway(
+            metrics_request_tx,
+            metrics_tx.clone(),
+            push,
+        )));
+    }
+
+    if let Some(retention_blocks) = config.retention_blocks {
+        tasks.push(tokio::spawn(periodic_prune(
+            pool.clone(),
+            retention_blocks,
+            config.prune_interval_secs,
+        )));
+    }
+
+    while !tasks.is_empty() {
+        let (result, _, remaining) = futures_util::future::select_all(tasks).await;
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                error!("Task failed: {e:?}");
+                std::process::exit(1);
+            }
+            Err(e) => {
+                error!("Task panicked: {:?}", e);
+                std::process::exit(1);
+            }
         }
+        tasks = remaining;
     }
 
     Ok(())
 }
 
+/// Sink configured to receive inserted events. A sink that cannot be created
+/// is left out, the database is the primary store.
+fn event_sink(config: &Config) -> Option<Arc<dyn EventSink>> {
+    let kafka = config.kafka.as_ref()?;
+    #[cfg(feature = "kafka")]
+    match monad_staking_indexer::sink::KafkaSink::new(kafka) {
+        Ok(sink) => Some(Arc::new(sink)),
+        Err(e) => {
+            error!("Events are not published to Kafka: {e:?}");
+            None
+        }
+    }
+    #[cfg(not(feature = "kafka"))]
+    {
+        error!(
+            topic = kafka.topic.as_str(),
+            "Events are not published to Kafka, it requires the kafka feature"
+        );
+        None
+    }
+}
+
+fn db_task_options(config: &Config, health: HealthState) -> DbTaskOptions {
+    DbTaskOptions {
+        operation_timeout: Duration::from_secs(config.db_operation_timeout_secs),
+        gap_options: config.gap_options(),
+        checkpoint_path: config.checkpoint_path.clone(),
+        duplicate_policy: config.duplicate_policy,
+        conflict_strategies: config.event_conflict_strategy,
+        block_conflict_strategy: config.block_conflict_strategy,
+        health,
+        sink: event_sink(config),
+        validator_state: None,
+        dead_letter_max_attempts: config.dead_letter_max_attempts,
+    }
+}
+
+/// Resolves the database credentials, Vault included, and prints the
+/// configuration with secrets redacted and the sources of its settings.
+async fn check_config(config: &Config, sources: &SettingSources) -> Result<()> {
+    let vault_client = config
+        .vault_client()
+        .await
+        .map_err(|e| eyre::eyre!("Failed to authenticate with Vault: {e}"))?;
+    let database_url = config
+        .connection_string(vault_client.as_ref())
+        .await
+        .map_err(|e| eyre::eyre!("Failed to build database connection string: {e}"))?;
+    let read_database_url = config
+        .read_connection_string(vault_client.as_ref())
+        .await
+        .map_err(|e| eyre::eyre!("Failed to build read replica connection string: {e}"))?;
+    print!(
+        "{}",
+        cli::check_config_summary(
+            config,
+            sources,
+            &database_url.url,
+            read_database_url.as_ref().map(|url| url.url.as_str())
+        )
+    );
+    println!("Configuration is valid");
+    Ok(())
+}
+
 async fn periodic_gap_check(
-    interval_secs: u64,
-    gap_tx: mpsc::UnboundedSender<DbRequest>,
+    runtime_rx: watch::Receiver<RuntimeConfig>,
+    gap_tx: CountingSender<DbRequest>,
+    pool: PgPool,
+    mut reconnect_provider: ReconnectProvider,
+    reorg_check_blocks: usize,
+    metrics_tx: mpsc::UnboundedSender<metrics::Metric>,
 ) -> Result<()> {
-    let mut interval = interval(Duration::from_secs(interval_secs));
+    let mut interval = ReloadableInterval::new(runtime_rx, |runtime| runtime.gap_check_interval);
+    let mut attempts = 0usize;
     interval.tick().await;
     loop {
         info!("Running periodic gap check...");
         let _ = gap_tx.send(DbRequest::GetBlockGaps);
+        if reorg_check_blocks > 0 && reconnect_provider.circuit_state() == CircuitState::Open {
+            // Skips this check and tries again at the next one.
+            let _ = metrics_tx.send(metrics::Metric::RpcCircuitOpen);
+            warn!(
+                "Reorg check skipped after {} consecutive failed RPC attempts",
+                reconnect_provider.consecutive_failures()
+            );
+            reconnect_provider.half_open_circuit();
+        } else if reorg_check_blocks > 0 {
+            match reorg::detect_reorg(
+                &pool,
+                &mut reconnect_provider,
+                &mut attempts,
+                reorg_check_blocks,
+            )
+            .await
+            {
+                Ok(reorged) if reorged.is_empty() => {}
+                Ok(reorged) => {
+                    warn!(
+                        block_count = reorged.len(),
+                        "Detected reorged blocks: {reorged:?}"
+                    );
+                    let _ = gap_tx.send(DbRequest::ReindexReorgedBlocks(reorged));
+                }
+                Err(e) => error!("Failed to check for reorgs: {e:?}"),
+            }
+        }
         interval.tick().await;
     }
 }
 
-async fn process_gaps_task(
-    reconnect_provider: ReconnectProvider,
-    log_tx: mpsc::UnboundedSender<DbRequest>,
-    mut gap_rx: mpsc::UnboundedReceiver<Range<u64>>,
-    chunk_size: u64,
-    metrics_tx: mpsc::UnboundedSender<metrics::Metric>,
+async fn periodic_dead_letter_replay(
+    interval_secs: u64,
+    db_tx: CountingSender<DbRequest>,
 ) -> Result<()> {
-    let mut attempts = 0usize;
-
-    while let Some(range) = gap_rx.recv().await {
-        let client = loop {
-            match reconnect_provider.connect(attempts).await {
-                Ok(client) => break client,
-                Err(e) => {
-                    attempts += 1;
-                    error!("Gaps task connection failed: {e:?}");
-                    metrics_tx.send(e).unwrap();
-                    tokio::time::sleep(Duration::from_secs(1)).await;
-                }
-            }
-        };
-
-        let chunks = chunk_range(range.clone(), chunk_size);
-        if chunks.len() > 1 {
-            info!(
-                "Backfilling large range: {:?} ({} blocks) in {} chunks",
-                range,
-                range.end - range.start,
-                chunks.len()
-            );
-        }
-
-        for chunk_range in chunks.iter() {
-            debug!("Backfilling chunk: blocks {:?}", chunk_range);
-            let blocks_processed = chunk_range.end - chunk_range.start;
-
-            let res = client
-                .historical_logs(chunk_range)
-                .await
-                .and_then(|logs| process_historical_logs(logs, log_tx.clone()));
+    let mut interval = interval(Duration::from_secs(interval_secs));
+    loop {
+        interval.tick().await;
+        let _ = db_tx.send(DbRequest::ReplayFailedBatches);
+    }
+}
 
-            let metric = match res {
-                Ok(()) => {
-                    debug!("Successfully backfilled {chunk_range:?}");
-                    metrics::Metric::BackfilledBlocks(blocks_processed)
-                }
-                Err(e) => {
-                    error!("Failed to backfill {chunk_range:?}: {e:?}");
-                    metrics::Metric::FailedToBackfill(blocks_processed)
-                }
-            };
-            let _ = metrics_tx.send(metric);
+async fn periodic_queue_depths(
+    queues: Vec<(&'static str, QueueDepth)>,
+    metrics_tx: mpsc::UnboundedSender<metrics::Metric>,
+) -> Result<()> {
+    let mut interval = interval(QUEUE_DEPTH_INTERVAL);
+    loop {
+        interval.tick().await;
+        for (queue, depth) in &queues {
+            let _ = metrics_tx.send(metrics::Metric::QueueDepth {
+                queue,
+                depth: depth.get(),
+            });
         }
-        info!(
-            "Finished backfilling range: {range:?} ({} blocks)",
-            range.end - range.start
-        );
     }
-    Ok(())
 }
 
-async fn process_live_blocks(
-    reconnect_provider: ReconnectProvider,
-    mut start_block: Option<u64>,
-    tx: mpsc::UnboundedSender<DbRequest>,
-    gap_tx: mpsc::UnboundedSender<Range<u64>>,
-    batch_size: usize,
+/// Samples how many blocks the highest stored or scanned block is behind the
+/// chain head. Sampled on a timer rather than on insert, so that the lag keeps
+/// growing when ingestion stops.
+///
+/// Failed attempts rotate through the RPC URLs. Once the circuit opens, the
+/// task skips an interval and then tries again with the circuit half open.
+async fn periodic_head_lag(
+    pool: PgPool,
+    mut reconnect_provider: ReconnectProvider,
     metrics_tx: mpsc::UnboundedSender<metrics::Metric>,
 ) -> Result<()> {
-    let mut current_block_buffer: Vec<events::StakingEvent> = Vec::new();
-    let mut current_block_meta: Option<events::BlockMeta> = None;
-    let mut batch = BlockBatch::new();
-    let mut block_count = 0;
+    let mut interval = interval(HEAD_LAG_INTERVAL);
+    let mut client = None;
     let mut attempts = 0usize;
-
-    info!("Starting live event stream from block {:?}", start_block);
-
     loop {
-        let client = loop {
-            match reconnect_provider.connect(attempts).await {
-                Ok(c) => break c,
-                Err(e) => {
-                    error!("Live blocks connection failed: {e:?}");
+        interval.tick().await;
+        if reconnect_provider.circuit_state() == CircuitState::Open {
+            let _ = metrics_tx.send(metrics::Metric::RpcCircuitOpen);
+            warn!(
+                "Head lag skipped after {} consecutive failed RPC attempts",
+                reconnect_provider.consecutive_failures()
+            );
+            reconnect_provider.half_open_circuit();
+            continue;
+        }
+        let connected = match client.take() {
+            Some(connected) => connected,
+            None => match reconnect_provider.connect(attempts).await {
+                Ok(connected) => connected,
+                Err(metric) => {
                     attempts += 1;
-                    metrics_tx.send(e).unwrap();
-                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    error!("Failed to connect to RPC for the head lag: {metric:?}");
+                    let _ = metrics_tx.send(metric);
+                    continue;
                 }
-            }
+            },
         };
-
-        let event_stream = match client.stream_events().await {
-            Ok(stream) => stream,
+        let head = match connected.get_block_number().await {
+            Ok(head) => head,
             Err(e) => {
-                error!("Failed to start event stream: {:?}", e);
-                tokio::time::sleep(Duration::from_secs(1)).await;
+                // Drops the connection, so the next attempt uses the next URL.
+                attempts += 1;
+                reconnect_provider.record_failure();
+                error!("Failed to get the chain head: {e:?}");
                 continue;
             }
         };
-
-        tokio::pin!(event_stream);
-
-        info!("Connected to event stream");
-
-        while let Some(log) = event_stream.next().await {
-            match events::extract_event(&log) {
-                Ok(Some(event)) => {
-                    let event_block_num = event.block_meta().block_number;
-
-                    if let Some(start) = start_block {
-                        if event_block_num > start {
-                            gap_tx.send(start..event_block_num).unwrap();
-                        }
-                        start_block = None;
-                    }
-
-                    if let Some(ref meta) = current_block_meta
-                        && meta.block_number != event_block_num
-                    {
-                        batch.add_block_meta(meta.clone());
-                        for evt in current_block_buffer.drain(..) {
-                            batch.add_event(evt);
-                        }
-                        block_count += 1;
-                    }
-
-                    current_block_meta = Some(event.block_meta().clone());
-                    current_block_buffer.push(event);
-
-                    if block_count >= batch_size {
-                        tx.send(DbRequest::InsertCompleteBlocks(Box::new(std::mem::take(
-                            &mut batch,
-                        ))))
-                        .expect("Channel closed");
-                        batch = BlockBatch::new();
-                        block_count = 0;
-                    }
-                }
-                Ok(None) => (),
-                Err(e) => {
-                    error!("Error extracting event: {}", e);
-                }
+        client = Some(connected);
+        reconnect_provider.record_success();
+
+        let stored = match (
+            db::repository::get_max_block_number(&pool).await,
+            db::repository::get_checkpoint(&pool).await,
+        ) {
+            (Ok(max_block), Ok(checkpoint)) => max_block.max(checkpoint),
+            (Err(e), _) | (_, Err(e)) => {
+                error!("Failed to get the highest stored block: {e}");
+                continue;
             }
+        };
+        // Nothing to compare with before the first batch is stored.
+        if let Some(stored) = stored {
+            let _ = metrics_tx.send(metrics::Metric::HeadLagBlocks(head.saturating_sub(stored)));
         }
-
-        error!("Event stream closed (timeout or error), reconnecting...");
-        let _ = metrics_tx.send(metrics::Metric::RpcTimeout);
     }
 }
 
-fn process_historical_logs(
-    mut logs: Vec<alloy::rpc::types::Log>,
-    tx: mpsc::UnboundedSender<DbRequest>,
+async fn periodic_row_counts(
+    pool: PgPool,
+    interval_secs: u64,
+    metrics_tx: mpsc::UnboundedSender<metrics::Metric>,
 ) -> Result<()> {
-    logs.sort_by_key(|l| (l.block_number, l.transaction_index, l.log_index));
-
-    let mut blocks_map: std::collections::HashMap<
-        u64,
-        (events::BlockMeta, Vec<events::StakingEvent>),
-    > = std::collections::HashMap::new();
-
-    for log in logs {
-        if let Some(event) = events::extract_event(&log)? {
-            let block_num = event.block_meta().block_number;
-            blocks_map
-                .entry(block_num)
-                .or_insert_with(|| (event.block_meta().clone(), Vec::new()))
-                .1
-                .push(event);
-        }
-    }
-
-    let mut block_metas_and_events: Vec<(u64, events::BlockMeta, Vec<events::StakingEvent>)> =
-        blocks_map
+    let mut interval = interval(Duration::from_secs(interval_secs));
+    loop {
+        interval.tick().await;
+        let event_counts = match db::repository::get_event_counts(&pool).await {
+            Ok(counts) => counts,
+            Err(e) => {
+                error!("Failed to count events: {e}");
+                continue;
+            }
+        };
+        let mut counts: HashMap<String, u64> = event_counts
             .into_iter()
-            .map(|(num, (meta, events))| (num, meta, events))
+            .map(|(event_type, count)| (event_type.table_name().to_string(), count))
             .collect();
-    block_metas_and_events.sort_by_key(|(num, _, _)| *num);
-
-    let mut batch = BlockBatch::new();
-    for (_, meta, events) in block_metas_and_events {
-        batch.add_block_meta(meta);
-        for event in events {
-            batch.add_event(event);
+        match db::repository::get_block_count(&pool).await {
+            Ok(count) => {
+                counts.insert("blocks".to_string(), count);
+            }
+            Err(e) => error!("Failed to count blocks: {e}"),
+        }
+        let _ = metrics_tx.send(metrics::Metric::RowCounts(counts));
+        match db::repository::get_jailed_validator_count(&pool).await {
+            Ok(count) => {
+                let _ = metrics_tx.send(metrics::Metric::JailedValidators(count));
+            }
+            Err(e) => error!("Failed to count jailed validators: {e}"),
         }
     }
+}
 
-    if !batch.block_meta.is_empty() {
-        tx.send(DbRequest::InsertCompleteBlocks(Box::new(batch)))
-            .expect("Channel closed");
+async fn periodic_prune(pool: PgPool, retention_blocks: u64, interval_secs: u64) -> Result<()> {
+    let mut interval = interval(Duration::from_secs(interval_secs));
+    loop {
+        interval.tick().await;
+        let max_block = match db::repository::get_max_block_number(&pool).await {
+            Ok(Some(max_block)) => max_block,
+            Ok(None) => continue,
+            Err(e) => {
+                error!("Failed to get max block for pruning: {e}");
+                continue;
+            }
+        };
+        let Some(prune_below) = max_block.checked_sub(retention_blocks) else {
+            continue;
+        };
+        info!("Pruning events below block {prune_below}");
+        if let Err(e) = db::repository::prune_before(&pool, prune_below).await {
+            error!("Failed to prune events below block {prune_below}: {e}");
+        }
     }
-
-    Ok(())
 }
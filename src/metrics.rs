@@ -1,188 +1,894 @@
+use crate::BatchDuplicates;
+use crate::alerts::Alerter;
+use crate::config::MetricsAuth;
 use crate::events::StakingEventType;
+use crate::health::HealthState;
+use crate::queue::QueueDepth;
 use axum::response::IntoResponse;
+use bigdecimal::{BigDecimal, ToPrimitive};
 use eyre::Result;
-use log::info;
-use std::collections::HashMap;
+use prometheus::core::Collector;
+use prometheus::{
+    Gauge, GaugeVec, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use sqlx::PgPool;
+use std::collections::{HashMap, HashSet};
 use tokio::sync::mpsc;
+use tokio::time::{Duration, interval};
+use tracing::{error, info, warn};
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    test,
+    derive(strum_macros::EnumDiscriminants),
+    strum_discriminants(name(MetricKind), derive(strum_macros::EnumIter))
+)]
 pub enum Metric {
+    /// `(inserted, total)` per event type, as returned by `db::insert_blocks`.
     InsertedEvents(HashMap<StakingEventType, (u64, u64)>),
+    DuplicateEvents(HashMap<StakingEventType, u64>),
+    /// Copies of blocks and events within one batch, dropped before the insert.
+    IntraBatchDuplicates(BatchDuplicates),
     BackfilledBlocks(u64),
     FailedToBackfill(u64),
-    FailedToInsert,
+    /// One backfill chunk of `blocks` blocks was fetched and handed to the DB
+    /// task, or failed, in `duration`.
+    BackfillChunk {
+        blocks: u64,
+        duration: Duration,
+        outcome: BackfillOutcome,
+    },
+    /// The gaps task started backfilling a range of this many blocks.
+    BackfillRangeStarted(u64),
+    /// The gaps task is done with a range of this many blocks, whether or not
+    /// all of its chunks succeeded.
+    BackfillRangeFinished(u64),
+    /// A batch could not be inserted, with the event table that failed if known.
+    FailedToInsert {
+        table: Option<StakingEventType>,
+    },
+    /// An insert attempt or statement ran out of time, exported as
+    /// `staking_insert_timeout_err`. A pool timeout is `DbPoolExhausted`.
     InsertTimeout,
+    DbPoolExhausted,
     DbConnected,
+    /// A database connection was closed at the end of its maximum lifetime.
+    DbConnectionClosed,
     RpcTimeout,
     RpcConnRefused,
+    /// An RPC task connected to its endpoint.
+    RpcConnected,
+    /// An RPC task gave up after too many consecutive connection failures.
+    RpcCircuitOpen,
+    /// Pushing the metrics to the pushgateway failed.
+    PushFailed,
+    /// Posting an alert to the webhook failed.
+    AlertFailed,
+    /// Re-reading the database credentials from Vault failed.
+    VaultRenewalFailed,
+    /// The configuration was re-read on SIGHUP and its runtime settings applied.
+    ConfigReloaded,
+    /// Re-reading the configuration on SIGHUP failed, the previous one stays in use.
+    ConfigReloadFailed,
+    NegativeStakes(u64),
+    PendingWithdrawals(BigDecimal),
+    IndexedBlockCount(u64),
+    /// Highest block of a successfully inserted batch.
+    MaxBlockInserted(u64),
+    /// Number of blocks in a successfully inserted batch.
+    BlocksInserted(u64),
+    DeadLetterDepth(u64),
+    /// Dead-lettered batches parked after too many failed replays.
+    DeadLetterParked(u64),
+    /// Blocks deleted because a reorg replaced them, or replaced by the batch
+    /// indexing them again.
+    ReorgedBlocksDeleted(u64),
+    /// Number of blocks in all gaps found by a gap check, zero without gaps.
+    MissingBlocks(u64),
+    /// Gaps waiting in the queue of the gaps task.
+    GapQueueDepth(u64),
+    /// Blocks between the chain head and the highest stored or scanned block,
+    /// sampled on a timer so that it grows when ingestion stops.
+    HeadLagBlocks(u64),
+    /// Number of rows per table, keyed by table name.
+    RowCounts(HashMap<String, u64>),
+    /// Number of validators whose current flags mark them as jailed.
+    JailedValidators(u64),
+    /// Total stake delegated to a validator with events in an inserted batch.
+    ValidatorDelegation {
+        validator_id: u64,
+        total: BigDecimal,
+    },
+    /// Newly stored undecoded events per hex-encoded `topic0`.
+    UnknownEvents(HashMap<String, u64>),
+    /// Time taken by one RPC operation.
+    RpcLatency {
+        operation: RpcOperation,
+        duration_ms: u64,
+    },
+    /// Rows written to one table by an insert and the time it took.
+    TableInsert {
+        table: &'static str,
+        rows: u64,
+        millis: u64,
+    },
+    /// Time from the newest block of a live batch being produced until it was
+    /// inserted.
+    IngestLatency(Duration),
+    /// Messages waiting in one of the internal channels.
+    QueueDepth {
+        queue: &'static str,
+        depth: u64,
+    },
 }
 
-#[derive(Debug, Clone)]
+/// How backfilling a chunk ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackfillOutcome {
+    Ok,
+    /// Fetching the logs failed.
+    RpcError,
+    /// The logs could not be decoded into events.
+    DecodeError,
+    /// The DB task stopped, so the batch could not be handed over.
+    DbChannelClosed,
+}
+
+impl BackfillOutcome {
+    const ALL: [BackfillOutcome; 4] = [
+        BackfillOutcome::Ok,
+        BackfillOutcome::RpcError,
+        BackfillOutcome::DecodeError,
+        BackfillOutcome::DbChannelClosed,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            BackfillOutcome::Ok => "ok",
+            BackfillOutcome::RpcError => "rpc_error",
+            BackfillOutcome::DecodeError => "decode_error",
+            BackfillOutcome::DbChannelClosed => "db_channel_closed",
+        }
+    }
+}
+
+/// RPC operations whose latency is measured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RpcOperation {
+    /// A `get_logs` call for a historical block range.
+    GetLogs,
+    /// Waiting for the next log of the live subscription.
+    StreamEvent,
+}
+
+impl RpcOperation {
+    fn label(self) -> &'static str {
+        match self {
+            RpcOperation::GetLogs => "get_logs",
+            RpcOperation::StreamEvent => "stream_event",
+        }
+    }
+}
+
+/// Default number of validators exported by `staking_validator_delegation`.
+pub const DEFAULT_MAX_TRACKED_VALIDATORS: usize = 50;
+
+/// Upper bounds of the `staking_rpc_latency_milliseconds` buckets.
+const RPC_LATENCY_BUCKETS_MS: [f64; 12] = [
+    10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0, 10000.0, 30000.0, 60000.0,
+];
+
+/// Upper bounds of the `staking_table_insert_duration_seconds` buckets.
+const INSERT_DURATION_BUCKETS_SECS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Upper bounds of the `staking_backfill_chunk_seconds` buckets.
+const BACKFILL_CHUNK_BUCKETS_SECS: [f64; 10] =
+    [0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+/// Upper bounds of the `staking_ingest_latency_seconds` buckets, 1s to 1h.
+const INGEST_LATENCY_BUCKETS_SECS: [f64; 11] = [
+    1.0, 2.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0,
+];
+
+/// Registers `collector` in `registry` and returns it for updating.
+fn register<C: Collector + Clone + 'static>(registry: &Registry, collector: C) -> C {
+    registry
+        .register(Box::new(collector.clone()))
+        .expect("metric names are unique");
+    collector
+}
+
+fn counter(registry: &Registry, name: &str, help: &str) -> IntCounter {
+    register(registry, IntCounter::new(name, help).expect("valid metric"))
+}
+
+fn gauge(registry: &Registry, name: &str, help: &str) -> IntGauge {
+    register(registry, IntGauge::new(name, help).expect("valid metric"))
+}
+
+/// Counter labeled by event type, with every type exported from the start.
+fn event_type_counter(registry: &Registry, name: &str, help: &str) -> IntCounterVec {
+    let counter = register(
+        registry,
+        IntCounterVec::new(Opts::new(name, help), &["event_type"]).expect("valid metric"),
+    );
+    for event_type in StakingEventType::all_types() {
+        counter.with_label_values(&[event_type.to_string()]);
+    }
+    counter
+}
+
+/// Counter labeled by backfill outcome, with every outcome exported from the start.
+fn backfill_outcome_counter(registry: &Registry, name: &str, help: &str) -> IntCounterVec {
+    let counter = register(
+        registry,
+        IntCounterVec::new(Opts::new(name, help), &["outcome"]).expect("valid metric"),
+    );
+    for outcome in BackfillOutcome::ALL {
+        counter.with_label_values(&[outcome.label()]);
+    }
+    counter
+}
+
+/// Prometheus collectors updated from the received [`Metric`]s.
 struct MetricsState {
-    inserted: HashMap<StakingEventType, u64>,
-    duplicates: HashMap<StakingEventType, u64>,
-    insert_events_err: u64,
-    insert_timeout_err: u64,
-    backfilled_blocks_ok: u64,
-    backfilled_blocks_err: u64,
-    db_connections: u64,
-    rpc_timeout_err: u64,
-    rpc_conn_refused_err: u64,
+    registry: Registry,
+    inserted: IntCounterVec,
+    duplicates: IntCounterVec,
+    intra_batch_duplicates: IntCounterVec,
+    intra_batch_duplicate_blocks: IntCounter,
+    insert_events_err: IntCounter,
+    insert_table_err: IntCounterVec,
+    insert_timeout_err: IntCounter,
+    db_pool_exhausted_err: IntCounter,
+    backfilled_blocks_ok: IntCounter,
+    backfilled_blocks_err: IntCounter,
+    backfill_chunk_duration: Histogram,
+    backfill_chunks: IntCounterVec,
+    backfill_chunk_blocks: IntCounterVec,
+    backfill_in_flight_blocks: IntGauge,
+    backfill_ranges_started: IntCounter,
+    backfill_ranges_finished: IntCounter,
+    db_connections: IntCounter,
+    db_connections_closed: IntCounter,
+    rpc_timeout_err: IntCounter,
+    rpc_conn_refused_err: IntCounter,
+    rpc_connections: IntCounter,
+    rpc_circuit_open_err: IntCounter,
+    push_err: IntCounter,
+    alert_err: IntCounter,
+    vault_renewal_err: IntCounter,
+    config_reloads: IntCounter,
+    config_reload_err: IntCounter,
+    negative_stakes: IntCounter,
+    pending_withdrawals: Gauge,
+    db_pool_size: IntGauge,
+    db_pool_active: IntGauge,
+    db_pool_idle: IntGauge,
+    indexed_block_count: IntGauge,
+    max_indexed_block: IntGauge,
+    blocks_inserted: IntCounter,
+    dead_letter_depth: IntGauge,
+    dead_letter_parked: IntGauge,
+    reorged_blocks_deleted: IntCounter,
+    missing_blocks: IntGauge,
+    gap_queue_depth: IntGauge,
+    head_lag_blocks: IntGauge,
+    jailed_validators: IntGauge,
+    validator_delegation: GaugeVec,
+    /// Validators exported by `validator_delegation`, at most `max_tracked_validators`.
+    tracked_validators: HashSet<u64>,
+    max_tracked_validators: usize,
+    row_counts: IntGaugeVec,
+    unknown_events: IntCounterVec,
+    table_insert_duration: HistogramVec,
+    table_insert_rows: IntCounterVec,
+    rpc_latency: HistogramVec,
+    ingest_latency: Histogram,
+    queue_depth: IntGaugeVec,
+    queue_depth_max: IntGaugeVec,
 }
 
 impl MetricsState {
     fn new() -> Self {
+        let registry = Registry::new();
+        let r = &registry;
         Self {
-            inserted: HashMap::new(),
-            duplicates: HashMap::new(),
-            backfilled_blocks_ok: 0,
-            backfilled_blocks_err: 0,
-            insert_events_err: 0,
-            insert_timeout_err: 0,
-            db_connections: 0,
-            rpc_timeout_err: 0,
-            rpc_conn_refused_err: 0,
+            inserted: event_type_counter(
+                r,
+                "staking_events_inserted_total",
+                "Total number of staking events inserted into the database",
+            ),
+            duplicates: event_type_counter(
+                r,
+                "staking_events_duplicates_total",
+                "Total number of duplicate staking events detected",
+            ),
+            intra_batch_duplicates: event_type_counter(
+                r,
+                "staking_intra_batch_duplicates_total",
+                "Number of repeated staking events dropped within a batch",
+            ),
+            intra_batch_duplicate_blocks: counter(
+                r,
+                "staking_intra_batch_duplicate_blocks_total",
+                "Number of repeated blocks dropped within a batch",
+            ),
+            backfilled_blocks_ok: counter(
+                r,
+                "staking_backfilled_blocks_ok",
+                "Number of blocks backfilled",
+            ),
+            backfilled_blocks_err: counter(
+                r,
+                "staking_backfilled_blocks_err",
+                "Number of blocks that failed to backfill",
+            ),
+            backfill_chunk_duration: register(
+                r,
+                Histogram::with_opts(
+                    HistogramOpts::new(
+                        "staking_backfill_chunk_seconds",
+                        "Time spent fetching and handing over one backfill chunk",
+                    )
+                    .buckets(BACKFILL_CHUNK_BUCKETS_SECS.to_vec()),
+                )
+                .expect("valid metric"),
+            ),
+            backfill_chunks: backfill_outcome_counter(
+                r,
+                "staking_backfill_chunks_total",
+                "Number of backfill chunks by outcome",
+            ),
+            backfill_chunk_blocks: backfill_outcome_counter(
+                r,
+                "staking_backfill_chunk_blocks_total",
+                "Number of blocks in backfill chunks by outcome",
+            ),
+            backfill_in_flight_blocks: gauge(
+                r,
+                "staking_backfill_in_flight_blocks",
+                "Number of blocks in gap ranges currently being backfilled",
+            ),
+            backfill_ranges_started: counter(
+                r,
+                "staking_backfill_ranges_started_total",
+                "Number of gap ranges the backfill started on",
+            ),
+            backfill_ranges_finished: counter(
+                r,
+                "staking_backfill_ranges_finished_total",
+                "Number of gap ranges the backfill is done with",
+            ),
+            insert_events_err: counter(
+                r,
+                "staking_insert_events_err",
+                "Number of events that failed to be inserted",
+            ),
+            insert_table_err: event_type_counter(
+                r,
+                "staking_insert_table_err",
+                "Number of failed inserts caused by each event table",
+            ),
+            insert_timeout_err: counter(
+                r,
+                "staking_insert_timeout_err",
+                "Number of insert operations that timed out",
+            ),
+            db_pool_exhausted_err: counter(
+                r,
+                "staking_db_pool_exhausted_err",
+                "Number of inserts that found no free database connection",
+            ),
+            db_connections: counter(
+                r,
+                "staking_db_connections_total",
+                "Total number of database connections established",
+            ),
+            db_connections_closed: counter(
+                r,
+                "staking_db_connections_closed_total",
+                "Total number of database connections closed after their maximum lifetime",
+            ),
+            rpc_timeout_err: counter(r, "staking_rpc_timeout_err", "Number of RPC timeout events"),
+            rpc_conn_refused_err: counter(
+                r,
+                "staking_rpc_conn_refused_err",
+                "Number of RPC connection refused errors",
+            ),
+            rpc_connections: counter(
+                r,
+                "staking_rpc_connections_total",
+                "Total number of successful RPC connections",
+            ),
+            rpc_circuit_open_err: counter(
+                r,
+                "staking_rpc_circuit_open_err",
+                "Number of RPC tasks stopped after too many consecutive connection failures",
+            ),
+            push_err: counter(
+                r,
+                "staking_metrics_push_err",
+                "Number of failed pushes to the pushgateway",
+            ),
+            alert_err: counter(
+                r,
+                "staking_alert_webhook_err",
+                "Number of alerts that could not be posted to the webhook",
+            ),
+            vault_renewal_err: counter(
+                r,
+                "staking_vault_renewal_err",
+                "Number of failed attempts to renew the database credentials from Vault",
+            ),
+            config_reloads: counter(
+                r,
+                "staking_config_reloads_total",
+                "Number of configuration reloads applied on SIGHUP",
+            ),
+            config_reload_err: counter(
+                r,
+                "staking_config_reload_err",
+                "Number of configuration reloads on SIGHUP that failed",
+            ),
+            negative_stakes: counter(
+                r,
+                "staking_negative_stakes_total",
+                "Number of delegations whose stake went negative on an insert and was clamped to zero",
+            ),
+            pending_withdrawals: register(
+                r,
+                Gauge::new(
+                    "staking_pending_withdrawals_amount",
+                    "Total amount undelegated but not yet withdrawn",
+                )
+                .expect("valid metric"),
+            ),
+            db_pool_size: gauge(
+                r,
+                "staking_db_pool_size",
+                "Number of open database connections in the pool",
+            ),
+            db_pool_active: gauge(
+                r,
+                "staking_db_pool_active_connections",
+                "Number of database connections currently in use",
+            ),
+            db_pool_idle: gauge(
+                r,
+                "staking_db_pool_idle_connections",
+                "Number of idle database connections in the pool",
+            ),
+            indexed_block_count: gauge(
+                r,
+                "staking_indexed_block_count",
+                "Number of blocks in the database",
+            ),
+            max_indexed_block: gauge(
+                r,
+                "staking_max_indexed_block",
+                "Highest block number inserted since startup",
+            ),
+            blocks_inserted: counter(
+                r,
+                "staking_blocks_inserted_total",
+                "Number of blocks in inserted batches",
+            ),
+            dead_letter_depth: gauge(
+                r,
+                "staking_dead_letter_batches",
+                "Number of failed batches waiting to be replayed",
+            ),
+            dead_letter_parked: gauge(
+                r,
+                "staking_dead_letter_parked_batches",
+                "Number of failed batches no longer replayed after too many failed attempts",
+            ),
+            reorged_blocks_deleted: counter(
+                r,
+                "staking_reorged_blocks_deleted_total",
+                "Number of blocks deleted because a reorg replaced them",
+            ),
+            missing_blocks: gauge(
+                r,
+                "staking_missing_blocks",
+                "Number of blocks in gaps still waiting to be backfilled",
+            ),
+            gap_queue_depth: gauge(
+                r,
+                "staking_gap_queue_depth",
+                "Number of gaps waiting to be backfilled, the most recent first",
+            ),
+            head_lag_blocks: gauge(
+                r,
+                "staking_head_lag_blocks",
+                "Number of blocks the highest stored or scanned block is behind the chain head",
+            ),
+            jailed_validators: gauge(
+                r,
+                "staking_validators_jailed",
+                "Number of validators that are jailed",
+            ),
+            validator_delegation: register(
+                r,
+                GaugeVec::new(
+                    Opts::new(
+                        "staking_validator_delegation",
+                        "Total amount delegated to each tracked validator",
+                    ),
+                    &["validator_id"],
+                )
+                .expect("valid metric"),
+            ),
+            tracked_validators: HashSet::new(),
+            max_tracked_validators: DEFAULT_MAX_TRACKED_VALIDATORS,
+            row_counts: register(
+                r,
+                IntGaugeVec::new(
+                    Opts::new("staking_rows_total", "Number of rows per table"),
+                    &["table"],
+                )
+                .expect("valid metric"),
+            ),
+            unknown_events: register(
+                r,
+                IntCounterVec::new(
+                    Opts::new(
+                        "staking_unknown_events_total",
+                        "Number of stored staking events with an unknown signature",
+                    ),
+                    &["topic0"],
+                )
+                .expect("valid metric"),
+            ),
+            table_insert_duration: register(
+                r,
+                HistogramVec::new(
+                    HistogramOpts::new(
+                        "staking_table_insert_duration_seconds",
+                        "Time spent inserting into each table",
+                    )
+                    .buckets(INSERT_DURATION_BUCKETS_SECS.to_vec()),
+                    &["table"],
+                )
+                .expect("valid metric"),
+            ),
+            table_insert_rows: register(
+                r,
+                IntCounterVec::new(
+                    Opts::new(
+                        "staking_table_insert_rows_total",
+                        "Number of rows inserted into each table",
+                    ),
+                    &["table"],
+                )
+                .expect("valid metric"),
+            ),
+            rpc_latency: register(
+                r,
+                HistogramVec::new(
+                    HistogramOpts::new(
+                        "staking_rpc_latency_milliseconds",
+                        "Latency of RPC operations",
+                    )
+                    .buckets(RPC_LATENCY_BUCKETS_MS.to_vec()),
+                    &["operation"],
+                )
+                .expect("valid metric"),
+            ),
+            ingest_latency: register(
+                r,
+                Histogram::with_opts(
+                    HistogramOpts::new(
+                        "staking_ingest_latency_seconds",
+                        "Time from a live block being produced until it is stored",
+                    )
+                    .buckets(INGEST_LATENCY_BUCKETS_SECS.to_vec()),
+                )
+                .expect("valid metric"),
+            ),
+            queue_depth: register(
+                r,
+                IntGaugeVec::new(
+                    Opts::new(
+                        "staking_queue_depth",
+                        "Number of messages waiting in each internal channel",
+                    ),
+                    &["queue"],
+                )
+                .expect("valid metric"),
+            ),
+            queue_depth_max: register(
+                r,
+                IntGaugeVec::new(
+                    Opts::new(
+                        "staking_queue_depth_max",
+                        "Highest sampled number of messages waiting in each internal channel since startup",
+                    ),
+                    &["queue"],
+                )
+                .expect("valid metric"),
+            ),
+            registry,
         }
     }
 
     fn record(&mut self, metric: Metric) {
         match metric {
             Metric::InsertedEvents(counts) => {
-                for (event_type, (inserted, total)) in counts {
-                    *self.inserted.entry(event_type).or_insert(0) += inserted;
-                    *self.duplicates.entry(event_type).or_insert(0) +=
-                        total.saturating_sub(inserted);
+                for (event_type, (inserted, _)) in counts {
+                    self.inserted
+                        .with_label_values(&[event_type.to_string()])
+                        .inc_by(inserted);
+                }
+            }
+            Metric::DuplicateEvents(counts) => {
+                for (event_type, duplicates) in counts {
+                    self.duplicates
+                        .with_label_values(&[event_type.to_string()])
+                        .inc_by(duplicates);
+                }
+            }
+            Metric::IntraBatchDuplicates(duplicates) => {
+                self.intra_batch_duplicate_blocks.inc_by(duplicates.blocks);
+                for (event_type, count) in duplicates.events {
+                    self.intra_batch_duplicates
+                        .with_label_values(&[event_type.to_string()])
+                        .inc_by(count);
                 }
             }
             Metric::BackfilledBlocks(count) => {
-                self.backfilled_blocks_ok += count;
+                self.backfilled_blocks_ok.inc_by(count);
             }
             Metric::FailedToBackfill(count) => {
-                self.backfilled_blocks_err += count;
+                self.backfilled_blocks_err.inc_by(count);
+            }
+            Metric::BackfillChunk {
+                blocks,
+                duration,
+                outcome,
+            } => {
+                self.backfill_chunk_duration.observe(duration.as_secs_f64());
+                self.backfill_chunks
+                    .with_label_values(&[outcome.label()])
+                    .inc();
+                self.backfill_chunk_blocks
+                    .with_label_values(&[outcome.label()])
+                    .inc_by(blocks);
             }
-            Metric::FailedToInsert => {
-                self.insert_events_err += 1;
+            Metric::BackfillRangeStarted(blocks) => {
+                self.backfill_ranges_started.inc();
+                self.backfill_in_flight_blocks.add(blocks as i64);
+            }
+            Metric::BackfillRangeFinished(blocks) => {
+                self.backfill_ranges_finished.inc();
+                self.backfill_in_flight_blocks.sub(blocks as i64);
+            }
+            Metric::FailedToInsert { table } => {
+                self.insert_events_err.inc();
+                if let Some(table) = table {
+                    self.insert_table_err
+                        .with_label_values(&[table.to_string()])
+                        .inc();
+                }
             }
             Metric::InsertTimeout => {
-                self.insert_timeout_err += 1;
+                self.insert_timeout_err.inc();
+            }
+            Metric::DbPoolExhausted => {
+                self.db_pool_exhausted_err.inc();
             }
             Metric::DbConnected => {
-                self.db_connections += 1;
+                self.db_connections.inc();
+            }
+            Metric::DbConnectionClosed => {
+                self.db_connections_closed.inc();
             }
             Metric::RpcTimeout => {
-                self.rpc_timeout_err += 1;
+                self.rpc_timeout_err.inc();
             }
             Metric::RpcConnRefused => {
-                self.rpc_conn_refused_err += 1;
+                self.rpc_conn_refused_err.inc();
+            }
+            Metric::RpcConnected => {
+                self.rpc_connections.inc();
+            }
+            Metric::RpcCircuitOpen => {
+                self.rpc_circuit_open_err.inc();
+            }
+            Metric::PushFailed => {
+                self.push_err.inc();
+            }
+            Metric::AlertFailed => {
+                self.alert_err.inc();
+            }
+            Metric::VaultRenewalFailed => {
+                self.vault_renewal_err.inc();
+            }
+            Metric::ConfigReloaded => {
+                self.config_reloads.inc();
+            }
+            Metric::ConfigReloadFailed => {
+                self.config_reload_err.inc();
+            }
+            Metric::NegativeStakes(count) => {
+                self.negative_stakes.inc_by(count);
+            }
+            Metric::PendingWithdrawals(total) => {
+                self.pending_withdrawals
+                    .set(total.to_f64().unwrap_or(f64::NAN));
+            }
+            Metric::IndexedBlockCount(count) => {
+                self.indexed_block_count.set(count as i64);
+            }
+            Metric::MaxBlockInserted(block) => {
+                // Backfilled batches are older than the live head, keep the highest.
+                if block as i64 > self.max_indexed_block.get() {
+                    self.max_indexed_block.set(block as i64);
+                }
+            }
+            Metric::BlocksInserted(count) => {
+                self.blocks_inserted.inc_by(count);
+            }
+            Metric::DeadLetterDepth(count) => {
+                self.dead_letter_depth.set(count as i64);
+            }
+            Metric::DeadLetterParked(count) => {
+                self.dead_letter_parked.set(count as i64);
+            }
+            Metric::ReorgedBlocksDeleted(count) => {
+                self.reorged_blocks_deleted.inc_by(count);
+            }
+            Metric::MissingBlocks(count) => {
+                self.missing_blocks.set(count as i64);
+            }
+            Metric::GapQueueDepth(depth) => {
+                self.gap_queue_depth.set(depth as i64);
+            }
+            Metric::HeadLagBlocks(lag) => {
+                self.head_lag_blocks.set(lag as i64);
+            }
+            Metric::RowCounts(counts) => {
+                for (table, count) in counts {
+                    self.row_counts
+                        .with_label_values(&[table])
+                        .set(count as i64);
+                }
+            }
+            Metric::JailedValidators(count) => {
+                self.jailed_validators.set(count as i64);
+            }
+            Metric::ValidatorDelegation {
+                validator_id,
+                total,
+            } => {
+                // The first validators seen are kept, to bound the number of series.
+                if !self.tracked_validators.contains(&validator_id) {
+                    if self.tracked_validators.len() >= self.max_tracked_validators {
+                        return;
+                    }
+                    self.tracked_validators.insert(validator_id);
+                }
+                self.validator_delegation
+                    .with_label_values(&[validator_id.to_string()])
+                    .set(total.to_f64().unwrap_or(f64::NAN));
+            }
+            Metric::UnknownEvents(counts) => {
+                for (topic0, count) in counts {
+                    self.unknown_events
+                        .with_label_values(&[topic0])
+                        .inc_by(count);
+                }
+            }
+            Metric::TableInsert {
+                table,
+                rows,
+                millis,
+            } => {
+                self.table_insert_duration
+                    .with_label_values(&[table])
+                    .observe(millis as f64 / 1000.0);
+                self.table_insert_rows
+                    .with_label_values(&[table])
+                    .inc_by(rows);
+            }
+            Metric::RpcLatency {
+                operation,
+                duration_ms,
+            } => {
+                self.rpc_latency
+                    .with_label_values(&[operation.label()])
+                    .observe(duration_ms as f64);
+            }
+            Metric::IngestLatency(latency) => {
+                self.ingest_latency.observe(latency.as_secs_f64());
+            }
+            Metric::QueueDepth { queue, depth } => {
+                self.queue_depth
+                    .with_label_values(&[queue])
+                    .set(depth as i64);
+                let max = self.queue_depth_max.with_label_values(&[queue]);
+                if depth as i64 > max.get() {
+                    max.set(depth as i64);
+                }
             }
         }
     }
 
-    fn as_prometheus_metrics(&self) -> String {
-        let mut output = String::new();
-
-        output.push_str("# HELP staking_events_inserted_total Total number of staking events inserted into the database\n");
-        output.push_str("# TYPE staking_events_inserted_total counter\n");
-        for event_type in StakingEventType::all_types() {
-            let count = self.inserted.get(&event_type).unwrap_or(&0);
-            output.push_str(&format!(
-                "staking_events_inserted_total{{event_type=\"{}\"}} {}\n",
-                event_type, count
-            ));
-        }
-
-        output.push_str("# HELP staking_events_duplicates_total Total number of duplicate staking events detected\n");
-        output.push_str("# TYPE staking_events_duplicates_total counter\n");
-        for event_type in StakingEventType::all_types() {
-            let count = self.duplicates.get(&event_type).unwrap_or(&0);
-            output.push_str(&format!(
-                "staking_events_duplicates_total{{event_type=\"{}\"}} {}\n",
-                event_type, count
-            ));
-        }
-
-        output.push_str("# HELP staking_backfilled_blocks_ok Number of blocks backfilled\n");
-        output.push_str("# TYPE staking_backfilled_blocks_ok counter\n");
-        output.push_str(&format!(
-            "staking_backfilled_blocks_ok {}\n",
-            self.backfilled_blocks_ok
-        ));
-
-        output.push_str(
-            "# HELP staking_backfilled_blocks_err Number of blocks that failed to backfill\n",
-        );
-        output.push_str("# TYPE staking_backfilled_blocks_err counter\n");
-        output.push_str(&format!(
-            "staking_backfilled_blocks_err {}\n",
-            self.backfilled_blocks_err
-        ));
-
-        output.push_str(
-            "# HELP staking_insert_events_err Number of events that failed to be inserted\n",
-        );
-        output.push_str("# TYPE staking_insert_events_err counter\n");
-        output.push_str(&format!(
-            "staking_insert_events_err {}\n",
-            self.insert_events_err
-        ));
-
-        output.push_str(
-            "# HELP staking_insert_timeout_err Number of insert operations that timed out\n",
-        );
-        output.push_str("# TYPE staking_insert_timeout_err counter\n");
-        output.push_str(&format!(
-            "staking_insert_timeout_err {}\n",
-            self.insert_timeout_err
-        ));
-
-        output.push_str(
-            "# HELP staking_db_connections_total Total number of database connections established\n",
-        );
-        output.push_str("# TYPE staking_db_connections_total counter\n");
-        output.push_str(&format!(
-            "staking_db_connections_total {}\n",
-            self.db_connections
-        ));
-
-        output.push_str(
-            "# HELP staking_rpc_timeout_err Number of RPC timeout events\n",
-        );
-        output.push_str("# TYPE staking_rpc_timeout_err counter\n");
-        output.push_str(&format!(
-            "staking_rpc_timeout_err {}\n",
-            self.rpc_timeout_err
-        ));
-
-        output.push_str(
-            "# HELP staking_rpc_conn_refused_err Number of RPC connection refused errors\n",
-        );
-        output.push_str("# TYPE staking_rpc_conn_refused_err counter\n");
-        output.push_str(&format!(
-            "staking_rpc_conn_refused_err {}\n",
-            self.rpc_conn_refused_err
-        ));
+    fn sample_pool(&mut self, pool: &PgPool) {
+        let size = pool.size();
+        let idle = pool.num_idle() as u32;
+        self.db_pool_size.set(size as i64);
+        self.db_pool_idle.set(idle as i64);
+        self.db_pool_active.set(size.saturating_sub(idle) as i64);
+    }
 
-        output
+    fn as_prometheus_metrics(&self) -> String {
+        TextEncoder::new()
+            .encode_to_string(&self.registry.gather())
+            .expect("metrics encode as text")
     }
 }
 
+/// Per-type duplicate counts from the `(inserted, total)` counts of an insert.
+/// Types without duplicates are left out.
+pub fn duplicate_counts(
+    counts: &HashMap<StakingEventType, (u64, u64)>,
+) -> HashMap<StakingEventType, u64> {
+    counts
+        .iter()
+        .map(|(event_type, (inserted, total))| (*event_type, total.saturating_sub(*inserted)))
+        .filter(|(_, duplicates)| *duplicates > 0)
+        .collect()
+}
+
+/// Request for the current metrics in the Prometheus text format.
 pub struct MetricsRequest {
-    response_tx: tokio::sync::oneshot::Sender<MetricsState>,
+    response_tx: tokio::sync::oneshot::Sender<String>,
 }
 
+/// Record metrics and answer requests for them until both channels close.
+/// At most `max_tracked_validators` validators get a delegation gauge. With an
+/// `alerter`, every metric is fed to it and its thresholds are checked every
+/// `check_interval`. The backlog of `metrics_rx` is kept in `queue_depth`, to
+/// be sampled like the counting channels.
 pub async fn process_metrics(
     mut metrics_rx: mpsc::UnboundedReceiver<Metric>,
+    queue_depth: QueueDepth,
     mut request_rx: mpsc::UnboundedReceiver<MetricsRequest>,
+    pool: PgPool,
+    max_tracked_validators: usize,
+    mut alerter: Option<Alerter>,
 ) -> Result<()> {
     let mut state = MetricsState::new();
+    state.max_tracked_validators = max_tracked_validators;
+    let mut alert_check = interval(
+        alerter
+            .as_ref()
+            .map_or(Duration::from_secs(60), Alerter::check_interval),
+    );
 
     loop {
         tokio::select! {
             Some(metric) = metrics_rx.recv() => {
+                queue_depth.set(metrics_rx.len() as u64);
+                if let Some(alerter) = &mut alerter {
+                    alerter.observe(&metric);
+                }
                 state.record(metric);
             }
+            // Stops with the metrics, so the task can end once both channels close.
+            _ = alert_check.tick(), if alerter.is_some() && !metrics_rx.is_closed() => {
+                if let Some(alerter) = &mut alerter {
+                    alerter.check();
+                }
+            }
             Some(request) = request_rx.recv() => {
-                let _ = request.response_tx.send(state.clone());
+                state.sample_pool(&pool);
+                let _ = request.response_tx.send(state.as_prometheus_metrics());
             }
             else => break,
         }
@@ -190,46 +896,826 @@ pub async fn process_metrics(
     Ok(())
 }
 
+/// Current metrics in the Prometheus text format, `None` if the metrics task
+/// has stopped.
+pub async fn request_metrics(request_tx: &mpsc::UnboundedSender<MetricsRequest>) -> Option<String> {
+    let (response_tx, response_rx) = tokio::sync::oneshot::channel();
+    request_tx.send(MetricsRequest { response_tx }).ok()?;
+    response_rx.await.ok()
+}
+
+/// Limits and access control of the metrics server.
+#[derive(Debug, Clone)]
+pub struct ServerOptions {
+    /// Time a scrape waits for the metrics task before the last snapshot is served.
+    pub request_timeout: Duration,
+    /// Requests handled at once, further ones wait for a slot.
+    pub max_concurrent_requests: usize,
+    /// Credentials required by the metrics path. Open when unset.
+    pub auth: Option<MetricsAuth>,
+    /// Path the metrics are served at, `/metrics` by default.
+    pub path: String,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        Self {
+            request_timeout: Duration::from_secs(5),
+            max_concurrent_requests: 16,
+            auth: None,
+            path: "/metrics".to_string(),
+        }
+    }
+}
+
+/// Header of responses served from an earlier snapshot, with its age in seconds.
+pub const SNAPSHOT_AGE_HEADER: &str = "x-metrics-snapshot-age-seconds";
+
+/// State shared by the `/metrics` requests.
+struct MetricsEndpoint {
+    request_timeout: Duration,
+    /// Expected `Authorization` header, if required.
+    authorization: Option<String>,
+    /// Last rendered metrics and when they were rendered.
+    snapshot: std::sync::Mutex<Option<(String, std::time::Instant)>>,
+}
+
+/// `Authorization` header value that `auth` accepts.
+fn expected_authorization(auth: &MetricsAuth) -> String {
+    use base64::Engine;
+
+    match auth {
+        MetricsAuth::Basic(auth) => {
+            let credentials = format!("{}:{}", auth.username, auth.password);
+            format!(
+                "Basic {}",
+                base64::engine::general_purpose::STANDARD.encode(credentials)
+            )
+        }
+        MetricsAuth::Bearer { bearer_token } => format!("Bearer {bearer_token}"),
+    }
+}
+
 async fn metrics_handler(
     axum::Extension(request_tx): axum::Extension<mpsc::UnboundedSender<MetricsRequest>>,
+    axum::Extension(endpoint): axum::Extension<std::sync::Arc<MetricsEndpoint>>,
+    headers: axum::http::HeaderMap,
 ) -> impl axum::response::IntoResponse {
-    let (response_tx, response_rx) = tokio::sync::oneshot::channel();
-    let _ = request_tx.send(MetricsRequest { response_tx });
+    use axum::http::{StatusCode, header};
 
-    let state = match response_rx.await {
-        Ok(s) => s,
-        Err(_) => {
+    if let Some(expected) = &endpoint.authorization {
+        let presented = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok());
+        if presented != Some(expected.as_str()) {
+            let challenge = if expected.starts_with("Basic") {
+                "Basic realm=\"metrics\""
+            } else {
+                "Bearer"
+            };
             return (
-                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to get metrics".to_string(),
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, challenge)],
+                "Unauthorized".to_string(),
             )
                 .into_response();
         }
-    };
+    }
 
-    (
-        [(
-            axum::http::header::CONTENT_TYPE,
-            "text/plain; version=0.0.4",
-        )],
-        state.as_prometheus_metrics(),
+    let content_type = (header::CONTENT_TYPE, "text/plain; version=0.0.4");
+    match tokio::time::timeout(endpoint.request_timeout, request_metrics(&request_tx)).await {
+        Ok(Some(metrics)) => {
+            *endpoint.snapshot.lock().expect("snapshot lock") =
+                Some((metrics.clone(), std::time::Instant::now()));
+            ([content_type], metrics).into_response()
+        }
+        result => {
+            let reason = match result {
+                Err(_) => "timed out",
+                Ok(_) => "metrics task stopped",
+            };
+            let snapshot = endpoint.snapshot.lock().expect("snapshot lock").clone();
+            let Some((metrics, rendered_at)) = snapshot else {
+                error!("Failed to get metrics: {reason}");
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Failed to get metrics".to_string(),
+                )
+                    .into_response();
+            };
+            let age = rendered_at.elapsed().as_secs();
+            warn!("Failed to get metrics: {reason}, serving a snapshot from {age}s ago");
+            let age = age.to_string();
+            (
+                [
+                    content_type,
+                    (
+                        header::HeaderName::from_static(SNAPSHOT_AGE_HEADER),
+                        age.as_str(),
+                    ),
+                ],
+                metrics,
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Time the readiness check waits for the database to answer.
+const READY_DB_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Alive while every event loop keeps sending heartbeats.
+async fn healthz_handler(
+    axum::Extension(health): axum::Extension<HealthState>,
+) -> impl axum::response::IntoResponse {
+    match health.check_liveness() {
+        Ok(()) => (axum::http::StatusCode::OK, "ok".to_string()),
+        Err(reason) => {
+            error!("Liveness check failed: {reason}");
+            (axum::http::StatusCode::SERVICE_UNAVAILABLE, reason)
+        }
+    }
+}
+
+/// Ready once the database answers queries and an RPC connection succeeded.
+async fn ready_handler(
+    axum::Extension(pool): axum::Extension<PgPool>,
+    axum::Extension(metrics_tx): axum::Extension<mpsc::UnboundedSender<Metric>>,
+    axum::Extension(health): axum::Extension<HealthState>,
+) -> impl axum::response::IntoResponse {
+    let unavailable = |reason: String| {
+        error!("Readiness check failed: {reason}");
+        (axum::http::StatusCode::SERVICE_UNAVAILABLE, reason)
+    };
+    match tokio::time::timeout(
+        READY_DB_TIMEOUT,
+        crate::db::repository::get_db_health(&pool),
     )
-        .into_response()
+    .await
+    {
+        Ok(Ok(())) => {
+            let _ = metrics_tx.send(Metric::DbConnected);
+        }
+        Ok(Err(e)) => return unavailable(format!("database unavailable: {e}")),
+        Err(_) => return unavailable("database unavailable: timed out".to_string()),
+    }
+    if !health.rpc_connected() {
+        return unavailable("no RPC connection yet".to_string());
+    }
+    (axum::http::StatusCode::OK, "ready".to_string())
+}
+
+/// Routes of the metrics server, the metrics at [`ServerOptions::path`].
+/// `/ready` is kept as an alias of `/readyz`.
+pub fn router(
+    request_tx: mpsc::UnboundedSender<MetricsRequest>,
+    pool: PgPool,
+    metrics_tx: mpsc::UnboundedSender<Metric>,
+    health: HealthState,
+    options: &ServerOptions,
+) -> axum::Router {
+    use axum::{Router, routing::get};
+
+    let endpoint = std::sync::Arc::new(MetricsEndpoint {
+        request_timeout: options.request_timeout,
+        authorization: options.auth.as_ref().map(expected_authorization),
+        snapshot: std::sync::Mutex::new(None),
+    });
+    Router::new()
+        .route(&options.path, get(metrics_handler))
+        .route("/healthz", get(healthz_handler))
+        .route("/readyz", get(ready_handler))
+        .route("/ready", get(ready_handler))
+        .layer(
+            tower::ServiceBuilder::new()
+                .layer(axum::Extension(request_tx))
+                .layer(axum::Extension(pool))
+                .layer(axum::Extension(metrics_tx))
+                .layer(axum::Extension(health))
+                .layer(axum::Extension(endpoint)),
+        )
+        // One limit shared by all routes, so a scrape storm cannot pile up handlers.
+        .layer(tower::limit::GlobalConcurrencyLimitLayer::new(
+            options.max_concurrent_requests,
+        ))
 }
 
+/// Serves the metrics routes together with the REST API of [`crate::api`],
+/// which queries `api_pool` and `validator_state`.
+#[allow(clippy::too_many_arguments)]
 pub async fn run_metrics_server(
     request_tx: mpsc::UnboundedSender<MetricsRequest>,
     bind_addr: String,
+    pool: PgPool,
+    api_pool: PgPool,
+    validator_state: crate::validator_state::SharedValidatorStateCache,
+    metrics_tx: mpsc::UnboundedSender<Metric>,
+    health: HealthState,
+    options: ServerOptions,
 ) -> Result<()> {
-    use axum::{Router, routing::get};
-
-    let app = Router::new()
-        .route("/metrics", get(metrics_handler))
-        .layer(tower::ServiceBuilder::new().layer(axum::Extension(request_tx)));
+    let app = router(request_tx, pool, metrics_tx, health, &options)
+        .merge(crate::api::router(api_pool, validator_state));
 
-    let listener = tokio::net::TcpListener::bind(&bind_addr).await?;
+    let listener = bind_with_retry(&bind_addr, Duration::from_millis(500)).await?;
     info!("Metrics server listening on http://{}", bind_addr);
 
     axum::serve(listener, app).await?;
     Ok(())
 }
+
+/// Longest wait between two attempts of [`bind_with_retry`].
+const MAX_BIND_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Bind to `bind_addr`, retrying while it is in use, e.g. by the previous
+/// instance during a rolling restart. The delay starts at `retry_delay` and
+/// doubles up to 30s. Other errors are returned immediately.
+pub async fn bind_with_retry(
+    bind_addr: &str,
+    mut retry_delay: Duration,
+) -> std::io::Result<tokio::net::TcpListener> {
+    loop {
+        match tokio::net::TcpListener::bind(bind_addr).await {
+            Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
+                warn!("Metrics address {bind_addr} is in use, retrying in {retry_delay:?}");
+                tokio::time::sleep(retry_delay).await;
+                retry_delay = (retry_delay * 2).min(MAX_BIND_RETRY_DELAY);
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use strum::IntoEnumIterator;
+
+    /// An example of every kind of metric. The match is exhaustive, so a new
+    /// variant does not compile until it has an example here, which
+    /// `test_every_metric_is_rendered` then checks is exported.
+    fn example_metric(kind: MetricKind) -> Metric {
+        match kind {
+            MetricKind::InsertedEvents => {
+                Metric::InsertedEvents(HashMap::from([(StakingEventType::Delegate, (1, 1))]))
+            }
+            MetricKind::DuplicateEvents => {
+                Metric::DuplicateEvents(HashMap::from([(StakingEventType::Delegate, 1)]))
+            }
+            MetricKind::IntraBatchDuplicates => Metric::IntraBatchDuplicates(BatchDuplicates {
+                blocks: 1,
+                events: HashMap::new(),
+            }),
+            MetricKind::BackfilledBlocks => Metric::BackfilledBlocks(1),
+            MetricKind::FailedToBackfill => Metric::FailedToBackfill(1),
+            MetricKind::BackfillChunk => Metric::BackfillChunk {
+                blocks: 1,
+                duration: Duration::from_secs(1),
+                outcome: BackfillOutcome::Ok,
+            },
+            MetricKind::BackfillRangeStarted => Metric::BackfillRangeStarted(1),
+            MetricKind::BackfillRangeFinished => Metric::BackfillRangeFinished(1),
+            MetricKind::FailedToInsert => Metric::FailedToInsert { table: None },
+            MetricKind::InsertTimeout => Metric::InsertTimeout,
+            MetricKind::DbPoolExhausted => Metric::DbPoolExhausted,
+            MetricKind::DbConnected => Metric::DbConnected,
+            MetricKind::DbConnectionClosed => Metric::DbConnectionClosed,
+            MetricKind::RpcTimeout => Metric::RpcTimeout,
+            MetricKind::RpcConnRefused => Metric::RpcConnRefused,
+            MetricKind::RpcConnected => Metric::RpcConnected,
+            MetricKind::RpcCircuitOpen => Metric::RpcCircuitOpen,
+            MetricKind::PushFailed => Metric::PushFailed,
+            MetricKind::AlertFailed => Metric::AlertFailed,
+            MetricKind::VaultRenewalFailed => Metric::VaultRenewalFailed,
+            MetricKind::ConfigReloaded => Metric::ConfigReloaded,
+            MetricKind::ConfigReloadFailed => Metric::ConfigReloadFailed,
+            MetricKind::NegativeStakes => Metric::NegativeStakes(1),
+            MetricKind::PendingWithdrawals => Metric::PendingWithdrawals(BigDecimal::from(1)),
+            MetricKind::IndexedBlockCount => Metric::IndexedBlockCount(1),
+            MetricKind::MaxBlockInserted => Metric::MaxBlockInserted(1),
+            MetricKind::BlocksInserted => Metric::BlocksInserted(1),
+            MetricKind::DeadLetterDepth => Metric::DeadLetterDepth(1),
+            MetricKind::DeadLetterParked => Metric::DeadLetterParked(1),
+            MetricKind::ReorgedBlocksDeleted => Metric::ReorgedBlocksDeleted(1),
+            MetricKind::MissingBlocks => Metric::MissingBlocks(1),
+            MetricKind::GapQueueDepth => Metric::GapQueueDepth(1),
+            MetricKind::HeadLagBlocks => Metric::HeadLagBlocks(1),
+            MetricKind::RowCounts => Metric::RowCounts(HashMap::from([("blocks".to_string(), 1)])),
+            MetricKind::JailedValidators => Metric::JailedValidators(1),
+            MetricKind::ValidatorDelegation => Metric::ValidatorDelegation {
+                validator_id: 1,
+                total: BigDecimal::from(1),
+            },
+            MetricKind::UnknownEvents => {
+                Metric::UnknownEvents(HashMap::from([("ab".repeat(32), 1)]))
+            }
+            MetricKind::RpcLatency => Metric::RpcLatency {
+                operation: RpcOperation::GetLogs,
+                duration_ms: 1,
+            },
+            MetricKind::TableInsert => Metric::TableInsert {
+                table: "blocks",
+                rows: 1,
+                millis: 1,
+            },
+            MetricKind::IngestLatency => Metric::IngestLatency(Duration::from_secs(1)),
+            MetricKind::QueueDepth => Metric::QueueDepth {
+                queue: "db",
+                depth: 1,
+            },
+        }
+    }
+
+    #[test]
+    fn test_every_metric_is_rendered() {
+        let initial = MetricsState::new().as_prometheus_metrics();
+        for kind in MetricKind::iter() {
+            let mut state = MetricsState::new();
+            state.record(example_metric(kind));
+            assert_ne!(
+                state.as_prometheus_metrics(),
+                initial,
+                "{kind:?} is not exported"
+            );
+        }
+    }
+
+    #[test]
+    fn test_duplicate_counts() {
+        let counts = HashMap::from([
+            (StakingEventType::Delegate, (3, 5)),
+            (StakingEventType::Withdraw, (2, 2)),
+        ]);
+        assert_eq!(
+            duplicate_counts(&counts),
+            HashMap::from([(StakingEventType::Delegate, 2)])
+        );
+    }
+
+    #[test]
+    fn test_record_intra_batch_duplicates() {
+        let mut state = MetricsState::new();
+        state.record(Metric::IntraBatchDuplicates(BatchDuplicates {
+            blocks: 1,
+            events: HashMap::from([(StakingEventType::Delegate, 2)]),
+        }));
+        state.record(Metric::IntraBatchDuplicates(BatchDuplicates {
+            blocks: 0,
+            events: HashMap::from([(StakingEventType::Delegate, 1)]),
+        }));
+
+        let output = state.as_prometheus_metrics();
+        assert!(
+            output.contains("staking_intra_batch_duplicates_total{event_type=\"Delegate\"} 3\n")
+        );
+        assert!(
+            output.contains("staking_intra_batch_duplicates_total{event_type=\"Withdraw\"} 0\n")
+        );
+        assert!(output.contains("staking_intra_batch_duplicate_blocks_total 1\n"));
+        assert!(output.contains("staking_events_duplicates_total{event_type=\"Delegate\"} 0\n"));
+    }
+
+    #[test]
+    fn test_record_inserted_and_duplicate_events() {
+        let mut state = MetricsState::new();
+        let counts = HashMap::from([(StakingEventType::Delegate, (3, 5))]);
+        state.record(Metric::DuplicateEvents(duplicate_counts(&counts)));
+        state.record(Metric::InsertedEvents(counts));
+
+        let output = state.as_prometheus_metrics();
+        assert!(output.contains("staking_events_inserted_total{event_type=\"Delegate\"} 3\n"));
+        assert!(output.contains("staking_events_duplicates_total{event_type=\"Delegate\"} 2\n"));
+        assert!(output.contains("staking_events_duplicates_total{event_type=\"Withdraw\"} 0\n"));
+    }
+
+    #[test]
+    fn test_render_rpc_error_counters() {
+        let mut state = MetricsState::new();
+        state.record(Metric::RpcTimeout);
+        state.record(Metric::RpcTimeout);
+        state.record(Metric::RpcConnRefused);
+        state.record(Metric::RpcCircuitOpen);
+
+        let output = state.as_prometheus_metrics();
+        assert!(
+            output.contains("# TYPE staking_rpc_timeout_err counter\nstaking_rpc_timeout_err 2\n")
+        );
+        assert!(output.contains(
+            "# TYPE staking_rpc_conn_refused_err counter\nstaking_rpc_conn_refused_err 1\n"
+        ));
+        assert!(output.contains(
+            "# TYPE staking_rpc_circuit_open_err counter\nstaking_rpc_circuit_open_err 1\n"
+        ));
+    }
+
+    #[test]
+    fn test_render_insert_failure_counters() {
+        let mut state = MetricsState::new();
+        state.record(Metric::InsertTimeout);
+        state.record(Metric::InsertTimeout);
+        state.record(Metric::DbPoolExhausted);
+
+        let output = state.as_prometheus_metrics();
+        assert!(
+            output.contains(
+                "# TYPE staking_insert_timeout_err counter\nstaking_insert_timeout_err 2\n"
+            )
+        );
+        assert!(output.contains(
+            "# TYPE staking_db_pool_exhausted_err counter\nstaking_db_pool_exhausted_err 1\n"
+        ));
+    }
+
+    #[test]
+    fn test_render_block_progress() {
+        let mut state = MetricsState::new();
+        state.record(Metric::MaxBlockInserted(200));
+        state.record(Metric::BlocksInserted(10));
+        // A backfilled batch does not move the gauge backwards.
+        state.record(Metric::MaxBlockInserted(50));
+        state.record(Metric::BlocksInserted(5));
+
+        let output = state.as_prometheus_metrics();
+        assert!(
+            output.contains(
+                "# TYPE staking_max_indexed_block gauge\nstaking_max_indexed_block 200\n"
+            )
+        );
+        assert!(output.contains(
+            "# TYPE staking_blocks_inserted_total counter\nstaking_blocks_inserted_total 15\n"
+        ));
+    }
+
+    #[test]
+    fn test_validator_delegation_tracks_a_bounded_set_of_validators() {
+        let mut state = MetricsState::new();
+        state.max_tracked_validators = 2;
+        for (validator_id, total) in [(1, 100), (2, 200), (3, 300), (1, 150)] {
+            state.record(Metric::ValidatorDelegation {
+                validator_id,
+                total: BigDecimal::from(total),
+            });
+        }
+
+        let output = state.as_prometheus_metrics();
+        assert!(output.contains("staking_validator_delegation{validator_id=\"1\"} 150\n"));
+        assert!(output.contains("staking_validator_delegation{validator_id=\"2\"} 200\n"));
+        assert!(!output.contains("validator_id=\"3\""));
+    }
+
+    #[test]
+    fn test_render_jailed_validators() {
+        let mut state = MetricsState::new();
+        state.record(Metric::JailedValidators(3));
+        state.record(Metric::JailedValidators(2));
+
+        let output = state.as_prometheus_metrics();
+        assert!(
+            output
+                .contains("# TYPE staking_validators_jailed gauge\nstaking_validators_jailed 2\n")
+        );
+    }
+
+    #[test]
+    fn test_render_row_counts() {
+        let mut state = MetricsState::new();
+        state.record(Metric::RowCounts(HashMap::from([
+            ("delegate_events".to_string(), 3),
+            ("blocks".to_string(), 7),
+        ])));
+        state.record(Metric::RowCounts(HashMap::from([(
+            "blocks".to_string(),
+            8,
+        )])));
+
+        let output = state.as_prometheus_metrics();
+        assert!(output.contains(
+            "# TYPE staking_rows_total gauge\n\
+             staking_rows_total{table=\"blocks\"} 8\n\
+             staking_rows_total{table=\"delegate_events\"} 3\n"
+        ));
+    }
+
+    #[test]
+    fn test_render_unknown_events() {
+        let mut state = MetricsState::new();
+        state.record(Metric::UnknownEvents(HashMap::from([
+            ("bb".to_string(), 1),
+            ("aa".to_string(), 2),
+        ])));
+        state.record(Metric::UnknownEvents(HashMap::from([(
+            "bb".to_string(),
+            3,
+        )])));
+
+        let output = state.as_prometheus_metrics();
+        assert!(output.contains(
+            "# TYPE staking_unknown_events_total counter\n\
+             staking_unknown_events_total{topic0=\"aa\"} 2\n\
+             staking_unknown_events_total{topic0=\"bb\"} 4\n"
+        ));
+    }
+
+    #[test]
+    fn test_render_table_insert_metrics() {
+        let mut state = MetricsState::new();
+        state.record(Metric::TableInsert {
+            table: "delegate_events",
+            rows: 10,
+            millis: 20,
+        });
+        state.record(Metric::TableInsert {
+            table: "delegate_events",
+            rows: 5,
+            millis: 300,
+        });
+        state.record(Metric::TableInsert {
+            table: "blocks",
+            rows: 2,
+            millis: 20000,
+        });
+
+        let output = state.as_prometheus_metrics();
+        for line in [
+            "# TYPE staking_table_insert_duration_seconds histogram\n",
+            "staking_table_insert_duration_seconds_bucket{table=\"delegate_events\",le=\"0.01\"} 0\n",
+            "staking_table_insert_duration_seconds_bucket{table=\"delegate_events\",le=\"0.025\"} 1\n",
+            "staking_table_insert_duration_seconds_bucket{table=\"delegate_events\",le=\"0.5\"} 2\n",
+            "staking_table_insert_duration_seconds_bucket{table=\"delegate_events\",le=\"+Inf\"} 2\n",
+            "staking_table_insert_duration_seconds_sum{table=\"delegate_events\"} 0.32\n",
+            "staking_table_insert_duration_seconds_count{table=\"delegate_events\"} 2\n",
+            "staking_table_insert_duration_seconds_bucket{table=\"blocks\",le=\"10\"} 0\n",
+            "staking_table_insert_duration_seconds_bucket{table=\"blocks\",le=\"+Inf\"} 1\n",
+            "# TYPE staking_table_insert_rows_total counter\n",
+            "staking_table_insert_rows_total{table=\"blocks\"} 2\n",
+            "staking_table_insert_rows_total{table=\"delegate_events\"} 15\n",
+        ] {
+            assert!(output.contains(line), "missing {line:?} in\n{output}");
+        }
+    }
+
+    #[test]
+    fn test_record_failed_inserts() {
+        let mut state = MetricsState::new();
+        state.record(Metric::FailedToInsert {
+            table: Some(StakingEventType::Withdraw),
+        });
+        state.record(Metric::FailedToInsert { table: None });
+
+        let output = state.as_prometheus_metrics();
+        assert!(output.contains("staking_insert_events_err 2\n"));
+        assert!(output.contains("staking_insert_table_err{event_type=\"Withdraw\"} 1\n"));
+        assert!(output.contains("staking_insert_table_err{event_type=\"Delegate\"} 0\n"));
+    }
+
+    #[test]
+    fn test_render_rpc_latency() {
+        let mut state = MetricsState::new();
+        state.record(Metric::RpcLatency {
+            operation: RpcOperation::GetLogs,
+            duration_ms: 40,
+        });
+        state.record(Metric::RpcLatency {
+            operation: RpcOperation::GetLogs,
+            duration_ms: 700,
+        });
+        state.record(Metric::RpcLatency {
+            operation: RpcOperation::StreamEvent,
+            duration_ms: 120000,
+        });
+
+        let output = state.as_prometheus_metrics();
+        for line in [
+            "# TYPE staking_rpc_latency_milliseconds histogram\n",
+            "staking_rpc_latency_milliseconds_bucket{operation=\"get_logs\",le=\"25\"} 0\n",
+            "staking_rpc_latency_milliseconds_bucket{operation=\"get_logs\",le=\"50\"} 1\n",
+            "staking_rpc_latency_milliseconds_bucket{operation=\"get_logs\",le=\"1000\"} 2\n",
+            "staking_rpc_latency_milliseconds_bucket{operation=\"get_logs\",le=\"+Inf\"} 2\n",
+            "staking_rpc_latency_milliseconds_sum{operation=\"get_logs\"} 740\n",
+            "staking_rpc_latency_milliseconds_count{operation=\"get_logs\"} 2\n",
+            "staking_rpc_latency_milliseconds_bucket{operation=\"stream_event\",le=\"60000\"} 0\n",
+            "staking_rpc_latency_milliseconds_bucket{operation=\"stream_event\",le=\"+Inf\"} 1\n",
+        ] {
+            assert!(output.contains(line), "missing {line:?} in\n{output}");
+        }
+    }
+
+    #[test]
+    fn test_render_backfill_chunks() {
+        let mut state = MetricsState::new();
+        for (blocks, secs, outcome) in [
+            (100, 2, BackfillOutcome::Ok),
+            (100, 3, BackfillOutcome::Ok),
+            (50, 45, BackfillOutcome::RpcError),
+            (20, 1, BackfillOutcome::DecodeError),
+        ] {
+            state.record(Metric::BackfillChunk {
+                blocks,
+                duration: Duration::from_secs(secs),
+                outcome,
+            });
+        }
+
+        let output = state.as_prometheus_metrics();
+        for line in [
+            "# TYPE staking_backfill_chunk_seconds histogram\n",
+            "staking_backfill_chunk_seconds_bucket{le=\"1\"} 1\n",
+            "staking_backfill_chunk_seconds_bucket{le=\"5\"} 3\n",
+            "staking_backfill_chunk_seconds_bucket{le=\"60\"} 4\n",
+            "staking_backfill_chunk_seconds_count 4\n",
+            "staking_backfill_chunks_total{outcome=\"ok\"} 2\n",
+            "staking_backfill_chunks_total{outcome=\"rpc_error\"} 1\n",
+            "staking_backfill_chunks_total{outcome=\"decode_error\"} 1\n",
+            "staking_backfill_chunks_total{outcome=\"db_channel_closed\"} 0\n",
+            "staking_backfill_chunk_blocks_total{outcome=\"ok\"} 200\n",
+            "staking_backfill_chunk_blocks_total{outcome=\"rpc_error\"} 50\n",
+        ] {
+            assert!(output.contains(line), "missing {line:?} in\n{output}");
+        }
+    }
+
+    #[test]
+    fn test_render_ingest_latency() {
+        let mut state = MetricsState::new();
+        for latency in [
+            Duration::from_millis(400),
+            Duration::from_secs(1),
+            Duration::from_secs(45),
+            Duration::from_secs(7200),
+        ] {
+            state.record(Metric::IngestLatency(latency));
+        }
+
+        let output = state.as_prometheus_metrics();
+        for line in [
+            "# TYPE staking_ingest_latency_seconds histogram\n",
+            "staking_ingest_latency_seconds_bucket{le=\"1\"} 2\n",
+            "staking_ingest_latency_seconds_bucket{le=\"30\"} 2\n",
+            "staking_ingest_latency_seconds_bucket{le=\"60\"} 3\n",
+            "staking_ingest_latency_seconds_bucket{le=\"3600\"} 3\n",
+            "staking_ingest_latency_seconds_bucket{le=\"+Inf\"} 4\n",
+            "staking_ingest_latency_seconds_sum 7246.4\n",
+            "staking_ingest_latency_seconds_count 4\n",
+        ] {
+            assert!(output.contains(line), "missing {line:?} in\n{output}");
+        }
+    }
+
+    #[test]
+    fn test_render_initial_state() {
+        let output = MetricsState::new().as_prometheus_metrics();
+        for line in output.lines().filter(|line| !line.starts_with('#')) {
+            let (name, value) = line.rsplit_once(' ').unwrap();
+            assert_eq!(value, "0", "{name} should start at zero");
+        }
+    }
+
+    #[test]
+    fn test_render_pool_metrics() {
+        let state = MetricsState::new();
+        state.db_pool_size.set(5);
+        state.db_pool_active.set(2);
+        state.db_pool_idle.set(3);
+
+        let output = state.as_prometheus_metrics();
+        assert!(output.contains("# TYPE staking_db_pool_size gauge\nstaking_db_pool_size 5\n"));
+        assert!(output.contains(
+            "# TYPE staking_db_pool_active_connections gauge\nstaking_db_pool_active_connections 2\n"
+        ));
+        assert!(output.contains(
+            "# TYPE staking_db_pool_idle_connections gauge\nstaking_db_pool_idle_connections 3\n"
+        ));
+    }
+
+    #[test]
+    fn test_render_queue_depth() {
+        let (tx, mut rx) = crate::queue::counting_channel();
+        for i in 0..10 {
+            tx.send(i).unwrap();
+        }
+        let mut state = MetricsState::new();
+        state.record(Metric::QueueDepth {
+            queue: "db",
+            depth: tx.depth().get(),
+        });
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        for _ in 0..4 {
+            runtime.block_on(rx.recv()).unwrap();
+        }
+        state.record(Metric::QueueDepth {
+            queue: "db",
+            depth: tx.depth().get(),
+        });
+
+        let output = state.as_prometheus_metrics();
+        assert!(output.contains("staking_queue_depth{queue=\"db\"} 6\n"));
+        assert!(output.contains("staking_queue_depth_max{queue=\"db\"} 10\n"));
+    }
+
+    /// Compares the output for a fixed sequence of metrics with the golden file,
+    /// so renamed or dropped metrics show up in review. Run with
+    /// `UPDATE_GOLDEN=1` to rewrite the file after an intended change.
+    #[test]
+    fn test_render_matches_golden_file() {
+        let golden_path =
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/metrics.prom");
+
+        let mut state = MetricsState::new();
+        for metric in [
+            Metric::InsertedEvents(HashMap::from([
+                (StakingEventType::Delegate, (3, 4)),
+                (StakingEventType::EpochChanged, (1, 1)),
+            ])),
+            Metric::DuplicateEvents(HashMap::from([(StakingEventType::Delegate, 1)])),
+            Metric::IntraBatchDuplicates(BatchDuplicates {
+                blocks: 1,
+                events: HashMap::from([(StakingEventType::Withdraw, 2)]),
+            }),
+            Metric::BackfilledBlocks(100),
+            Metric::FailedToBackfill(10),
+            Metric::BackfillChunk {
+                blocks: 100,
+                duration: Duration::from_millis(500),
+                outcome: BackfillOutcome::Ok,
+            },
+            Metric::BackfillChunk {
+                blocks: 10,
+                duration: Duration::from_secs(15),
+                outcome: BackfillOutcome::RpcError,
+            },
+            Metric::BackfillRangeStarted(100),
+            Metric::BackfillRangeStarted(50),
+            Metric::BackfillRangeFinished(100),
+            Metric::FailedToInsert {
+                table: Some(StakingEventType::Undelegate),
+            },
+            Metric::InsertTimeout,
+            Metric::DbPoolExhausted,
+            Metric::DbConnected,
+            Metric::DbConnectionClosed,
+            Metric::RpcTimeout,
+            Metric::RpcConnRefused,
+            Metric::RpcConnected,
+            Metric::RpcCircuitOpen,
+            Metric::PushFailed,
+            Metric::AlertFailed,
+            Metric::VaultRenewalFailed,
+            Metric::ConfigReloaded,
+            Metric::ConfigReloaded,
+            Metric::ConfigReloadFailed,
+            Metric::NegativeStakes(1),
+            Metric::PendingWithdrawals(BigDecimal::from(1_500_000_000_000_000_000u64)),
+            Metric::IndexedBlockCount(500),
+            Metric::MaxBlockInserted(600),
+            Metric::MaxBlockInserted(550),
+            Metric::BlocksInserted(20),
+            Metric::DeadLetterDepth(2),
+            Metric::DeadLetterParked(1),
+            Metric::ReorgedBlocksDeleted(3),
+            Metric::MissingBlocks(100),
+            Metric::GapQueueDepth(3),
+            Metric::HeadLagBlocks(12),
+            Metric::RowCounts(HashMap::from([
+                ("blocks".to_string(), 500),
+                ("delegate_events".to_string(), 3),
+            ])),
+            Metric::JailedValidators(1),
+            Metric::ValidatorDelegation {
+                validator_id: 7,
+                total: BigDecimal::from(100_000),
+            },
+            Metric::UnknownEvents(HashMap::from([("ab".repeat(32), 2)])),
+            Metric::TableInsert {
+                table: "delegate_events",
+                rows: 3,
+                millis: 40,
+            },
+            Metric::RpcLatency {
+                operation: RpcOperation::GetLogs,
+                duration_ms: 120,
+            },
+            Metric::IngestLatency(Duration::from_secs(3)),
+            Metric::QueueDepth {
+                queue: "db",
+                depth: 5,
+            },
+            Metric::QueueDepth {
+                queue: "db",
+                depth: 2,
+            },
+        ] {
+            state.record(metric);
+        }
+        let output = state.as_prometheus_metrics();
+
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(&golden_path, &output).unwrap();
+        }
+        let golden = std::fs::read_to_string(&golden_path).unwrap();
+        assert!(
+            output == golden,
+            "metrics differ from {}, rerun with UPDATE_GOLDEN=1 to accept:\n{output}",
+            golden_path.display()
+        );
+    }
+}
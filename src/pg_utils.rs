@@ -1,8 +1,12 @@
 //! Utilities for running Postgres locally for testing and development.
 
+use std::any::TypeId;
+use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::{Child, Command};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
 
 use scopeguard::defer;
 use sqlx::PgPool;
@@ -206,17 +210,181 @@ pub fn execute_migrations(pg_host: &str, user: &str, db_name: &str) -> Result<()
 /// this point, passwords are equal to the usernames.
 pub fn with_postgres_and_schema<F: FnOnce(&str) -> Result<()>>(f: F) -> Result<()> {
     with_postgres(|pg_host| {
-        execute_migrations(pg_host, "monad_staking_setup", "monad_staking")?;
+        execute_migrations(pg_host, "monad_staking_setup", "monad_staking_indexer")?;
         f(pg_host)
     })
 }
 
+/// Run a Postgres client program as the database superuser, printing its
+/// output if it fails.
+fn run_as_superuser(mut cmd: Command, pg_host: &str, name: &str) -> Result<()> {
+    let output = cmd
+        .env("PGUSER", "postgres")
+        .env("PGPASSWORD", "postgres")
+        .env("PGHOST", pg_host)
+        .output()
+        .or_app_err(&format!("Failed to spawn `{name}`."))?;
+
+    if !output.status.success() {
+        std::io::Write::write_all(&mut std::io::stdout(), &output.stdout)
+            .or_app_err(&format!("Failed to write {name} stdout"))?;
+        std::io::Write::write_all(&mut std::io::stderr(), &output.stderr)
+            .or_app_err(&format!("Failed to write {name} stderr"))?;
+        return Err(Error::new(format!("`{name}` failed.")));
+    }
+
+    Ok(())
+}
+
+/// Dump the database `db_name` with `pg_dump` to a file in the Postgres
+/// directory `pg_host`, returning its path.
+pub fn snapshot_database(pg_host: &str, db_name: &str) -> Result<PathBuf> {
+    let snapshot = Path::new(pg_host).join(format!("{db_name}.dump"));
+    dump_database(pg_host, db_name, &snapshot)?;
+    Ok(snapshot)
+}
+
+fn dump_database(pg_host: &str, db_name: &str, snapshot: &Path) -> Result<()> {
+    let mut pg_dump = new_command("pg_dump");
+    pg_dump
+        .args(["--format", "custom", "--dbname", db_name, "--file"])
+        .arg(snapshot);
+    run_as_superuser(pg_dump, pg_host, "pg_dump")
+}
+
+/// Replace the database `db_name` with a snapshot from [`snapshot_database`].
+///
+/// The database is dropped as a whole, so objects created after the snapshot
+/// are removed too, and open connections to it are terminated.
+pub fn restore_snapshot(pg_host: &str, db_name: &str, snapshot: &Path) -> Result<()> {
+    let mut psql = new_command("psql");
+    psql.args(["--dbname", "postgres", "--command"])
+        .arg(format!("DROP DATABASE {db_name} WITH (FORCE)"));
+    run_as_superuser(psql, pg_host, "psql")?;
+
+    let mut pg_restore = new_command("pg_restore");
+    pg_restore
+        .args(["--create", "--exit-on-error", "--dbname", "postgres"])
+        .arg(snapshot);
+    run_as_superuser(pg_restore, pg_host, "pg_restore")
+}
+
+/// The Postgres shared by the [`with_postgres_snapshot`] calls of a test binary.
+struct SharedPostgres {
+    pg_host: String,
+    /// Snapshot of the database right after the migrations.
+    schema: PathBuf,
+    /// Snapshot after each `setup`, by the type of the closure.
+    snapshots: HashMap<TypeId, PathBuf>,
+}
+
+/// Held for the whole of a [`with_postgres_snapshot`] call, as all calls use
+/// the same database.
+static SHARED_POSTGRES: Mutex<Option<SharedPostgres>> = Mutex::new(None);
+
+impl SharedPostgres {
+    fn start(db_name: &str) -> Result<Self> {
+        // Postgres gets killed when the thread that spawned it exits, see
+        // [`new_command`], and test threads exit after every test. So it is
+        // spawned from a thread that lives as long as the test binary.
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let started = tempfile::tempdir()
+                .or_app_err("Failed to create temporary directory for Postgres.")
+                .and_then(|dir| Ok((start_postgres(dir.path())?, dir)));
+            let _running = match started {
+                Ok((postgres, dir)) => {
+                    let pg_host = dir
+                        .path()
+                        .to_str()
+                        .expect("Temp dir path should be valid UTF-8.")
+                        .to_string();
+                    let _ = started_tx.send(Ok(pg_host));
+                    (postgres, dir)
+                }
+                Err(e) => {
+                    let _ = started_tx.send(Err(e));
+                    return;
+                }
+            };
+            loop {
+                std::thread::park();
+            }
+        });
+        let pg_host = started_rx
+            .recv()
+            .or_app_err("Postgres thread exited before starting Postgres.")??;
+
+        execute_migrations(&pg_host, "monad_staking_setup", db_name)?;
+        let schema = Path::new(&pg_host).join("schema.dump");
+        dump_database(&pg_host, db_name, &schema)?;
+        Ok(Self {
+            pg_host,
+            schema,
+            snapshots: HashMap::new(),
+        })
+    }
+}
+
+/// Run `f` against a database populated by `setup`, sharing the expensive
+/// setup between tests.
+///
+/// The first call with a given `setup` runs it on a database with all
+/// migrations and snapshots the result with `pg_dump`. Every call restores
+/// that snapshot before calling `f`, so `f` may modify the database without
+/// affecting later calls. All calls in a test binary share one Postgres and run
+/// one at a time. Both closures get `PGHOST` as argument, as for
+/// [`with_postgres_and_schema`]. The Postgres lives until the test binary exits.
+pub fn with_postgres_snapshot<S, F>(setup: S, f: F) -> Result<()>
+where
+    S: FnOnce(&str) -> Result<()> + 'static,
+    F: FnOnce(&str) -> Result<()>,
+{
+    let db = "monad_staking_indexer";
+    // Every call starts from a restored snapshot, so a call that panicked
+    // leaves nothing behind that matters.
+    let mut shared = SHARED_POSTGRES
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    let shared = match &mut *shared {
+        Some(shared) => shared,
+        None => shared.insert(SharedPostgres::start(db)?),
+    };
+
+    let pg_host = shared.pg_host.clone();
+    let snapshot = match shared.snapshots.get(&TypeId::of::<S>()) {
+        Some(snapshot) => snapshot.clone(),
+        None => {
+            restore_snapshot(&pg_host, db, &shared.schema)?;
+            setup(&pg_host)?;
+            let snapshot =
+                Path::new(&pg_host).join(format!("snapshot-{}.dump", shared.snapshots.len()));
+            dump_database(&pg_host, db, &snapshot)?;
+            shared.snapshots.insert(TypeId::of::<S>(), snapshot.clone());
+            snapshot
+        }
+    };
+    restore_snapshot(&pg_host, db, &snapshot)?;
+    f(&pg_host)
+}
+
 /// Start a Postgres instance with a temporary data directory, run all migrations against it.
 ///
 /// Async version of [`with_postgres_and_schema`]. When the Postgres is ready and all migrations
 /// have been executed, calls the async closure with a `PgPool` connection pool. The `app` and
-/// `setup` users exist at this point, passwords are equal to the usernames.
+/// `setup` users exist at this point, passwords are equal to the usernames. The closure fails
+/// if it does not complete within 30 seconds, see [`with_postgres_and_schema_async_timeout`].
 pub fn with_postgres_and_schema_async<F, Fut>(f: F) -> Result<()>
+where
+    F: FnOnce(PgPool) -> Fut,
+    Fut: std::future::Future<Output = std::result::Result<(), Box<dyn std::error::Error>>>,
+{
+    with_postgres_and_schema_async_timeout(Duration::from_secs(30), f)
+}
+
+/// As [`with_postgres_and_schema_async`], failing if the closure does not complete within
+/// `timeout`. Migrations and starting Postgres do not count towards the timeout.
+pub fn with_postgres_and_schema_async_timeout<F, Fut>(timeout: Duration, f: F) -> Result<()>
 where
     F: FnOnce(PgPool) -> Fut,
     Fut: std::future::Future<Output = std::result::Result<(), Box<dyn std::error::Error>>>,
@@ -232,13 +400,13 @@ where
         runtime
             .block_on(async {
                 let (tx, _) = mpsc::unbounded_channel();
-                let pool = crate::db::create_pool(&connection_url, tx)
+                let pool = crate::db::create_pool(&connection_url, &Default::default(), tx)
                     .await
                     .map_err(|e| format!("Failed to create pool: {}", e))?;
 
-                tokio::time::timeout(std::time::Duration::from_secs(5), f(pool))
+                tokio::time::timeout(timeout, f(pool))
                     .await
-                    .map_err(|_| "Test timeout after 5 seconds".into())
+                    .map_err(|_| format!("Test timeout after {:?}", timeout).into())
                     .and_then(|result| result)
             })
             .map_err(|e| Error::new(format!("Async callback failed: {}", e)))
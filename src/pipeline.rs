@@ -0,0 +1,537 @@
+//! Fetching staking logs from a [`Connector`] and handing them to the DB task:
+//! the live stream, the backfill of gaps and the backfill command.
+
+use std::ops::Range;
+
+use alloy::primitives::Address;
+use eyre::Result;
+use futures_util::stream::StreamExt;
+use tokio::sync::{mpsc, watch};
+use tokio::time::{Duration, Instant, interval};
+use tracing::{Instrument, debug, error, field, info, info_span, warn};
+
+use crate::config::BackfillProfile;
+use crate::health::HealthState;
+use crate::metrics::{self, BackfillOutcome};
+use crate::provider::{CircuitState, Connector, LogProvider};
+use crate::queue::{CountingReceiver, CountingSender, GapQueue, counting_channel};
+use crate::reload::RuntimeConfig;
+use crate::{
+    BatchOrigin, BlockBatch, DbRequest, DbTaskOptions, GapOrigin, LiveBatcher,
+    build_block_batch_from_logs, chunk_range, db, events, process_db_requests,
+};
+
+/// How often the live stream reports progress when batches fill up slowly.
+const LIVE_CHECKPOINT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Live events further than this many blocks beyond the chain tip are logged
+/// as suspicious, they hint at a test or mock endpoint.
+const MAX_BLOCKS_AHEAD_OF_TIP: u64 = 1000;
+
+/// Least time between two chain tip queries of the live stream, so that events
+/// far beyond the tip do not open a connection each.
+const TIP_QUERY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Pause of the gaps task after re-queueing failed chunks of a re-indexed
+/// range, so that a chunk that keeps failing is not retried in a tight loop.
+const FAILED_REINDEX_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Backfills `range` with `profile`, storing it with a DB task of its own, and
+/// waits until it is stored. With `replace`, each chunk replaces the stored
+/// blocks and events of its range. Fails if any chunk could not be fetched or
+/// decoded, or any batch could not be inserted.
+pub async fn backfill<C: Connector>(
+    mut connector: C,
+    pools: db::DbPools,
+    options: DbTaskOptions,
+    profile: &BackfillProfile,
+    metrics_tx: mpsc::UnboundedSender<metrics::Metric>,
+    range: Range<u64>,
+    replace: bool,
+) -> Result<()> {
+    eyre::ensure!(
+        range.start < range.end,
+        "Nothing to backfill in {range:?}, --from must be below --to"
+    );
+    let health = options.health.clone();
+    let (db_tx, db_rx) = counting_channel();
+    // Gaps found by the DB task are not backfilled by this command.
+    let (gap_tx, _gap_rx) = counting_channel();
+    let db_task = tokio::spawn(process_db_requests(
+        pools,
+        db_rx,
+        gap_tx,
+        metrics_tx.clone(),
+        options,
+    ));
+
+    let mut attempts = 0usize;
+    let client = connect_with_retry(
+        &mut connector,
+        &mut attempts,
+        "Backfill",
+        &metrics_tx,
+        &health,
+    )
+    .await?;
+
+    info!("Backfilling blocks {range:?}");
+    let res = backfill_range(
+        &mut connector,
+        &client,
+        &range,
+        profile,
+        replace,
+        "backfill",
+        &db_tx,
+        &metrics_tx,
+    )
+    .await;
+
+    drop(db_tx);
+    let failed_batches = db_task.await??;
+    let failed_chunks = res?;
+    eyre::ensure!(
+        failed_chunks.is_empty(),
+        "Failed to backfill {} chunk(s): {failed_chunks:?}",
+        failed_chunks.len()
+    );
+    eyre::ensure!(
+        failed_batches == 0,
+        "Failed to insert {failed_batches} batch(es) of {range:?}, see the dead-letter queue"
+    );
+    info!("Finished backfilling blocks {range:?}");
+    Ok(())
+}
+
+/// Connects with `connector`, retrying every second until it succeeds or its
+/// circuit breaker opens. A successful connection marks `health` as connected
+/// to RPC.
+async fn connect_with_retry<C: Connector>(
+    connector: &mut C,
+    attempts: &mut usize,
+    task_name: &str,
+    metrics_tx: &mpsc::UnboundedSender<metrics::Metric>,
+    health: &HealthState,
+) -> Result<C::Connection> {
+    loop {
+        if connector.circuit_state() == CircuitState::Open {
+            let _ = metrics_tx.send(metrics::Metric::RpcCircuitOpen);
+            eyre::bail!(
+                "{task_name} gave up after {} consecutive failed RPC connection attempts",
+                connector.consecutive_failures()
+            );
+        }
+        match connector.connect(*attempts).await {
+            Ok(client) => {
+                health.record_rpc_connected();
+                let _ = metrics_tx.send(metrics::Metric::RpcConnected);
+                return Ok(client);
+            }
+            Err(e) => {
+                *attempts += 1;
+                error!(
+                    task = task_name,
+                    attempt = *attempts,
+                    "{task_name} connection failed: {e:?}"
+                );
+                metrics_tx.send(e).unwrap();
+            }
+        }
+        if connector.circuit_state() != CircuitState::Open {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    }
+}
+
+/// Backfills the ranges received on `gap_rx`, with the chunk size, concurrency
+/// and request rate of the backfill profile of their origin.
+///
+/// Received ranges wait in a [`GapQueue`], so that after falling behind the
+/// most recent blocks are backfilled first, taking turns with the catch-up.
+pub async fn process_gaps_task<C: Connector>(
+    mut connector: C,
+    log_tx: CountingSender<DbRequest>,
+    mut gap_rx: CountingReceiver<(Range<u64>, GapOrigin)>,
+    runtime_rx: watch::Receiver<RuntimeConfig>,
+    metrics_tx: mpsc::UnboundedSender<metrics::Metric>,
+    health: HealthState,
+) -> Result<()> {
+    let mut attempts = 0usize;
+    let mut queue = GapQueue::with_metrics(metrics_tx.clone());
+
+    while let Some((range, origin)) = queue.next(&mut gap_rx).await {
+        let client = connect_with_retry(
+            &mut connector,
+            &mut attempts,
+            "Gaps task",
+            &metrics_tx,
+            &health,
+        )
+        .await?;
+
+        let range_blocks = range.end - range.start;
+        let _ = metrics_tx.send(metrics::Metric::BackfillRangeStarted(range_blocks));
+        let profile = runtime_rx.borrow().backfill.profile(origin).clone();
+        let failed_chunks = backfill_range(
+            &mut connector,
+            &client,
+            &range,
+            &profile,
+            origin == GapOrigin::Reindex,
+            "gaps",
+            &log_tx,
+            &metrics_tx,
+        )
+        .await?;
+        let _ = metrics_tx.send(metrics::Metric::BackfillRangeFinished(range_blocks));
+        if failed_chunks.is_empty() {
+            info!(
+                range_start = range.start,
+                range_end = range.end,
+                "Finished backfilling range: {range:?} ({} blocks)",
+                range_blocks
+            );
+        } else if origin == GapOrigin::Reindex {
+            // The blocks of a re-indexed range were deleted beforehand, so
+            // they stay missing until their chunks succeed.
+            warn!(
+                range_start = range.start,
+                range_end = range.end,
+                "Re-queueing {} failed chunk(s) of re-indexed range {range:?}: {failed_chunks:?}",
+                failed_chunks.len()
+            );
+            for chunk in failed_chunks {
+                queue.push(chunk, origin);
+            }
+            tokio::time::sleep(FAILED_REINDEX_RETRY_DELAY).await;
+        } else {
+            warn!(
+                range_start = range.start,
+                range_end = range.end,
+                "Backfilled range {range:?} except {} failed chunk(s), left to the gap check: {failed_chunks:?}",
+                failed_chunks.len()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Backfills `range` in chunks with the chunk size, concurrency and request
+/// rate of `profile`, handing the chunks to the DB task in order, as
+/// replacements of the stored ones with `replace`. Returns the chunks that
+/// could not be fetched or decoded, and fails once the DB task stopped.
+#[allow(clippy::too_many_arguments)]
+async fn backfill_range<C: Connector>(
+    connector: &mut C,
+    client: &C::Connection,
+    range: &Range<u64>,
+    profile: &BackfillProfile,
+    replace: bool,
+    task_name: &'static str,
+    log_tx: &CountingSender<DbRequest>,
+    metrics_tx: &mpsc::UnboundedSender<metrics::Metric>,
+) -> Result<Vec<Range<u64>>> {
+    let contract_address = connector.contract_address();
+    connector
+        .rate_limiter()
+        .set_rate(profile.max_requests_per_second);
+    let chunks = chunk_range(range.clone(), profile.chunk_size);
+    if chunks.len() > 1 {
+        info!(
+            range_start = range.start,
+            range_end = range.end,
+            chunk_count = chunks.len(),
+            "Backfilling large range: {:?} ({} blocks) in {} chunks",
+            range,
+            range.end - range.start,
+            chunks.len()
+        );
+    }
+
+    // Chunks are fetched concurrently but handed to the DB task in order.
+    let mut fetched = futures_util::stream::iter(chunks)
+        .map(|chunk_range| async move {
+            debug!(
+                range_start = chunk_range.start,
+                range_end = chunk_range.end,
+                "Backfilling chunk: blocks {:?}",
+                chunk_range
+            );
+            let span = info_span!(
+                "backfill_chunk",
+                task_name,
+                block_number = chunk_range.start,
+                block_count = chunk_range.end - chunk_range.start,
+                event_type = field::Empty,
+            );
+            let start = Instant::now();
+            let logs = client
+                .historical_logs(&chunk_range)
+                .instrument(span.clone())
+                .await;
+            (chunk_range, logs, span, start)
+        })
+        .buffered(profile.concurrency);
+
+    let mut failed_chunks = Vec::new();
+    while let Some((chunk_range, logs, span, start)) = fetched.next().await {
+        let blocks_processed = chunk_range.end - chunk_range.start;
+        let res = span.in_scope(|| match logs {
+            Ok(logs) => {
+                process_historical_logs(logs, &chunk_range, replace, contract_address, log_tx)
+            }
+            Err(e) => Err(BackfillError::Rpc(e)),
+        });
+        let _ = metrics_tx.send(metrics::Metric::BackfillChunk {
+            blocks: blocks_processed,
+            duration: start.elapsed(),
+            outcome: match &res {
+                Ok(()) => BackfillOutcome::Ok,
+                Err(e) => e.outcome(),
+            },
+        });
+
+        let metric = match &res {
+            Ok(()) => {
+                connector.record_success();
+                debug!(
+                    range_start = chunk_range.start,
+                    range_end = chunk_range.end,
+                    "Successfully backfilled {chunk_range:?}"
+                );
+                metrics::Metric::BackfilledBlocks(blocks_processed)
+            }
+            Err(e) => {
+                error!(
+                    range_start = chunk_range.start,
+                    range_end = chunk_range.end,
+                    "Failed to backfill {chunk_range:?}: {e}"
+                );
+                metrics::Metric::FailedToBackfill(blocks_processed)
+            }
+        };
+        let _ = metrics_tx.send(metric);
+        match res {
+            Ok(()) => {}
+            Err(BackfillError::DbChannelClosed) => {
+                eyre::bail!("{task_name} cannot hand over backfilled blocks, the DB task stopped")
+            }
+            Err(_) => failed_chunks.push(chunk_range),
+        }
+    }
+    Ok(failed_chunks)
+}
+
+/// Hand a batch of completed blocks from the live stream to the DB task.
+fn send_live_batch(tx: &CountingSender<DbRequest>, batch: BlockBatch) {
+    tx.send(DbRequest::InsertCompleteBlocks(
+        Box::new(batch),
+        BatchOrigin::Live,
+    ))
+    .expect("Channel closed");
+}
+
+/// Stores the events of the live stream in batches of complete blocks,
+/// reconnecting when the stream closes. The range from `start_block` up to the
+/// first streamed event is queued for a catch-up backfill.
+#[allow(clippy::too_many_arguments)]
+pub async fn process_live_blocks<C: Connector + Clone>(
+    mut connector: C,
+    mut start_block: Option<u64>,
+    tx: CountingSender<DbRequest>,
+    gap_tx: CountingSender<(Range<u64>, GapOrigin)>,
+    batch_size: usize,
+    batch_flush_timeout: Duration,
+    metrics_tx: mpsc::UnboundedSender<metrics::Metric>,
+    health: HealthState,
+) -> Result<()> {
+    let mut batcher = LiveBatcher::new(batch_size);
+    let mut attempts = 0usize;
+    let mut checkpoint_interval = interval(LIVE_CHECKPOINT_INTERVAL);
+    let mut flush_interval = interval(batch_flush_timeout);
+    // Chain tip as last reported by the RPC node. Once an event is far beyond
+    // it, the tip is queried again.
+    let mut known_tip: Option<u64> = None;
+    let mut tip_queried_at: Option<Instant> = None;
+    let contract_address = connector.contract_address();
+
+    info!("Starting live event stream from block {:?}", start_block);
+
+    loop {
+        let client = connect_with_retry(
+            &mut connector,
+            &mut attempts,
+            "Live blocks",
+            &metrics_tx,
+            &health,
+        )
+        .await?;
+
+        let event_stream = match client.stream_events().await {
+            Ok(stream) => {
+                connector.record_success();
+                stream
+            }
+            Err(e) => {
+                error!("Failed to start event stream: {:?}", e);
+                connector.record_failure();
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+
+        tokio::pin!(event_stream);
+
+        info!("Connected to event stream");
+
+        loop {
+            // The checkpoint interval wakes the loop up even without new logs.
+            health.ping("live_blocks");
+            let log = tokio::select! {
+                log = event_stream.next() => match log {
+                    Some(log) => log,
+                    None => break,
+                },
+                _ = checkpoint_interval.tick() => {
+                    if let Some(batch) = batcher.checkpoint() {
+                        send_live_batch(&tx, batch);
+                    }
+                    continue;
+                }
+                _ = flush_interval.tick() => {
+                    // Send a partial batch so quiet periods do not leave blocks unstored.
+                    if let Some(batch) = batcher.flush() {
+                        debug!(block_count = batch.block_meta.len(), "Flushing partial live batch");
+                        send_live_batch(&tx, batch);
+                    }
+                    continue;
+                }
+            };
+
+            match events::extract_event(&log, contract_address) {
+                Ok(Some(event)) => {
+                    let event_block_num = event.block_meta().block_number;
+                    debug!(
+                        block_number = event_block_num,
+                        event_type = %event.event_type(),
+                        validator_id = event.validator_id(),
+                        "Received {event}"
+                    );
+                    let span = info_span!(
+                        "live_event",
+                        task_name = "live_blocks",
+                        block_number = event_block_num,
+                        event_type = %event.event_type(),
+                    );
+
+                    let beyond_tip = |tip: u64| event_block_num > tip + MAX_BLOCKS_AHEAD_OF_TIP;
+                    let query_due =
+                        tip_queried_at.is_none_or(|at| at.elapsed() >= TIP_QUERY_INTERVAL);
+                    if known_tip.is_none_or(beyond_tip) && query_due {
+                        tip_queried_at = Some(Instant::now());
+                        // A clone, so failures do not count against the stream's circuit.
+                        let tip = connector
+                            .clone()
+                            .get_current_block_number()
+                            .instrument(span.clone())
+                            .await;
+                        match tip {
+                            Ok(tip) => {
+                                if beyond_tip(tip) {
+                                    warn!(
+                                        block_number = event_block_num,
+                                        chain_tip = tip,
+                                        "Live event is over {MAX_BLOCKS_AHEAD_OF_TIP} blocks past the chain tip"
+                                    );
+                                }
+                                known_tip = Some(tip);
+                            }
+                            Err(e) => warn!("Failed to query the chain tip: {e}"),
+                        }
+                    }
+                    // Nothing below awaits, so the span can stay entered.
+                    let _entered = span.enter();
+
+                    if let Some(start) = start_block {
+                        if event_block_num > start {
+                            info!(
+                                gap_start = start,
+                                gap_end = event_block_num,
+                                "Queueing catch-up range {:?}",
+                                start..event_block_num
+                            );
+                            gap_tx
+                                .send((start..event_block_num, GapOrigin::CatchUp))
+                                .unwrap();
+                        }
+                        start_block = None;
+                    }
+                    if let Some(batch) = batcher.push(event) {
+                        debug!(block_count = batch.block_meta.len(), "Sending live batch");
+                        send_live_batch(&tx, batch);
+                    }
+                }
+                Ok(None) => (),
+                Err(e) => {
+                    error!(
+                        block_number = log.block_number,
+                        "Error extracting event: {}", e
+                    );
+                }
+            }
+        }
+
+        error!("Event stream closed (timeout or error), reconnecting...");
+        let _ = metrics_tx.send(metrics::Metric::RpcTimeout);
+        connector.disconnect();
+        // Flushed now, buffered events would not survive the reconnect otherwise.
+        if let Some(batch) = batcher.stream_closed() {
+            send_live_batch(&tx, batch);
+        }
+    }
+}
+
+/// Why backfilling a chunk failed.
+#[derive(Debug, thiserror::Error)]
+enum BackfillError {
+    #[error("Failed to fetch logs: {0:?}")]
+    Rpc(eyre::Report),
+    #[error("Failed to decode logs: {0:?}")]
+    Decode(eyre::Report),
+    #[error("DB request channel closed")]
+    DbChannelClosed,
+}
+
+impl BackfillError {
+    fn outcome(&self) -> BackfillOutcome {
+        match self {
+            BackfillError::Rpc(_) => BackfillOutcome::RpcError,
+            BackfillError::Decode(_) => BackfillOutcome::DecodeError,
+            BackfillError::DbChannelClosed => BackfillOutcome::DbChannelClosed,
+        }
+    }
+}
+
+fn process_historical_logs(
+    logs: Vec<alloy::rpc::types::Log>,
+    range: &Range<u64>,
+    replace: bool,
+    contract_address: Address,
+    tx: &CountingSender<DbRequest>,
+) -> std::result::Result<(), BackfillError> {
+    let mut batch =
+        build_block_batch_from_logs(logs, contract_address).map_err(BackfillError::Decode)?;
+    tracing::Span::current().record("event_type", batch.event_type_names().as_str());
+
+    // Sent even without any blocks so that the scanned range advances the checkpoint.
+    batch.scanned = Some(range.clone());
+    batch.replaces = replace.then(|| range.clone());
+    tx.send(DbRequest::InsertCompleteBlocks(
+        Box::new(batch),
+        BatchOrigin::Backfill,
+    ))
+    .map_err(|_| BackfillError::DbChannelClosed)?;
+
+    Ok(())
+}
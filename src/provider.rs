@@ -1,82 +1,360 @@
-use crate::{STAKING_CONTRACT_ADDRESS, metrics::Metric};
+use crate::metrics::{Metric, RpcOperation};
+use crate::rate_limit::RateLimiter;
 
 use std::ops::Range;
 
 use async_stream::stream;
 use eyre::Result;
 use futures_util::stream::{Stream, StreamExt};
-use log::{debug, error, info};
-use tokio::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::{Duration, Instant};
+use tracing::{debug, error, info};
 
 use alloy::{
+    eips::BlockNumberOrTag,
+    primitives::Address,
     providers::{Provider, ProviderBuilder, RootProvider, WsConnect},
     pubsub::PubSubFrontend,
-    rpc::types::Filter,
+    rpc::types::{BlockTransactionsKind, Filter},
 };
 
-#[derive(Clone)]
+/// State of the [`CircuitBreaker`] guarding connection attempts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// The last connection worked; failures are counted from zero.
+    Closed,
+    /// A connection succeeded after failures, but has not proven itself yet.
+    /// Failures keep counting from where they were.
+    HalfOpen,
+    /// Too many consecutive failures; no further attempts are made.
+    Open,
+}
+
+/// Counts consecutive connection failures and opens after `max_failures` of them.
+/// Without a limit it never opens.
+#[derive(Debug, Clone)]
+struct CircuitBreaker {
+    max_failures: Option<u64>,
+    consecutive_failures: u64,
+    state: CircuitState,
+}
+
+impl CircuitBreaker {
+    fn new(max_failures: Option<u64>) -> Self {
+        CircuitBreaker {
+            max_failures,
+            consecutive_failures: 0,
+            state: CircuitState::Closed,
+        }
+    }
+
+    fn record_failure(&mut self) {
+        self.consecutive_failures += 1;
+        if self
+            .max_failures
+            .is_some_and(|max| self.consecutive_failures >= max)
+        {
+            self.state = CircuitState::Open;
+        }
+    }
+
+    fn record_connected(&mut self) {
+        if self.consecutive_failures > 0 {
+            self.state = CircuitState::HalfOpen;
+        }
+    }
+
+    fn record_success(&mut self) {
+        self.consecutive_failures = 0;
+        self.state = CircuitState::Closed;
+    }
+
+    /// Allows one more attempt after the circuit opened. The failures still
+    /// count, so the next one opens it again.
+    fn half_open(&mut self) {
+        if self.state == CircuitState::Open {
+            self.state = CircuitState::HalfOpen;
+        }
+    }
+}
+
+/// Connection settings for the RPC endpoints, rotated between on each attempt.
+///
+/// Every [`Connector::connect`] returns a new [`ConnectedProvider`] owned by the
+/// caller and keeps a handle of it, so the websocket stays open until both are
+/// dropped: the provider's by the next attempt, a failure or
+/// [`disconnect`](ReconnectProvider::disconnect). Consecutive failures are
+/// tracked by a circuit breaker, which opens after `max_retries` of them.
+///
+/// Clones share the limit of `eth_getLogs` calls, but count their failures
+/// and connect separately.
 pub struct ReconnectProvider {
     urls: Vec<String>,
+    /// Staking contract whose logs are requested.
+    contract_address: Address,
     watchdog_timeout: Duration,
+    breaker: CircuitBreaker,
+    rate_limiter: RateLimiter,
+    metrics_tx: mpsc::UnboundedSender<Metric>,
+    /// The connection handed out last, while it is considered open.
+    provider: Option<ConnectedProvider>,
+}
+
+impl Clone for ReconnectProvider {
+    /// A clone starts without a connection of its own.
+    fn clone(&self) -> Self {
+        ReconnectProvider {
+            urls: self.urls.clone(),
+            contract_address: self.contract_address,
+            watchdog_timeout: self.watchdog_timeout,
+            breaker: self.breaker.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+            metrics_tx: self.metrics_tx.clone(),
+            provider: None,
+        }
+    }
 }
 
+/// An open websocket connection. Clones share it, dropping the last one closes
+/// the connection.
+#[derive(Clone)]
 pub struct ConnectedProvider {
     provider: RootProvider<PubSubFrontend>,
+    contract_address: Address,
     watchdog_timeout: Duration,
+    rate_limiter: RateLimiter,
+    metrics_tx: mpsc::UnboundedSender<Metric>,
 }
 
 impl ReconnectProvider {
-    pub fn new(urls: Vec<String>, watchdog_timeout_secs: u64) -> Self {
-        assert!(!urls.is_empty(), "RPC URLs list cannot be empty");
+    pub fn new(
+        urls: Vec<String>,
+        contract_address: Address,
+        watchdog_timeout_secs: u64,
+        max_retries: Option<u64>,
+        metrics_tx: mpsc::UnboundedSender<Metric>,
+    ) -> Result<Self> {
+        eyre::ensure!(!urls.is_empty(), "No RPC URLs configured");
 
-        ReconnectProvider {
+        Ok(ReconnectProvider {
             urls,
+            contract_address,
             watchdog_timeout: Duration::from_secs(watchdog_timeout_secs),
-        }
+            breaker: CircuitBreaker::new(max_retries),
+            rate_limiter: RateLimiter::new(0),
+            metrics_tx,
+            provider: None,
+        })
+    }
+
+    /// Lets a periodic task try again once it has backed off from an open
+    /// circuit, instead of giving up like the pipeline tasks.
+    pub fn half_open_circuit(&mut self) {
+        self.breaker.half_open();
+    }
+
+    /// Whether the connection handed out last is still held, i.e. no attempt
+    /// or failure has happened since and it was not disconnected.
+    pub fn is_connected(&self) -> bool {
+        self.provider.is_some()
+    }
+}
+
+impl ConnectedProvider {
+    /// Hash of block `block_number` on the canonical chain, hex encoded like
+    /// [`BlockMeta::block_hash`](crate::events::BlockMeta::block_hash), or `None`
+    /// if the node does not have the block.
+    pub async fn get_block_hash(&self, block_number: u64) -> Result<Option<String>> {
+        let block = self
+            .provider
+            .get_block_by_number(
+                BlockNumberOrTag::Number(block_number),
+                BlockTransactionsKind::Hashes,
+            )
+            .await?;
+        Ok(block.map(|block| hex::encode(block.header.hash)))
+    }
+}
+
+/// Opens connections to a [`LogProvider`] and counts their failures with a
+/// circuit breaker, either a [`ReconnectProvider`] or, in tests, a mock.
+pub trait Connector: Send {
+    type Connection: LogProvider + Clone + Send + Sync;
+
+    /// Staking contract whose logs are requested.
+    fn contract_address(&self) -> Address;
+
+    /// Limit of `eth_getLogs` calls over all connections, unlimited until set.
+    fn rate_limiter(&self) -> &RateLimiter;
+
+    fn circuit_state(&self) -> CircuitState;
+
+    fn consecutive_failures(&self) -> u64;
+
+    /// Counts a failure of an established connection, e.g. a stream that could
+    /// not be started.
+    fn record_failure(&mut self);
+
+    /// Marks the current connection as working, closing the circuit.
+    fn record_success(&mut self);
+
+    /// Drops the connection handed out last, which closes it once its users
+    /// dropped theirs as well.
+    fn disconnect(&mut self);
+
+    /// Connects for the `attempt`th time. Fails with [`Metric::RpcCircuitOpen`]
+    /// without trying once the circuit is open.
+    fn connect(
+        &mut self,
+        attempt: usize,
+    ) -> impl Future<Output = std::result::Result<Self::Connection, Metric>> + Send;
+
+    /// Number of the latest block of the chain, queried over a connection of
+    /// its own that is closed again afterwards.
+    fn get_current_block_number(&mut self) -> impl Future<Output = Result<u64>> + Send;
+}
+
+impl Connector for ReconnectProvider {
+    type Connection = ConnectedProvider;
+
+    fn contract_address(&self) -> Address {
+        self.contract_address
+    }
+
+    fn rate_limiter(&self) -> &RateLimiter {
+        &self.rate_limiter
     }
 
-    pub async fn connect(&self, attempt: usize) -> std::result::Result<ConnectedProvider, Metric> {
+    fn circuit_state(&self) -> CircuitState {
+        self.breaker.state
+    }
+
+    fn consecutive_failures(&self) -> u64 {
+        self.breaker.consecutive_failures
+    }
+
+    /// Also drops the connection, which failed.
+    fn record_failure(&mut self) {
+        self.breaker.record_failure();
+        self.disconnect();
+    }
+
+    fn record_success(&mut self) {
+        self.breaker.record_success();
+    }
+
+    fn disconnect(&mut self) {
+        drop(self.provider.take());
+    }
+
+    /// Connects to the next URL.
+    async fn connect(&mut self, attempt: usize) -> std::result::Result<ConnectedProvider, Metric> {
+        // A new attempt replaces the previous connection.
+        self.disconnect();
+        if self.breaker.state == CircuitState::Open {
+            return Err(Metric::RpcCircuitOpen);
+        }
+
         let url = &self.urls[attempt % self.urls.len()];
-        debug!("Attempting to connect to RPC: {}", url);
+        debug!(
+            url = url.as_str(),
+            attempt = attempt,
+            "Attempting to connect to RPC: {}",
+            url
+        );
 
         let ws = WsConnect::new(url);
         let connection_timeout = Duration::from_secs(5);
 
         match tokio::time::timeout(connection_timeout, ProviderBuilder::new().on_ws(ws)).await {
             Ok(Ok(provider)) => {
-                info!("Successfully connected to RPC: {}", url);
-                Ok(ConnectedProvider {
+                info!(url = url.as_str(), "Successfully connected to RPC: {}", url);
+                self.breaker.record_connected();
+                let connected = ConnectedProvider {
                     provider,
+                    contract_address: self.contract_address,
                     watchdog_timeout: self.watchdog_timeout,
-                })
+                    rate_limiter: self.rate_limiter.clone(),
+                    metrics_tx: self.metrics_tx.clone(),
+                };
+                self.provider = Some(connected.clone());
+                Ok(connected)
             }
             Ok(Err(e)) => {
-                error!("Failed to connect to {url}: {e:?}");
+                error!(url = url.as_str(), "Failed to connect to {url}: {e:?}");
+                self.breaker.record_failure();
                 Err(Metric::RpcConnRefused)
             }
             Err(_) => {
-                error!("Timed out connecting to {url}");
+                error!(url = url.as_str(), "Timed out connecting to {url}");
+                self.breaker.record_failure();
                 Err(Metric::RpcTimeout)
             }
         }
     }
+
+    /// Tries the URLs in order until one of them connects.
+    async fn get_current_block_number(&mut self) -> Result<u64> {
+        let mut last_error = Metric::RpcConnRefused;
+        for attempt in 0..self.urls.len() {
+            match self.connect(attempt).await {
+                Ok(client) => {
+                    let block_number = client.get_block_number().await;
+                    self.disconnect();
+                    return block_number;
+                }
+                Err(metric) => last_error = metric,
+            }
+        }
+        eyre::bail!("Failed to connect to RPC: {last_error:?}")
+    }
 }
 
-impl ConnectedProvider {
-    pub async fn historical_logs(&self, range: &Range<u64>) -> Result<Vec<alloy::rpc::types::Log>> {
+/// Source of staking contract logs, either an RPC connection or, in tests, an
+/// in-memory list.
+pub trait LogProvider {
+    /// Logs of the staking contract in the blocks of `range`.
+    fn historical_logs(
+        &self,
+        range: &Range<u64>,
+    ) -> impl Future<Output = Result<Vec<alloy::rpc::types::Log>>> + Send;
+
+    /// Number of the latest block of the chain.
+    fn get_block_number(&self) -> impl Future<Output = Result<u64>> + Send;
+
+    /// Stream of new staking contract logs, ending when the source is exhausted.
+    fn stream_events(
+        self,
+    ) -> impl Future<Output = Result<impl Stream<Item = alloy::rpc::types::Log> + Send>> + Send;
+}
+
+impl LogProvider for ConnectedProvider {
+    async fn historical_logs(&self, range: &Range<u64>) -> Result<Vec<alloy::rpc::types::Log>> {
         let filter = Filter::new()
-            .address(STAKING_CONTRACT_ADDRESS)
+            .address(self.contract_address)
             .from_block(range.start)
             .to_block(range.end.saturating_sub(1));
 
-        self.provider.get_logs(&filter).await.map_err(Into::into)
+        self.rate_limiter.acquire().await;
+        let start = Instant::now();
+        let logs = self.provider.get_logs(&filter).await;
+        let _ = self.metrics_tx.send(Metric::RpcLatency {
+            operation: RpcOperation::GetLogs,
+            duration_ms: start.elapsed().as_millis() as u64,
+        });
+        logs.map_err(Into::into)
+    }
+
+    async fn get_block_number(&self) -> Result<u64> {
+        Ok(self.provider.get_block_number().await?)
     }
 
-    pub async fn stream_events(self) -> Result<impl Stream<Item = alloy::rpc::types::Log>> {
-        let filter = Filter::new().address(STAKING_CONTRACT_ADDRESS);
+    async fn stream_events(self) -> Result<impl Stream<Item = alloy::rpc::types::Log> + Send> {
+        let filter = Filter::new().address(self.contract_address);
         let event_stream = self.provider.subscribe_logs(&filter).await?.into_stream();
 
         let watchdog_timeout = self.watchdog_timeout;
+        let metrics_tx = self.metrics_tx;
         let provider_monitor = self.provider;
 
         Ok(stream! {
@@ -84,8 +362,15 @@ impl ConnectedProvider {
             let _keep_alive = provider_monitor;
 
             loop {
+                let start = Instant::now();
                 match tokio::time::timeout(watchdog_timeout, stream.next()).await {
-                    Ok(Some(log)) => yield log,
+                    Ok(Some(log)) => {
+                        let _ = metrics_tx.send(Metric::RpcLatency {
+                            operation: RpcOperation::StreamEvent,
+                            duration_ms: start.elapsed().as_millis() as u64,
+                        });
+                        yield log
+                    }
                     Ok(None) => break,
                     Err(_) => break,
                 }
@@ -93,3 +378,109 @@ impl ConnectedProvider {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_circuit_opens_after_max_failures() {
+        let mut breaker = CircuitBreaker::new(Some(3));
+        breaker.record_failure();
+        breaker.record_failure();
+        assert_eq!(breaker.state, CircuitState::Closed);
+        breaker.record_failure();
+        assert_eq!(breaker.state, CircuitState::Open);
+    }
+
+    #[test]
+    fn test_circuit_half_open_until_success() {
+        let mut breaker = CircuitBreaker::new(Some(3));
+        breaker.record_failure();
+        breaker.record_failure();
+        breaker.record_connected();
+        assert_eq!(breaker.state, CircuitState::HalfOpen);
+
+        // A connection that fails before proving itself does not reset the count.
+        breaker.record_failure();
+        assert_eq!(breaker.state, CircuitState::Open);
+
+        let mut breaker = CircuitBreaker::new(Some(3));
+        breaker.record_failure();
+        breaker.record_connected();
+        breaker.record_success();
+        assert_eq!(breaker.state, CircuitState::Closed);
+        assert_eq!(breaker.consecutive_failures, 0);
+    }
+
+    #[test]
+    fn test_circuit_without_limit_never_opens() {
+        let mut breaker = CircuitBreaker::new(None);
+        for _ in 0..1000 {
+            breaker.record_failure();
+        }
+        assert_eq!(breaker.state, CircuitState::Closed);
+
+        breaker.record_connected();
+        assert_eq!(breaker.state, CircuitState::HalfOpen);
+    }
+
+    #[test]
+    fn test_half_open_circuit_reopens_on_failure() {
+        let mut breaker = CircuitBreaker::new(Some(2));
+        breaker.half_open();
+        assert_eq!(breaker.state, CircuitState::Closed);
+
+        breaker.record_failure();
+        breaker.record_failure();
+        breaker.half_open();
+        assert_eq!(breaker.state, CircuitState::HalfOpen);
+        breaker.record_failure();
+        assert_eq!(breaker.state, CircuitState::Open);
+    }
+
+    #[test]
+    fn test_connect_and_disconnect() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            // Accepts websocket connections and keeps them open.
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("ws://{}", listener.local_addr().unwrap());
+            tokio::spawn(async move {
+                let mut connections = Vec::new();
+                while let Ok((stream, _)) = listener.accept().await {
+                    if let Ok(ws) = tokio_tungstenite::accept_async(stream).await {
+                        connections.push(ws);
+                    }
+                }
+            });
+
+            let (metrics_tx, _metrics_rx) = mpsc::unbounded_channel();
+            let mut provider = ReconnectProvider::new(
+                vec![url, "ws://127.0.0.1:1".to_string()],
+                Address::ZERO,
+                60,
+                None,
+                metrics_tx,
+            )
+            .unwrap();
+            assert!(!provider.is_connected());
+
+            // The provider keeps the connection after the caller dropped it.
+            drop(provider.connect(0).await.unwrap());
+            assert!(provider.is_connected());
+            assert!(!provider.clone().is_connected());
+            provider.disconnect();
+            assert!(!provider.is_connected());
+
+            let _client = provider.connect(0).await.unwrap();
+            provider.record_failure();
+            assert!(!provider.is_connected());
+
+            // A failed attempt replaces the previous connection.
+            let _client = provider.connect(0).await.unwrap();
+            assert!(provider.connect(1).await.is_err());
+            assert!(!provider.is_connected());
+        });
+    }
+}
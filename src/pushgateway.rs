@@ -0,0 +1,71 @@
+//! Periodic push of the metrics to a Prometheus pushgateway, for deployments
+//! where the metrics server cannot be scraped.
+
+use eyre::Result;
+use tokio::sync::mpsc;
+use tokio::time::{Duration, interval};
+use tracing::{debug, error};
+
+use crate::config::PushConfig;
+use crate::metrics::{self, Metric, MetricsRequest};
+
+/// Time limit of a single push.
+const PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// URL metrics of `job` are pushed to.
+fn push_url(gateway_url: &str, job: &str) -> String {
+    format!("{}/metrics/job/{job}", gateway_url.trim_end_matches('/'))
+}
+
+/// Push the current metrics every `config.interval_secs`, next to the scrape
+/// server. Failed pushes are logged and counted, and the next push is tried on
+/// schedule. HTTP(S) proxies are taken from the `HTTP_PROXY`, `HTTPS_PROXY` and
+/// `NO_PROXY` environment variables.
+pub async fn run_pushgateway(
+    request_tx: mpsc::UnboundedSender<MetricsRequest>,
+    metrics_tx: mpsc::UnboundedSender<Metric>,
+    config: PushConfig,
+) -> Result<()> {
+    let client = reqwest::Client::builder().timeout(PUSH_TIMEOUT).build()?;
+    let url = push_url(&config.gateway_url, &config.job);
+    let mut interval = interval(Duration::from_secs(config.interval_secs));
+
+    loop {
+        interval.tick().await;
+        let Some(body) = metrics::request_metrics(&request_tx).await else {
+            return Ok(());
+        };
+
+        let mut request = client
+            .post(&url)
+            .header(reqwest::header::CONTENT_TYPE, "text/plain; version=0.0.4")
+            .body(body);
+        if let Some(auth) = &config.basic_auth {
+            request = request.basic_auth(&auth.username, Some(&auth.password));
+        }
+        match request.send().await.and_then(|r| r.error_for_status()) {
+            Ok(_) => debug!("Pushed metrics to {url}"),
+            Err(e) => {
+                error!("Failed to push metrics to {url}: {e}");
+                let _ = metrics_tx.send(Metric::PushFailed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_url() {
+        assert_eq!(
+            push_url("http://pushgateway:9091", "indexer"),
+            "http://pushgateway:9091/metrics/job/indexer"
+        );
+        assert_eq!(
+            push_url("https://pushgateway:9091/", "indexer"),
+            "https://pushgateway:9091/metrics/job/indexer"
+        );
+    }
+}
@@ -0,0 +1,330 @@
+use std::cmp;
+use std::collections::{BinaryHeap, HashSet, VecDeque};
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tokio::sync::mpsc::{
+    self,
+    error::{SendError, TryRecvError},
+};
+
+use crate::{GapOrigin, metrics};
+
+/// Number of messages sent on a counting channel and not received yet.
+#[derive(Debug, Clone, Default)]
+pub struct QueueDepth(Arc<AtomicU64>);
+
+impl QueueDepth {
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Sets the depth of a channel that is not a counting channel, from the
+    /// length its receiver reports.
+    pub fn set(&self, depth: u64) {
+        self.0.store(depth, Ordering::Relaxed);
+    }
+}
+
+/// Sending half of [`counting_channel`], with the API of `mpsc::UnboundedSender`.
+#[derive(Debug)]
+pub struct CountingSender<T> {
+    tx: mpsc::UnboundedSender<T>,
+    depth: QueueDepth,
+}
+
+impl<T> Clone for CountingSender<T> {
+    fn clone(&self) -> Self {
+        CountingSender {
+            tx: self.tx.clone(),
+            depth: self.depth.clone(),
+        }
+    }
+}
+
+impl<T> CountingSender<T> {
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        // Counted before sending, so the receiver never sees a message it
+        // cannot subtract.
+        self.depth.0.fetch_add(1, Ordering::Relaxed);
+        self.tx.send(value).inspect_err(|_| {
+            self.depth.0.fetch_sub(1, Ordering::Relaxed);
+        })
+    }
+
+    pub fn depth(&self) -> QueueDepth {
+        self.depth.clone()
+    }
+}
+
+/// Receiving half of [`counting_channel`], with the API of `mpsc::UnboundedReceiver`.
+#[derive(Debug)]
+pub struct CountingReceiver<T> {
+    rx: mpsc::UnboundedReceiver<T>,
+    depth: QueueDepth,
+}
+
+impl<T> CountingReceiver<T> {
+    pub async fn recv(&mut self) -> Option<T> {
+        let value = self.rx.recv().await?;
+        self.depth.0.fetch_sub(1, Ordering::Relaxed);
+        Some(value)
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let value = self.rx.try_recv()?;
+        self.depth.0.fetch_sub(1, Ordering::Relaxed);
+        Ok(value)
+    }
+
+    pub fn depth(&self) -> QueueDepth {
+        self.depth.clone()
+    }
+}
+
+/// Unbounded channel that keeps count of its queued messages, so that a backlog
+/// shows up in the `staking_queue_depth` metric before it exhausts memory.
+pub fn counting_channel<T>() -> (CountingSender<T>, CountingReceiver<T>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let depth = QueueDepth::default();
+    (
+        CountingSender {
+            tx,
+            depth: depth.clone(),
+        },
+        CountingReceiver { rx, depth },
+    )
+}
+
+/// A gap in the heap of a [`GapQueue`]. Gaps starting at higher blocks come
+/// first, equal ones in the order they were queued.
+#[derive(Debug, PartialEq, Eq)]
+struct QueuedGap {
+    range: Range<u64>,
+    origin: GapOrigin,
+    sequence: u64,
+}
+
+impl Ord for QueuedGap {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        self.range
+            .start
+            .cmp(&other.range.start)
+            .then_with(|| other.sequence.cmp(&self.sequence))
+    }
+}
+
+impl PartialOrd for QueuedGap {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Gaps waiting to be backfilled, the most recent blocks first, as they are
+/// the most likely to be queried.
+///
+/// The catch-up ranges wait in their own lane, in the order they were queued,
+/// and take turns with the others, so that a steady stream of repairs above
+/// them cannot starve the catch-up. A range already waiting is not queued
+/// again.
+#[derive(Debug, Default)]
+pub struct GapQueue {
+    heap: BinaryHeap<QueuedGap>,
+    catch_up: VecDeque<Range<u64>>,
+    ranges: HashSet<Range<u64>>,
+    queued: u64,
+    catch_up_next: bool,
+    metrics_tx: Option<mpsc::UnboundedSender<metrics::Metric>>,
+}
+
+impl GapQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A queue that reports its length as [`metrics::Metric::GapQueueDepth`]
+    /// whenever a gap is pushed or popped.
+    pub fn with_metrics(metrics_tx: mpsc::UnboundedSender<metrics::Metric>) -> Self {
+        GapQueue {
+            metrics_tx: Some(metrics_tx),
+            ..Self::default()
+        }
+    }
+
+    /// Queues `range`, unless it is already waiting. Returns whether it was
+    /// queued.
+    pub fn push(&mut self, range: Range<u64>, origin: GapOrigin) -> bool {
+        if !self.ranges.insert(range.clone()) {
+            return false;
+        }
+        if origin == GapOrigin::CatchUp {
+            self.catch_up.push_back(range);
+        } else {
+            self.heap.push(QueuedGap {
+                range,
+                origin,
+                sequence: self.queued,
+            });
+        }
+        self.queued += 1;
+        self.report_depth();
+        true
+    }
+
+    /// The gap starting at the highest block, or the oldest catch-up range
+    /// when it is the catch-up lane's turn.
+    pub fn pop(&mut self) -> Option<(Range<u64>, GapOrigin)> {
+        let catch_up_turn = self.catch_up_next || self.heap.is_empty();
+        let gap = if catch_up_turn && !self.catch_up.is_empty() {
+            (self.catch_up.pop_front()?, GapOrigin::CatchUp)
+        } else {
+            let gap = self.heap.pop()?;
+            (gap.range, gap.origin)
+        };
+        self.catch_up_next = gap.1 != GapOrigin::CatchUp;
+        self.ranges.remove(&gap.0);
+        self.report_depth();
+        Some(gap)
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len() + self.catch_up.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn report_depth(&self) {
+        if let Some(metrics_tx) = &self.metrics_tx {
+            let _ = metrics_tx.send(metrics::Metric::GapQueueDepth(self.len() as u64));
+        }
+    }
+
+    /// Move the gaps waiting in `rx` into the queue, waiting for one if both
+    /// are empty, then pop the next one. `None` once `rx` is closed and the
+    /// queue is empty.
+    pub async fn next(
+        &mut self,
+        rx: &mut CountingReceiver<(Range<u64>, GapOrigin)>,
+    ) -> Option<(Range<u64>, GapOrigin)> {
+        while let Ok((range, origin)) = rx.try_recv() {
+            self.push(range, origin);
+        }
+        if self.is_empty() {
+            let (range, origin) = rx.recv().await?;
+            self.push(range, origin);
+        }
+        self.pop()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_depth_follows_send_and_recv() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (tx, mut rx) = counting_channel();
+        let depth = tx.depth();
+
+        for i in 0..3 {
+            tx.clone().send(i).unwrap();
+        }
+        assert_eq!(depth.get(), 3);
+
+        assert_eq!(runtime.block_on(rx.recv()), Some(0));
+        assert_eq!(depth.get(), 2);
+
+        drop(rx);
+        assert!(tx.send(3).is_err());
+        assert_eq!(depth.get(), 2);
+    }
+
+    #[test]
+    fn test_gap_queue_pops_highest_start_first() {
+        let mut queue = GapQueue::new();
+        queue.push(100..200, GapOrigin::Repair);
+        queue.push(5000..5010, GapOrigin::Repair);
+        queue.push(300..400, GapOrigin::Reindex);
+        queue.push(5000..5020, GapOrigin::Repair);
+        assert_eq!(queue.len(), 4);
+
+        assert_eq!(queue.pop(), Some((5000..5010, GapOrigin::Repair)));
+        assert_eq!(queue.pop(), Some((5000..5020, GapOrigin::Repair)));
+        assert_eq!(queue.pop(), Some((300..400, GapOrigin::Reindex)));
+        assert_eq!(queue.pop(), Some((100..200, GapOrigin::Repair)));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn test_gap_queue_alternates_with_catch_up() {
+        let mut queue = GapQueue::new();
+        queue.push(100..200, GapOrigin::CatchUp);
+        queue.push(200..300, GapOrigin::CatchUp);
+        queue.push(5000..5010, GapOrigin::Repair);
+        queue.push(6000..6010, GapOrigin::Repair);
+
+        assert_eq!(queue.pop(), Some((6000..6010, GapOrigin::Repair)));
+        assert_eq!(queue.pop(), Some((100..200, GapOrigin::CatchUp)));
+        // A newer repair does not keep the catch-up waiting.
+        queue.push(7000..7010, GapOrigin::Repair);
+        assert_eq!(queue.pop(), Some((7000..7010, GapOrigin::Repair)));
+        assert_eq!(queue.pop(), Some((200..300, GapOrigin::CatchUp)));
+        assert_eq!(queue.pop(), Some((5000..5010, GapOrigin::Repair)));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn test_gap_queue_skips_waiting_ranges() {
+        let (metrics_tx, mut metrics_rx) = mpsc::unbounded_channel();
+        let mut queue = GapQueue::with_metrics(metrics_tx);
+        assert!(queue.push(100..200, GapOrigin::Repair));
+        assert!(!queue.push(100..200, GapOrigin::Repair));
+        assert!(!queue.push(100..200, GapOrigin::CatchUp));
+        assert!(queue.push(100..300, GapOrigin::Repair));
+        assert_eq!(queue.len(), 2);
+
+        assert_eq!(queue.pop(), Some((100..200, GapOrigin::Repair)));
+        // Queued again once it is no longer waiting.
+        assert!(queue.push(100..200, GapOrigin::Repair));
+
+        let depths: Vec<_> = std::iter::from_fn(|| metrics_rx.try_recv().ok()).collect();
+        assert_eq!(
+            depths,
+            [1, 2, 1, 2].map(metrics::Metric::GapQueueDepth).to_vec()
+        );
+    }
+
+    #[test]
+    fn test_gap_queue_drains_channel() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (tx, mut rx) = counting_channel();
+        let mut queue = GapQueue::new();
+
+        tx.send((100..200, GapOrigin::Repair)).unwrap();
+        tx.send((300..400, GapOrigin::Repair)).unwrap();
+        assert_eq!(
+            runtime.block_on(queue.next(&mut rx)),
+            Some((300..400, GapOrigin::Repair))
+        );
+        assert_eq!(tx.depth().get(), 0);
+        assert_eq!(queue.len(), 1);
+
+        // A newer gap queued meanwhile goes first.
+        tx.send((500..600, GapOrigin::Repair)).unwrap();
+        assert_eq!(
+            runtime.block_on(queue.next(&mut rx)),
+            Some((500..600, GapOrigin::Repair))
+        );
+
+        drop(tx);
+        assert_eq!(
+            runtime.block_on(queue.next(&mut rx)),
+            Some((100..200, GapOrigin::Repair))
+        );
+        assert_eq!(runtime.block_on(queue.next(&mut rx)), None);
+    }
+}
@@ -0,0 +1,165 @@
+//! Limiting the rate of RPC requests.
+
+use std::sync::{Arc, Mutex};
+
+use tokio::time::{Duration, Instant};
+
+/// A token bucket refilled with `rate` tokens per second, holding at most one
+/// second worth of them. A rate of zero is unlimited.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: u32,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    /// A full bucket.
+    pub fn new(rate: u32, now: Instant) -> Self {
+        Self {
+            rate,
+            tokens: f64::from(rate),
+            refilled_at: now,
+        }
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    /// Change the rate, keeping the tokens collected so far up to the new capacity.
+    pub fn set_rate(&mut self, rate: u32, now: Instant) {
+        self.refill(now);
+        self.rate = rate;
+        self.tokens = self.tokens.min(f64::from(rate));
+    }
+
+    /// Take a token, or return how long it takes until one is available.
+    pub fn try_acquire(&mut self, now: Instant) -> Result<(), Duration> {
+        if self.rate == 0 {
+            return Ok(());
+        }
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - self.tokens) / f64::from(self.rate),
+            ))
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() * f64::from(self.rate)).min(f64::from(self.rate));
+        self.refilled_at = now;
+    }
+}
+
+/// A [`TokenBucket`] shared by all requests to one provider, clones included.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    bucket: Arc<Mutex<TokenBucket>>,
+}
+
+/// No limit.
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl RateLimiter {
+    /// Allow `rate` requests per second, any number if zero.
+    pub fn new(rate: u32) -> Self {
+        Self {
+            bucket: Arc::new(Mutex::new(TokenBucket::new(rate, Instant::now()))),
+        }
+    }
+
+    pub fn set_rate(&self, rate: u32) {
+        let mut bucket = self.bucket.lock().unwrap();
+        if bucket.rate() != rate {
+            bucket.set_rate(rate, Instant::now());
+        }
+    }
+
+    /// Wait until a request may be sent.
+    pub async fn acquire(&self) {
+        loop {
+            let wait = match self.bucket.lock().unwrap().try_acquire(Instant::now()) {
+                Ok(()) => return,
+                Err(wait) => wait,
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket_limits_rate() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2, start);
+        assert_eq!(bucket.try_acquire(start), Ok(()));
+        assert_eq!(bucket.try_acquire(start), Ok(()));
+        assert_eq!(bucket.try_acquire(start), Err(Duration::from_millis(500)));
+
+        let later = start + Duration::from_millis(250);
+        assert_eq!(bucket.try_acquire(later), Err(Duration::from_millis(250)));
+        let later = start + Duration::from_millis(500);
+        assert_eq!(bucket.try_acquire(later), Ok(()));
+        assert!(bucket.try_acquire(later).is_err());
+    }
+
+    #[test]
+    fn test_token_bucket_holds_one_second_of_tokens() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(3, start);
+        let later = start + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert_eq!(bucket.try_acquire(later), Ok(()));
+        }
+        assert!(bucket.try_acquire(later).is_err());
+    }
+
+    #[test]
+    fn test_token_bucket_zero_rate_is_unlimited() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(0, start);
+        for _ in 0..1000 {
+            assert_eq!(bucket.try_acquire(start), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_token_bucket_set_rate() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(10, start);
+        bucket.set_rate(1, start);
+        assert_eq!(bucket.try_acquire(start), Ok(()));
+        assert_eq!(bucket.try_acquire(start), Err(Duration::from_secs(1)));
+
+        bucket.set_rate(0, start);
+        assert_eq!(bucket.try_acquire(start), Ok(()));
+    }
+
+    #[test]
+    fn test_rate_limiter_waits_for_tokens() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let limiter = RateLimiter::new(20);
+            let start = Instant::now();
+            for _ in 0..25 {
+                limiter.clone().acquire().await;
+            }
+            // The first 20 requests use the full bucket, the other 5 wait 50ms each.
+            assert!(start.elapsed() >= Duration::from_millis(240));
+        });
+    }
+}
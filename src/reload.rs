@@ -0,0 +1,293 @@
+//! Applying configuration changes on SIGHUP, without restarting the indexer.
+
+use std::path::PathBuf;
+
+use eyre::Result;
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::{mpsc, watch};
+use tokio::time::{Duration, Instant, Interval, interval, interval_at};
+use tracing::{error, info, warn};
+
+use crate::config::{BackfillConfig, Config};
+use crate::logging;
+use crate::metrics::Metric;
+
+/// The settings that take effect without a restart, see [`Config::runtime_config`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeConfig {
+    pub backfill: BackfillConfig,
+    pub gap_check_interval: Duration,
+    /// `logging.level`, validated.
+    pub log_level: String,
+}
+
+/// Settings only read at startup that differ between `old` and `new`.
+pub fn restart_required(old: &Config, new: &Config) -> Vec<&'static str> {
+    // Destructured so that a new setting has to be sorted in here.
+    let Config {
+        rpc_urls,
+        network,
+        contract_address,
+        chain_start_block,
+        database_url,
+        db_host,
+        db_socket_dir,
+        db_read_host,
+        db_port,
+        db_name,
+        db_auth,
+        backfill: _,
+        gap_check_interval_secs: _,
+        reorg_check_blocks,
+        gap_max_ranges,
+        gap_merge_distance,
+        dead_letter_retry_interval_secs,
+        dead_letter_max_attempts,
+        db_batch_size,
+        batch_flush_timeout_secs,
+        db_operation_timeout_secs,
+        db_statement_timeout_secs,
+        db_lock_timeout_secs,
+        watchdog_timeout_secs,
+        rpc_max_retries,
+        min_start_block,
+        retention_blocks,
+        prune_interval_secs,
+        row_count_interval_secs,
+        checkpoint_path,
+        duplicate_policy,
+        event_conflict_strategy,
+        block_conflict_strategy,
+        database,
+        metrics,
+        logging,
+        alerts,
+        kafka,
+        warnings: _,
+    } = new;
+    [
+        ("rpc_urls", old.rpc_urls != *rpc_urls),
+        ("network", old.network != *network),
+        (
+            "contract_address",
+            old.contract_address != *contract_address,
+        ),
+        (
+            "chain_start_block",
+            old.chain_start_block != *chain_start_block,
+        ),
+        ("database_url", old.database_url != *database_url),
+        ("db_host", old.db_host != *db_host),
+        ("db_socket_dir", old.db_socket_dir != *db_socket_dir),
+        ("db_read_host", old.db_read_host != *db_read_host),
+        ("db_port", old.db_port != *db_port),
+        ("db_name", old.db_name != *db_name),
+        ("db_credentials, vault or aws", old.db_auth != *db_auth),
+        (
+            "reorg_check_blocks",
+            old.reorg_check_blocks != *reorg_check_blocks,
+        ),
+        ("gap_max_ranges", old.gap_max_ranges != *gap_max_ranges),
+        (
+            "gap_merge_distance",
+            old.gap_merge_distance != *gap_merge_distance,
+        ),
+        (
+            "dead_letter_retry_interval_secs",
+            old.dead_letter_retry_interval_secs != *dead_letter_retry_interval_secs,
+        ),
+        (
+            "dead_letter_max_attempts",
+            old.dead_letter_max_attempts != *dead_letter_max_attempts,
+        ),
+        ("db_batch_size", old.db_batch_size != *db_batch_size),
+        (
+            "batch_flush_timeout_secs",
+            old.batch_flush_timeout_secs != *batch_flush_timeout_secs,
+        ),
+        (
+            "db_operation_timeout_secs",
+            old.db_operation_timeout_secs != *db_operation_timeout_secs,
+        ),
+        (
+            "db_statement_timeout_secs",
+            old.db_statement_timeout_secs != *db_statement_timeout_secs,
+        ),
+        (
+            "db_lock_timeout_secs",
+            old.db_lock_timeout_secs != *db_lock_timeout_secs,
+        ),
+        (
+            "watchdog_timeout_secs",
+            old.watchdog_timeout_secs != *watchdog_timeout_secs,
+        ),
+        ("rpc_max_retries", old.rpc_max_retries != *rpc_max_retries),
+        ("min_start_block", old.min_start_block != *min_start_block),
+        (
+            "retention_blocks",
+            old.retention_blocks != *retention_blocks,
+        ),
+        (
+            "prune_interval_secs",
+            old.prune_interval_secs != *prune_interval_secs,
+        ),
+        (
+            "row_count_interval_secs",
+            old.row_count_interval_secs != *row_count_interval_secs,
+        ),
+        ("checkpoint_path", old.checkpoint_path != *checkpoint_path),
+        (
+            "duplicate_policy",
+            old.duplicate_policy != *duplicate_policy,
+        ),
+        (
+            "event_conflict_strategy",
+            old.event_conflict_strategy != *event_conflict_strategy,
+        ),
+        (
+            "block_conflict_strategy",
+            old.block_conflict_strategy != *block_conflict_strategy,
+        ),
+        ("database.pool", old.database.pool != database.pool),
+        ("database.tls", old.database.tls != database.tls),
+        ("metrics", old.metrics != *metrics),
+        ("logging.format", old.logging.format != logging.format),
+        (
+            "logging.tracing_enabled",
+            old.logging.tracing_enabled != logging.tracing_enabled,
+        ),
+        ("alerts", old.alerts != *alerts),
+        ("kafka", old.kafka != *kafka),
+    ]
+    .into_iter()
+    .filter(|(_, changed)| *changed)
+    .map(|(setting, _)| setting)
+    .collect()
+}
+
+/// Reads the configuration again on SIGHUP and publishes its [`RuntimeConfig`]
+/// to the tasks that use it. Other changed settings are only logged.
+pub struct ConfigReloader {
+    path: Option<PathBuf>,
+    profile: Option<String>,
+    overrides: Vec<(&'static str, String)>,
+    /// The configuration of the last successful reload, or the one the indexer
+    /// started with. Settings that require a restart are reported once.
+    config: Config,
+    runtime_tx: watch::Sender<RuntimeConfig>,
+    metrics_tx: mpsc::UnboundedSender<Metric>,
+}
+
+impl ConfigReloader {
+    /// `path`, `profile` and `overrides` are passed to [`Config::load`] as at
+    /// startup.
+    pub fn new(
+        path: Option<PathBuf>,
+        profile: Option<String>,
+        overrides: Vec<(&'static str, String)>,
+        config: Config,
+        runtime_tx: watch::Sender<RuntimeConfig>,
+        metrics_tx: mpsc::UnboundedSender<Metric>,
+    ) -> Self {
+        Self {
+            path,
+            profile,
+            overrides,
+            config,
+            runtime_tx,
+            metrics_tx,
+        }
+    }
+
+    pub async fn run(mut self) -> Result<()> {
+        let mut hangups = signal(SignalKind::hangup())?;
+        while hangups.recv().await.is_some() {
+            info!("Received SIGHUP, reloading the configuration");
+            if let Err(e) = self.reload() {
+                error!("Failed to reload the configuration, keeping the current one: {e}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Load and validate the configuration, then apply its runtime settings.
+    /// Nothing is applied if it is invalid. Returns the settings that changed
+    /// since the last reload but only take effect after a restart.
+    pub fn reload(&mut self) -> Result<Vec<&'static str>, String> {
+        let result = self.apply();
+        let _ = self.metrics_tx.send(match result {
+            Ok(_) => Metric::ConfigReloaded,
+            Err(_) => Metric::ConfigReloadFailed,
+        });
+        result
+    }
+
+    fn apply(&mut self) -> Result<Vec<&'static str>, String> {
+        let config = Config::load(
+            self.path.as_deref(),
+            self.profile.as_deref(),
+            &self.overrides,
+        )
+        .map_err(|e| e.to_string())?;
+        let runtime = config.runtime_config()?;
+
+        logging::set_level(&runtime.log_level)?;
+        let restart = restart_required(&self.config, &config);
+        for setting in &restart {
+            warn!("Setting {setting} changed, the new value requires a restart");
+        }
+        info!("Applying runtime configuration {runtime:?}");
+        self.runtime_tx.send_replace(runtime);
+        self.config = config;
+        Ok(restart)
+    }
+}
+
+/// An interval whose period follows the [`RuntimeConfig`].
+pub struct ReloadableInterval {
+    runtime_rx: watch::Receiver<RuntimeConfig>,
+    period_of: fn(&RuntimeConfig) -> Duration,
+    period: Duration,
+    interval: Interval,
+}
+
+impl ReloadableInterval {
+    pub fn new(
+        mut runtime_rx: watch::Receiver<RuntimeConfig>,
+        period_of: fn(&RuntimeConfig) -> Duration,
+    ) -> Self {
+        let period = period_of(&runtime_rx.borrow_and_update());
+        Self {
+            runtime_rx,
+            period_of,
+            period,
+            interval: interval(period),
+        }
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Completes at the next tick, the first one immediately. After the period
+    /// changed, the next tick is one new period after the change.
+    pub async fn tick(&mut self) -> Instant {
+        loop {
+            tokio::select! {
+                instant = self.interval.tick() => return instant,
+                changed = self.runtime_rx.changed() => {
+                    if changed.is_err() {
+                        // Without a sender, the period cannot change anymore.
+                        return self.interval.tick().await;
+                    }
+                    let period = (self.period_of)(&self.runtime_rx.borrow_and_update());
+                    if period != self.period {
+                        info!("Interval changed from {:?} to {period:?}", self.period);
+                        self.period = period;
+                        self.interval = interval_at(Instant::now() + period, period);
+                    }
+                }
+            }
+        }
+    }
+}
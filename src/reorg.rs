@@ -0,0 +1,113 @@
+//! Detection of stored blocks that are no longer part of the canonical chain.
+
+use std::collections::HashMap;
+
+use eyre::{Result, eyre};
+use sqlx::PgPool;
+use tracing::{debug, warn};
+
+use crate::db;
+use crate::events::BlockMeta;
+use crate::provider::{Connector, ReconnectProvider};
+
+/// Compare the hashes of the `recent_blocks` highest stored blocks with those of
+/// the canonical chain and return the numbers of the blocks that differ, in
+/// ascending order. Blocks the node does not have yet are not reported.
+///
+/// `attempts` counts the failed attempts over all checks, so that each one
+/// connects to the next RPC URL.
+pub async fn detect_reorg(
+    pool: &PgPool,
+    provider: &mut ReconnectProvider,
+    attempts: &mut usize,
+    recent_blocks: usize,
+) -> Result<Vec<u64>> {
+    let stored = db::repository::get_latest_blocks(pool, recent_blocks).await?;
+    if stored.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let client = provider.connect(*attempts).await.map_err(|metric| {
+        *attempts += 1;
+        eyre!("Failed to connect to RPC for the reorg check: {metric:?}")
+    })?;
+    let mut canonical = HashMap::with_capacity(stored.len());
+    for block in &stored {
+        let hash = match client.get_block_hash(block.block_number).await {
+            Ok(hash) => hash,
+            Err(e) => {
+                *attempts += 1;
+                provider.record_failure();
+                return Err(e);
+            }
+        };
+        match hash {
+            Some(hash) => {
+                canonical.insert(block.block_number, hash);
+            }
+            None => debug!(
+                block_number = block.block_number,
+                "Block not known to the node yet, skipping reorg check"
+            ),
+        }
+    }
+    provider.record_success();
+    // Checks are minutes apart, so the connection is not kept open in between.
+    provider.disconnect();
+
+    let reorged = mismatched_blocks(&stored, &canonical);
+    for block_number in &reorged {
+        warn!(
+            block_number = *block_number,
+            "Stored hash of block {} differs from the canonical chain", block_number
+        );
+    }
+    Ok(reorged)
+}
+
+/// Numbers of the `stored` blocks whose hash differs from the one in `canonical`,
+/// in ascending order. Blocks missing from `canonical` are skipped.
+fn mismatched_blocks(stored: &[BlockMeta], canonical: &HashMap<u64, String>) -> Vec<u64> {
+    let mut mismatched: Vec<u64> = stored
+        .iter()
+        .filter(|block| {
+            canonical
+                .get(&block.block_number)
+                .is_some_and(|hash| *hash != block.block_hash)
+        })
+        .map(|block| block.block_number)
+        .collect();
+    mismatched.sort_unstable();
+    mismatched
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(block_number: u64, block_hash: &str) -> BlockMeta {
+        BlockMeta {
+            block_number,
+            block_hash: block_hash.to_string(),
+            block_timestamp: 1_000 + block_number,
+        }
+    }
+
+    #[test]
+    fn test_mismatched_blocks() {
+        let stored = vec![block(12, "cc"), block(11, "bb"), block(10, "aa")];
+        let canonical = HashMap::from([
+            (10, "aa".to_string()),
+            (11, "b2".to_string()),
+            (12, "c2".to_string()),
+        ]);
+        assert_eq!(mismatched_blocks(&stored, &canonical), vec![11, 12]);
+    }
+
+    #[test]
+    fn test_mismatched_blocks_skips_unknown_blocks() {
+        let stored = vec![block(11, "bb"), block(10, "aa")];
+        let canonical = HashMap::from([(10, "aa".to_string())]);
+        assert!(mismatched_blocks(&stored, &canonical).is_empty());
+    }
+}
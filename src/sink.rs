@@ -0,0 +1,174 @@
+use std::fmt;
+
+use eyre::Result;
+use futures_util::future::BoxFuture;
+use tracing::debug;
+
+use crate::events::StakingEvent;
+
+#[cfg(feature = "kafka")]
+pub use kafka::KafkaSink;
+
+/// Destination that stored events are forwarded to, in addition to the database.
+///
+/// Events are published after the insert of their batch committed, batch by
+/// batch in the order the chain emitted them: by block, transaction index and
+/// log index. A batch that is replayed, e.g. by overlapping backfills, is published
+/// again, so consumers must tolerate duplicates.
+pub trait EventSink: Send + Sync {
+    fn publish<'a>(&'a self, event: &'a StakingEvent) -> BoxFuture<'a, Result<()>>;
+}
+
+impl fmt::Debug for dyn EventSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EventSink")
+    }
+}
+
+/// Drops all events.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NullSink;
+
+impl EventSink for NullSink {
+    fn publish<'a>(&'a self, _event: &'a StakingEvent) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Logs every event at debug level.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogSink;
+
+impl EventSink for LogSink {
+    fn publish<'a>(&'a self, event: &'a StakingEvent) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            debug!(
+                block_number = event.block_meta().block_number,
+                event_type = %event.event_type(),
+                "Published {event}"
+            );
+            Ok(())
+        })
+    }
+}
+
+/// Publishing to Kafka, which requires the `kafka` feature.
+#[cfg(feature = "kafka")]
+mod kafka {
+    use std::time::Duration;
+
+    use eyre::{Result, WrapErr};
+    use futures_util::future::BoxFuture;
+    use rdkafka::ClientConfig;
+    use rdkafka::producer::{FutureProducer, FutureRecord};
+    use tokio::sync::mpsc::{self, error::TrySendError};
+    use tracing::error;
+
+    use super::EventSink;
+    use crate::config::KafkaConfig;
+    use crate::events::StakingEvent;
+
+    /// Time a Kafka message may wait in the producer queue for delivery.
+    const KAFKA_SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Produces every event as JSON to a Kafka topic. Events of a validator are
+    /// keyed by its id, so they land in the same partition and keep their order.
+    ///
+    /// Publishing only queues the event, a background task hands it to the
+    /// producer and logs failed deliveries, so the DB task never waits for Kafka.
+    /// While the queue is full, publishing fails and the event is dropped.
+    pub struct KafkaSink {
+        queue: mpsc::Sender<StakingEvent>,
+        topic: String,
+    }
+
+    impl KafkaSink {
+        /// Creates the producer and spawns its task. Brokers are connected to in
+        /// the background, so an unreachable cluster only shows up as failed
+        /// deliveries.
+        pub fn new(config: &KafkaConfig) -> Result<Self> {
+            let producer: FutureProducer = ClientConfig::new()
+                .set("bootstrap.servers", &config.brokers)
+                .set("client.id", &config.client_id)
+                .set(
+                    "message.timeout.ms",
+                    KAFKA_SEND_TIMEOUT.as_millis().to_string(),
+                )
+                .create()
+                .wrap_err("Failed to create Kafka producer")?;
+            let (queue, queue_rx) = mpsc::channel(config.queue_capacity);
+            tokio::spawn(produce_events(producer, config.topic.clone(), queue_rx));
+            Ok(Self {
+                queue,
+                topic: config.topic.clone(),
+            })
+        }
+    }
+
+    impl EventSink for KafkaSink {
+        fn publish<'a>(&'a self, event: &'a StakingEvent) -> BoxFuture<'a, Result<()>> {
+            Box::pin(async move {
+                self.queue.try_send(event.clone()).map_err(|e| match e {
+                    TrySendError::Full(_) => eyre::eyre!(
+                        "Kafka queue of topic '{}' is full, dropped the event",
+                        self.topic
+                    ),
+                    TrySendError::Closed(_) => {
+                        eyre::eyre!("Kafka producer of topic '{}' stopped", self.topic)
+                    }
+                })
+            })
+        }
+    }
+
+    /// Hands the queued events to `producer` until the sink is dropped. Deliveries
+    /// are awaited in tasks of their own, so events are produced without waiting
+    /// for the previous ones to be acknowledged.
+    async fn produce_events(
+        producer: FutureProducer,
+        topic: String,
+        mut queue: mpsc::Receiver<StakingEvent>,
+    ) {
+        while let Some(event) = queue.recv().await {
+            let block_number = event.block_meta().block_number;
+            let payload = match serde_json::to_string(&event) {
+                Ok(payload) => payload,
+                Err(e) => {
+                    error!(block_number, "Failed to serialize {event}: {e}");
+                    continue;
+                }
+            };
+            let key = event.validator_id().map(|id| id.to_string());
+            let mut record = FutureRecord::to(&topic).payload(&payload);
+            if let Some(key) = &key {
+                record = record.key(key);
+            }
+            let delivery = match producer.send_result(record) {
+                Ok(delivery) => delivery,
+                Err((e, _)) => {
+                    error!(
+                        block_number,
+                        "Failed to produce to Kafka topic '{topic}': {e}"
+                    );
+                    continue;
+                }
+            };
+            let topic = topic.clone();
+            tokio::spawn(async move {
+                match delivery.await {
+                    Ok(Ok(_)) => {}
+                    Ok(Err((e, _))) => {
+                        error!(
+                            block_number,
+                            "Failed to deliver to Kafka topic '{topic}': {e}"
+                        )
+                    }
+                    Err(_) => error!(
+                        block_number,
+                        "Kafka producer of topic '{topic}' dropped a message"
+                    ),
+                }
+            });
+        }
+    }
+}
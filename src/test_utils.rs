@@ -1,35 +1,406 @@
-use std::ops::Range;
+use std::{collections::HashMap, ops::Range, time::Duration};
 
+use alloy::primitives::{Address, B256, LogData, U256};
+use alloy::rpc::types::Log;
+use alloy::sol_types::SolEvent;
+use futures_util::stream::Stream;
 use sqlx::PgPool;
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::sync::mpsc::UnboundedReceiver;
 
-use crate::{DbRequest, metrics, process_db_requests};
+use crate::events::{self, BlockMeta, StakingEvent, StakingEventType, TxMeta};
+use crate::provider::{CircuitState, Connector, LogProvider};
+use crate::queue::{CountingReceiver, CountingSender, counting_channel};
+use crate::rate_limit::RateLimiter;
+use crate::{
+    BlockBatch, DbRequest, DbTaskOptions, GapOrigin, STAKING_CONTRACT_ADDRESS, contract_abi, db,
+    metrics, process_db_requests,
+};
 
 pub fn init_test_logger() {
-    let _ = env_logger::builder()
-        .filter_level(log::LevelFilter::Info)
-        .format_target(false)
-        .is_test(true)
+    let _ = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .with_target(false)
+        .with_test_writer()
         .try_init();
 }
 
-pub fn spawn_process_event_logs(
-    pool: &PgPool,
-) -> (
-    UnboundedSender<DbRequest>,
-    UnboundedReceiver<Range<u64>>,
+/// Request sender, gap receiver and metrics receiver of a spawned DB task.
+pub type DbTaskChannels = (
+    CountingSender<DbRequest>,
+    CountingReceiver<(Range<u64>, GapOrigin)>,
     UnboundedReceiver<metrics::Metric>,
-) {
-    let (db_tx, db_rx) = tokio::sync::mpsc::unbounded_channel();
-    let (gap_tx, gap_rx) = tokio::sync::mpsc::unbounded_channel();
+);
+
+pub fn spawn_process_event_logs(pool: &PgPool) -> DbTaskChannels {
+    let (db_tx, db_rx) = counting_channel();
+    let (gap_tx, gap_rx) = counting_channel();
     let (metrics_tx, metrics_rx) = tokio::sync::mpsc::unbounded_channel();
 
     let pool_clone = pool.clone();
     tokio::spawn(async move {
-        if let Err(e) = process_db_requests(pool_clone, db_rx, gap_tx, metrics_tx, 30).await {
+        if let Err(e) = process_db_requests(
+            db::DbPools::single(pool_clone),
+            db_rx,
+            gap_tx,
+            metrics_tx,
+            DbTaskOptions::default(),
+        )
+        .await
+        {
             eprintln!("process_db_requests failed: {}", e);
         }
     });
 
     (db_tx, gap_rx, metrics_rx)
 }
+
+/// Asks the DB task behind `db_tx` for the number of stored `event_type` events.
+pub async fn get_event_count(
+    db_tx: &CountingSender<DbRequest>,
+    event_type: StakingEventType,
+) -> u64 {
+    let (reply_tx, reply_rx) = tokio::sync::oneshot::channel();
+    db_tx
+        .send(DbRequest::GetEventCount(event_type, reply_tx))
+        .unwrap();
+    reply_rx.await.expect("Failed to count events")
+}
+
+/// Waits for the next `InsertedEvents` metric, skipping the per-table, block
+/// progress and replaced block metrics sent ahead of it and the delegation
+/// totals reported after the previous batch.
+pub async fn next_inserted_events(
+    metrics_rx: &mut UnboundedReceiver<metrics::Metric>,
+) -> Option<HashMap<StakingEventType, (u64, u64)>> {
+    loop {
+        match metrics_rx.recv().await? {
+            metrics::Metric::InsertedEvents(counts) => return Some(counts),
+            metrics::Metric::TableInsert { .. }
+            | metrics::Metric::BlocksInserted(_)
+            | metrics::Metric::MaxBlockInserted(_)
+            | metrics::Metric::IngestLatency(_)
+            | metrics::Metric::ReorgedBlocksDeleted(_)
+            | metrics::Metric::ValidatorDelegation { .. }
+            | metrics::Metric::PendingWithdrawals(_) => continue,
+            other => panic!("unexpected metric {other:?}"),
+        }
+    }
+}
+
+/// Batch of `events` and the blocks they were emitted in.
+pub fn batch_of(events: Vec<StakingEvent>) -> BlockBatch {
+    let mut batch = BlockBatch::new();
+    for event in events {
+        batch.add_block_meta(event.block_meta().clone());
+        batch.add_event(event);
+    }
+    batch
+}
+
+/// Inserts `events` without going through the DB task, ignoring duplicates and
+/// keeping the stored rows on conflicts.
+pub async fn insert_events(
+    pool: &PgPool,
+    events: Vec<StakingEvent>,
+) -> Result<db::InsertReport, db::repository::DbError> {
+    db::insert_blocks(
+        pool,
+        &batch_of(events),
+        Duration::from_secs(1),
+        db::DuplicatePolicy::Ignore,
+        db::ConflictStrategies::default(),
+        db::BlockConflictStrategy::Ignore,
+    )
+    .await
+}
+
+/// Delegator address used by the event factories below.
+pub const TEST_DELEGATOR: &str = "1234567890123456789012345678901234567890";
+
+/// Metadata of block `block`, with a hash and timestamp derived from its number.
+pub fn make_block_meta(block: u64) -> BlockMeta {
+    BlockMeta {
+        block_number: block,
+        block_hash: format!("0xhash{block}"),
+        block_timestamp: 1234567890 + block,
+    }
+}
+
+/// Metadata of the first transaction in block `block`. All events made for the
+/// same block share it.
+pub fn make_tx_meta(block: u64) -> TxMeta {
+    TxMeta {
+        transaction_hash: format!("0xtx{block}"),
+        transaction_index: 0,
+        log_index: 0,
+    }
+}
+
+/// `event` moved to log `log_index` of transaction `transaction_index` in its
+/// block, with a transaction hash of its own.
+pub fn at_position(
+    mut event: StakingEvent,
+    transaction_index: u64,
+    log_index: u64,
+) -> StakingEvent {
+    let block = event.block_meta().block_number;
+    let tx_meta = match &mut event {
+        StakingEvent::Delegate(e) => &mut e.tx_meta,
+        StakingEvent::Undelegate(e) => &mut e.tx_meta,
+        StakingEvent::Withdraw(e) => &mut e.tx_meta,
+        StakingEvent::ClaimRewards(e) => &mut e.tx_meta,
+        StakingEvent::ValidatorRewarded(e) => &mut e.tx_meta,
+        StakingEvent::EpochChanged(e) => &mut e.tx_meta,
+        StakingEvent::ValidatorCreated(e) => &mut e.tx_meta,
+        StakingEvent::ValidatorStatusChanged(e) => &mut e.tx_meta,
+        StakingEvent::CommissionChanged(e) => &mut e.tx_meta,
+        StakingEvent::Unknown(e) => &mut e.tx_meta,
+    };
+    *tx_meta = TxMeta {
+        transaction_hash: format!("0xtx{block}-{transaction_index}"),
+        transaction_index,
+        log_index,
+    };
+    event
+}
+
+pub fn make_delegate_event(block: u64, val_id: u64, delegator: &str, amount: u64) -> StakingEvent {
+    StakingEvent::Delegate(events::DelegateEvent {
+        val_id,
+        delegator: delegator.to_string(),
+        amount: amount.into(),
+        activation_epoch: 1,
+        block_meta: make_block_meta(block),
+        tx_meta: make_tx_meta(block),
+    })
+}
+
+pub fn make_undelegate_event(
+    block: u64,
+    val_id: u64,
+    delegator: &str,
+    withdrawal_id: i16,
+    amount: u64,
+) -> StakingEvent {
+    StakingEvent::Undelegate(events::UndelegateEvent {
+        val_id,
+        delegator: delegator.to_string(),
+        withdrawal_id,
+        amount: amount.into(),
+        activation_epoch: 1,
+        block_meta: make_block_meta(block),
+        tx_meta: make_tx_meta(block),
+    })
+}
+
+pub fn make_withdraw_event(
+    block: u64,
+    val_id: u64,
+    delegator: &str,
+    withdrawal_id: i16,
+    amount: u64,
+) -> StakingEvent {
+    StakingEvent::Withdraw(events::WithdrawEvent {
+        val_id,
+        delegator: delegator.to_string(),
+        withdrawal_id,
+        amount: amount.into(),
+        activation_epoch: 1,
+        block_meta: make_block_meta(block),
+        tx_meta: make_tx_meta(block),
+    })
+}
+
+pub fn make_claim_rewards_event(
+    block: u64,
+    val_id: u64,
+    delegator: &str,
+    amount: u64,
+) -> StakingEvent {
+    StakingEvent::ClaimRewards(events::ClaimRewardsEvent {
+        val_id,
+        delegator: delegator.to_string(),
+        amount: amount.into(),
+        epoch: 10,
+        block_meta: make_block_meta(block),
+        tx_meta: make_tx_meta(block),
+    })
+}
+
+pub fn make_validator_rewarded_event(block: u64, validator_id: u64, amount: u64) -> StakingEvent {
+    StakingEvent::ValidatorRewarded(events::ValidatorRewardedEvent {
+        validator_id,
+        from: TEST_DELEGATOR.to_string(),
+        amount: amount.into(),
+        epoch: 10,
+        block_meta: make_block_meta(block),
+        tx_meta: make_tx_meta(block),
+    })
+}
+
+pub fn make_epoch_changed_event(block: u64, new_epoch: u64) -> StakingEvent {
+    StakingEvent::EpochChanged(events::EpochChangedEvent {
+        old_epoch: new_epoch.saturating_sub(1),
+        new_epoch,
+        block_meta: make_block_meta(block),
+        tx_meta: make_tx_meta(block),
+    })
+}
+
+pub fn make_validator_created_event(
+    block: u64,
+    validator_id: u64,
+    commission: u64,
+) -> StakingEvent {
+    StakingEvent::ValidatorCreated(events::ValidatorCreatedEvent {
+        validator_id,
+        auth_address: TEST_DELEGATOR.to_string(),
+        commission: commission.into(),
+        block_meta: make_block_meta(block),
+        tx_meta: make_tx_meta(block),
+    })
+}
+
+pub fn make_validator_status_changed_event(
+    block: u64,
+    validator_id: u64,
+    flags: u64,
+) -> StakingEvent {
+    StakingEvent::ValidatorStatusChanged(events::ValidatorStatusChangedEvent {
+        validator_id,
+        flags,
+        block_meta: make_block_meta(block),
+        tx_meta: make_tx_meta(block),
+    })
+}
+
+pub fn make_commission_changed_event(
+    block: u64,
+    validator_id: u64,
+    old_commission: u64,
+    new_commission: u64,
+) -> StakingEvent {
+    StakingEvent::CommissionChanged(events::CommissionChangedEvent {
+        validator_id,
+        old_commission: old_commission.into(),
+        new_commission: new_commission.into(),
+        block_meta: make_block_meta(block),
+        tx_meta: make_tx_meta(block),
+    })
+}
+
+/// [`LogProvider`] backed by an in-memory list of logs, for testing log
+/// processing without an RPC node. As a [`Connector`] every connection
+/// succeeds and serves the same logs.
+#[derive(Clone, Default)]
+pub struct MockProvider {
+    logs: Vec<Log>,
+    rate_limiter: RateLimiter,
+}
+
+impl MockProvider {
+    pub fn with_logs(logs: Vec<Log>) -> Self {
+        MockProvider {
+            logs,
+            ..Default::default()
+        }
+    }
+}
+
+impl LogProvider for MockProvider {
+    async fn historical_logs(&self, range: &Range<u64>) -> eyre::Result<Vec<Log>> {
+        Ok(self
+            .logs
+            .iter()
+            .filter(|log| log.block_number.is_some_and(|n| range.contains(&n)))
+            .cloned()
+            .collect())
+    }
+
+    /// The highest block with a log.
+    async fn get_block_number(&self) -> eyre::Result<u64> {
+        Ok(self
+            .logs
+            .iter()
+            .filter_map(|log| log.block_number)
+            .max()
+            .unwrap_or_default())
+    }
+
+    /// Yields all logs in order, then ends like a closed subscription.
+    async fn stream_events(self) -> eyre::Result<impl Stream<Item = Log> + Send> {
+        Ok(futures_util::stream::iter(self.logs))
+    }
+}
+
+impl Connector for MockProvider {
+    type Connection = MockProvider;
+
+    fn contract_address(&self) -> Address {
+        STAKING_CONTRACT_ADDRESS
+    }
+
+    fn rate_limiter(&self) -> &RateLimiter {
+        &self.rate_limiter
+    }
+
+    fn circuit_state(&self) -> CircuitState {
+        CircuitState::Closed
+    }
+
+    fn consecutive_failures(&self) -> u64 {
+        0
+    }
+
+    fn record_failure(&mut self) {}
+
+    fn record_success(&mut self) {}
+
+    fn disconnect(&mut self) {}
+
+    async fn connect(&mut self, _attempt: usize) -> Result<MockProvider, metrics::Metric> {
+        Ok(self.clone())
+    }
+
+    async fn get_current_block_number(&mut self) -> eyre::Result<u64> {
+        self.get_block_number().await
+    }
+}
+
+/// An RPC log of the staking contract with `data`, in transaction `tx_index` of
+/// block `block_number`.
+pub fn make_rpc_log(block_number: u64, tx_index: u64, log_index: u64, data: LogData) -> Log {
+    Log {
+        inner: alloy::primitives::Log {
+            address: STAKING_CONTRACT_ADDRESS,
+            data,
+        },
+        block_hash: Some(B256::with_last_byte(block_number as u8)),
+        block_number: Some(block_number),
+        block_timestamp: Some(1234567890 + block_number),
+        transaction_hash: Some(B256::with_last_byte(tx_index as u8)),
+        transaction_index: Some(tx_index),
+        log_index: Some(log_index),
+        removed: false,
+    }
+}
+
+/// A `Delegate` log of 1000 to validator `val_id`, in transaction `tx_index`.
+pub fn make_delegate_log(block_number: u64, tx_index: u64, val_id: u64) -> Log {
+    let event = contract_abi::StakingPrecompile::Delegate {
+        valId: val_id,
+        delegator: Address::repeat_byte(0x12),
+        amount: U256::from(1000u64),
+        activationEpoch: 1,
+    };
+    make_rpc_log(block_number, tx_index, tx_index, event.encode_log_data())
+}
+
+/// An `EpochChanged` log from epoch 1 to 2, in transaction `tx_index`.
+pub fn make_epoch_changed_log(block_number: u64, tx_index: u64) -> Log {
+    let event = contract_abi::StakingPrecompile::EpochChanged {
+        oldEpoch: 1,
+        newEpoch: 2,
+    };
+    make_rpc_log(block_number, tx_index, tx_index, event.encode_log_data())
+}
@@ -0,0 +1,217 @@
+//! The latest state of every validator, kept in memory so the API can serve it
+//! without querying the database.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use bigdecimal::BigDecimal;
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::db::repository::{self, DbError, ValidatorStateRow};
+use crate::events::{BlockMeta, StakingEvent, TxMeta};
+
+/// Position of an event as `(block_number, log_index)`, later events have
+/// greater positions.
+type EventPosition = (u64, u64);
+
+fn position(block_meta: &BlockMeta, tx_meta: &TxMeta) -> EventPosition {
+    (block_meta.block_number, tx_meta.log_index)
+}
+
+/// Latest attributes of one validator. As in the `validators` table, an
+/// attribute is `None` until the event that sets it has been seen.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ValidatorState {
+    pub flags: Option<u64>,
+    pub commission: Option<BigDecimal>,
+    pub auth_address: Option<String>,
+    #[serde(skip)]
+    flags_position: Option<EventPosition>,
+    #[serde(skip)]
+    commission_position: Option<EventPosition>,
+}
+
+impl ValidatorState {
+    fn set_flags(&mut self, flags: u64, at: EventPosition) {
+        if self.flags_position < Some(at) {
+            self.flags = Some(flags);
+            self.flags_position = Some(at);
+        }
+    }
+
+    fn set_commission(&mut self, commission: &BigDecimal, at: EventPosition) {
+        if self.commission_position < Some(at) {
+            self.commission = Some(commission.clone());
+            self.commission_position = Some(at);
+        }
+    }
+}
+
+/// Latest state per validator id.
+///
+/// Backfilled events may arrive after newer ones, so an attribute is only
+/// replaced by an event at a later position, like in the `validators` table.
+#[derive(Debug, Clone, Default)]
+pub struct ValidatorStateCache {
+    inner: HashMap<u64, ValidatorState>,
+}
+
+/// The cache shared by the DB task, which updates it, and the API.
+pub type SharedValidatorStateCache = Arc<RwLock<ValidatorStateCache>>;
+
+impl ValidatorStateCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The state of all validators stored in the `validators` table.
+    pub async fn load(pool: &PgPool) -> Result<Self, DbError> {
+        let rows = repository::list_validator_states(pool).await?;
+        Ok(rows.into_iter().collect())
+    }
+
+    /// Apply `event` if it changes the state of a validator, other events are
+    /// ignored.
+    pub fn update(&mut self, event: &StakingEvent) {
+        match event {
+            StakingEvent::ValidatorCreated(e) => {
+                let state = self.inner.entry(e.validator_id).or_default();
+                state.auth_address = Some(e.auth_address.clone());
+                state.set_commission(&e.commission, position(&e.block_meta, &e.tx_meta));
+            }
+            StakingEvent::CommissionChanged(e) => {
+                self.inner
+                    .entry(e.validator_id)
+                    .or_default()
+                    .set_commission(&e.new_commission, position(&e.block_meta, &e.tx_meta));
+            }
+            StakingEvent::ValidatorStatusChanged(e) => {
+                self.inner
+                    .entry(e.validator_id)
+                    .or_default()
+                    .set_flags(e.flags, position(&e.block_meta, &e.tx_meta));
+            }
+            _ => {}
+        }
+    }
+
+    pub fn get(&self, validator_id: u64) -> Option<&ValidatorState> {
+        self.inner.get(&validator_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+}
+
+impl FromIterator<ValidatorStateRow> for ValidatorStateCache {
+    fn from_iter<I: IntoIterator<Item = ValidatorStateRow>>(rows: I) -> Self {
+        let inner = rows
+            .into_iter()
+            .map(|row| {
+                let at = |block: Option<i64>, index: Option<i64>| {
+                    Some((block? as u64, index.unwrap_or(0) as u64))
+                };
+                let state = ValidatorState {
+                    flags: row.flags.map(|flags| flags as u64),
+                    commission: row.commission,
+                    auth_address: row.auth_address,
+                    flags_position: at(row.flags_block, row.flags_log_index),
+                    commission_position: at(row.commission_block, row.commission_log_index),
+                };
+                (row.validator_id as u64, state)
+            })
+            .collect();
+        Self { inner }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{
+        TEST_DELEGATOR, make_commission_changed_event, make_delegate_event,
+        make_validator_created_event, make_validator_status_changed_event,
+    };
+
+    fn commission_changed(validator_id: u64, commission: u64, block: u64) -> StakingEvent {
+        make_commission_changed_event(block, validator_id, 0, commission)
+    }
+
+    /// A status change at log `index` of `block`, to order changes within a
+    /// block.
+    fn status_changed(validator_id: u64, flags: u64, block: u64, index: u64) -> StakingEvent {
+        let mut event = make_validator_status_changed_event(block, validator_id, flags);
+        if let StakingEvent::ValidatorStatusChanged(changed) = &mut event {
+            changed.tx_meta.log_index = index;
+        }
+        event
+    }
+
+    #[test]
+    fn test_update_keeps_latest_state() {
+        let mut cache = ValidatorStateCache::new();
+        cache.update(&make_validator_created_event(100, 1, 5));
+        cache.update(&commission_changed(1, 7, 110));
+        cache.update(&status_changed(1, 1, 120, 2));
+        cache.update(&status_changed(1, 0, 120, 3));
+
+        assert_eq!(cache.len(), 1);
+        let state = cache.get(1).unwrap();
+        assert_eq!(state.auth_address.as_deref(), Some(TEST_DELEGATOR));
+        assert_eq!(state.commission, Some(7.into()));
+        assert_eq!(state.flags, Some(0));
+        assert!(cache.get(2).is_none());
+    }
+
+    #[test]
+    fn test_update_ignores_older_events() {
+        let mut cache = ValidatorStateCache::new();
+        cache.update(&commission_changed(1, 7, 110));
+        cache.update(&status_changed(1, 1, 120, 1));
+
+        // A backfilled creation sets the address but not the older commission.
+        cache.update(&make_validator_created_event(100, 1, 5));
+        cache.update(&status_changed(1, 0, 120, 0));
+
+        let state = cache.get(1).unwrap();
+        assert_eq!(state.auth_address.as_deref(), Some(TEST_DELEGATOR));
+        assert_eq!(state.commission, Some(7.into()));
+        assert_eq!(state.flags, Some(1));
+    }
+
+    #[test]
+    fn test_update_ignores_other_events() {
+        let mut cache = ValidatorStateCache::new();
+        cache.update(&make_delegate_event(100, 1, TEST_DELEGATOR, 1000));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_from_rows_keeps_positions() {
+        let mut cache: ValidatorStateCache = [ValidatorStateRow {
+            validator_id: 1,
+            auth_address: Some("auth".to_string()),
+            commission: Some(7.into()),
+            commission_block: Some(110),
+            commission_log_index: Some(0),
+            flags: None,
+            flags_block: None,
+            flags_log_index: None,
+        }]
+        .into_iter()
+        .collect();
+
+        cache.update(&commission_changed(1, 5, 100));
+        cache.update(&status_changed(1, 1, 90, 0));
+
+        let state = cache.get(1).unwrap();
+        assert_eq!(state.commission, Some(7.into()));
+        assert_eq!(state.flags, Some(1));
+    }
+}
@@ -0,0 +1,279 @@
+use std::sync::Arc;
+
+use eyre::{Result, WrapErr};
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tokio::time::Duration;
+use tracing::{error, info, warn};
+use vaultrs::client::VaultClient;
+
+use crate::config::{Config, DatabaseUrl, DbCredentials};
+use crate::db::DbPools;
+use crate::metrics::Metric;
+
+/// Never renew more often than this, even for tokens with a very short TTL.
+const MIN_RENEW_DELAY: Duration = Duration::from_secs(5);
+
+/// Delay before retrying a failed renewal.
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Longest delay between retries of a failed credential renewal.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+/// Timeout of a request for database credentials.
+const CREDENTIALS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Database credentials and how long Vault keeps them valid. `None` for
+/// credentials that do not expire.
+#[derive(Debug)]
+pub struct LeasedCredentials {
+    pub credentials: DbCredentials,
+    pub lease: Option<Duration>,
+}
+
+/// Response of the database secrets engine to a credentials request.
+#[derive(Deserialize)]
+struct CredentialsResponse {
+    lease_duration: u64,
+    data: DbCredentials,
+}
+
+/// Reads a new set of credentials for `role` from the database secrets engine
+/// mounted at `mount`.
+pub async fn read_database_credentials(
+    client: &VaultClient,
+    mount: &str,
+    role: &str,
+) -> Result<LeasedCredentials> {
+    let url = format!(
+        "{}/v1/{mount}/creds/{role}",
+        client.settings.address.as_str().trim_end_matches('/')
+    );
+    let response = reqwest::Client::builder()
+        .timeout(CREDENTIALS_TIMEOUT)
+        .build()?
+        .get(&url)
+        .header("X-Vault-Token", client.settings.token.as_str())
+        .send()
+        .await
+        .wrap_err_with(|| format!("Failed to request database credentials from {url}"))?
+        .error_for_status()
+        .wrap_err_with(|| format!("Vault refused database credentials for role '{role}'"))?;
+    parse_credentials(&response.bytes().await?)
+}
+
+fn parse_credentials(body: &[u8]) -> Result<LeasedCredentials> {
+    let response: CredentialsResponse =
+        serde_json::from_slice(body).wrap_err("Invalid database credentials response")?;
+    Ok(LeasedCredentials {
+        credentials: response.data,
+        lease: (response.lease_duration > 0).then(|| Duration::from_secs(response.lease_duration)),
+    })
+}
+
+/// Keeps the Vault token of a [`VaultClient`] alive by renewing it at half its TTL.
+pub struct VaultTokenRefresher {
+    client: Arc<VaultClient>,
+    ttl_warn_threshold: Duration,
+}
+
+impl VaultTokenRefresher {
+    pub fn new(client: Arc<VaultClient>, ttl_warn_threshold: Duration) -> Self {
+        Self {
+            client,
+            ttl_warn_threshold,
+        }
+    }
+
+    pub async fn run(self) -> Result<()> {
+        loop {
+            let auth_info = match vaultrs::token::renew_self(self.client.as_ref(), None).await {
+                Ok(auth_info) => auth_info,
+                Err(e) => {
+                    error!("Failed to renew Vault token: {e}");
+                    tokio::time::sleep(RETRY_DELAY).await;
+                    continue;
+                }
+            };
+
+            if auth_info.lease_duration == 0 {
+                info!("Vault token does not expire, stopping renewal");
+                return Ok(());
+            }
+
+            let ttl = Duration::from_secs(auth_info.lease_duration);
+            if ttl < self.ttl_warn_threshold {
+                warn!(
+                    "Vault token TTL is down to {}s, below the {}s threshold",
+                    ttl.as_secs(),
+                    self.ttl_warn_threshold.as_secs()
+                );
+            } else {
+                info!("Renewed Vault token, TTL {}s", ttl.as_secs());
+            }
+
+            if !auth_info.renewable {
+                warn!("Vault token is not renewable, stopping renewal");
+                return Ok(());
+            }
+
+            tokio::time::sleep(renew_delay(ttl)).await;
+        }
+    }
+}
+
+/// Reads new database credentials from Vault at half the lease of the current
+/// ones and hands them to the connection pools, so that new connections log in
+/// with valid credentials instead of failing once the old lease ended.
+pub struct VaultCredentialRenewer {
+    config: Config,
+    client: Arc<VaultClient>,
+    pools: DbPools,
+    lease: Duration,
+    metrics_tx: mpsc::UnboundedSender<Metric>,
+}
+
+impl VaultCredentialRenewer {
+    pub fn new(
+        config: Config,
+        client: Arc<VaultClient>,
+        pools: DbPools,
+        lease: Duration,
+        metrics_tx: mpsc::UnboundedSender<Metric>,
+    ) -> Self {
+        Self {
+            config,
+            client,
+            pools,
+            lease,
+            metrics_tx,
+        }
+    }
+
+    pub async fn run(self) -> Result<()> {
+        let mut lease = self.lease;
+        loop {
+            tokio::time::sleep(renew_delay(lease)).await;
+
+            let mut failures = 0;
+            let renewed = loop {
+                match self.renew().await {
+                    Ok(renewed) => break renewed,
+                    Err(e) => {
+                        error!("Failed to renew database credentials from Vault: {e}");
+                        let _ = self.metrics_tx.send(Metric::VaultRenewalFailed);
+                        tokio::time::sleep(retry_delay(failures)).await;
+                        failures += 1;
+                    }
+                }
+            };
+            let Some(renewed) = renewed else {
+                info!("Renewed database credentials do not expire, stopping renewal");
+                return Ok(());
+            };
+            lease = renewed;
+            info!("Renewed database credentials, lease {}s", lease.as_secs());
+        }
+    }
+
+    /// Reads new credentials into the pools and returns the shortest of their
+    /// leases.
+    async fn renew(&self) -> Result<Option<Duration>> {
+        let database_url = self
+            .config
+            .connection_string(Some(self.client.as_ref()))
+            .await
+            .map_err(|e| eyre::eyre!("{e}"))?;
+        let read_database_url = self
+            .config
+            .read_connection_string(Some(self.client.as_ref()))
+            .await
+            .map_err(|e| eyre::eyre!("{e}"))?;
+        self.pools.set_database_urls(
+            &database_url.url,
+            read_database_url.as_ref().map(|url| url.url.as_str()),
+            &self.config.pool_settings(),
+        )?;
+        Ok(shortest_lease(&database_url, read_database_url.as_ref()))
+    }
+}
+
+/// The lease that ends first among the credentials of the primary and the
+/// read replica, `None` if neither expires.
+pub fn shortest_lease(
+    database_url: &DatabaseUrl,
+    read_database_url: Option<&DatabaseUrl>,
+) -> Option<Duration> {
+    database_url
+        .lease
+        .into_iter()
+        .chain(read_database_url.and_then(|url| url.lease))
+        .min()
+}
+
+fn renew_delay(ttl: Duration) -> Duration {
+    (ttl / 2).max(MIN_RENEW_DELAY)
+}
+
+/// Delay after `failures` earlier failed attempts, doubling from
+/// [`MIN_RENEW_DELAY`] up to [`MAX_RETRY_DELAY`].
+fn retry_delay(failures: u32) -> Duration {
+    MIN_RENEW_DELAY
+        .saturating_mul(1 << failures.min(16))
+        .min(MAX_RETRY_DELAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_credentials() {
+        let leased = parse_credentials(
+            br#"{
+                "request_id": "6d5a7b2e-0b1c-4a4f-9c3e-2f4b1a0d8e71",
+                "lease_id": "database/creds/indexer/2f6a614c",
+                "renewable": true,
+                "lease_duration": 3600,
+                "data": {
+                    "username": "v-token-indexer-x8Yd2",
+                    "password": "A1a-secret"
+                },
+                "warnings": null
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(leased.lease, Some(Duration::from_secs(3600)));
+        assert!(format!("{:?}", leased.credentials).contains("v-token-indexer-x8Yd2"));
+
+        let leased = parse_credentials(
+            br#"{"lease_duration": 0, "data": {"username": "a", "password": "b"}}"#,
+        )
+        .unwrap();
+        assert_eq!(leased.lease, None);
+
+        assert!(parse_credentials(br#"{"errors": ["permission denied"]}"#).is_err());
+    }
+
+    #[test]
+    fn test_renew_delay_is_half_the_ttl() {
+        assert_eq!(
+            renew_delay(Duration::from_secs(3600)),
+            Duration::from_secs(1800)
+        );
+    }
+
+    #[test]
+    fn test_renew_delay_has_a_minimum() {
+        assert_eq!(renew_delay(Duration::from_secs(4)), MIN_RENEW_DELAY);
+    }
+
+    #[test]
+    fn test_retry_delay_backs_off() {
+        assert_eq!(retry_delay(0), Duration::from_secs(5));
+        assert_eq!(retry_delay(1), Duration::from_secs(10));
+        assert_eq!(retry_delay(3), Duration::from_secs(40));
+        assert_eq!(retry_delay(10), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(u32::MAX), MAX_RETRY_DELAY);
+    }
+}
@@ -0,0 +1,96 @@
+use bigdecimal::BigDecimal;
+use monad_staking_indexer::{
+    db::repository,
+    events::{self, StakingEvent},
+    pg_utils,
+    test_utils::{self, insert_events, make_delegate_event, make_validator_created_event},
+};
+
+/// Addresses as they were stored before checksumming, and their EIP-55 form.
+const LEGACY_DELEGATOR: &str = "abcdefabcdefabcdefabcdefabcdefabcdefabcd";
+const DELEGATOR: &str = "ABcdEFABcdEFabcdEfAbCdefabcdeFABcDEFabCD";
+const OTHER_LEGACY_DELEGATOR: &str = "abcdef0123456789abcdef0123456789abcdef01";
+const LEGACY_AUTH_ADDRESS: &str = "00000000000000000000000000000000000000aa";
+const AUTH_ADDRESS: &str = "00000000000000000000000000000000000000AA";
+
+fn legacy_events() -> Vec<StakingEvent> {
+    let StakingEvent::ValidatorCreated(created) = make_validator_created_event(100, 1, 10) else {
+        unreachable!()
+    };
+    vec![
+        StakingEvent::ValidatorCreated(events::ValidatorCreatedEvent {
+            auth_address: LEGACY_AUTH_ADDRESS.to_string(),
+            ..created
+        }),
+        make_delegate_event(101, 1, LEGACY_DELEGATOR, 1000),
+    ]
+}
+
+#[test]
+fn test_checksum_legacy_addresses() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        insert_events(&pool, legacy_events()).await?;
+
+        // validator_created_events, validators, delegate_events, delegations.
+        assert_eq!(repository::checksum_legacy_addresses(&pool).await?, 4);
+
+        let validator = repository::get_validator(&pool, 1).await?.unwrap();
+        assert_eq!(validator.auth_address.as_deref(), Some(AUTH_ADDRESS));
+        assert_eq!(
+            repository::get_stake(&pool, 1, DELEGATOR).await?,
+            BigDecimal::from(1000)
+        );
+        let delegations =
+            repository::get_delegate_events_by_delegator(&pool, DELEGATOR, 10, 0).await?;
+        assert_eq!(delegations.len(), 1);
+        assert!(
+            repository::get_delegate_events_by_delegator(&pool, LEGACY_DELEGATOR, 10, 0)
+                .await?
+                .is_empty()
+        );
+
+        // Done once, addresses stored later are left as they are.
+        insert_events(
+            &pool,
+            vec![make_delegate_event(102, 1, LEGACY_DELEGATOR, 1000)],
+        )
+        .await?;
+        assert_eq!(repository::checksum_legacy_addresses(&pool).await?, 0);
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_checksum_legacy_addresses_in_batches() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let mut events = legacy_events();
+        events.push(make_delegate_event(102, 1, OTHER_LEGACY_DELEGATOR, 500));
+        insert_events(&pool, events).await?;
+
+        // Every address in a batch of its own, the two delegators included.
+        assert_eq!(
+            repository::checksum_legacy_addresses_in_batches(&pool, 1).await?,
+            6
+        );
+        for legacy in [LEGACY_DELEGATOR, OTHER_LEGACY_DELEGATOR] {
+            assert!(
+                repository::get_delegate_events_by_delegator(&pool, legacy, 10, 0)
+                    .await?
+                    .is_empty()
+            );
+        }
+        assert_eq!(
+            repository::get_stake(&pool, 1, DELEGATOR).await?,
+            BigDecimal::from(1000)
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
@@ -0,0 +1,76 @@
+use bigdecimal::BigDecimal;
+use monad_staking_indexer::{
+    aggregates::{ValidatorSummary, compute_validator_summary},
+    events::StakingEvent,
+    pg_utils,
+    test_utils::{
+        self, TEST_DELEGATOR, insert_events, make_claim_rewards_event,
+        make_commission_changed_event, make_delegate_event, make_epoch_changed_event,
+        make_undelegate_event, make_validator_created_event, make_validator_rewarded_event,
+        make_validator_status_changed_event, make_withdraw_event,
+    },
+};
+
+const OTHER_DELEGATOR: &str = "ABcdEFABcdEFabcdEfAbCdefabcdeFABcDEFabCD";
+
+/// One event of every type for validator 1, spread over blocks 99 to 109, and
+/// a delegation to and a reward of validator 2 that must not be counted.
+fn fixture() -> Vec<StakingEvent> {
+    vec![
+        make_validator_created_event(99, 1, 10),
+        make_delegate_event(100, 1, TEST_DELEGATOR, 1000),
+        make_delegate_event(101, 1, OTHER_DELEGATOR, 500),
+        make_undelegate_event(102, 1, TEST_DELEGATOR, 1, 300),
+        make_withdraw_event(103, 1, TEST_DELEGATOR, 1, 300),
+        make_validator_rewarded_event(104, 1, 50),
+        make_validator_rewarded_event(105, 1, 70),
+        make_claim_rewards_event(106, 1, TEST_DELEGATOR, 20),
+        make_commission_changed_event(107, 1, 10, 20),
+        make_validator_status_changed_event(108, 1, 0),
+        make_epoch_changed_event(109, 4),
+        make_delegate_event(110, 2, TEST_DELEGATOR, 7),
+        make_validator_rewarded_event(111, 2, 9),
+    ]
+}
+
+#[test]
+fn test_validator_summary() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        insert_events(&pool, fixture()).await?;
+
+        assert_eq!(
+            compute_validator_summary(&pool, 1).await?,
+            Some(ValidatorSummary {
+                validator_id: 1,
+                auth_address: Some(TEST_DELEGATOR.to_string()),
+                commission: Some(BigDecimal::from(20)),
+                created_block: Some(99),
+                is_active: Some(true),
+                total_delegated: BigDecimal::from(1200),
+                delegator_count: 2,
+                total_rewards: BigDecimal::from(120),
+                total_claimed: BigDecimal::from(20),
+                commission_changes: 1,
+            })
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_unknown_validator_summary() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        insert_events(&pool, fixture()).await?;
+
+        assert_eq!(compute_validator_summary(&pool, 3).await?, None);
+
+        Ok(())
+    })
+    .unwrap();
+}
@@ -0,0 +1,258 @@
+use std::sync::{Arc, RwLock};
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use monad_staking_indexer::{
+    api, db,
+    events::{self, StakingEvent},
+    pg_utils,
+    test_utils::{
+        self, TEST_DELEGATOR, insert_events, make_block_meta, make_delegate_event,
+        make_validator_created_event, make_validator_rewarded_event,
+        make_validator_status_changed_event,
+    },
+    validator_state::ValidatorStateCache,
+};
+use tower::ServiceExt;
+
+const DELEGATOR: &str = "ABcdEFABcdEFabcdEfAbCdefabcdeFABcDEFabCD";
+
+fn delegate(delegator: &str, block: u64) -> events::DelegateEvent {
+    let StakingEvent::Delegate(event) = make_delegate_event(block, 1, delegator, 1000) else {
+        unreachable!()
+    };
+    event
+}
+
+/// A reward of 50 to `validator_id` in `epoch`, which the factory leaves at 10.
+fn reward(validator_id: u64, epoch: u64, block: u64) -> events::ValidatorRewardedEvent {
+    let StakingEvent::ValidatorRewarded(event) =
+        make_validator_rewarded_event(block, validator_id, 50)
+    else {
+        unreachable!()
+    };
+    events::ValidatorRewardedEvent { epoch, ..event }
+}
+
+async fn get(pool: &sqlx::PgPool, path: &str) -> (StatusCode, String) {
+    let validator_state = ValidatorStateCache::load(pool).await.unwrap();
+    let response = api::router(pool.clone(), Arc::new(RwLock::new(validator_state)))
+        .oneshot(Request::get(path).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[test]
+fn test_api_delegates() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        insert_events(
+            &pool,
+            vec![
+                StakingEvent::Delegate(delegate(DELEGATOR, 100)),
+                StakingEvent::Delegate(delegate(TEST_DELEGATOR, 101)),
+                StakingEvent::Delegate(delegate(DELEGATOR, 102)),
+                StakingEvent::Delegate(delegate(DELEGATOR, 103)),
+            ],
+        )
+        .await?;
+
+        // Checksummed addresses with prefix are accepted as well.
+        let path = "/v1/delegates?delegator=0xABCDEFABCDEFABCDEFABCDEFABCDEFABCDEFABCD&limit=2";
+        let (status, body) = get(&pool, path).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let page: Vec<events::DelegateEvent> = serde_json::from_str(&body)?;
+        assert_eq!(
+            page,
+            vec![delegate(DELEGATOR, 100), delegate(DELEGATOR, 102)]
+        );
+
+        let path = format!("/v1/delegates?delegator={DELEGATOR}&limit=2&offset=2");
+        let (status, body) = get(&pool, &path).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let page: Vec<events::DelegateEvent> = serde_json::from_str(&body)?;
+        assert_eq!(page, vec![delegate(DELEGATOR, 103)]);
+
+        let (status, _) = get(&pool, "/v1/delegates?delegator=0x1234").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_api_events() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        insert_events(
+            &pool,
+            vec![
+                StakingEvent::Delegate(delegate(DELEGATOR, 100)),
+                StakingEvent::Delegate(delegate(TEST_DELEGATOR, 101)),
+                StakingEvent::ValidatorRewarded(reward(1, 1, 102)),
+                StakingEvent::Delegate(delegate(DELEGATOR, 200)),
+            ],
+        )
+        .await?;
+
+        let (status, body) = get(&pool, "/v1/events?from_block=100&to_block=200").await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let events: Vec<StakingEvent> = serde_json::from_str(&body)?;
+        assert_eq!(events.len(), 3);
+
+        // Delegators match in any case.
+        let path = format!(
+            "/v1/events?from_block=100&to_block=300&delegators=0x{}&event_types=delegate",
+            DELEGATOR.to_lowercase()
+        );
+        let (status, body) = get(&pool, &path).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let events: Vec<StakingEvent> = serde_json::from_str(&body)?;
+        let blocks: Vec<_> = events
+            .iter()
+            .map(|event| event.block_meta().block_number)
+            .collect();
+        assert_eq!(blocks, vec![100, 200]);
+
+        for path in [
+            "/v1/events?from_block=200&to_block=100",
+            "/v1/events?from_block=0&to_block=20000",
+            "/v1/events?from_block=100&to_block=200&event_types=Stake",
+        ] {
+            let (status, _) = get(&pool, path).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{path}");
+        }
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_api_validator_rewards() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        insert_events(
+            &pool,
+            vec![
+                StakingEvent::ValidatorRewarded(reward(7, 1, 100)),
+                StakingEvent::ValidatorRewarded(reward(7, 2, 101)),
+                StakingEvent::ValidatorRewarded(reward(8, 2, 102)),
+                StakingEvent::ValidatorRewarded(reward(7, 3, 103)),
+            ],
+        )
+        .await?;
+
+        let (status, body) = get(&pool, "/v1/validators/7/rewards?epoch_start=2&epoch_end=3").await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let rewards: Vec<events::ValidatorRewardedEvent> = serde_json::from_str(&body)?;
+        assert_eq!(rewards, vec![reward(7, 2, 101), reward(7, 3, 103)]);
+
+        let (status, body) = get(&pool, "/v1/validators/7/rewards").await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let rewards: Vec<events::ValidatorRewardedEvent> = serde_json::from_str(&body)?;
+        assert_eq!(rewards.len(), 3);
+
+        let (status, _) = get(&pool, "/v1/validators/7/rewards?epoch_start=3&epoch_end=2").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_api_gaps() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let (status, body) = get(&pool, "/v1/gaps").await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&body)?,
+            serde_json::json!({ "ranges": [], "missing_blocks": 0 })
+        );
+
+        db::repository::upsert_blocks(&pool, &[make_block_meta(100), make_block_meta(105)]).await?;
+
+        let (status, body) = get(&pool, "/v1/gaps").await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&body)?,
+            serde_json::json!({
+                "ranges": [{ "start": 101, "end": 105 }],
+                "missing_blocks": 4,
+            })
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_api_validator_summary() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        insert_events(
+            &pool,
+            vec![
+                StakingEvent::Delegate(delegate(DELEGATOR, 100)),
+                StakingEvent::ValidatorRewarded(reward(1, 1, 101)),
+            ],
+        )
+        .await?;
+
+        let (status, body) = get(&pool, "/v1/validators/1/summary").await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let summary: serde_json::Value = serde_json::from_str(&body)?;
+        assert_eq!(summary["validator_id"], 1);
+        assert_eq!(summary["delegator_count"], 1);
+        assert_eq!(summary["auth_address"], serde_json::Value::Null);
+
+        let (status, body) = get(&pool, "/v1/validators/2/summary").await;
+        assert_eq!(status, StatusCode::NOT_FOUND, "{body}");
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_api_validator_state() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        insert_events(
+            &pool,
+            vec![
+                make_validator_created_event(100, 1, 5),
+                make_validator_status_changed_event(101, 1, 1),
+            ],
+        )
+        .await?;
+
+        let (status, body) = get(&pool, "/v1/validators/1/state").await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let state: serde_json::Value = serde_json::from_str(&body)?;
+        assert_eq!(state["auth_address"], TEST_DELEGATOR);
+        assert_eq!(state["commission"], "5");
+        assert_eq!(state["flags"], 1);
+
+        let (status, body) = get(&pool, "/v1/validators/2/state").await;
+        assert_eq!(status, StatusCode::NOT_FOUND, "{body}");
+
+        Ok(())
+    })
+    .unwrap();
+}
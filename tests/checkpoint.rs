@@ -0,0 +1,190 @@
+use monad_staking_indexer::{
+    BatchOrigin, DbRequest, DbTaskOptions, GapOrigin,
+    checkpoint::Checkpoint,
+    db, pg_utils, process_db_requests, queue, startup_start_block,
+    test_utils::{self, TEST_DELEGATOR, batch_of, make_delegate_event},
+};
+use std::ops::Range;
+
+/// A batch as produced by scanning `scanned`, with one event in each of `blocks`.
+fn scanned_batch(scanned: Range<u64>, blocks: &[u64]) -> DbRequest {
+    let mut batch = batch_of(
+        blocks
+            .iter()
+            .map(|&block| make_delegate_event(block, 1, TEST_DELEGATOR, 1000))
+            .collect(),
+    );
+    batch.scanned = Some(scanned);
+    DbRequest::InsertCompleteBlocks(Box::new(batch), BatchOrigin::Backfill)
+}
+
+#[test]
+fn test_set_checkpoint_is_monotonic() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        assert_eq!(db::repository::get_checkpoint(&pool).await?, None);
+        db::repository::set_checkpoint(&pool, 100).await?;
+        assert_eq!(db::repository::get_checkpoint(&pool).await?, Some(100));
+        db::repository::set_checkpoint(&pool, 50).await?;
+        assert_eq!(db::repository::get_checkpoint(&pool).await?, Some(100));
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_restart_does_not_rescan_quiet_range() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        assert_eq!(startup_start_block(&pool, Some(10), None).await?, Some(10));
+
+        let (tx, _gaps_rx, mut metrics_rx) = test_utils::spawn_process_event_logs(&pool);
+        // Events only at 50 and 100, everything after that up to 999 is quiet.
+        tx.send(scanned_batch(10..1000, &[50, 100])).unwrap();
+        test_utils::next_inserted_events(&mut metrics_rx)
+            .await
+            .unwrap();
+        drop(tx);
+
+        assert_eq!(
+            db::repository::get_max_block_number(&pool).await?,
+            Some(100)
+        );
+        assert_eq!(db::repository::get_checkpoint(&pool).await?, Some(999));
+
+        // After a restart the catch-up starts at the checkpoint and gap
+        // detection does not report the range between the two events.
+        assert_eq!(startup_start_block(&pool, Some(10), None).await?, Some(999));
+        let (tx, mut gaps_rx, _metrics_rx) = test_utils::spawn_process_event_logs(&pool);
+        tx.send(DbRequest::GetBlockGaps).unwrap();
+        drop(tx);
+        assert_eq!(gaps_rx.recv().await, None);
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_checkpoint_waits_for_missing_ranges() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        db::repository::set_checkpoint(&pool, 99).await?;
+        let (tx, _gaps_rx, mut metrics_rx) = test_utils::spawn_process_event_logs(&pool);
+
+        // The live stream is ahead of the backfill.
+        tx.send(scanned_batch(300..400, &[350])).unwrap();
+        test_utils::next_inserted_events(&mut metrics_rx)
+            .await
+            .unwrap();
+        assert_eq!(db::repository::get_checkpoint(&pool).await?, Some(99));
+
+        // Quiet backfill chunks are recorded even without any blocks.
+        tx.send(scanned_batch(100..200, &[])).unwrap();
+        test_utils::next_inserted_events(&mut metrics_rx)
+            .await
+            .unwrap();
+        assert_eq!(db::repository::get_checkpoint(&pool).await?, Some(199));
+
+        tx.send(scanned_batch(200..300, &[250])).unwrap();
+        test_utils::next_inserted_events(&mut metrics_rx)
+            .await
+            .unwrap();
+        assert_eq!(db::repository::get_checkpoint(&pool).await?, Some(399));
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_gap_after_checkpoint_is_reported() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        db::repository::set_checkpoint(&pool, 99).await?;
+        let (tx, mut gaps_rx, mut metrics_rx) = test_utils::spawn_process_event_logs(&pool);
+
+        tx.send(scanned_batch(300..400, &[350])).unwrap();
+        test_utils::next_inserted_events(&mut metrics_rx)
+            .await
+            .unwrap();
+
+        tx.send(DbRequest::GetBlockGaps).unwrap();
+        assert_eq!(gaps_rx.recv().await, Some((100..350, GapOrigin::Repair)));
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_checkpoint_file_follows_committed_checkpoint() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("checkpoint");
+        db::repository::set_checkpoint(&pool, 99).await?;
+
+        let (tx, rx) = queue::counting_channel();
+        let (gap_tx, _gap_rx) = queue::counting_channel();
+        let (metrics_tx, _metrics_rx) = tokio::sync::mpsc::unbounded_channel();
+        let task = tokio::spawn(process_db_requests(
+            db::DbPools::single(pool.clone()),
+            rx,
+            gap_tx,
+            metrics_tx,
+            DbTaskOptions {
+                checkpoint_path: Some(path.clone()),
+                ..Default::default()
+            },
+        ));
+
+        // A range beyond the checkpoint does not move it, so nothing is written.
+        tx.send(scanned_batch(300..400, &[350])).unwrap();
+        tx.send(scanned_batch(100..300, &[150])).unwrap();
+        drop(tx);
+        task.await?.unwrap();
+
+        assert_eq!(db::repository::get_checkpoint(&pool).await?, Some(399));
+        assert_eq!(Checkpoint::load(&path).unwrap(), Some(399));
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_start_block_falls_back_to_checkpoint_file() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        // Nothing listens on port 1, so every query fails.
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .acquire_timeout(std::time::Duration::from_secs(1))
+            .connect_lazy("postgres://indexer@127.0.0.1:1/indexer")
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checkpoint");
+
+        // Without a checkpoint file the error is passed on.
+        assert!(
+            startup_start_block(&pool, Some(10), Some(&path))
+                .await
+                .is_err()
+        );
+        assert!(startup_start_block(&pool, Some(10), None).await.is_err());
+
+        Checkpoint::save(&path, 500).unwrap();
+        assert_eq!(
+            startup_start_block(&pool, Some(10), Some(&path))
+                .await
+                .unwrap(),
+            Some(500)
+        );
+    });
+}
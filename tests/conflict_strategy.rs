@@ -0,0 +1,219 @@
+use bigdecimal::BigDecimal;
+use monad_staking_indexer::{
+    db,
+    db::{ConflictStrategies, ConflictStrategy},
+    events::{StakingEvent, StakingEventType},
+    pg_utils,
+    test_utils::{
+        self, TEST_DELEGATOR, make_claim_rewards_event, make_delegate_event, make_undelegate_event,
+    },
+};
+use tokio::time::Duration;
+
+async fn insert(
+    pool: &sqlx::PgPool,
+    events: Vec<StakingEvent>,
+    strategies: ConflictStrategies,
+) -> Result<db::InsertReport, db::repository::DbError> {
+    db::insert_blocks(
+        pool,
+        &test_utils::batch_of(events),
+        Duration::from_secs(1),
+        db::DuplicatePolicy::Ignore,
+        strategies,
+        db::BlockConflictStrategy::Ignore,
+    )
+    .await
+}
+
+async fn stored_amount(
+    pool: &sqlx::PgPool,
+    table: &str,
+    block: i64,
+) -> Result<BigDecimal, sqlx::Error> {
+    sqlx::query_scalar(&format!(
+        "SELECT amount FROM {table} WHERE block_number = $1"
+    ))
+    .bind(block)
+    .fetch_one(pool)
+    .await
+}
+
+#[test]
+fn test_ignore_strategy_keeps_stored_rows() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        insert(
+            &pool,
+            vec![make_delegate_event(100, 1, TEST_DELEGATOR, 1000)],
+            ConflictStrategies::default(),
+        )
+        .await?;
+        let report = insert(
+            &pool,
+            vec![make_delegate_event(100, 1, TEST_DELEGATOR, 2000)],
+            ConflictStrategies::default(),
+        )
+        .await?;
+        assert_eq!(report.event_counts[&StakingEventType::Delegate], (0, 1));
+
+        assert_eq!(
+            stored_amount(&pool, "delegate_events", 100).await?,
+            BigDecimal::from(1000)
+        );
+        assert_eq!(
+            db::repository::get_stake(&pool, 1, TEST_DELEGATOR).await?,
+            BigDecimal::from(1000)
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_upsert_strategy_corrects_stake() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let strategies = ConflictStrategies {
+            delegate: ConflictStrategy::Upsert,
+            ..Default::default()
+        };
+        insert(
+            &pool,
+            vec![
+                make_delegate_event(100, 1, TEST_DELEGATOR, 1000),
+                make_undelegate_event(101, 1, TEST_DELEGATOR, 1, 300),
+            ],
+            strategies,
+        )
+        .await?;
+
+        let report = insert(
+            &pool,
+            vec![
+                make_delegate_event(100, 1, TEST_DELEGATOR, 2000),
+                make_undelegate_event(101, 1, TEST_DELEGATOR, 1, 500),
+                make_delegate_event(102, 1, TEST_DELEGATOR, 10),
+            ],
+            strategies,
+        )
+        .await?;
+        assert_eq!(report.event_counts[&StakingEventType::Delegate], (2, 2));
+        assert_eq!(report.event_counts[&StakingEventType::Undelegate], (0, 1));
+
+        assert_eq!(
+            stored_amount(&pool, "delegate_events", 100).await?,
+            BigDecimal::from(2000)
+        );
+        assert_eq!(
+            stored_amount(&pool, "undelegate_events", 101).await?,
+            BigDecimal::from(300)
+        );
+        assert_eq!(
+            db::repository::get_stake(&pool, 1, TEST_DELEGATOR).await?,
+            BigDecimal::from(1710)
+        );
+
+        // Unchanged events are not written again.
+        let report = insert(
+            &pool,
+            vec![make_delegate_event(100, 1, TEST_DELEGATOR, 2000)],
+            strategies,
+        )
+        .await?;
+        assert_eq!(report.event_counts[&StakingEventType::Delegate], (0, 1));
+        assert_eq!(
+            db::repository::get_stake(&pool, 1, TEST_DELEGATOR).await?,
+            BigDecimal::from(1710)
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_upsert_strategy_is_per_table() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let strategies = ConflictStrategies {
+            claim_rewards: ConflictStrategy::Upsert,
+            ..Default::default()
+        };
+        insert(
+            &pool,
+            vec![
+                make_delegate_event(100, 1, TEST_DELEGATOR, 1000),
+                make_claim_rewards_event(100, 1, TEST_DELEGATOR, 5),
+            ],
+            strategies,
+        )
+        .await?;
+        let report = insert(
+            &pool,
+            vec![
+                make_delegate_event(100, 1, TEST_DELEGATOR, 2000),
+                make_claim_rewards_event(100, 1, TEST_DELEGATOR, 7),
+            ],
+            strategies,
+        )
+        .await?;
+        assert_eq!(report.event_counts[&StakingEventType::Delegate], (0, 1));
+        assert_eq!(report.event_counts[&StakingEventType::ClaimRewards], (1, 1));
+
+        assert_eq!(
+            stored_amount(&pool, "delegate_events", 100).await?,
+            BigDecimal::from(1000)
+        );
+        assert_eq!(
+            stored_amount(&pool, "claim_rewards_events", 100).await?,
+            BigDecimal::from(7)
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_upsert_strategy_writes_last_of_repeated_events() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let strategies = ConflictStrategies {
+            delegate: ConflictStrategy::Upsert,
+            ..Default::default()
+        };
+        insert(
+            &pool,
+            vec![make_delegate_event(100, 1, TEST_DELEGATOR, 1000)],
+            strategies,
+        )
+        .await?;
+        insert(
+            &pool,
+            vec![
+                make_delegate_event(100, 1, TEST_DELEGATOR, 2000),
+                make_delegate_event(100, 1, TEST_DELEGATOR, 3000),
+            ],
+            strategies,
+        )
+        .await?;
+
+        assert_eq!(
+            stored_amount(&pool, "delegate_events", 100).await?,
+            BigDecimal::from(3000)
+        );
+        assert_eq!(
+            db::repository::get_stake(&pool, 1, TEST_DELEGATOR).await?,
+            BigDecimal::from(3000)
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
@@ -0,0 +1,38 @@
+use monad_staking_indexer::{db, pg_utils, test_utils};
+use sqlx::ConnectOptions;
+
+async fn current_user(conn: &mut sqlx::PgConnection) -> Result<String, sqlx::Error> {
+    sqlx::query_scalar("SELECT current_user::TEXT")
+        .fetch_one(conn)
+        .await
+}
+
+/// Simulates a rotation by switching the pool to the app user's credentials.
+#[test]
+fn test_new_connections_use_rotated_credentials() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let rotated_url = (*pool.connect_options())
+            .clone()
+            .username("monad_staking_app")
+            .password("monad_staking_app")
+            .to_url_lossy();
+        let pools = db::DbPools::single(pool.clone());
+
+        let mut before = pool.acquire().await?;
+        assert_eq!(current_user(&mut before).await?, "monad_staking_setup");
+
+        pools
+            .set_database_urls(rotated_url.as_str(), None, &Default::default())
+            .map_err(|e| e.to_string())?;
+
+        // The open connection keeps its session, a new one logs in again.
+        let mut after = pool.acquire().await?;
+        assert_eq!(current_user(&mut after).await?, "monad_staking_app");
+        assert_eq!(current_user(&mut before).await?, "monad_staking_setup");
+
+        Ok(())
+    })
+    .unwrap();
+}
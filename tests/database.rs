@@ -1,8 +1,8 @@
 use std::time::Duration;
 
 use monad_staking_indexer::{
-    BlockBatch, DbRequest, db,
-    events::{self, BlockMeta, StakingEvent, StakingEventType},
+    BatchOrigin, BlockBatch, DbRequest, GapOrigin, db,
+    events::{BlockMeta, StakingEventType},
     metrics, pg_utils, test_utils,
 };
 
@@ -13,35 +13,22 @@ fn process_single_block() {
 
         let (tx, mut gaps_rx, mut metrics_rx) = test_utils::spawn_process_event_logs(&pool);
 
-        let delegate = events::DelegateEvent {
-            val_id: 1,
-            delegator: "1234567890123456789012345678901234567890".to_string(),
-            amount: 1000u64.into(),
-            activation_epoch: 1,
-            block_meta: events::BlockMeta {
-                block_number: 100,
-                block_hash: "0xabcdef".to_string(),
-                block_timestamp: 1234567890,
-            },
-            tx_meta: events::TxMeta {
-                transaction_hash: "0x123abc".to_string(),
-                transaction_index: 0,
-            },
-        };
-
-        let mut batch = BlockBatch::new();
-        batch.add_block_meta(delegate.block_meta.clone());
-        batch.add_event(StakingEvent::Delegate(delegate));
-        tx.send(DbRequest::InsertCompleteBlocks(Box::new(batch)))
+        let batch = test_utils::batch_of(vec![test_utils::make_delegate_event(
+            100,
+            1,
+            test_utils::TEST_DELEGATOR,
+            1000,
+        )]);
+        tx.send(DbRequest::InsertCompleteBlocks(
+            Box::new(batch),
+            BatchOrigin::Backfill,
+        ))
+        .unwrap();
+
+        let hm = test_utils::next_inserted_events(&mut metrics_rx)
+            .await
             .unwrap();
-
-        let got = metrics_rx.recv().await.unwrap();
-
-        if let metrics::Metric::InsertedEvents(hm) = got {
-            assert_eq!(hm.get(&StakingEventType::Delegate), Some(&(1, 1)));
-        } else {
-            panic!("unexpected");
-        };
+        assert_eq!(hm.get(&StakingEventType::Delegate), Some(&(1, 1)));
 
         tx.send(DbRequest::GetBlockGaps).unwrap();
 
@@ -60,46 +47,44 @@ fn processes_non_consecutive_blocks() {
 
         let (tx, mut gaps_rx, mut metrics_rx) = test_utils::spawn_process_event_logs(&pool);
 
-        let delegate = events::DelegateEvent {
-            val_id: 1,
-            delegator: "1234567890123456789012345678901234567890".to_string(),
-            amount: 1000u64.into(),
-            activation_epoch: 1,
-            block_meta: events::BlockMeta {
-                block_number: 100,
-                block_hash: "0xabcdef".to_string(),
-                block_timestamp: 1234567890,
-            },
-            tx_meta: events::TxMeta {
-                transaction_hash: "0x123abc".to_string(),
-                transaction_index: 0,
-            },
-        };
-
-        let mut delegate2 = delegate.clone();
-        delegate2.block_meta.block_number = 200;
-        delegate2.block_meta.block_hash = "0xbbbbbb".to_string();
-
-        let mut batch1 = BlockBatch::new();
-        batch1.add_block_meta(delegate.block_meta.clone());
-        batch1.add_event(StakingEvent::Delegate(delegate));
-
-        let mut batch2 = BlockBatch::new();
-        batch2.add_block_meta(delegate2.block_meta.clone());
-        batch2.add_event(StakingEvent::Delegate(delegate2));
-
-        tx.send(DbRequest::InsertCompleteBlocks(Box::new(batch1))).unwrap();
-        tx.send(DbRequest::InsertCompleteBlocks(Box::new(batch2))).unwrap();
+        let batch1 = test_utils::batch_of(vec![test_utils::make_delegate_event(
+            100,
+            1,
+            test_utils::TEST_DELEGATOR,
+            1000,
+        )]);
+        let batch2 = test_utils::batch_of(vec![test_utils::make_delegate_event(
+            200,
+            1,
+            test_utils::TEST_DELEGATOR,
+            1000,
+        )]);
+
+        tx.send(DbRequest::InsertCompleteBlocks(
+            Box::new(batch1),
+            BatchOrigin::Backfill,
+        ))
+        .unwrap();
+        tx.send(DbRequest::InsertCompleteBlocks(
+            Box::new(batch2),
+            BatchOrigin::Backfill,
+        ))
+        .unwrap();
 
         tx.send(DbRequest::GetBlockGaps).unwrap();
         drop(tx);
 
-        metrics_rx.recv().await.unwrap();
-        metrics_rx.recv().await.unwrap();
+        test_utils::next_inserted_events(&mut metrics_rx)
+            .await
+            .unwrap();
+        test_utils::next_inserted_events(&mut metrics_rx)
+            .await
+            .unwrap();
 
-        let gap = gaps_rx.recv().await.unwrap();
+        let (gap, origin) = gaps_rx.recv().await.unwrap();
         assert_eq!(gap.start, 101);
         assert_eq!(gap.end, 200);
+        assert_eq!(origin, GapOrigin::Repair);
 
         assert!(gaps_rx.recv().await.is_none());
 
@@ -108,13 +93,75 @@ fn processes_non_consecutive_blocks() {
     .unwrap();
 }
 
+/// Collects the block progress metrics sent for the next inserted batch.
+async fn next_block_progress(
+    metrics_rx: &mut tokio::sync::mpsc::UnboundedReceiver<metrics::Metric>,
+) -> (Option<u64>, Option<u64>) {
+    let (mut inserted, mut max_block) = (None, None);
+    loop {
+        match metrics_rx.recv().await.unwrap() {
+            metrics::Metric::BlocksInserted(n) => inserted = Some(n),
+            metrics::Metric::MaxBlockInserted(n) => max_block = Some(n),
+            metrics::Metric::InsertedEvents(_) => return (inserted, max_block),
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn reports_block_progress_per_batch() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let (tx, _gaps_rx, mut metrics_rx) = test_utils::spawn_process_event_logs(&pool);
+
+        let mut batch = BlockBatch::new();
+        batch.add_block_meta(test_utils::make_block_meta(200));
+        batch.add_block_meta(test_utils::make_block_meta(201));
+        tx.send(DbRequest::InsertCompleteBlocks(
+            Box::new(batch),
+            BatchOrigin::Backfill,
+        ))
+        .unwrap();
+        assert_eq!(
+            next_block_progress(&mut metrics_rx).await,
+            (Some(2), Some(201))
+        );
+
+        // A backfilled batch reports its own maximum, the gauge keeps the
+        // highest value seen.
+        let mut backfill = BlockBatch::new();
+        backfill.add_block_meta(test_utils::make_block_meta(100));
+        tx.send(DbRequest::InsertCompleteBlocks(
+            Box::new(backfill),
+            BatchOrigin::Backfill,
+        ))
+        .unwrap();
+        assert_eq!(
+            next_block_progress(&mut metrics_rx).await,
+            (Some(1), Some(100))
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
+
 async fn insert_blockmeta(
     pool: &sqlx::PgPool,
     meta: &BlockMeta,
-) -> Result<std::collections::HashMap<StakingEventType, (u64, u64)>, db::repository::DbError> {
+) -> Result<db::InsertReport, db::repository::DbError> {
     let mut batch = BlockBatch::new();
     batch.add_block_meta(meta.clone());
-    db::insert_blocks(pool, &batch, Duration::from_secs(1)).await
+    db::insert_blocks(
+        pool,
+        &batch,
+        Duration::from_secs(1),
+        db::DuplicatePolicy::Ignore,
+        db::ConflictStrategies::default(),
+        db::BlockConflictStrategy::Ignore,
+    )
+    .await
 }
 
 #[test]
@@ -122,19 +169,19 @@ fn test_block_gaps_with_consecutive_blocks() {
     pg_utils::with_postgres_and_schema_async(|pool| async move {
         test_utils::init_test_logger();
 
-        let gaps = db::repository::get_block_gaps(&pool).await?;
+        let gaps = db::repository::get_block_gaps(&pool, &Default::default())
+            .await?
+            .ranges;
         assert_eq!(gaps.len(), 0);
 
         for i in 1..10 {
-            let block_meta = events::BlockMeta {
-                block_number: i,
-                block_hash: format!("0xhash{}", i),
-                block_timestamp: 1234567890 + i,
-            };
+            let block_meta = test_utils::make_block_meta(i);
             insert_blockmeta(&pool, &block_meta).await?;
         }
 
-        let gaps = db::repository::get_block_gaps(&pool).await?;
+        let gaps = db::repository::get_block_gaps(&pool, &Default::default())
+            .await?
+            .ranges;
         assert_eq!(gaps.len(), 0);
 
         Ok(())
@@ -150,28 +197,16 @@ fn test_get_max_block_number() {
         let max_block = db::repository::get_max_block_number(&pool).await?;
         assert_eq!(max_block, None);
 
-        let block_meta_1 = events::BlockMeta {
-            block_number: 100,
-            block_hash: "0xhash100".to_string(),
-            block_timestamp: 1234567890,
-        };
+        let block_meta_1 = test_utils::make_block_meta(100);
         insert_blockmeta(&pool, &block_meta_1).await?;
 
         let max_block = db::repository::get_max_block_number(&pool).await?;
         assert_eq!(max_block, Some(100));
 
-        let block_meta_2 = events::BlockMeta {
-            block_number: 50,
-            block_hash: "0xhash50".to_string(),
-            block_timestamp: 1234567850,
-        };
+        let block_meta_2 = test_utils::make_block_meta(50);
         insert_blockmeta(&pool, &block_meta_2).await?;
 
-        let block_meta_3 = events::BlockMeta {
-            block_number: 200,
-            block_hash: "0xhash200".to_string(),
-            block_timestamp: 1234567900,
-        };
+        let block_meta_3 = test_utils::make_block_meta(200);
         insert_blockmeta(&pool, &block_meta_3).await?;
 
         let max_block = db::repository::get_max_block_number(&pool).await?;
@@ -182,25 +217,149 @@ fn test_get_max_block_number() {
     .unwrap();
 }
 
+#[test]
+fn test_get_min_block_number() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let min_block = db::repository::get_min_block_number(&pool).await?;
+        assert_eq!(min_block, None);
+
+        for block_number in [100, 50, 200] {
+            let block_meta = test_utils::make_block_meta(block_number);
+            insert_blockmeta(&pool, &block_meta).await?;
+        }
+
+        let min_block = db::repository::get_min_block_number(&pool).await?;
+        assert_eq!(min_block, Some(50));
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_get_block_count() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        assert_eq!(db::repository::get_block_count(&pool).await?, 0);
+
+        for block_number in [10, 11, 20] {
+            let block_meta = test_utils::make_block_meta(block_number);
+            insert_blockmeta(&pool, &block_meta).await?;
+        }
+
+        assert_eq!(db::repository::get_block_count(&pool).await?, 3);
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_get_latest_blocks() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        assert!(
+            db::repository::get_latest_blocks(&pool, 2)
+                .await?
+                .is_empty()
+        );
+
+        insert_sparse_blocks(&pool, &[10, 30, 20]).await?;
+        let latest: Vec<u64> = db::repository::get_latest_blocks(&pool, 2)
+            .await?
+            .iter()
+            .map(|block| block.block_number)
+            .collect();
+        assert_eq!(latest, vec![30, 20]);
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_gap_check_reports_indexed_block_count() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        for block_number in [10, 11] {
+            let block_meta = test_utils::make_block_meta(block_number);
+            insert_blockmeta(&pool, &block_meta).await?;
+        }
+
+        let (tx, _gaps_rx, mut metrics_rx) = test_utils::spawn_process_event_logs(&pool);
+        tx.send(DbRequest::GetBlockGaps).unwrap();
+
+        assert_eq!(
+            metrics_rx.recv().await,
+            Some(metrics::Metric::MissingBlocks(0))
+        );
+        assert_eq!(
+            metrics_rx.recv().await,
+            Some(metrics::Metric::IndexedBlockCount(2))
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_gap_check_reports_outstanding_gap_blocks() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        insert_sparse_blocks(&pool, &[10, 15, 20]).await?;
+
+        let (tx, mut gaps_rx, mut metrics_rx) = test_utils::spawn_process_event_logs(&pool);
+        tx.send(DbRequest::GetBlockGaps).unwrap();
+        assert_eq!(
+            metrics_rx.recv().await,
+            Some(metrics::Metric::MissingBlocks(8))
+        );
+        assert_eq!(gaps_rx.recv().await, Some((11..15, GapOrigin::Repair)));
+        assert_eq!(gaps_rx.recv().await, Some((16..20, GapOrigin::Repair)));
+
+        // Once the gaps are filled, the gauge drops back to zero.
+        insert_sparse_blocks(&pool, &[11, 12, 13, 14, 16, 17, 18, 19]).await?;
+        tx.send(DbRequest::GetBlockGaps).unwrap();
+        let missing = loop {
+            match metrics_rx.recv().await {
+                Some(metrics::Metric::MissingBlocks(count)) => break count,
+                Some(_) => continue,
+                None => panic!("metrics channel closed"),
+            }
+        };
+        assert_eq!(missing, 0);
+
+        Ok(())
+    })
+    .unwrap();
+}
+
 #[test]
 fn test_get_block_gaps_with_multiple_gaps() {
     pg_utils::with_postgres_and_schema_async(|pool| async move {
         test_utils::init_test_logger();
 
-        let gaps = db::repository::get_block_gaps(&pool).await?;
+        let gaps = db::repository::get_block_gaps(&pool, &Default::default())
+            .await?
+            .ranges;
         assert_eq!(gaps.len(), 0);
 
         let blocks_to_insert = vec![10, 15, 20, 25, 100, 105, 110, 500];
         for block_num in blocks_to_insert {
-            let block_meta = events::BlockMeta {
-                block_number: block_num,
-                block_hash: format!("0xhash{}", block_num),
-                block_timestamp: 1234567890 + block_num,
-            };
+            let block_meta = test_utils::make_block_meta(block_num);
             insert_blockmeta(&pool, &block_meta).await?;
         }
 
-        let gaps = db::repository::get_block_gaps(&pool).await?;
+        let gaps = db::repository::get_block_gaps(&pool, &Default::default())
+            .await?
+            .ranges;
         assert_eq!(gaps.len(), 7);
 
         assert_eq!(gaps[0].start, 11);
@@ -228,3 +387,90 @@ fn test_get_block_gaps_with_multiple_gaps() {
     })
     .unwrap();
 }
+
+async fn insert_sparse_blocks(
+    pool: &sqlx::PgPool,
+    blocks: &[u64],
+) -> Result<(), db::repository::DbError> {
+    for &block_number in blocks {
+        let block_meta = test_utils::make_block_meta(block_number);
+        insert_blockmeta(pool, &block_meta).await?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_get_block_gaps_merges_close_gaps() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        insert_sparse_blocks(&pool, &[10, 12, 14, 50, 51, 60]).await?;
+
+        let gaps = db::repository::get_block_gaps(&pool, &Default::default()).await?;
+        assert_eq!(gaps.ranges, vec![11..12, 13..14, 15..50, 52..60]);
+        assert_eq!(gaps.missing_blocks, 45);
+
+        // Gaps separated by a single known block are merged, 50..52 is kept.
+        let options = db::repository::GapOptions {
+            merge_distance: 2,
+            ..Default::default()
+        };
+        let gaps = db::repository::get_block_gaps(&pool, &options).await?;
+        assert_eq!(gaps.ranges, vec![11..50, 52..60]);
+        assert_eq!(gaps.missing_blocks, 45);
+
+        let options = db::repository::GapOptions {
+            merge_distance: 3,
+            ..Default::default()
+        };
+        let gaps = db::repository::get_block_gaps(&pool, &options).await?;
+        assert_eq!(gaps.ranges, vec![11..60]);
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_get_block_gaps_caps_ranges_oldest_first() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        insert_sparse_blocks(&pool, &[10, 20, 30, 40, 50]).await?;
+
+        let options = db::repository::GapOptions {
+            max_gaps: 2,
+            ..Default::default()
+        };
+        let gaps = db::repository::get_block_gaps(&pool, &options).await?;
+        assert_eq!(gaps.ranges, vec![11..20, 21..30]);
+        assert_eq!(gaps.missing_blocks, 36);
+
+        // Once the returned ranges are filled, the next call picks up the rest.
+        insert_sparse_blocks(&pool, &(11..30).collect::<Vec<_>>()).await?;
+        let gaps = db::repository::get_block_gaps(&pool, &options).await?;
+        assert_eq!(gaps.ranges, vec![31..40, 41..50]);
+        assert_eq!(gaps.missing_blocks, 18);
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_db_health() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        db::repository::get_db_health(&pool).await?;
+
+        pool.close().await;
+        assert!(matches!(
+            db::repository::get_db_health(&pool).await,
+            Err(db::repository::DbError::Sqlx(_))
+        ));
+
+        Ok(())
+    })
+    .unwrap();
+}
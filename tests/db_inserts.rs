@@ -1,51 +1,31 @@
+use monad_staking_indexer::test_utils::{
+    TEST_DELEGATOR, batch_of, insert_events, make_claim_rewards_event,
+    make_commission_changed_event, make_delegate_event, make_epoch_changed_event,
+    make_undelegate_event, make_validator_status_changed_event, make_withdraw_event,
+};
 use monad_staking_indexer::{
-    BlockBatch, db,
-    events::{self, StakingEventType},
-    pg_utils, test_utils,
+    BatchOrigin, BlockBatch, DbRequest, db, events, metrics, pg_utils, test_utils,
 };
+use tokio::sync::mpsc;
 use tokio::time::Duration;
 
-async fn insert_single_event(
-    pool: &sqlx::PgPool,
-    event: &events::StakingEvent,
-) -> Result<std::collections::HashMap<StakingEventType, (u64, u64)>, db::repository::DbError> {
-    let mut batch = BlockBatch::new();
-    batch.add_block_meta(event.block_meta().clone());
-    batch.add_event(event.clone());
-    db::insert_blocks(pool, &batch, Duration::from_secs(1)).await
-}
-
 #[test]
 fn test_delegate_event_duplicates() {
     pg_utils::with_postgres_and_schema_async(|pool| async move {
         test_utils::init_test_logger();
 
-        let event1 = events::StakingEvent::Delegate(events::DelegateEvent {
-            val_id: 1,
-            delegator: "1234567890123456789012345678901234567890".to_string(),
-            amount: 1000u64.into(),
-            activation_epoch: 1,
-            block_meta: events::BlockMeta {
-                block_number: 100,
-                block_hash: "0xabc1".to_string(),
-                block_timestamp: 1234567890,
-            },
-            tx_meta: events::TxMeta {
-                transaction_hash: "0xtx1".to_string(),
-                transaction_index: 0,
-            },
-        });
-
-        let mut event2 = event1.clone();
-        if let events::StakingEvent::Delegate(ref mut e) = event2 {
-            e.val_id = 2;
-        }
+        let event1 = make_delegate_event(100, 1, TEST_DELEGATOR, 1000);
+        let event2 = make_delegate_event(100, 2, TEST_DELEGATOR, 1000);
 
-        insert_single_event(&pool, &event1).await?;
-        insert_single_event(&pool, &event2).await?;
+        insert_events(&pool, vec![event1.clone()]).await?;
+        insert_events(&pool, vec![event2.clone()]).await?;
 
-        let result = insert_single_event(&pool, &event1).await?;
-        let total_inserted: u64 = result.values().map(|(inserted, _)| inserted).sum();
+        let result = insert_events(&pool, vec![event1.clone()]).await?;
+        let total_inserted: u64 = result
+            .event_counts
+            .values()
+            .map(|(inserted, _)| inserted)
+            .sum();
         assert_eq!(total_inserted, 0);
 
         Ok(())
@@ -58,33 +38,18 @@ fn test_undelegate_event_duplicates() {
     pg_utils::with_postgres_and_schema_async(|pool| async move {
         test_utils::init_test_logger();
 
-        let event1 = events::StakingEvent::Undelegate(events::UndelegateEvent {
-            val_id: 1,
-            delegator: "1234567890123456789012345678901234567890".to_string(),
-            withdrawal_id: 100,
-            amount: 1000u64.into(),
-            activation_epoch: 1,
-            block_meta: events::BlockMeta {
-                block_number: 100,
-                block_hash: "0xabc1".to_string(),
-                block_timestamp: 1234567890,
-            },
-            tx_meta: events::TxMeta {
-                transaction_hash: "0xtx1".to_string(),
-                transaction_index: 0,
-            },
-        });
-
-        let mut event2 = event1.clone();
-        if let events::StakingEvent::Undelegate(ref mut e) = event2 {
-            e.val_id = 2;
-        }
+        let event1 = make_undelegate_event(100, 1, TEST_DELEGATOR, 100, 1000);
+        let event2 = make_undelegate_event(100, 2, TEST_DELEGATOR, 100, 1000);
 
-        insert_single_event(&pool, &event1).await?;
-        insert_single_event(&pool, &event2).await?;
+        insert_events(&pool, vec![event1.clone()]).await?;
+        insert_events(&pool, vec![event2.clone()]).await?;
 
-        let result = insert_single_event(&pool, &event1).await?;
-        let total_inserted: u64 = result.values().map(|(inserted, _)| inserted).sum();
+        let result = insert_events(&pool, vec![event1.clone()]).await?;
+        let total_inserted: u64 = result
+            .event_counts
+            .values()
+            .map(|(inserted, _)| inserted)
+            .sum();
         assert_eq!(total_inserted, 0);
 
         Ok(())
@@ -97,33 +62,18 @@ fn test_withdraw_event_duplicates() {
     pg_utils::with_postgres_and_schema_async(|pool| async move {
         test_utils::init_test_logger();
 
-        let event1 = events::StakingEvent::Withdraw(events::WithdrawEvent {
-            val_id: 1,
-            delegator: "1234567890123456789012345678901234567890".to_string(),
-            withdrawal_id: 100,
-            amount: 1000u64.into(),
-            activation_epoch: 1,
-            block_meta: events::BlockMeta {
-                block_number: 100,
-                block_hash: "0xabc1".to_string(),
-                block_timestamp: 1234567890,
-            },
-            tx_meta: events::TxMeta {
-                transaction_hash: "0xtx1".to_string(),
-                transaction_index: 0,
-            },
-        });
-
-        let mut event2 = event1.clone();
-        if let events::StakingEvent::Withdraw(ref mut e) = event2 {
-            e.val_id = 2;
-        }
+        let event1 = make_withdraw_event(100, 1, TEST_DELEGATOR, 100, 1000);
+        let event2 = make_withdraw_event(100, 2, TEST_DELEGATOR, 100, 1000);
 
-        insert_single_event(&pool, &event1).await?;
-        insert_single_event(&pool, &event2).await?;
+        insert_events(&pool, vec![event1.clone()]).await?;
+        insert_events(&pool, vec![event2.clone()]).await?;
 
-        let result = insert_single_event(&pool, &event1).await?;
-        let total_inserted: u64 = result.values().map(|(inserted, _)| inserted).sum();
+        let result = insert_events(&pool, vec![event1.clone()]).await?;
+        let total_inserted: u64 = result
+            .event_counts
+            .values()
+            .map(|(inserted, _)| inserted)
+            .sum();
         assert_eq!(total_inserted, 0);
 
         Ok(())
@@ -136,32 +86,18 @@ fn test_claim_rewards_event_duplicates() {
     pg_utils::with_postgres_and_schema_async(|pool| async move {
         test_utils::init_test_logger();
 
-        let event1 = events::StakingEvent::ClaimRewards(events::ClaimRewardsEvent {
-            val_id: 1,
-            delegator: "1234567890123456789012345678901234567890".to_string(),
-            amount: 1000u64.into(),
-            epoch: 10,
-            block_meta: events::BlockMeta {
-                block_number: 100,
-                block_hash: "0xabc1".to_string(),
-                block_timestamp: 1234567890,
-            },
-            tx_meta: events::TxMeta {
-                transaction_hash: "0xtx1".to_string(),
-                transaction_index: 0,
-            },
-        });
-
-        let mut event2 = event1.clone();
-        if let events::StakingEvent::ClaimRewards(ref mut e) = event2 {
-            e.val_id = 2;
-        }
+        let event1 = make_claim_rewards_event(100, 1, TEST_DELEGATOR, 1000);
+        let event2 = make_claim_rewards_event(100, 2, TEST_DELEGATOR, 1000);
 
-        insert_single_event(&pool, &event1).await?;
-        insert_single_event(&pool, &event2).await?;
+        insert_events(&pool, vec![event1.clone()]).await?;
+        insert_events(&pool, vec![event2.clone()]).await?;
 
-        let result = insert_single_event(&pool, &event1).await?;
-        let total_inserted: u64 = result.values().map(|(inserted, _)| inserted).sum();
+        let result = insert_events(&pool, vec![event1.clone()]).await?;
+        let total_inserted: u64 = result
+            .event_counts
+            .values()
+            .map(|(inserted, _)| inserted)
+            .sum();
         assert_eq!(total_inserted, 0);
 
         Ok(())
@@ -174,31 +110,18 @@ fn test_validator_status_changed_event_duplicates() {
     pg_utils::with_postgres_and_schema_async(|pool| async move {
         test_utils::init_test_logger();
 
-        let event1 =
-            events::StakingEvent::ValidatorStatusChanged(events::ValidatorStatusChangedEvent {
-                validator_id: 1,
-                flags: 1,
-                block_meta: events::BlockMeta {
-                    block_number: 100,
-                    block_hash: "0xabc1".to_string(),
-                    block_timestamp: 1234567890,
-                },
-                tx_meta: events::TxMeta {
-                    transaction_hash: "0xtx1".to_string(),
-                    transaction_index: 0,
-                },
-            });
-
-        let mut event2 = event1.clone();
-        if let events::StakingEvent::ValidatorStatusChanged(ref mut e) = event2 {
-            e.validator_id = 2;
-        }
+        let event1 = make_validator_status_changed_event(100, 1, 1);
+        let event2 = make_validator_status_changed_event(100, 2, 1);
 
-        insert_single_event(&pool, &event1).await?;
-        insert_single_event(&pool, &event2).await?;
+        insert_events(&pool, vec![event1.clone()]).await?;
+        insert_events(&pool, vec![event2.clone()]).await?;
 
-        let result = insert_single_event(&pool, &event1).await?;
-        let total_inserted: u64 = result.values().map(|(inserted, _)| inserted).sum();
+        let result = insert_events(&pool, vec![event1.clone()]).await?;
+        let total_inserted: u64 = result
+            .event_counts
+            .values()
+            .map(|(inserted, _)| inserted)
+            .sum();
         assert_eq!(total_inserted, 0);
 
         Ok(())
@@ -211,34 +134,322 @@ fn test_commission_changed_event_duplicates() {
     pg_utils::with_postgres_and_schema_async(|pool| async move {
         test_utils::init_test_logger();
 
-        let event1 = events::StakingEvent::CommissionChanged(events::CommissionChangedEvent {
-            validator_id: 1,
-            old_commission: 100u64.into(),
-            new_commission: 150u64.into(),
-            block_meta: events::BlockMeta {
-                block_number: 100,
-                block_hash: "0xabc1".to_string(),
-                block_timestamp: 1234567890,
-            },
-            tx_meta: events::TxMeta {
-                transaction_hash: "0xtx1".to_string(),
-                transaction_index: 0,
-            },
-        });
-
-        let mut event2 = event1.clone();
-        if let events::StakingEvent::CommissionChanged(ref mut e) = event2 {
-            e.validator_id = 2;
-        }
+        let event1 = make_commission_changed_event(100, 1, 100, 150);
+        let event2 = make_commission_changed_event(100, 2, 100, 150);
 
-        insert_single_event(&pool, &event1).await?;
-        insert_single_event(&pool, &event2).await?;
+        insert_events(&pool, vec![event1.clone()]).await?;
+        insert_events(&pool, vec![event2.clone()]).await?;
 
-        let result = insert_single_event(&pool, &event1).await?;
-        let total_inserted: u64 = result.values().map(|(inserted, _)| inserted).sum();
+        let result = insert_events(&pool, vec![event1.clone()]).await?;
+        let total_inserted: u64 = result
+            .event_counts
+            .values()
+            .map(|(inserted, _)| inserted)
+            .sum();
         assert_eq!(total_inserted, 0);
 
         Ok(())
     })
     .unwrap();
 }
+
+#[test]
+fn test_known_events_are_counted_as_duplicates() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        insert_events(
+            &pool,
+            vec![make_delegate_event(100, 1, TEST_DELEGATOR, 1000)],
+        )
+        .await?;
+
+        // Same transaction, one validator already stored and one new.
+        let report = insert_events(
+            &pool,
+            vec![
+                make_delegate_event(100, 1, TEST_DELEGATOR, 1000),
+                make_delegate_event(100, 2, TEST_DELEGATOR, 1000),
+            ],
+        )
+        .await?;
+        assert_eq!(
+            report.event_counts[&events::StakingEventType::Delegate],
+            (1, 2)
+        );
+
+        assert_eq!(
+            db::repository::get_event_count(&pool, events::StakingEventType::Delegate).await?,
+            2
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_insert_report_table_stats() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let event = make_epoch_changed_event(100, 2);
+
+        let report = insert_events(&pool, vec![event.clone()]).await?;
+        let tables: Vec<(&str, u64)> = report
+            .table_stats
+            .iter()
+            .map(|stats| (stats.table, stats.rows))
+            .collect();
+        assert_eq!(tables, vec![("blocks", 1), ("epoch_changed_events", 1)]);
+
+        // Known events are still reported, with no rows written.
+        let report = insert_events(&pool, vec![event.clone()]).await?;
+        let tables: Vec<(&str, u64)> = report
+            .table_stats
+            .iter()
+            .map(|stats| (stats.table, stats.rows))
+            .collect();
+        assert_eq!(tables, vec![("blocks", 0), ("epoch_changed_events", 0)]);
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_deleting_block_deletes_its_events() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let event = make_epoch_changed_event(100, 2);
+        insert_events(&pool, vec![event.clone()]).await?;
+
+        sqlx::query("DELETE FROM blocks WHERE block_number = 100")
+            .execute(&pool)
+            .await?;
+        assert_eq!(
+            db::repository::get_event_count(&pool, events::StakingEventType::EpochChanged).await?,
+            0
+        );
+
+        // An event cannot be stored without its block.
+        let orphan = sqlx::query(
+            "INSERT INTO epoch_changed_events \
+             (old_epoch, new_epoch, block_number, transaction_hash, transaction_index) \
+             VALUES (1, 2, 100, '0xtx1', 0)",
+        )
+        .execute(&pool)
+        .await;
+        assert!(orphan.is_err());
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_intra_batch_duplicates_are_dropped_before_insert() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let event = make_epoch_changed_event(100, 2);
+        let batch = batch_of(vec![event.clone(), event]);
+
+        let (tx, _gaps_rx, mut metrics_rx) = test_utils::spawn_process_event_logs(&pool);
+        tx.send(DbRequest::InsertCompleteBlocks(
+            Box::new(batch),
+            BatchOrigin::Backfill,
+        ))
+        .unwrap();
+
+        let Some(metrics::Metric::IntraBatchDuplicates(duplicates)) = metrics_rx.recv().await
+        else {
+            panic!("expected intra-batch duplicates");
+        };
+        assert_eq!(duplicates.blocks, 1);
+        assert_eq!(
+            duplicates.events[&events::StakingEventType::EpochChanged],
+            1
+        );
+
+        // Nothing was stored before, so the insert reports no duplicates.
+        let counts = test_utils::next_inserted_events(&mut metrics_rx)
+            .await
+            .unwrap();
+        assert_eq!(counts[&events::StakingEventType::EpochChanged], (1, 1));
+        assert_eq!(db::repository::get_block_count(&pool).await?, 1);
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+/// Reads the metrics of one insert and returns its ingest latency, if reported.
+async fn next_ingest_latency(
+    metrics_rx: &mut mpsc::UnboundedReceiver<metrics::Metric>,
+) -> Option<Duration> {
+    let mut latency = None;
+    loop {
+        match metrics_rx.recv().await? {
+            metrics::Metric::IngestLatency(measured) => latency = Some(measured),
+            metrics::Metric::InsertedEvents(_) => return latency,
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn test_ingest_latency_is_only_reported_for_live_batches() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs();
+        let batch_at = |block_number: u64, block_timestamp: u64| {
+            let mut batch = BlockBatch::new();
+            batch.add_block_meta(events::BlockMeta {
+                block_number,
+                block_hash: format!("0xhash{block_number}"),
+                block_timestamp,
+            });
+            batch
+        };
+        let (tx, _gaps_rx, mut metrics_rx) = test_utils::spawn_process_event_logs(&pool);
+
+        tx.send(DbRequest::InsertCompleteBlocks(
+            Box::new(batch_at(100, now - 5)),
+            BatchOrigin::Live,
+        ))
+        .unwrap();
+        let latency = next_ingest_latency(&mut metrics_rx).await.unwrap();
+        assert!(latency >= Duration::from_secs(5), "{latency:?}");
+        assert!(latency < Duration::from_secs(60), "{latency:?}");
+
+        tx.send(DbRequest::InsertCompleteBlocks(
+            Box::new(batch_at(50, now - 86_400)),
+            BatchOrigin::Backfill,
+        ))
+        .unwrap();
+        assert_eq!(next_ingest_latency(&mut metrics_rx).await, None);
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_insert_error_identifies_table_and_blocks() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        sqlx::query("ALTER TABLE withdraw_events ADD CONSTRAINT reject_all CHECK (val_id < 0)")
+            .execute(&pool)
+            .await?;
+
+        let err = insert_events(
+            &pool,
+            [105, 100, 110]
+                .into_iter()
+                .map(|block| make_withdraw_event(block, 1, TEST_DELEGATOR, 0, 1000))
+                .collect(),
+        )
+        .await
+        .unwrap_err();
+        let db::repository::DbError::TableInsert {
+            table, block_range, ..
+        } = &err
+        else {
+            panic!("expected a table insert error, got {err:?}");
+        };
+        assert_eq!(*table, events::StakingEventType::Withdraw);
+        assert_eq!(*block_range, (100, 110));
+        assert!(
+            err.to_string()
+                .contains("Withdraw events of blocks 100..=110")
+        );
+
+        // Nothing of the batch was stored.
+        assert_eq!(db::repository::get_block_count(&pool).await?, 0);
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_upsert_blocks_updates_changed_hashes() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let block = |block_number: u64, block_hash: &str| events::BlockMeta {
+            block_number,
+            block_hash: block_hash.to_string(),
+            block_timestamp: 1_000 + block_number,
+        };
+        let stored_hash = |block_number: i64| {
+            sqlx::query_scalar::<_, String>("SELECT block_hash FROM blocks WHERE block_number = $1")
+                .bind(block_number)
+                .fetch_one(&pool)
+        };
+
+        let upserted =
+            db::repository::upsert_blocks(&pool, &[block(100, "0xa"), block(101, "0xb")]).await?;
+        assert_eq!(upserted, 2);
+
+        // Unchanged blocks are not rewritten, a changed hash replaces the stored one.
+        let upserted =
+            db::repository::upsert_blocks(&pool, &[block(100, "0xa"), block(101, "0xc")]).await?;
+        assert_eq!(upserted, 1);
+        assert_eq!(stored_hash(100).await?, "0xa");
+        assert_eq!(stored_hash(101).await?, "0xc");
+
+        assert_eq!(db::repository::upsert_blocks(&pool, &[]).await?, 0);
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_insert_upserts_blocks_in_transaction() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let batch_with_hash = |block_hash: &str| {
+            let mut meta = test_utils::make_block_meta(100);
+            meta.block_hash = block_hash.to_string();
+            let mut batch = BlockBatch::new();
+            batch.add_block_meta(meta);
+            batch
+        };
+        let insert = |batch: BlockBatch, strategy: db::BlockConflictStrategy| {
+            let pool = pool.clone();
+            async move {
+                db::insert_blocks(
+                    &pool,
+                    &batch,
+                    Duration::from_secs(1),
+                    db::DuplicatePolicy::Ignore,
+                    db::ConflictStrategies::default(),
+                    strategy,
+                )
+                .await
+            }
+        };
+
+        insert(batch_with_hash("0xa"), db::BlockConflictStrategy::Upsert).await?;
+        let report = insert(batch_with_hash("0xb"), db::BlockConflictStrategy::Ignore).await?;
+        assert_eq!(report.blocks_updated, 0);
+        let report = insert(batch_with_hash("0xb"), db::BlockConflictStrategy::Upsert).await?;
+        assert_eq!(report.blocks_updated, 1);
+        assert_eq!(
+            db::repository::get_latest_blocks(&pool, 1).await?[0].block_hash,
+            "0xb"
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
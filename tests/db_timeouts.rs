@@ -0,0 +1,214 @@
+use monad_staking_indexer::{
+    BlockBatch, db, db::repository::DbError, insert_blocks_with_retry, metrics::Metric, pg_utils,
+    test_utils,
+};
+use sqlx::ConnectOptions;
+use tokio::{sync::mpsc, time::Duration};
+
+fn single_block_batch(block_number: u64) -> BlockBatch {
+    let mut batch = BlockBatch::new();
+    batch.add_block_meta(test_utils::make_block_meta(block_number));
+    batch
+}
+
+/// Hold an exclusive lock on `blocks` for `duration`, blocking every insert.
+async fn lock_blocks_for(pool: &sqlx::PgPool, duration: Duration) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("LOCK TABLE blocks IN EXCLUSIVE MODE")
+        .execute(&mut *tx)
+        .await?;
+    tokio::spawn(async move {
+        tokio::time::sleep(duration).await;
+        let _ = tx.rollback().await;
+    });
+    Ok(())
+}
+
+#[test]
+fn test_pool_connections_have_timeouts() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let statement_timeout: String = sqlx::query_scalar("SHOW statement_timeout")
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(statement_timeout, "10s");
+        let lock_timeout: String = sqlx::query_scalar("SHOW lock_timeout")
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(lock_timeout, "5s");
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_pool_reports_connections_closed_after_max_lifetime() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let url = pool.connect_options().to_url_lossy();
+        let (metrics_tx, mut metrics_rx) = mpsc::unbounded_channel();
+        let settings = db::PoolSettings {
+            max_lifetime: Some(Duration::from_secs(4)),
+            ..Default::default()
+        };
+        let short_lived = db::create_pool(url.as_str(), &settings, metrics_tx).await?;
+        assert_eq!(
+            short_lived.options().get_max_lifetime(),
+            Some(Duration::from_secs(4))
+        );
+
+        // sqlx reaps idle connections past their lifetime every 4s from the
+        // start, without a hook. A connection opened a second later is still
+        // idle past its lifetime when it is acquired in between two runs.
+        let first = short_lived.acquire().await?;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        drop(short_lived.acquire().await?);
+        tokio::time::sleep(Duration::from_millis(4500)).await;
+        drop(short_lived.acquire().await?);
+        drop(first);
+
+        let closed = tokio::time::timeout(Duration::from_secs(5), async {
+            while let Some(metric) = metrics_rx.recv().await {
+                if metric == Metric::DbConnectionClosed {
+                    return true;
+                }
+            }
+            false
+        })
+        .await?;
+        assert!(closed);
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_statement_timeout_maps_to_db_error() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let mut tx = pool.begin().await?;
+        sqlx::query("SET LOCAL statement_timeout = '10ms'")
+            .execute(&mut *tx)
+            .await?;
+        let err: DbError = sqlx::query("SELECT pg_sleep(1)")
+            .execute(&mut *tx)
+            .await
+            .unwrap_err()
+            .into();
+        assert!(
+            matches!(err, DbError::StatementTimeout(_)),
+            "unexpected error {err:?}"
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_insert_retries_once_after_operation_timeout() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+        let (metrics_tx, mut metrics_rx) = mpsc::unbounded_channel();
+
+        // The lock outlasts the first attempt but not the longer retry.
+        lock_blocks_for(&pool, Duration::from_millis(700)).await?;
+        let batch = single_block_batch(100);
+        insert_blocks_with_retry(
+            &pool,
+            &batch,
+            Duration::from_millis(500),
+            db::DuplicatePolicy::Ignore,
+            db::ConflictStrategies::default(),
+            db::BlockConflictStrategy::Ignore,
+            &metrics_tx,
+        )
+        .await?;
+
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM blocks")
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(count, 1);
+        assert_eq!(metrics_rx.try_recv().ok(), Some(Metric::InsertTimeout));
+        assert!(metrics_rx.try_recv().is_err());
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_insert_retries_once_after_statement_timeout() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let url = pool.connect_options().to_url_lossy();
+        let (pool_metrics_tx, _pool_metrics_rx) = mpsc::unbounded_channel();
+        let settings = db::PoolSettings {
+            statement_timeout: Duration::from_millis(300),
+            ..Default::default()
+        };
+        let impatient = db::create_pool(url.as_str(), &settings, pool_metrics_tx).await?;
+        let (metrics_tx, mut metrics_rx) = mpsc::unbounded_channel();
+
+        // The lock outlasts the statements of the first attempt only.
+        lock_blocks_for(&pool, Duration::from_millis(500)).await?;
+        let batch = single_block_batch(100);
+        insert_blocks_with_retry(
+            &impatient,
+            &batch,
+            Duration::from_secs(5),
+            db::DuplicatePolicy::Ignore,
+            db::ConflictStrategies::default(),
+            db::BlockConflictStrategy::Ignore,
+            &metrics_tx,
+        )
+        .await?;
+
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM blocks")
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(count, 1);
+        assert_eq!(metrics_rx.try_recv().ok(), Some(Metric::InsertTimeout));
+        assert!(metrics_rx.try_recv().is_err());
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_insert_fails_when_retry_times_out() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+        let (metrics_tx, mut metrics_rx) = mpsc::unbounded_channel();
+
+        lock_blocks_for(&pool, Duration::from_secs(3)).await?;
+        let batch = single_block_batch(100);
+        let err = insert_blocks_with_retry(
+            &pool,
+            &batch,
+            Duration::from_millis(500),
+            db::DuplicatePolicy::Ignore,
+            db::ConflictStrategies::default(), db::BlockConflictStrategy::Ignore,
+            &metrics_tx,
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            matches!(err, DbError::OperationTimedOut { elapsed, rows: 1 } if elapsed == Duration::from_secs(1)),
+            "unexpected error {err:?}"
+        );
+        assert_eq!(metrics_rx.try_recv().ok(), Some(Metric::InsertTimeout));
+        assert_eq!(metrics_rx.try_recv().ok(), Some(Metric::InsertTimeout));
+        assert!(metrics_rx.try_recv().is_err());
+
+        Ok(())
+    })
+    .unwrap();
+}
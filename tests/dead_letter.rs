@@ -0,0 +1,168 @@
+use monad_staking_indexer::{
+    BatchOrigin, BlockBatch, DbRequest, DbTaskOptions, db,
+    events::StakingEventType,
+    metrics::Metric,
+    pg_utils, process_db_requests, queue,
+    test_utils::{self, TEST_DELEGATOR, batch_of, make_delegate_event},
+};
+use tokio::sync::mpsc::UnboundedReceiver;
+
+fn delegate_batch(block_number: u64) -> BlockBatch {
+    let mut batch = batch_of(vec![make_delegate_event(
+        block_number,
+        1,
+        TEST_DELEGATOR,
+        1000,
+    )]);
+    batch.scanned = Some(block_number..block_number + 1);
+    batch
+}
+
+async fn next_dead_letter_depth(metrics_rx: &mut UnboundedReceiver<Metric>) -> u64 {
+    loop {
+        if let Some(Metric::DeadLetterDepth(depth)) = metrics_rx.recv().await {
+            return depth;
+        }
+    }
+}
+
+#[test]
+fn test_failed_batch_is_spooled_and_replayed() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let (tx, _gaps_rx, mut metrics_rx) = test_utils::spawn_process_event_logs(&pool);
+
+        sqlx::query("ALTER TABLE delegate_events RENAME TO delegate_events_unavailable")
+            .execute(&pool)
+            .await?;
+        tx.send(DbRequest::InsertCompleteBlocks(
+            Box::new(delegate_batch(100)),
+            BatchOrigin::Backfill,
+        ))
+        .unwrap();
+        assert_eq!(next_dead_letter_depth(&mut metrics_rx).await, 1);
+
+        let failed = db::repository::get_due_failed_batches(&pool, 10).await?;
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].attempts, 0);
+        assert_eq!(failed[0].batch.delegate.len(), 1);
+        assert_eq!(failed[0].batch.scanned, Some(100..101));
+        assert_eq!(db::repository::get_block_count(&pool).await?, 0);
+        assert_eq!(db::repository::get_checkpoint(&pool).await?, None);
+
+        sqlx::query("ALTER TABLE delegate_events_unavailable RENAME TO delegate_events")
+            .execute(&pool)
+            .await?;
+        tx.send(DbRequest::ReplayFailedBatches).unwrap();
+        assert_eq!(next_dead_letter_depth(&mut metrics_rx).await, 0);
+
+        assert_eq!(
+            test_utils::get_event_count(&tx, StakingEventType::Delegate).await,
+            1
+        );
+        assert_eq!(db::repository::get_block_count(&pool).await?, 1);
+        assert_eq!(db::repository::get_checkpoint(&pool).await?, Some(100));
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_failed_replay_backs_off() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let (tx, _gaps_rx, mut metrics_rx) = test_utils::spawn_process_event_logs(&pool);
+
+        sqlx::query("ALTER TABLE delegate_events RENAME TO delegate_events_unavailable")
+            .execute(&pool)
+            .await?;
+        tx.send(DbRequest::InsertCompleteBlocks(
+            Box::new(delegate_batch(100)),
+            BatchOrigin::Backfill,
+        ))
+        .unwrap();
+        assert_eq!(next_dead_letter_depth(&mut metrics_rx).await, 1);
+
+        tx.send(DbRequest::ReplayFailedBatches).unwrap();
+        assert_eq!(next_dead_letter_depth(&mut metrics_rx).await, 1);
+
+        // The failed replay pushed the next attempt into the future.
+        assert!(
+            db::repository::get_due_failed_batches(&pool, 10)
+                .await?
+                .is_empty()
+        );
+        let attempts: i32 = sqlx::query_scalar("SELECT attempts FROM failed_batches")
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(attempts, 1);
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_batch_is_parked_after_max_attempts() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let (tx, rx) = queue::counting_channel();
+        let (gap_tx, _gap_rx) = queue::counting_channel();
+        let (metrics_tx, mut metrics_rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(process_db_requests(
+            db::DbPools::single(pool.clone()),
+            rx,
+            gap_tx,
+            metrics_tx,
+            DbTaskOptions {
+                dead_letter_max_attempts: 2,
+                ..Default::default()
+            },
+        ));
+
+        sqlx::query("ALTER TABLE delegate_events RENAME TO delegate_events_unavailable")
+            .execute(&pool)
+            .await?;
+        tx.send(DbRequest::InsertCompleteBlocks(
+            Box::new(delegate_batch(100)),
+            BatchOrigin::Backfill,
+        ))
+        .unwrap();
+        assert_eq!(next_dead_letter_depth(&mut metrics_rx).await, 1);
+
+        let make_due = "UPDATE failed_batches SET next_attempt_at = CURRENT_TIMESTAMP";
+        for attempts in 1..=2 {
+            sqlx::query(make_due).execute(&pool).await?;
+            tx.send(DbRequest::ReplayFailedBatches).unwrap();
+            let depth = next_dead_letter_depth(&mut metrics_rx).await;
+            assert_eq!(depth, if attempts < 2 { 1 } else { 0 });
+        }
+        assert_eq!(metrics_rx.recv().await, Some(Metric::DeadLetterParked(1)));
+
+        // Parked batches are kept, but not replayed even once due.
+        sqlx::query("ALTER TABLE delegate_events_unavailable RENAME TO delegate_events")
+            .execute(&pool)
+            .await?;
+        sqlx::query(make_due).execute(&pool).await?;
+        assert!(
+            db::repository::get_due_failed_batches(&pool, 10)
+                .await?
+                .is_empty()
+        );
+        assert_eq!(
+            db::repository::get_failed_batch_counts(&pool).await?,
+            (0, 1)
+        );
+        let attempts: i32 = sqlx::query_scalar("SELECT attempts FROM failed_batches")
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(attempts, 2);
+
+        Ok(())
+    })
+    .unwrap();
+}
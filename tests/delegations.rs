@@ -0,0 +1,238 @@
+use bigdecimal::BigDecimal;
+use monad_staking_indexer::{
+    db, pg_utils,
+    test_utils::{self, TEST_DELEGATOR, insert_events, make_delegate_event, make_undelegate_event},
+};
+
+#[test]
+fn test_stake_follows_delegate_and_undelegate() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        assert_eq!(
+            db::repository::get_stake(&pool, 1, TEST_DELEGATOR).await?,
+            BigDecimal::from(0)
+        );
+
+        insert_events(
+            &pool,
+            vec![
+                make_delegate_event(100, 1, TEST_DELEGATOR, 1000),
+                make_delegate_event(101, 1, TEST_DELEGATOR, 500),
+            ],
+        )
+        .await?;
+        insert_events(
+            &pool,
+            vec![make_undelegate_event(102, 1, TEST_DELEGATOR, 1, 300)],
+        )
+        .await?;
+
+        assert_eq!(
+            db::repository::get_stake(&pool, 1, TEST_DELEGATOR).await?,
+            BigDecimal::from(1200)
+        );
+        assert_eq!(
+            db::repository::get_stake(&pool, 2, TEST_DELEGATOR).await?,
+            BigDecimal::from(0)
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_stake_ignores_duplicate_events() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        insert_events(
+            &pool,
+            vec![make_delegate_event(100, 1, TEST_DELEGATOR, 1000)],
+        )
+        .await?;
+        insert_events(
+            &pool,
+            vec![make_undelegate_event(101, 1, TEST_DELEGATOR, 1, 400)],
+        )
+        .await?;
+
+        // Replaying the same blocks, e.g. from overlapping backfill chunks.
+        insert_events(
+            &pool,
+            vec![make_delegate_event(100, 1, TEST_DELEGATOR, 1000)],
+        )
+        .await?;
+        insert_events(
+            &pool,
+            vec![
+                make_delegate_event(100, 1, TEST_DELEGATOR, 1000),
+                make_undelegate_event(101, 1, TEST_DELEGATOR, 1, 400),
+            ],
+        )
+        .await?;
+
+        assert_eq!(
+            db::repository::get_stake(&pool, 1, TEST_DELEGATOR).await?,
+            BigDecimal::from(600)
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_stake_out_of_order_replay() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        // The undelegation is indexed live before its delegation is backfilled.
+        let report = insert_events(
+            &pool,
+            vec![make_undelegate_event(200, 1, TEST_DELEGATOR, 1, 400)],
+        )
+        .await?;
+        assert_eq!(report.negative_stakes, 1);
+        assert_eq!(
+            db::repository::get_stake(&pool, 1, TEST_DELEGATOR).await?,
+            BigDecimal::from(0)
+        );
+
+        let stored = sqlx::query_scalar::<_, BigDecimal>("SELECT stake FROM delegations")
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(stored, BigDecimal::from(0));
+
+        // The clamped undelegation is not subtracted again.
+        let report = insert_events(
+            &pool,
+            vec![make_delegate_event(100, 1, TEST_DELEGATOR, 1000)],
+        )
+        .await?;
+        assert_eq!(report.negative_stakes, 0);
+        assert_eq!(
+            db::repository::get_stake(&pool, 1, TEST_DELEGATOR).await?,
+            BigDecimal::from(1000)
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_total_stake() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        assert_eq!(
+            db::repository::get_total_stake(&pool, 1).await?,
+            BigDecimal::from(0)
+        );
+
+        insert_events(
+            &pool,
+            vec![
+                make_delegate_event(100, 1, TEST_DELEGATOR, 1000),
+                make_delegate_event(101, 1, "ABcdEFABcdEFabcdEfAbCdefabcdeFABcDEFabCD", 250),
+                make_delegate_event(102, 2, TEST_DELEGATOR, 7),
+            ],
+        )
+        .await?;
+
+        assert_eq!(
+            db::repository::get_total_stake(&pool, 1).await?,
+            BigDecimal::from(1250)
+        );
+        assert_eq!(
+            db::repository::get_total_stake(&pool, 2).await?,
+            BigDecimal::from(7)
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_total_stakes_of_several_validators() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        insert_events(
+            &pool,
+            vec![
+                make_delegate_event(100, 1, TEST_DELEGATOR, 1000),
+                make_delegate_event(101, 2, TEST_DELEGATOR, 7),
+                make_undelegate_event(102, 2, TEST_DELEGATOR, 1, 10),
+            ],
+        )
+        .await?;
+
+        let stakes = db::repository::get_total_stakes(&pool, &[1, 2, 3]).await?;
+        assert_eq!(
+            stakes,
+            std::collections::HashMap::from([
+                (1, BigDecimal::from(1000)),
+                (2, BigDecimal::from(0)),
+                (3, BigDecimal::from(0)),
+            ])
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_top_delegators() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        const WHALE: &str = "ABcdEFABcdEFabcdEfAbCdefabcdeFABcDEFabCD";
+        const EXITED: &str = "0000000000000000000000000000000000000001";
+
+        assert!(
+            db::repository::get_top_delegators(&pool, 10)
+                .await?
+                .is_empty()
+        );
+
+        insert_events(
+            &pool,
+            vec![
+                make_delegate_event(100, 1, TEST_DELEGATOR, 1000),
+                make_delegate_event(101, 2, TEST_DELEGATOR, 500),
+                make_delegate_event(102, 1, WHALE, 2000),
+                make_delegate_event(103, 3, EXITED, 100),
+            ],
+        )
+        .await?;
+        insert_events(
+            &pool,
+            vec![
+                make_undelegate_event(104, 1, TEST_DELEGATOR, 1, 300),
+                make_undelegate_event(105, 3, EXITED, 1, 100),
+            ],
+        )
+        .await?;
+
+        // Stakes are summed across validators; fully undelegated ones are left out.
+        assert_eq!(
+            db::repository::get_top_delegators(&pool, 10).await?,
+            vec![
+                (WHALE.to_string(), BigDecimal::from(2000)),
+                (TEST_DELEGATOR.to_string(), BigDecimal::from(1200)),
+            ]
+        );
+        assert_eq!(
+            db::repository::get_top_delegators(&pool, 1).await?,
+            vec![(WHALE.to_string(), BigDecimal::from(2000))]
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
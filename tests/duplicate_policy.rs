@@ -0,0 +1,176 @@
+use monad_staking_indexer::{
+    BatchOrigin, DbRequest, DbTaskOptions, db,
+    db::DuplicatePolicy,
+    db::repository::DbError,
+    events::{StakingEvent, StakingEventType},
+    pg_utils, process_db_requests, queue,
+    test_utils::{self, TEST_DELEGATOR, batch_of, make_delegate_event, make_epoch_changed_event},
+};
+use tokio::sync::mpsc;
+use tokio::time::Duration;
+
+async fn insert(
+    pool: &sqlx::PgPool,
+    events: Vec<StakingEvent>,
+    policy: DuplicatePolicy,
+) -> Result<db::InsertReport, DbError> {
+    db::insert_blocks(
+        pool,
+        &batch_of(events),
+        Duration::from_secs(1),
+        policy,
+        db::ConflictStrategies::default(),
+        db::BlockConflictStrategy::Ignore,
+    )
+    .await
+}
+
+fn assert_duplicate(err: DbError, expected_type: StakingEventType, expected_block: u64) {
+    let DbError::DuplicateEvent {
+        event_type,
+        block_meta,
+        ..
+    } = err
+    else {
+        panic!("expected a duplicate event error, got {err:?}");
+    };
+    assert_eq!(event_type, expected_type);
+    assert_eq!(block_meta.block_number, expected_block);
+}
+
+#[test]
+fn test_ignore_policy_skips_duplicates() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        insert(
+            &pool,
+            vec![make_delegate_event(100, 1, TEST_DELEGATOR, 1000)],
+            DuplicatePolicy::Ignore,
+        )
+        .await?;
+        let report = insert(
+            &pool,
+            vec![
+                make_delegate_event(100, 1, TEST_DELEGATOR, 2000),
+                make_delegate_event(101, 1, TEST_DELEGATOR, 1000),
+            ],
+            DuplicatePolicy::Ignore,
+        )
+        .await?;
+        assert_eq!(report.event_counts[&StakingEventType::Delegate], (1, 2));
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_error_policy_rejects_duplicates() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        insert(
+            &pool,
+            vec![make_delegate_event(100, 1, TEST_DELEGATOR, 1000)],
+            DuplicatePolicy::Error,
+        )
+        .await?;
+        let err = insert(
+            &pool,
+            vec![
+                make_delegate_event(101, 1, TEST_DELEGATOR, 1000),
+                make_delegate_event(100, 1, TEST_DELEGATOR, 1000),
+            ],
+            DuplicatePolicy::Error,
+        )
+        .await
+        .unwrap_err();
+        assert_duplicate(err, StakingEventType::Delegate, 100);
+
+        // The new event of the rejected batch was not stored either.
+        assert_eq!(db::repository::get_block_count(&pool).await?, 1);
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_verify_policy_rejects_changed_duplicates() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let stored = vec![
+            make_delegate_event(100, 1, TEST_DELEGATOR, 1000),
+            make_epoch_changed_event(100, 5),
+        ];
+        insert(&pool, stored.clone(), DuplicatePolicy::Verify).await?;
+
+        let report = insert(&pool, stored, DuplicatePolicy::Verify).await?;
+        assert_eq!(report.event_counts[&StakingEventType::Delegate], (0, 1));
+        assert_eq!(report.event_counts[&StakingEventType::EpochChanged], (0, 1));
+
+        let err = insert(
+            &pool,
+            vec![make_delegate_event(100, 1, TEST_DELEGATOR, 2000)],
+            DuplicatePolicy::Verify,
+        )
+        .await
+        .unwrap_err();
+        assert_duplicate(err, StakingEventType::Delegate, 100);
+
+        let err = insert(
+            &pool,
+            vec![make_epoch_changed_event(100, 6)],
+            DuplicatePolicy::Verify,
+        )
+        .await
+        .unwrap_err();
+        assert_duplicate(err, StakingEventType::EpochChanged, 100);
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_duplicate_stops_db_task() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let (tx, rx) = queue::counting_channel();
+        let (gap_tx, _gap_rx) = queue::counting_channel();
+        let (metrics_tx, _metrics_rx) = mpsc::unbounded_channel();
+        let task = tokio::spawn(process_db_requests(
+            db::DbPools::single(pool.clone()),
+            rx,
+            gap_tx,
+            metrics_tx,
+            DbTaskOptions {
+                duplicate_policy: DuplicatePolicy::Error,
+                ..Default::default()
+            },
+        ));
+
+        for _ in 0..2 {
+            let batch = batch_of(vec![make_delegate_event(100, 1, TEST_DELEGATOR, 1000)]);
+            tx.send(DbRequest::InsertCompleteBlocks(
+                Box::new(batch),
+                BatchOrigin::Backfill,
+            ))
+            .unwrap();
+        }
+
+        let result = tokio::time::timeout(Duration::from_secs(10), task).await??;
+        assert!(result.is_err());
+        // The rejected batch is not dead-lettered.
+        assert_eq!(
+            db::repository::get_failed_batch_counts(&pool).await?,
+            (0, 0)
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
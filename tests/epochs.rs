@@ -0,0 +1,117 @@
+use monad_staking_indexer::{
+    db, pg_utils,
+    test_utils::{self, insert_events, make_epoch_changed_event},
+};
+
+fn epoch_row(epoch: i64, start_block: i64) -> db::repository::EpochRow {
+    db::repository::EpochRow {
+        epoch,
+        start_block,
+        start_timestamp: 1234567890 + start_block,
+    }
+}
+
+#[test]
+fn test_epochs_in_order() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        assert_eq!(db::repository::get_epoch_for_block(&pool, 100).await?, None);
+
+        insert_events(&pool, vec![make_epoch_changed_event(100, 5)]).await?;
+        insert_events(&pool, vec![make_epoch_changed_event(200, 6)]).await?;
+
+        assert_eq!(db::repository::get_epoch_for_block(&pool, 99).await?, None);
+        assert_eq!(
+            db::repository::get_epoch_for_block(&pool, 100).await?,
+            Some(5)
+        );
+        assert_eq!(
+            db::repository::get_epoch_for_block(&pool, 199).await?,
+            Some(5)
+        );
+        assert_eq!(
+            db::repository::get_epoch_for_block(&pool, 200).await?,
+            Some(6)
+        );
+        assert_eq!(
+            db::repository::get_epoch_for_block(&pool, 1000).await?,
+            Some(6)
+        );
+
+        assert_eq!(
+            db::repository::get_epochs(&pool, 0..10).await?,
+            vec![epoch_row(5, 100), epoch_row(6, 200)]
+        );
+        assert_eq!(
+            db::repository::get_epochs(&pool, 6..7).await?,
+            vec![epoch_row(6, 200)]
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_epochs_out_of_order_backfill() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        // The live stream sees the newest epochs first.
+        insert_events(&pool, vec![make_epoch_changed_event(300, 7)]).await?;
+        insert_events(&pool, vec![make_epoch_changed_event(200, 6)]).await?;
+        // A later duplicate announcement of an epoch does not move its start.
+        insert_events(&pool, vec![make_epoch_changed_event(250, 6)]).await?;
+        // Backfill delivers the oldest epoch and an earlier start of epoch 6.
+        insert_events(
+            &pool,
+            vec![
+                make_epoch_changed_event(100, 5),
+                make_epoch_changed_event(150, 6),
+            ],
+        )
+        .await?;
+
+        assert_eq!(
+            db::repository::get_epochs(&pool, 0..10).await?,
+            vec![epoch_row(5, 100), epoch_row(6, 150), epoch_row(7, 300)]
+        );
+        assert_eq!(
+            db::repository::get_epoch_for_block(&pool, 149).await?,
+            Some(5)
+        );
+        assert_eq!(
+            db::repository::get_epoch_for_block(&pool, 260).await?,
+            Some(6)
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_epoch_boundary_blocks() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        assert!(
+            db::repository::get_epoch_boundary_blocks(&pool)
+                .await?
+                .is_empty()
+        );
+
+        // Inserted newest first, as a backfill of older blocks would.
+        insert_events(&pool, vec![make_epoch_changed_event(200, 6)]).await?;
+        insert_events(&pool, vec![make_epoch_changed_event(100, 5)]).await?;
+
+        assert_eq!(
+            db::repository::get_epoch_boundary_blocks(&pool).await?,
+            vec![(5, 100), (6, 200)]
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
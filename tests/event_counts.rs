@@ -0,0 +1,76 @@
+use std::collections::HashMap;
+
+use monad_staking_indexer::{
+    db,
+    events::StakingEventType,
+    pg_utils,
+    test_utils::{
+        self, TEST_DELEGATOR, insert_events, make_delegate_event, make_epoch_changed_event,
+        make_undelegate_event,
+    },
+};
+
+fn expected_counts(counts: &[(StakingEventType, u64)]) -> HashMap<StakingEventType, u64> {
+    let mut expected: HashMap<StakingEventType, u64> = StakingEventType::all_types()
+        .iter()
+        .map(|&event_type| (event_type, 0))
+        .collect();
+    expected.extend(counts.iter().copied());
+    expected
+}
+
+#[test]
+fn test_event_counts() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        assert_eq!(
+            db::repository::get_event_counts(&pool).await?,
+            expected_counts(&[])
+        );
+        assert_eq!(db::repository::get_block_range(&pool).await?, None);
+
+        insert_events(
+            &pool,
+            vec![
+                make_delegate_event(100, 1, TEST_DELEGATOR, 1000),
+                make_delegate_event(101, 1, TEST_DELEGATOR, 1000),
+                make_undelegate_event(102, 1, TEST_DELEGATOR, 1, 400),
+                make_epoch_changed_event(200, 2),
+            ],
+        )
+        .await?;
+
+        assert_eq!(
+            db::repository::get_event_counts(&pool).await?,
+            expected_counts(&[
+                (StakingEventType::Delegate, 2),
+                (StakingEventType::Undelegate, 1),
+                (StakingEventType::EpochChanged, 1),
+            ])
+        );
+        assert_eq!(
+            db::repository::get_event_counts_in_range(&pool, 101..200).await?,
+            expected_counts(&[
+                (StakingEventType::Delegate, 1),
+                (StakingEventType::Undelegate, 1),
+            ])
+        );
+        assert_eq!(
+            db::repository::get_event_count(&pool, StakingEventType::Delegate).await?,
+            2
+        );
+        assert_eq!(
+            db::repository::get_event_count(&pool, StakingEventType::Withdraw).await?,
+            0
+        );
+        assert_eq!(db::repository::get_block_count(&pool).await?, 4);
+        assert_eq!(
+            db::repository::get_block_range(&pool).await?,
+            Some((100, 200))
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
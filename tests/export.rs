@@ -0,0 +1,91 @@
+use std::collections::HashSet;
+
+use monad_staking_indexer::{
+    events::{StakingEvent, StakingEventType},
+    export,
+    filter::EventFilter,
+    pg_utils,
+    test_utils::{
+        self, TEST_DELEGATOR, at_position, insert_events, make_delegate_event,
+        make_validator_created_event,
+    },
+};
+
+#[test]
+fn test_export_events_ndjson() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        insert_events(
+            &pool,
+            vec![
+                at_position(make_validator_created_event(100, 1, 50), 1, 1),
+                make_delegate_event(100, 1, TEST_DELEGATOR, 1000),
+                make_delegate_event(150, 1, TEST_DELEGATOR, 1000),
+                make_delegate_event(200, 1, TEST_DELEGATOR, 1000),
+            ],
+        )
+        .await?;
+
+        let mut output = Vec::new();
+        let written =
+            export::export_events_ndjson(&pool, 100, 200, &EventFilter::default(), &mut output)
+                .await?;
+        assert_eq!(written, 3);
+
+        let lines: Vec<&str> = std::str::from_utf8(&output)?.lines().collect();
+        assert_eq!(lines.len(), 3);
+        let exported: Vec<StakingEvent> = lines
+            .iter()
+            .map(|line| serde_json::from_str(line))
+            .collect::<Result<_, _>>()?;
+        let positions: Vec<(String, u64)> = exported
+            .iter()
+            .map(|event| {
+                (
+                    event.event_type().to_string(),
+                    event.block_meta().block_number,
+                )
+            })
+            .collect();
+        assert_eq!(
+            positions,
+            vec![
+                ("Delegate".to_string(), 100),
+                ("ValidatorCreated".to_string(), 100),
+                ("Delegate".to_string(), 150),
+            ]
+        );
+        let StakingEvent::ValidatorCreated(created) = &exported[1] else {
+            panic!("unexpected event {:?}", exported[1]);
+        };
+        assert_eq!(created.commission, bigdecimal::BigDecimal::from(50));
+        assert_eq!(created.block_meta.block_hash, "0xhash100");
+        assert_eq!(created.tx_meta.transaction_hash, "0xtx100-1");
+
+        let mut output = Vec::new();
+        let filter = EventFilter {
+            event_types: Some(HashSet::from([StakingEventType::Delegate])),
+            block_range: Some(150..1000),
+            ..Default::default()
+        };
+        assert_eq!(
+            export::export_events_ndjson(&pool, 100, 200, &filter, &mut output).await?,
+            1
+        );
+        let exported: StakingEvent = serde_json::from_slice(&output)?;
+        assert_eq!(exported.event_type(), StakingEventType::Delegate);
+        assert_eq!(exported.block_meta().block_number, 150);
+
+        let mut output = Vec::new();
+        assert_eq!(
+            export::export_events_ndjson(&pool, 300, 400, &EventFilter::default(), &mut output)
+                .await?,
+            0
+        );
+        assert!(output.is_empty());
+
+        Ok(())
+    })
+    .unwrap();
+}
@@ -0,0 +1,87 @@
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use monad_staking_indexer::{health::HealthState, metrics, pg_utils, test_utils};
+use tokio::sync::mpsc;
+use tokio::time::Duration;
+use tower::ServiceExt;
+
+async fn get(pool: &sqlx::PgPool, health: &HealthState, path: &str) -> (StatusCode, String) {
+    let (request_tx, _request_rx) = mpsc::unbounded_channel();
+    let (metrics_tx, _metrics_rx) = mpsc::unbounded_channel();
+    let app = metrics::router(
+        request_tx,
+        pool.clone(),
+        metrics_tx,
+        health.clone(),
+        &metrics::ServerOptions::default(),
+    );
+
+    let response = app
+        .oneshot(Request::get(path).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[test]
+fn test_healthz_reports_stuck_tasks() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let health = HealthState::new(Duration::from_millis(200));
+        assert_eq!(get(&pool, &health, "/healthz").await.0, StatusCode::OK);
+
+        health.ping("db");
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let (status, body) = get(&pool, &health, "/healthz").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body, "no heartbeat from db");
+
+        health.ping("db");
+        assert_eq!(get(&pool, &health, "/healthz").await.0, StatusCode::OK);
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_readyz_requires_rpc_connection() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let health = HealthState::default();
+        let (status, body) = get(&pool, &health, "/readyz").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body, "no RPC connection yet");
+
+        health.record_rpc_connected();
+        assert_eq!(get(&pool, &health, "/readyz").await.0, StatusCode::OK);
+        assert_eq!(get(&pool, &health, "/ready").await.0, StatusCode::OK);
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_readyz_requires_database() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        // Nothing listens on port 1, so every query fails.
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .acquire_timeout(Duration::from_secs(1))
+            .connect_lazy("postgres://indexer@127.0.0.1:1/indexer")
+            .unwrap();
+        let health = HealthState::default();
+        health.record_rpc_connected();
+
+        let (status, body) = get(&pool, &health, "/readyz").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(body.starts_with("database unavailable"), "{body}");
+    });
+}
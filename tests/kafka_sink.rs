@@ -0,0 +1,71 @@
+#![cfg(feature = "kafka")]
+
+use std::time::Duration;
+
+use monad_staking_indexer::config::KafkaConfig;
+use monad_staking_indexer::sink::{EventSink, KafkaSink};
+use monad_staking_indexer::test_utils::{TEST_DELEGATOR, make_delegate_event};
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::{ClientConfig, Message};
+use testcontainers::runners::AsyncRunner;
+use testcontainers_modules::kafka::{KAFKA_PORT, Kafka};
+
+fn kafka_config(brokers: &str, queue_capacity: usize) -> KafkaConfig {
+    KafkaConfig {
+        brokers: brokers.to_string(),
+        topic: "staking-events".to_string(),
+        client_id: "indexer-test".to_string(),
+        queue_capacity,
+    }
+}
+
+#[test]
+#[ignore = "starts a Kafka container, requires Docker"]
+fn test_kafka_sink_produces_keyed_json() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let kafka = Kafka::default().start().await.unwrap();
+        let port = kafka.get_host_port_ipv4(KAFKA_PORT).await.unwrap();
+        let brokers = format!("127.0.0.1:{port}");
+
+        let sink = KafkaSink::new(&kafka_config(&brokers, 10)).unwrap();
+        let event = make_delegate_event(100, 7, TEST_DELEGATOR, 1000);
+        sink.publish(&event).await.unwrap();
+
+        let consumer: StreamConsumer = ClientConfig::new()
+            .set("bootstrap.servers", &brokers)
+            .set("group.id", "indexer-test")
+            .set("auto.offset.reset", "earliest")
+            .create()
+            .unwrap();
+        consumer.subscribe(&["staking-events"]).unwrap();
+        let message = tokio::time::timeout(Duration::from_secs(30), consumer.recv())
+            .await
+            .expect("no message produced")
+            .unwrap();
+
+        assert_eq!(message.key(), Some("7".as_bytes()));
+        let payload: serde_json::Value =
+            serde_json::from_slice(message.payload().unwrap()).unwrap();
+        assert_eq!(payload, serde_json::to_value(&event).unwrap());
+    });
+}
+
+#[test]
+fn test_kafka_sink_drops_events_while_queue_is_full() {
+    // On a current thread runtime the producer task does not run before the
+    // test yields, so the queue is not drained.
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        // Creating the producer does not connect, publishing only queues.
+        let sink = KafkaSink::new(&kafka_config("127.0.0.1:1", 1)).unwrap();
+        let event = make_delegate_event(100, 7, TEST_DELEGATOR, 1000);
+        sink.publish(&event).await.unwrap();
+
+        let error = sink.publish(&event).await.unwrap_err();
+        assert!(error.to_string().contains("full"), "{error}");
+    });
+}
@@ -0,0 +1,229 @@
+use axum::body::Body;
+use axum::http::{HeaderMap, Request, StatusCode, header};
+use monad_staking_indexer::config::{BasicAuth, MetricsAuth};
+use monad_staking_indexer::queue::QueueDepth;
+use monad_staking_indexer::{health::HealthState, metrics};
+use tokio::sync::mpsc;
+use tokio::time::Duration;
+use tower::ServiceExt;
+
+/// Pool that is never connected, the metrics server only reads its size.
+fn lazy_pool() -> sqlx::PgPool {
+    sqlx::postgres::PgPoolOptions::new()
+        .connect_lazy("postgres://indexer@127.0.0.1:1/indexer")
+        .unwrap()
+}
+
+/// Router answering metrics requests from a running metrics task.
+fn app_with_metrics_task(options: metrics::ServerOptions) -> axum::Router {
+    let (metrics_tx, metrics_rx) = mpsc::unbounded_channel();
+    let (request_tx, request_rx) = mpsc::unbounded_channel();
+    tokio::spawn(metrics::process_metrics(
+        metrics_rx,
+        QueueDepth::default(),
+        request_rx,
+        lazy_pool(),
+        metrics::DEFAULT_MAX_TRACKED_VALIDATORS,
+        None,
+    ));
+    metrics::router(
+        request_tx,
+        lazy_pool(),
+        metrics_tx,
+        HealthState::default(),
+        &options,
+    )
+}
+
+async fn get(
+    app: &axum::Router,
+    path: &str,
+    authorization: Option<&str>,
+) -> (StatusCode, HeaderMap, String) {
+    let mut request = Request::get(path);
+    if let Some(authorization) = authorization {
+        request = request.header(header::AUTHORIZATION, authorization);
+    }
+    let response = app
+        .clone()
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, headers, String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[test]
+fn test_metrics_bearer_auth() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let app = app_with_metrics_task(metrics::ServerOptions {
+            auth: Some(MetricsAuth::Bearer {
+                bearer_token: "secret".to_string(),
+            }),
+            ..Default::default()
+        });
+
+        let (status, headers, _) = get(&app, "/metrics", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(headers[header::WWW_AUTHENTICATE], "Bearer");
+        let (status, _, _) = get(&app, "/metrics", Some("Bearer wrong")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, _, body) = get(&app, "/metrics", Some("Bearer secret")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("staking_events_inserted_total"), "{body}");
+
+        // Probes do not need credentials.
+        let (status, _, _) = get(&app, "/healthz", None).await;
+        assert_eq!(status, StatusCode::OK);
+    });
+}
+
+#[test]
+fn test_metrics_basic_auth() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let app = app_with_metrics_task(metrics::ServerOptions {
+            auth: Some(MetricsAuth::Basic(BasicAuth {
+                username: "prometheus".to_string(),
+                password: "secret".to_string(),
+            })),
+            ..Default::default()
+        });
+
+        let (status, headers, _) = get(&app, "/metrics", Some("Basic d3Jvbmc6d3Jvbmc=")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(headers[header::WWW_AUTHENTICATE], "Basic realm=\"metrics\"");
+
+        // "prometheus:secret"
+        let (status, _, _) = get(&app, "/metrics", Some("Basic cHJvbWV0aGV1czpzZWNyZXQ=")).await;
+        assert_eq!(status, StatusCode::OK);
+    });
+}
+
+#[test]
+fn test_metrics_timeout_serves_last_snapshot() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let (metrics_tx, metrics_rx) = mpsc::unbounded_channel();
+        let (task_request_tx, task_request_rx) = mpsc::unbounded_channel();
+        tokio::spawn(metrics::process_metrics(
+            metrics_rx,
+            QueueDepth::default(),
+            task_request_rx,
+            lazy_pool(),
+            metrics::DEFAULT_MAX_TRACKED_VALIDATORS,
+            None,
+        ));
+        // Only the first request reaches the metrics task, later ones are never
+        // answered, as if the task were stuck.
+        let (request_tx, mut request_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            if let Some(request) = request_rx.recv().await {
+                let _ = task_request_tx.send(request);
+            }
+            let mut stuck = Vec::new();
+            while let Some(request) = request_rx.recv().await {
+                stuck.push(request);
+            }
+        });
+        let app = metrics::router(
+            request_tx,
+            lazy_pool(),
+            metrics_tx,
+            HealthState::default(),
+            &metrics::ServerOptions {
+                request_timeout: Duration::from_millis(200),
+                ..Default::default()
+            },
+        );
+
+        let (status, headers, fresh) = get(&app, "/metrics", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!headers.contains_key(metrics::SNAPSHOT_AGE_HEADER));
+
+        let (status, headers, stale) = get(&app, "/metrics", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[metrics::SNAPSHOT_AGE_HEADER], "0");
+        assert_eq!(stale, fresh);
+    });
+}
+
+#[test]
+fn test_metrics_timeout_without_snapshot_fails() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let (request_tx, _request_rx) = mpsc::unbounded_channel();
+        let (metrics_tx, _metrics_rx) = mpsc::unbounded_channel();
+        let app = metrics::router(
+            request_tx,
+            lazy_pool(),
+            metrics_tx,
+            HealthState::default(),
+            &metrics::ServerOptions {
+                request_timeout: Duration::from_millis(100),
+                ..Default::default()
+            },
+        );
+
+        let (status, _, _) = get(&app, "/metrics", None).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    });
+}
+
+#[test]
+fn test_metrics_custom_path() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let app = app_with_metrics_task(metrics::ServerOptions {
+            path: "/internal/metrics".to_string(),
+            ..Default::default()
+        });
+
+        let (status, _, body) = get(&app, "/internal/metrics", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("staking_events_inserted_total"), "{body}");
+
+        let (status, _, _) = get(&app, "/metrics", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _, _) = get(&app, "/healthz", None).await;
+        assert_eq!(status, StatusCode::OK);
+    });
+}
+
+#[test]
+fn test_bind_retries_while_address_in_use() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let previous = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let bind_addr = previous.local_addr().unwrap().to_string();
+        // The previous instance releases the address shortly after.
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            drop(previous);
+        });
+
+        let listener = tokio::time::timeout(
+            Duration::from_secs(5),
+            metrics::bind_with_retry(&bind_addr, Duration::from_millis(20)),
+        )
+        .await
+        .expect("bind did not succeed after the address was released")
+        .unwrap();
+        assert_eq!(listener.local_addr().unwrap().to_string(), bind_addr);
+    });
+}
+
+#[test]
+fn test_bind_fails_on_invalid_address() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let result = metrics::bind_with_retry("not an address", Duration::from_millis(20)).await;
+        assert!(result.is_err());
+    });
+}
@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+
+use futures_util::StreamExt;
+use monad_staking_indexer::{
+    db,
+    events::StakingEventType,
+    pg_utils,
+    test_utils::{
+        self, TEST_DELEGATOR, at_position, insert_events, make_delegate_event,
+        make_epoch_changed_event,
+    },
+};
+use sqlx::ConnectOptions;
+
+#[test]
+fn test_inserted_blocks_are_notified() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let url = pool.connect_options().to_url_lossy();
+        let notifications = db::subscribe_notifications(url.as_str()).await?;
+        tokio::pin!(notifications);
+
+        let events = vec![
+            make_delegate_event(100, 1, TEST_DELEGATOR, 1000),
+            at_position(make_delegate_event(100, 1, TEST_DELEGATOR, 1000), 1, 1),
+            at_position(make_epoch_changed_event(100, 2), 2, 2),
+            make_delegate_event(101, 1, TEST_DELEGATOR, 1000),
+        ];
+        insert_events(&pool, events.clone()).await?;
+
+        assert_eq!(
+            notifications.next().await,
+            Some(db::BlockNotification {
+                block_number: 100,
+                counts: HashMap::from([
+                    (StakingEventType::Delegate, 2),
+                    (StakingEventType::EpochChanged, 1),
+                ]),
+            })
+        );
+        assert_eq!(
+            notifications.next().await,
+            Some(db::BlockNotification {
+                block_number: 101,
+                counts: HashMap::from([(StakingEventType::Delegate, 1)]),
+            })
+        );
+
+        // Replaying the batch indexes no new blocks, so nothing is sent.
+        insert_events(&pool, events).await?;
+        insert_events(
+            &pool,
+            vec![make_delegate_event(102, 1, TEST_DELEGATOR, 1000)],
+        )
+        .await?;
+        assert_eq!(
+            notifications.next().await.map(|n| n.block_number),
+            Some(102)
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
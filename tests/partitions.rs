@@ -0,0 +1,103 @@
+use monad_staking_indexer::{
+    BatchOrigin, DbRequest, db,
+    events::{StakingEvent, StakingEventType},
+    pg_utils,
+    test_utils::{
+        self, TEST_DELEGATOR, batch_of, insert_events, make_delegate_event, make_tx_meta,
+    },
+};
+
+#[test]
+fn test_ensure_partitions_is_idempotent() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        // The migration creates the first partition of each of the 10 tables.
+        assert_eq!(db::ensure_partitions(&pool, 0).await?, 0);
+        assert_eq!(db::ensure_partitions(&pool, 25_000_000).await?, 20);
+        assert_eq!(db::ensure_partitions(&pool, 25_000_000).await?, 0);
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_dedup_across_partition_boundary() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        db::ensure_partitions(&pool, 10_000_000).await?;
+        let events = vec![
+            make_delegate_event(9_999_999, 1, TEST_DELEGATOR, 1000),
+            make_delegate_event(10_000_000, 1, TEST_DELEGATOR, 1000),
+        ];
+
+        let report = insert_events(&pool, events.clone()).await?;
+        assert_eq!(
+            report.event_counts.get(&StakingEventType::Delegate),
+            Some(&(2, 2))
+        );
+
+        let partitions: Vec<String> = sqlx::query_scalar(
+            "SELECT tableoid::regclass::text FROM delegate_events ORDER BY block_number",
+        )
+        .fetch_all(&pool)
+        .await?;
+        assert_eq!(partitions, vec!["delegate_events_p0", "delegate_events_p1"]);
+
+        // Replaying the batch is deduplicated in both partitions.
+        let report = insert_events(&pool, events).await?;
+        assert_eq!(
+            report.event_counts.get(&StakingEventType::Delegate),
+            Some(&(0, 2))
+        );
+
+        // Uniqueness includes the block number: the same transaction hash at a
+        // different block is a different row. A transaction is only ever part
+        // of one block, so this does not happen with chain data.
+        let mut moved = make_delegate_event(10_000_001, 1, TEST_DELEGATOR, 1000);
+        if let StakingEvent::Delegate(ref mut e) = moved {
+            e.tx_meta = make_tx_meta(9_999_999);
+        }
+        let report = insert_events(&pool, vec![moved]).await?;
+        assert_eq!(
+            report.event_counts.get(&StakingEventType::Delegate),
+            Some(&(1, 1))
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_db_requests_create_missing_partitions() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let (tx, _gaps_rx, mut metrics_rx) = test_utils::spawn_process_event_logs(&pool);
+        let batch = batch_of(vec![make_delegate_event(
+            35_000_000,
+            1,
+            TEST_DELEGATOR,
+            1000,
+        )]);
+        tx.send(DbRequest::InsertCompleteBlocks(
+            Box::new(batch),
+            BatchOrigin::Backfill,
+        ))
+        .unwrap();
+
+        let counts = test_utils::next_inserted_events(&mut metrics_rx)
+            .await
+            .unwrap();
+        assert_eq!(counts.get(&StakingEventType::Delegate), Some(&(1, 1)));
+
+        // The next partition is created ahead of time.
+        assert_eq!(db::ensure_partitions(&pool, 45_000_000).await?, 0);
+
+        Ok(())
+    })
+    .unwrap();
+}
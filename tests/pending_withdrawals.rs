@@ -0,0 +1,183 @@
+use bigdecimal::BigDecimal;
+use monad_staking_indexer::{
+    db, pg_utils,
+    test_utils::{
+        self, TEST_DELEGATOR, at_position, insert_events, make_undelegate_event,
+        make_withdraw_event,
+    },
+};
+
+#[test]
+fn test_pending_withdrawal_in_order() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        insert_events(
+            &pool,
+            vec![make_undelegate_event(100, 1, TEST_DELEGATOR, 0, 500)],
+        )
+        .await?;
+
+        let pending = db::repository::get_pending_withdrawals(&pool, 1).await?;
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].delegator, TEST_DELEGATOR);
+        assert_eq!(pending[0].withdrawal_id, 0);
+        assert_eq!(pending[0].amount, BigDecimal::from(500));
+        assert_eq!(pending[0].undelegate_block, 100);
+        assert_eq!(
+            db::repository::get_total_pending_withdrawals(&pool).await?,
+            BigDecimal::from(500)
+        );
+
+        insert_events(
+            &pool,
+            vec![make_withdraw_event(200, 1, TEST_DELEGATOR, 0, 500)],
+        )
+        .await?;
+
+        assert!(
+            db::repository::get_pending_withdrawals(&pool, 1)
+                .await?
+                .is_empty()
+        );
+        assert_eq!(
+            db::repository::get_total_pending_withdrawals(&pool).await?,
+            BigDecimal::from(0)
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_pending_withdrawal_withdraw_indexed_first() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        // The live stream sees the withdrawal before backfill reaches the undelegation.
+        insert_events(
+            &pool,
+            vec![make_withdraw_event(200, 1, TEST_DELEGATOR, 0, 500)],
+        )
+        .await?;
+        assert!(
+            db::repository::get_pending_withdrawals(&pool, 1)
+                .await?
+                .is_empty()
+        );
+
+        insert_events(
+            &pool,
+            vec![make_undelegate_event(100, 1, TEST_DELEGATOR, 0, 500)],
+        )
+        .await?;
+        assert!(
+            db::repository::get_pending_withdrawals(&pool, 1)
+                .await?
+                .is_empty()
+        );
+        assert_eq!(
+            db::repository::get_total_pending_withdrawals(&pool).await?,
+            BigDecimal::from(0)
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_pending_withdrawal_id_reuse() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        // The same withdrawal id is reused after the first withdrawal completes.
+        insert_events(
+            &pool,
+            vec![
+                make_undelegate_event(100, 1, TEST_DELEGATOR, 0, 500),
+                make_withdraw_event(200, 1, TEST_DELEGATOR, 0, 500),
+                make_undelegate_event(300, 1, TEST_DELEGATOR, 0, 700),
+            ],
+        )
+        .await?;
+
+        let pending = db::repository::get_pending_withdrawals(&pool, 1).await?;
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].amount, BigDecimal::from(700));
+        assert_eq!(pending[0].undelegate_block, 300);
+
+        // Other validators are tracked separately.
+        insert_events(
+            &pool,
+            vec![make_undelegate_event(400, 2, TEST_DELEGATOR, 0, 100)],
+        )
+        .await?;
+        assert_eq!(
+            db::repository::get_pending_withdrawals(&pool, 1)
+                .await?
+                .len(),
+            1
+        );
+        assert_eq!(
+            db::repository::get_total_pending_withdrawals(&pool).await?,
+            BigDecimal::from(800)
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_pending_withdrawal_id_reuse_in_same_block() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        insert_events(
+            &pool,
+            vec![make_undelegate_event(100, 1, TEST_DELEGATOR, 0, 500)],
+        )
+        .await?;
+
+        // The withdrawal completes and its id is reused later in the same block.
+        insert_events(
+            &pool,
+            vec![
+                at_position(make_undelegate_event(200, 1, TEST_DELEGATOR, 0, 700), 3, 5),
+                at_position(make_withdraw_event(200, 1, TEST_DELEGATOR, 0, 500), 1, 2),
+            ],
+        )
+        .await?;
+
+        let pending = db::repository::get_pending_withdrawals(&pool, 1).await?;
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].amount, BigDecimal::from(700));
+        assert_eq!(pending[0].undelegate_block, 200);
+
+        // A second withdrawal later in the block completes the reused id,
+        // whichever batch it arrives in.
+        insert_events(
+            &pool,
+            vec![at_position(
+                make_withdraw_event(200, 1, TEST_DELEGATOR, 0, 700),
+                3,
+                6,
+            )],
+        )
+        .await?;
+        assert!(
+            db::repository::get_pending_withdrawals(&pool, 1)
+                .await?
+                .is_empty()
+        );
+        assert_eq!(
+            db::repository::get_total_pending_withdrawals(&pool).await?,
+            BigDecimal::from(0)
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
@@ -0,0 +1,175 @@
+use monad_staking_indexer::{
+    DbTaskOptions, GapOrigin,
+    config::{BackfillConfig, BackfillProfile},
+    db,
+    events::StakingEventType,
+    pg_utils, pipeline,
+    queue::counting_channel,
+    reload::RuntimeConfig,
+    test_utils::{self, MockProvider, TEST_DELEGATOR, make_delegate_log, make_epoch_changed_log},
+};
+use std::time::Duration;
+
+fn profile(chunk_size: u64) -> BackfillProfile {
+    BackfillProfile {
+        chunk_size,
+        concurrency: 2,
+        max_requests_per_second: 0,
+    }
+}
+
+fn mock_provider() -> MockProvider {
+    MockProvider::with_logs(vec![
+        make_delegate_log(10, 0, 1),
+        make_epoch_changed_log(11, 0),
+        make_delegate_log(12, 0, 2),
+        make_delegate_log(30, 0, 1),
+    ])
+}
+
+#[test]
+fn test_backfill_stores_logs_in_range() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let (metrics_tx, _metrics_rx) = tokio::sync::mpsc::unbounded_channel();
+        pipeline::backfill(
+            mock_provider(),
+            db::DbPools::single(pool.clone()),
+            DbTaskOptions::default(),
+            &profile(3),
+            metrics_tx,
+            10..20,
+            false,
+        )
+        .await?;
+
+        assert_eq!(
+            db::repository::get_event_count(&pool, StakingEventType::Delegate).await?,
+            2
+        );
+        assert_eq!(
+            db::repository::get_event_count(&pool, StakingEventType::EpochChanged).await?,
+            1
+        );
+        assert_eq!(db::repository::get_checkpoint(&pool).await?, Some(19));
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_backfill_fails_when_batches_are_dead_lettered() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        sqlx::query("ALTER TABLE delegate_events RENAME TO delegate_events_unavailable")
+            .execute(&pool)
+            .await?;
+        let (metrics_tx, _metrics_rx) = tokio::sync::mpsc::unbounded_channel();
+        let error = pipeline::backfill(
+            mock_provider(),
+            db::DbPools::single(pool.clone()),
+            DbTaskOptions::default(),
+            &profile(3),
+            metrics_tx,
+            10..20,
+            false,
+        )
+        .await
+        .unwrap_err();
+        assert!(error.to_string().contains("dead-letter"), "{error}");
+        // Only chunk 10..13 holds delegations.
+        assert_eq!(
+            db::repository::get_failed_batch_counts(&pool).await?,
+            (1, 0)
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_reindex_replaces_stored_events() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        // Decoded with a bug, not in the logs.
+        test_utils::insert_events(
+            &pool,
+            vec![test_utils::make_delegate_event(15, 5, TEST_DELEGATOR, 100)],
+        )
+        .await?;
+
+        let (metrics_tx, _metrics_rx) = tokio::sync::mpsc::unbounded_channel();
+        pipeline::backfill(
+            mock_provider(),
+            db::DbPools::single(pool.clone()),
+            DbTaskOptions::default(),
+            &profile(3),
+            metrics_tx,
+            10..20,
+            true,
+        )
+        .await?;
+
+        assert_eq!(
+            db::repository::get_event_count(&pool, StakingEventType::Delegate).await?,
+            2
+        );
+        assert_eq!(
+            db::repository::get_stake(&pool, 5, TEST_DELEGATOR).await?,
+            bigdecimal::BigDecimal::from(0)
+        );
+        assert_eq!(db::repository::get_min_block_number(&pool).await?, Some(10));
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_gaps_task_backfills_queued_ranges() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let (db_tx, _gap_rx, _metrics_rx) = test_utils::spawn_process_event_logs(&pool);
+        let (gap_tx, gap_rx) = counting_channel();
+        let (_runtime_tx, runtime_rx) = tokio::sync::watch::channel(RuntimeConfig {
+            backfill: BackfillConfig {
+                initial: profile(100),
+                gaps: profile(1),
+            },
+            gap_check_interval: Duration::from_secs(60),
+            log_level: "info".to_string(),
+        });
+        let (metrics_tx, _metrics_rx) = tokio::sync::mpsc::unbounded_channel();
+        let gaps_task = tokio::spawn(pipeline::process_gaps_task(
+            mock_provider(),
+            db_tx.clone(),
+            gap_rx,
+            runtime_rx,
+            metrics_tx,
+            Default::default(),
+        ));
+
+        gap_tx.send((10..12, GapOrigin::Repair))?;
+        gap_tx.send((12..40, GapOrigin::CatchUp))?;
+        drop(gap_tx);
+        gaps_task.await??;
+
+        assert_eq!(
+            test_utils::get_event_count(&db_tx, StakingEventType::Delegate).await,
+            3
+        );
+        assert_eq!(
+            test_utils::get_event_count(&db_tx, StakingEventType::EpochChanged).await,
+            1
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
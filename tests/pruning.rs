@@ -0,0 +1,81 @@
+use bigdecimal::BigDecimal;
+use monad_staking_indexer::{
+    db, pg_utils,
+    test_utils::{self, TEST_DELEGATOR, insert_events, make_delegate_event},
+};
+
+async fn count(pool: &sqlx::PgPool, table: &str) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {table}"))
+        .fetch_one(pool)
+        .await
+}
+
+#[test]
+fn test_prune_removes_old_rows() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        insert_events(
+            &pool,
+            (1..=10)
+                .map(|block| make_delegate_event(block, 1, TEST_DELEGATOR, 1000))
+                .collect(),
+        )
+        .await?;
+        assert_eq!(db::repository::get_pruned_below(&pool).await?, None);
+
+        // A batch size smaller than the number of rows exercises the batching loop.
+        let report = db::repository::prune_before_in_batches(&pool, 6, 2).await?;
+        assert_eq!(report.events_deleted, 5);
+        assert_eq!(report.blocks_deleted, 5);
+
+        assert_eq!(count(&pool, "blocks").await?, 5);
+        assert_eq!(count(&pool, "delegate_events").await?, 5);
+        assert_eq!(db::repository::get_pruned_below(&pool).await?, Some(6));
+
+        // Aggregates are kept.
+        assert_eq!(
+            db::repository::get_total_stake(&pool, 1).await?,
+            BigDecimal::from(10000)
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_gap_detection_ignores_pruned_region() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        insert_events(
+            &pool,
+            (1..=10)
+                .chain(15..=20)
+                .map(|block| make_delegate_event(block, 1, TEST_DELEGATOR, 1000))
+                .collect(),
+        )
+        .await?;
+        db::repository::prune_before(&pool, 8).await?;
+
+        let gaps = db::repository::get_block_gaps(&pool, &Default::default())
+            .await?
+            .ranges;
+        assert_eq!(gaps, vec![11..15]);
+
+        // A straggling block below the watermark must not create a gap up to it.
+        insert_events(&pool, vec![make_delegate_event(3, 1, TEST_DELEGATOR, 1000)]).await?;
+        let gaps = db::repository::get_block_gaps(&pool, &Default::default())
+            .await?
+            .ranges;
+        assert_eq!(gaps, vec![11..15]);
+
+        // The watermark never moves backwards.
+        db::repository::prune_before(&pool, 5).await?;
+        assert_eq!(db::repository::get_pruned_below(&pool).await?, Some(8));
+
+        Ok(())
+    })
+    .unwrap();
+}
@@ -0,0 +1,131 @@
+use axum::http::HeaderMap;
+use axum::routing::post;
+use monad_staking_indexer::config::{BasicAuth, PushConfig};
+use monad_staking_indexer::queue::QueueDepth;
+use monad_staking_indexer::{events::StakingEventType, metrics, pushgateway};
+use std::collections::HashMap;
+use tokio::sync::mpsc;
+use tokio::time::Duration;
+
+/// Starts a server standing in for the pushgateway, which forwards the path,
+/// `Authorization` header and body of every push.
+async fn spawn_gateway() -> (String, mpsc::UnboundedReceiver<(String, String, String)>) {
+    let (pushes_tx, pushes_rx) = mpsc::unbounded_channel();
+    let app = axum::Router::new().route(
+        "/metrics/job/:job",
+        post(
+            move |axum::extract::Path(job): axum::extract::Path<String>,
+                  headers: HeaderMap,
+                  body: String| async move {
+                let auth = headers
+                    .get(axum::http::header::AUTHORIZATION)
+                    .map(|value| value.to_str().unwrap().to_string())
+                    .unwrap_or_default();
+                let _ = pushes_tx.send((job, auth, body));
+            },
+        ),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    (url, pushes_rx)
+}
+
+#[test]
+fn test_metrics_are_pushed_to_gateway() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let (gateway_url, mut pushes_rx) = spawn_gateway().await;
+
+        // The metrics task only reads the pool size, it never connects.
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .connect_lazy("postgres://indexer@127.0.0.1:1/indexer")
+            .unwrap();
+        let (metrics_tx, metrics_rx) = mpsc::unbounded_channel();
+        let (request_tx, request_rx) = mpsc::unbounded_channel();
+        tokio::spawn(metrics::process_metrics(
+            metrics_rx,
+            QueueDepth::default(),
+            request_rx,
+            pool,
+            metrics::DEFAULT_MAX_TRACKED_VALIDATORS,
+            None,
+        ));
+        metrics_tx
+            .send(metrics::Metric::InsertedEvents(HashMap::from([(
+                StakingEventType::Delegate,
+                (3, 3),
+            )])))
+            .unwrap();
+
+        tokio::spawn(pushgateway::run_pushgateway(
+            request_tx,
+            metrics_tx,
+            PushConfig {
+                gateway_url,
+                job: "indexer".to_string(),
+                interval_secs: 1,
+                basic_auth: Some(BasicAuth {
+                    username: "user".to_string(),
+                    password: "secret".to_string(),
+                }),
+            },
+        ));
+
+        // The first push may render before the inserted events are recorded.
+        let delegate_line = "staking_events_inserted_total{event_type=\"Delegate\"} 3\n";
+        let (job, auth, body) = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                let push = pushes_rx.recv().await.unwrap();
+                if push.2.contains(delegate_line) {
+                    break push;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(job, "indexer");
+        // base64("user:secret")
+        assert_eq!(auth, "Basic dXNlcjpzZWNyZXQ=");
+        assert!(body.contains("# TYPE staking_events_inserted_total counter\n"));
+        assert!(body.contains("# TYPE staking_missing_blocks gauge\n"));
+    });
+}
+
+#[test]
+fn test_failed_push_is_counted() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .connect_lazy("postgres://indexer@127.0.0.1:1/indexer")
+            .unwrap();
+        let (_unused_tx, unused_rx) = mpsc::unbounded_channel();
+        let (request_tx, request_rx) = mpsc::unbounded_channel();
+        tokio::spawn(metrics::process_metrics(
+            unused_rx,
+            QueueDepth::default(),
+            request_rx,
+            pool,
+            metrics::DEFAULT_MAX_TRACKED_VALIDATORS,
+            None,
+        ));
+
+        let (metrics_tx, mut metrics_rx) = mpsc::unbounded_channel();
+        tokio::spawn(pushgateway::run_pushgateway(
+            request_tx,
+            metrics_tx,
+            PushConfig {
+                // Nothing listens on port 1.
+                gateway_url: "http://127.0.0.1:1".to_string(),
+                job: "indexer".to_string(),
+                interval_secs: 1,
+                basic_auth: None,
+            },
+        ));
+
+        let metric = tokio::time::timeout(Duration::from_secs(15), metrics_rx.recv())
+            .await
+            .unwrap();
+        assert_eq!(metric, Some(metrics::Metric::PushFailed));
+    });
+}
@@ -0,0 +1,54 @@
+use monad_staking_indexer::{
+    db,
+    events::{self, StakingEvent, StakingEventType},
+    pg_utils,
+    test_utils::{self, insert_events, make_block_meta},
+};
+
+fn raw_event(block_number: u64, log_index: u64) -> StakingEvent {
+    StakingEvent::Unknown(events::RawEvent {
+        topic0: "ff".repeat(32),
+        topics: vec!["ff".repeat(32), "01".repeat(32)],
+        data: vec![0xab, 0xcd],
+        block_meta: make_block_meta(block_number),
+        tx_meta: events::TxMeta {
+            transaction_hash: format!("0xraw{}", block_number),
+            transaction_index: 0,
+            log_index,
+        },
+    })
+}
+
+#[test]
+fn test_raw_events_insert_and_read_back() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let event = raw_event(100, 3);
+        let report = insert_events(&pool, vec![event.clone()]).await?;
+        assert_eq!(report.event_counts[&StakingEventType::Unknown], (1, 1));
+        assert_eq!(report.unknown_events[&"ff".repeat(32)], 1);
+
+        let replay = insert_events(&pool, vec![event.clone()]).await?;
+        assert!(replay.unknown_events.is_empty());
+        assert_eq!(replay.event_counts[&StakingEventType::Unknown], (0, 1));
+
+        let counts = db::repository::get_event_counts(&pool).await?;
+        assert_eq!(counts[&StakingEventType::Unknown], 1);
+
+        let events = db::repository::get_events_in_block_range(&pool, 100..101).await?;
+        assert_eq!(events.len(), 1);
+        let StakingEvent::Unknown(stored) = &events[0] else {
+            panic!("expected an unknown event, got {}", events[0]);
+        };
+        let StakingEvent::Unknown(expected) = &event else {
+            unreachable!()
+        };
+        assert_eq!(stored.tx_meta.log_index, expected.tx_meta.log_index);
+        assert_eq!(stored.topics, expected.topics);
+        assert_eq!(stored.data, expected.data);
+
+        Ok(())
+    })
+    .unwrap();
+}
@@ -0,0 +1,77 @@
+use monad_staking_indexer::{
+    BatchOrigin, DbRequest, DbTaskOptions, db, pg_utils, process_db_requests, queue,
+    test_utils::{self, TEST_DELEGATOR, batch_of, make_delegate_event},
+};
+use sqlx::ConnectOptions;
+use tokio::sync::mpsc;
+
+/// Inserts blocks 100 and 200 and returns the gap reported afterwards.
+async fn gap_with(pools: db::DbPools) -> Option<std::ops::Range<u64>> {
+    let (tx, rx) = queue::counting_channel();
+    let (gap_tx, mut gap_rx) = queue::counting_channel();
+    let (metrics_tx, mut metrics_rx) = mpsc::unbounded_channel();
+    tokio::spawn(process_db_requests(
+        pools,
+        rx,
+        gap_tx,
+        metrics_tx,
+        DbTaskOptions::default(),
+    ));
+
+    let batch = batch_of(vec![
+        make_delegate_event(100, 1, TEST_DELEGATOR, 1000),
+        make_delegate_event(200, 1, TEST_DELEGATOR, 1000),
+    ]);
+    tx.send(DbRequest::InsertCompleteBlocks(
+        Box::new(batch),
+        BatchOrigin::Backfill,
+    ))
+    .unwrap();
+    test_utils::next_inserted_events(&mut metrics_rx)
+        .await
+        .unwrap();
+
+    tx.send(DbRequest::GetBlockGaps).unwrap();
+    gap_rx.recv().await.map(|(range, _origin)| range)
+}
+
+#[test]
+fn test_gap_check_with_separate_reader_pool() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let url = pool.connect_options().to_url_lossy();
+        let (metrics_tx, _metrics_rx) = mpsc::unbounded_channel();
+        let pools = db::create_pools(
+            url.as_str(),
+            Some(url.as_str()),
+            &db::PoolSettings::default(),
+            metrics_tx,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(gap_with(pools).await, Some(101..200));
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_gap_check_without_reader_pool() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let url = pool.connect_options().to_url_lossy();
+        let (metrics_tx, _metrics_rx) = mpsc::unbounded_channel();
+        let pools = db::create_pools(url.as_str(), None, &db::PoolSettings::default(), metrics_tx)
+            .await
+            .unwrap();
+
+        assert_eq!(gap_with(pools).await, Some(101..200));
+
+        Ok(())
+    })
+    .unwrap();
+}
@@ -0,0 +1,209 @@
+use bigdecimal::BigDecimal;
+use monad_staking_indexer::{
+    BatchOrigin, DbRequest, GapOrigin, db,
+    events::StakingEvent,
+    metrics, pg_utils,
+    test_utils::{
+        self, TEST_DELEGATOR, batch_of, insert_events, make_commission_changed_event,
+        make_delegate_event, make_undelegate_event, make_validator_created_event,
+    },
+};
+use std::ops::Range;
+
+/// A backfilled batch of `range` that replaces the stored blocks and events.
+fn replacement(range: Range<u64>, events: Vec<StakingEvent>) -> DbRequest {
+    let mut batch = batch_of(events);
+    batch.scanned = Some(range.clone());
+    batch.replaces = Some(range);
+    DbRequest::InsertCompleteBlocks(Box::new(batch), BatchOrigin::Backfill)
+}
+
+#[test]
+fn test_delete_range_reverts_derived_state() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        insert_events(
+            &pool,
+            vec![
+                make_delegate_event(100, 1, TEST_DELEGATOR, 1000),
+                make_validator_created_event(101, 1, 40),
+                make_delegate_event(200, 1, TEST_DELEGATOR, 500),
+                make_undelegate_event(210, 1, TEST_DELEGATOR, 1, 300),
+                make_commission_changed_event(220, 1, 0, 50),
+            ],
+        )
+        .await?;
+
+        let report = db::repository::delete_range(&pool, 200..300).await?;
+        assert_eq!(report.events_deleted, 3);
+        assert_eq!(report.blocks_deleted, 3);
+
+        assert_eq!(
+            db::repository::get_block_range(&pool).await?,
+            Some((100, 101))
+        );
+        assert_eq!(
+            db::repository::get_stake(&pool, 1, TEST_DELEGATOR).await?,
+            BigDecimal::from(1000)
+        );
+        assert!(
+            db::repository::get_pending_withdrawals(&pool, 1)
+                .await?
+                .is_empty()
+        );
+        // The commission falls back to the one set before the range.
+        let validator = db::repository::get_validator(&pool, 1).await?.unwrap();
+        assert_eq!(validator.commission, Some(BigDecimal::from(40)));
+
+        // Nothing left to delete.
+        let report = db::repository::delete_range(&pool, 200..300).await?;
+        assert_eq!(report, db::repository::DeleteReport::default());
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_replacement_batch_replaces_rows() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        insert_events(
+            &pool,
+            vec![
+                make_delegate_event(100, 1, TEST_DELEGATOR, 1000),
+                make_delegate_event(200, 1, TEST_DELEGATOR, 500),
+                make_undelegate_event(210, 1, TEST_DELEGATOR, 1, 300),
+                make_commission_changed_event(220, 1, 0, 50),
+                make_delegate_event(300, 1, TEST_DELEGATOR, 10),
+            ],
+        )
+        .await?;
+
+        // The backfill delivers the range again, decoded differently.
+        let (tx, _gaps_rx, mut metrics_rx) = test_utils::spawn_process_event_logs(&pool);
+        tx.send(replacement(
+            200..300,
+            vec![
+                make_delegate_event(200, 1, TEST_DELEGATOR, 700),
+                make_undelegate_event(210, 1, TEST_DELEGATOR, 1, 100),
+            ],
+        ))
+        .unwrap();
+        test_utils::next_inserted_events(&mut metrics_rx)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            db::repository::get_stake(&pool, 1, TEST_DELEGATOR).await?,
+            BigDecimal::from(1000 + 700 - 100 + 10)
+        );
+        let pending = db::repository::get_pending_withdrawals(&pool, 1).await?;
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].amount, BigDecimal::from(100));
+        // No commission change left, and none before the range to fall back to.
+        let validator = db::repository::get_validator(&pool, 1).await?.unwrap();
+        assert_eq!(validator.commission, None);
+        assert_eq!(db::repository::get_block_count(&pool).await?, 4);
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_reorged_blocks_are_deleted_before_reindexing() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        insert_events(
+            &pool,
+            vec![
+                make_delegate_event(200, 1, TEST_DELEGATOR, 500),
+                make_undelegate_event(201, 1, TEST_DELEGATOR, 1, 300),
+                make_commission_changed_event(210, 1, 0, 50),
+            ],
+        )
+        .await?;
+
+        let (tx, mut gaps_rx, mut metrics_rx) = test_utils::spawn_process_event_logs(&pool);
+        tx.send(DbRequest::ReindexReorgedBlocks(vec![210, 200, 201]))
+            .unwrap();
+        assert_eq!(gaps_rx.recv().await, Some((200..202, GapOrigin::Reindex)));
+        assert_eq!(gaps_rx.recv().await, Some((210..211, GapOrigin::Reindex)));
+        // Deleted from every table before the blocks are queued again.
+        assert_eq!(
+            metrics_rx.recv().await,
+            Some(metrics::Metric::ReorgedBlocksDeleted(3))
+        );
+        assert_eq!(db::repository::get_block_count(&pool).await?, 0);
+        let counts = db::repository::get_event_counts(&pool).await?;
+        assert!(counts.values().all(|&count| count == 0), "{counts:?}");
+        assert_eq!(
+            db::repository::get_stake(&pool, 1, TEST_DELEGATOR).await?,
+            BigDecimal::from(0)
+        );
+        assert!(
+            db::repository::get_pending_withdrawals(&pool, 1)
+                .await?
+                .is_empty()
+        );
+
+        // The canonical chain has no staking events in these blocks.
+        tx.send(replacement(200..202, Vec::new())).unwrap();
+        tx.send(replacement(210..211, Vec::new())).unwrap();
+        for _ in 0..2 {
+            test_utils::next_inserted_events(&mut metrics_rx)
+                .await
+                .unwrap();
+        }
+        assert_eq!(db::repository::get_block_count(&pool).await?, 0);
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_reorged_blocks_are_found_again_after_restart() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        insert_events(
+            &pool,
+            vec![
+                make_delegate_event(100, 1, TEST_DELEGATOR, 1000),
+                make_delegate_event(200, 1, TEST_DELEGATOR, 500),
+                make_undelegate_event(201, 1, TEST_DELEGATOR, 1, 300),
+                make_commission_changed_event(210, 1, 0, 50),
+                make_delegate_event(300, 1, TEST_DELEGATOR, 100),
+            ],
+        )
+        .await?;
+        db::repository::set_checkpoint(&pool, 300).await?;
+
+        let (tx, mut gaps_rx, mut metrics_rx) = test_utils::spawn_process_event_logs(&pool);
+        tx.send(DbRequest::ReindexReorgedBlocks(vec![200, 201, 210]))
+            .unwrap();
+        assert_eq!(gaps_rx.recv().await, Some((200..202, GapOrigin::Reindex)));
+        assert_eq!(gaps_rx.recv().await, Some((210..211, GapOrigin::Reindex)));
+        assert_eq!(
+            metrics_rx.recv().await,
+            Some(metrics::Metric::ReorgedBlocksDeleted(3))
+        );
+        assert_eq!(db::repository::get_checkpoint(&pool).await?, Some(199));
+
+        // The process stops before the queued ranges are backfilled.
+        drop(tx);
+        assert_eq!(gaps_rx.recv().await, None);
+
+        let (tx, mut gaps_rx, _metrics_rx) = test_utils::spawn_process_event_logs(&pool);
+        tx.send(DbRequest::GetBlockGaps).unwrap();
+        assert_eq!(gaps_rx.recv().await, Some((200..300, GapOrigin::Repair)));
+
+        Ok(())
+    })
+    .unwrap();
+}
@@ -0,0 +1,139 @@
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+use monad_staking_indexer::config::Config;
+use monad_staking_indexer::metrics::Metric;
+use monad_staking_indexer::reload::{ConfigReloader, ReloadableInterval, restart_required};
+use tokio::sync::{mpsc, watch};
+
+fn config_toml(rpc_url: &str, gap_check_interval_secs: u64, backfill_chunk_size: u64) -> String {
+    format!(
+        r#"
+        rpc_urls = ["{rpc_url}"]
+        db_host = "localhost"
+        db_name = "staking"
+        gap_check_interval_secs = {gap_check_interval_secs}
+
+        [db_credentials]
+        user = "indexer"
+        password = "secret"
+
+        [backfill.gaps]
+        chunk_size = {backfill_chunk_size}
+        "#
+    )
+}
+
+fn write_config(path: &Path, toml: &str) {
+    let mut file = std::fs::File::create(path).unwrap();
+    file.write_all(toml.as_bytes()).unwrap();
+}
+
+#[test]
+fn test_reload_changes_gap_check_interval() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.toml");
+    write_config(&path, &config_toml("wss://a.example.com", 3600, 100));
+
+    let config = Config::load(Some(&path), None, &[]).unwrap();
+    let (runtime_tx, runtime_rx) = watch::channel(config.runtime_config().unwrap());
+    let (metrics_tx, mut metrics_rx) = mpsc::unbounded_channel();
+    let mut reloader = ConfigReloader::new(
+        Some(path.clone()),
+        None,
+        Vec::new(),
+        config,
+        runtime_tx,
+        metrics_tx,
+    );
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let (tick_tx, mut tick_rx) = mpsc::unbounded_channel();
+        let mut interval =
+            ReloadableInterval::new(runtime_rx.clone(), |runtime| runtime.gap_check_interval);
+        let task = tokio::spawn(async move {
+            loop {
+                interval.tick().await;
+                tick_tx.send(interval.period()).unwrap();
+            }
+        });
+
+        // The first tick is immediate, the next one an hour later.
+        assert_eq!(tick_rx.recv().await, Some(Duration::from_secs(3600)));
+        let pending = tokio::time::timeout(Duration::from_millis(200), tick_rx.recv()).await;
+        assert!(pending.is_err());
+
+        write_config(&path, &config_toml("wss://b.example.com", 1, 50));
+        assert_eq!(reloader.reload().unwrap(), vec!["rpc_urls"]);
+        assert_eq!(metrics_rx.recv().await, Some(Metric::ConfigReloaded));
+        assert_eq!(runtime_rx.borrow().backfill.gaps.chunk_size, 50);
+
+        let tick = tokio::time::timeout(Duration::from_secs(5), tick_rx.recv()).await;
+        assert_eq!(tick.unwrap(), Some(Duration::from_secs(1)));
+        assert!(!task.is_finished());
+
+        // An invalid file leaves the runtime settings alone.
+        write_config(&path, &config_toml("wss://b.example.com", 0, 50));
+        assert!(reloader.reload().is_err());
+        assert_eq!(metrics_rx.recv().await, Some(Metric::ConfigReloadFailed));
+        assert_eq!(
+            runtime_rx.borrow().gap_check_interval,
+            Duration::from_secs(1)
+        );
+
+        // Changes are compared with the last applied configuration, so the
+        // new RPC URL is not reported again.
+        write_config(&path, &config_toml("wss://b.example.com", 2, 50));
+        assert!(reloader.reload().unwrap().is_empty());
+        assert_eq!(metrics_rx.recv().await, Some(Metric::ConfigReloaded));
+
+        task.abort();
+    });
+}
+
+#[test]
+fn test_restart_required() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.toml");
+    write_config(&path, &config_toml("wss://a.example.com", 300, 100));
+    let old = Config::load(Some(&path), None, &[]).unwrap();
+
+    write_config(&path, &config_toml("wss://a.example.com", 60, 10));
+    let new = Config::load(Some(&path), None, &[]).unwrap();
+    assert!(restart_required(&old, &new).is_empty());
+
+    write_config(
+        &path,
+        &config_toml("wss://b.example.com", 60, 10).replace("secret", "rotated"),
+    );
+    let new = Config::load(Some(&path), None, &[]).unwrap();
+    assert_eq!(
+        restart_required(&old, &new),
+        vec!["rpc_urls", "db_credentials, vault or aws"]
+    );
+
+    write_config(
+        &path,
+        &format!(
+            r#"
+            db_batch_size = 7
+            {}
+
+            [metrics]
+            port = 9999
+
+            [alerts]
+            webhook_url = "https://hooks.example.com/alerts"
+            failed_inserts = 5
+            "#,
+            config_toml("wss://a.example.com", 300, 100)
+        ),
+    );
+    let new = Config::load(Some(&path), None, &[]).unwrap();
+    assert_eq!(
+        restart_required(&old, &new),
+        vec!["db_batch_size", "metrics", "alerts"]
+    );
+}
@@ -0,0 +1,69 @@
+use monad_staking_indexer::{db, db::repository::DbError, pg_utils, test_utils};
+
+#[test]
+fn test_schema_check_passes_with_all_migrations() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        db::check_schema_version(&pool).await?;
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_schema_check_names_missing_migration() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        // Pretend the latest migration has not been applied.
+        let migrator = sqlx::migrate!("./migrations");
+        let latest = migrator.iter().last().unwrap();
+        sqlx::query("DELETE FROM _sqlx_migrations WHERE version = $1")
+            .bind(latest.version)
+            .execute(&pool)
+            .await?;
+
+        let err = db::check_schema_version(&pool).await.unwrap_err();
+        match err {
+            DbError::MissingMigrations(missing) => {
+                assert_eq!(
+                    missing,
+                    vec![format!("{} ({})", latest.version, latest.description)]
+                );
+            }
+            err => panic!("unexpected error {err:?}"),
+        }
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_schema_check_without_migrations_table() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        sqlx::query("DROP TABLE _sqlx_migrations")
+            .execute(&pool)
+            .await?;
+
+        let migrations = std::fs::read_dir("migrations")?
+            .filter(|entry| {
+                entry
+                    .as_ref()
+                    .is_ok_and(|entry| entry.path().extension().is_some_and(|ext| ext == "sql"))
+            })
+            .count();
+        let err = db::check_schema_version(&pool).await.unwrap_err();
+        assert!(
+            matches!(&err, DbError::MissingMigrations(missing) if missing.len() == migrations),
+            "unexpected error {err:?}"
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
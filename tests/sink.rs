@@ -0,0 +1,110 @@
+use std::sync::Arc;
+
+use futures_util::future::BoxFuture;
+use monad_staking_indexer::{
+    BatchOrigin, DbRequest, DbTaskOptions, db,
+    events::StakingEvent,
+    pg_utils, process_db_requests, queue,
+    sink::EventSink,
+    test_utils::{
+        TEST_DELEGATOR, at_position, batch_of, make_delegate_event, make_undelegate_event,
+        make_validator_created_event,
+    },
+};
+use tokio::sync::mpsc;
+
+/// Hands every published event to a channel.
+struct ChannelSink(mpsc::UnboundedSender<StakingEvent>);
+
+impl EventSink for ChannelSink {
+    fn publish<'a>(&'a self, event: &'a StakingEvent) -> BoxFuture<'a, eyre::Result<()>> {
+        Box::pin(async move {
+            self.0.send(event.clone())?;
+            Ok(())
+        })
+    }
+}
+
+#[test]
+fn test_inserted_events_are_published() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        let (published_tx, mut published_rx) = mpsc::unbounded_channel();
+        let (tx, rx) = queue::counting_channel();
+        let (gap_tx, _gap_rx) = queue::counting_channel();
+        let (metrics_tx, _metrics_rx) = mpsc::unbounded_channel();
+        tokio::spawn(process_db_requests(
+            db::DbPools::single(pool.clone()),
+            rx,
+            gap_tx,
+            metrics_tx,
+            DbTaskOptions {
+                sink: Some(Arc::new(ChannelSink(published_tx))),
+                ..Default::default()
+            },
+        ));
+
+        let batch = batch_of(vec![
+            make_delegate_event(100, 1, TEST_DELEGATOR, 1000),
+            make_delegate_event(101, 1, TEST_DELEGATOR, 1000),
+        ]);
+        tx.send(DbRequest::InsertCompleteBlocks(
+            Box::new(batch),
+            BatchOrigin::Live,
+        ))
+        .unwrap();
+
+        for block in [100, 101] {
+            let event = published_rx.recv().await.unwrap();
+            assert_eq!(event.block_meta().block_number, block);
+            // Only stored events are published.
+            let stored = db::repository::get_events_in_block_range(&pool, block..block + 1).await?;
+            assert_eq!(stored.len(), 1);
+        }
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_events_are_published_in_chain_order() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        let (published_tx, mut published_rx) = mpsc::unbounded_channel();
+        let (tx, rx) = queue::counting_channel();
+        let (gap_tx, _gap_rx) = queue::counting_channel();
+        let (metrics_tx, _metrics_rx) = mpsc::unbounded_channel();
+        tokio::spawn(process_db_requests(
+            db::DbPools::single(pool.clone()),
+            rx,
+            gap_tx,
+            metrics_tx,
+            DbTaskOptions {
+                sink: Some(Arc::new(ChannelSink(published_tx))),
+                ..Default::default()
+            },
+        ));
+
+        // The batch holds events grouped by type, delegations first.
+        let batch = batch_of(vec![
+            at_position(make_delegate_event(100, 1, TEST_DELEGATOR, 1000), 1, 3),
+            at_position(make_delegate_event(101, 1, TEST_DELEGATOR, 1000), 0, 0),
+            at_position(make_undelegate_event(100, 1, TEST_DELEGATOR, 0, 500), 1, 2),
+            at_position(make_validator_created_event(100, 1, 10), 0, 1),
+        ]);
+        tx.send(DbRequest::InsertCompleteBlocks(
+            Box::new(batch),
+            BatchOrigin::Live,
+        ))
+        .unwrap();
+
+        let mut positions = Vec::new();
+        for _ in 0..4 {
+            let event = published_rx.recv().await.unwrap();
+            positions.push((event.block_meta().block_number, event.tx_meta().log_index));
+        }
+        assert_eq!(positions, vec![(100, 1), (100, 2), (100, 3), (101, 0)]);
+
+        Ok(())
+    })
+    .unwrap();
+}
@@ -0,0 +1,85 @@
+use monad_staking_indexer::{BlockBatch, db, pg_utils, startup_start_block, test_utils};
+use tokio::time::Duration;
+
+async fn insert_block(
+    pool: &sqlx::PgPool,
+    block_number: u64,
+) -> Result<(), db::repository::DbError> {
+    let mut batch = BlockBatch::new();
+    batch.add_block_meta(test_utils::make_block_meta(block_number));
+    db::insert_blocks(
+        pool,
+        &batch,
+        Duration::from_secs(1),
+        db::DuplicatePolicy::Ignore,
+        db::ConflictStrategies::default(),
+        db::BlockConflictStrategy::Ignore,
+    )
+    .await?;
+    Ok(())
+}
+
+#[test]
+fn test_start_block_on_first_run() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        assert_eq!(startup_start_block(&pool, None, None).await?, None);
+        assert_eq!(db::repository::get_checkpoint(&pool).await?, None);
+        assert_eq!(
+            startup_start_block(&pool, Some(1000), None).await?,
+            Some(1000)
+        );
+        assert_eq!(db::repository::get_checkpoint(&pool).await?, Some(1000));
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_start_block_resumes_from_indexed_blocks() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        for block in [500, 501, 502, 2000] {
+            insert_block(&pool, block).await?;
+        }
+
+        // Once something is indexed, min_start_block no longer applies.
+        assert_eq!(startup_start_block(&pool, None, None).await?, Some(2000));
+        assert_eq!(
+            startup_start_block(&pool, Some(1000), None).await?,
+            Some(2000)
+        );
+
+        // The checkpoint stops before the first gap, so that it is backfilled.
+        assert_eq!(db::repository::get_checkpoint(&pool).await?, Some(502));
+        let gaps = db::repository::get_block_gaps(&pool, &Default::default()).await?;
+        assert_eq!(gaps.ranges, vec![503..2000]);
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_start_block_seeds_checkpoint_below_indexed_blocks() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        insert_block(&pool, 500).await?;
+        insert_block(&pool, 501).await?;
+
+        assert_eq!(
+            startup_start_block(&pool, Some(100), None).await?,
+            Some(501)
+        );
+        assert_eq!(db::repository::get_checkpoint(&pool).await?, Some(100));
+        let gaps = db::repository::get_block_gaps(&pool, &Default::default()).await?;
+        assert_eq!(gaps.ranges, vec![101..500]);
+
+        Ok(())
+    })
+    .unwrap();
+}
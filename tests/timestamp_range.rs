@@ -0,0 +1,44 @@
+use monad_staking_indexer::{db, pg_utils, test_utils};
+
+#[test]
+fn test_get_events_by_timestamp_range_is_inclusive() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        let events = (100..105)
+            .map(|block| {
+                if block % 2 == 0 {
+                    test_utils::make_delegate_event(block, 1, test_utils::TEST_DELEGATOR, 1000)
+                } else {
+                    test_utils::make_epoch_changed_event(block, block)
+                }
+            })
+            .collect();
+        test_utils::insert_events(&pool, events).await?;
+
+        let from_ts = test_utils::make_block_meta(101).block_timestamp;
+        let to_ts = test_utils::make_block_meta(103).block_timestamp;
+        let found = db::repository::get_events_by_timestamp_range(&pool, from_ts, to_ts).await?;
+
+        let blocks: Vec<u64> = found.block_meta.iter().map(|m| m.block_number).collect();
+        assert_eq!(blocks, vec![101, 102, 103]);
+        assert_eq!(found.total_event_count(), 3);
+        assert_eq!(found.delegate.len(), 1);
+        assert_eq!(
+            found.delegate[0].block_meta,
+            test_utils::make_block_meta(102)
+        );
+        assert_eq!(found.epoch_changed.len(), 2);
+
+        let single = db::repository::get_events_by_timestamp_range(&pool, to_ts, to_ts).await?;
+        assert_eq!(single.epoch_changed.len(), 1);
+        assert_eq!(single.epoch_changed[0].block_meta.block_number, 103);
+
+        let empty = db::repository::get_events_by_timestamp_range(&pool, 0, from_ts - 2).await?;
+        assert!(empty.block_meta.is_empty());
+        assert_eq!(empty.total_event_count(), 0);
+
+        Ok(())
+    })
+    .unwrap();
+}
@@ -0,0 +1,233 @@
+use monad_staking_indexer::{
+    db, events, pg_utils,
+    test_utils::{
+        self, TEST_DELEGATOR, at_position, insert_events, make_commission_changed_event,
+        make_validator_created_event, make_validator_status_changed_event,
+    },
+};
+
+#[test]
+fn test_validator_registry_in_order() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        assert_eq!(db::repository::get_validator(&pool, 1).await?, None);
+
+        insert_events(&pool, vec![make_validator_created_event(100, 1, 50)]).await?;
+        insert_events(&pool, vec![make_commission_changed_event(200, 1, 50, 75)]).await?;
+        insert_events(&pool, vec![make_validator_status_changed_event(300, 1, 2)]).await?;
+
+        let validator = db::repository::get_validator(&pool, 1).await?.unwrap();
+        assert_eq!(validator.auth_address.as_deref(), Some(TEST_DELEGATOR));
+        assert_eq!(validator.commission, Some(75u64.into()));
+        assert_eq!(validator.flags, Some(2));
+        assert_eq!(validator.created_block, Some(100));
+        assert_eq!(validator.updated_block, 300);
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_validator_registry_out_of_order_backfill() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        // The live stream indexes the newest state first.
+        insert_events(
+            &pool,
+            vec![
+                make_commission_changed_event(200, 1, 60, 75),
+                make_validator_status_changed_event(200, 1, 2),
+            ],
+        )
+        .await?;
+
+        // Backfill then delivers older events for the same validator.
+        insert_events(
+            &pool,
+            vec![
+                make_validator_created_event(100, 1, 50),
+                make_commission_changed_event(150, 1, 50, 60),
+                make_validator_status_changed_event(150, 1, 1),
+            ],
+        )
+        .await?;
+
+        let validator = db::repository::get_validator(&pool, 1).await?.unwrap();
+        assert_eq!(validator.auth_address.as_deref(), Some(TEST_DELEGATOR));
+        assert_eq!(validator.commission, Some(75u64.into()));
+        assert_eq!(validator.flags, Some(2));
+        assert_eq!(validator.created_block, Some(100));
+        assert_eq!(validator.updated_block, 200);
+
+        // Replaying the newest events must not change anything either.
+        insert_events(
+            &pool,
+            vec![
+                make_commission_changed_event(200, 1, 60, 75),
+                make_validator_status_changed_event(200, 1, 2),
+            ],
+        )
+        .await?;
+        let replayed = db::repository::get_validator(&pool, 1).await?.unwrap();
+        assert_eq!(replayed, validator);
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_validator_registry_orders_by_log_index() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        // Changes within one block, the later logs inserted first.
+        insert_events(
+            &pool,
+            vec![
+                at_position(make_validator_status_changed_event(100, 1, 2), 5, 5),
+                at_position(make_commission_changed_event(100, 1, 60, 75), 4, 4),
+            ],
+        )
+        .await?;
+        insert_events(
+            &pool,
+            vec![
+                at_position(make_validator_status_changed_event(100, 1, 1), 3, 3),
+                at_position(make_commission_changed_event(100, 1, 50, 60), 2, 2),
+            ],
+        )
+        .await?;
+
+        let validator = db::repository::get_validator(&pool, 1).await?.unwrap();
+        assert_eq!(validator.flags, Some(2));
+        assert_eq!(validator.commission, Some(75u64.into()));
+
+        // Reverting a later change falls back to the last log of the block.
+        insert_events(&pool, vec![make_validator_status_changed_event(200, 1, 4)]).await?;
+        db::repository::delete_range(&pool, 200..300).await?;
+        let state = db::repository::list_validator_states(&pool).await?;
+        assert_eq!(state[0].flags, Some(2));
+        assert_eq!(state[0].flags_log_index, Some(5));
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_list_validators() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        insert_events(
+            &pool,
+            vec![
+                make_validator_created_event(100, 2, 10),
+                make_validator_created_event(101, 1, 20),
+            ],
+        )
+        .await?;
+
+        let validators = db::repository::list_validators(&pool).await?;
+        assert_eq!(validators.len(), 2);
+        assert_eq!(validators[0].validator_id, 1);
+        assert_eq!(validators[0].commission, Some(20u64.into()));
+        assert_eq!(validators[1].validator_id, 2);
+        assert_eq!(validators[1].commission, Some(10u64.into()));
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_validators_created_in_block_range() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        insert_events(
+            &pool,
+            vec![
+                make_validator_created_event(99, 1, 10),
+                make_validator_created_event(100, 2, 20),
+                make_validator_created_event(150, 3, 30),
+                make_validator_created_event(200, 4, 40),
+                make_validator_created_event(201, 5, 50),
+            ],
+        )
+        .await?;
+
+        let created =
+            db::repository::get_validators_created_in_block_range(&pool, 100, 200).await?;
+        let ids: Vec<i64> = created.iter().map(|row| row.validator_id).collect();
+        assert_eq!(ids, vec![2, 3, 4]);
+        assert_eq!(created[0].block_number, 100);
+        assert_eq!(created[0].commission, bigdecimal::BigDecimal::from(20));
+        assert_eq!(created[0].transaction_hash, "0xtx100");
+        assert_eq!(created[0].auth_address, TEST_DELEGATOR);
+
+        let single = db::repository::get_validators_created_in_block_range(&pool, 150, 150).await?;
+        assert_eq!(single.len(), 1);
+        assert_eq!(single[0].validator_id, 3);
+
+        assert!(
+            db::repository::get_validators_created_in_block_range(&pool, 151, 199)
+                .await?
+                .is_empty()
+        );
+
+        Ok(())
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_decoded_flag_columns() {
+    pg_utils::with_postgres_and_schema_async(|pool| async move {
+        test_utils::init_test_logger();
+
+        insert_events(
+            &pool,
+            vec![
+                make_validator_status_changed_event(100, 1, 0),
+                make_validator_status_changed_event(100, 2, 0b100),
+                make_validator_status_changed_event(100, 3, 1 << 40 | 0b1),
+            ],
+        )
+        .await?;
+
+        let rows = sqlx::query_as::<_, (i64, bool, bool, bool, bool, i64)>(
+            "SELECT validator_id, is_active, is_stake_too_low, is_withdrawn, is_jailed, unknown_flags \
+             FROM validator_status_changed_events ORDER BY validator_id",
+        )
+        .fetch_all(&pool)
+        .await?;
+        assert_eq!(
+            rows,
+            vec![
+                (1, true, false, false, false, 0),
+                (2, false, false, false, true, 0),
+                (3, false, true, false, false, 1 << 40),
+            ]
+        );
+
+        let jailed: Vec<i64> =
+            sqlx::query_scalar("SELECT validator_id FROM validators WHERE is_jailed")
+                .fetch_all(&pool)
+                .await?;
+        assert_eq!(jailed, vec![2]);
+        assert_eq!(db::repository::get_jailed_validator_count(&pool).await?, 1);
+
+        let validator = db::repository::get_validator(&pool, 3).await?.unwrap();
+        let flags = validator.decoded_flags().unwrap();
+        assert!(flags.contains(events::ValidatorFlags::STAKE_TOO_LOW));
+        assert_eq!(flags.unknown_bits(), 1 << 40);
+
+        Ok(())
+    })
+    .unwrap();
+}